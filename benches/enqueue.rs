@@ -0,0 +1,44 @@
+use std::net::UdpSocket;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use udp_logger::UdpLoggerBuilder;
+
+const MESSAGES: usize = 100;
+
+fn enqueue(c: &mut Criterion) {
+    // Nothing reads from the receiver, the kernel discards datagrams once its buffer fills
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+        .manual()
+        .build()
+        .unwrap();
+    let messages = || -> Vec<String> { (0..MESSAGES).map(|i| format!("message {}", i)).collect() };
+
+    let mut group = c.benchmark_group("enqueue");
+    group.bench_function("send_raw", |b| {
+        b.iter_batched(
+            messages,
+            |messages| {
+                for msg in messages {
+                    logger.send_raw(&msg).unwrap();
+                }
+                logger.drain()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("log_batch", |b| {
+        b.iter_batched(
+            messages,
+            |messages| {
+                logger.log_batch(messages).unwrap();
+                logger.drain()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, enqueue);
+criterion_main!(benches);
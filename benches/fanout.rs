@@ -0,0 +1,47 @@
+use std::net::UdpSocket;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use log::{Level, Log, Record};
+use udp_logger::UdpLoggerBuilder;
+
+const MESSAGES: usize = 100;
+
+/// Queued payloads are `Vec<u8>`s by default and `bytes::Bytes` with the `bytes`
+/// feature, so compare the two with `cargo bench --bench fanout` then
+/// `cargo bench --bench fanout --features bytes`
+fn fanout(c: &mut Criterion) {
+    // Nothing reads from the receiver, the kernel discards datagrams once its buffer fills
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let payload = match cfg!(feature = "bytes") {
+        true => "bytes",
+        false => "vec",
+    };
+    let mut group = c.benchmark_group(format!("fanout_{}", payload));
+    // With requeues, the drain keeps a copy of every message it pops until it's sent
+    for (name, requeues) in [("no_requeue", None), ("requeue", Some(3))].iter() {
+        let mut builder = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .manual()
+            .with_batching(1024);
+        if let Some(requeues) = requeues {
+            builder = builder.with_requeue(*requeues);
+        }
+        let logger = builder.build().unwrap();
+        group.bench_function(*name, |b| {
+            b.iter(|| {
+                for i in 0..MESSAGES {
+                    logger.log(
+                        &Record::builder()
+                            .args(format_args!("message {}", i))
+                            .level(Level::Info)
+                            .build(),
+                    );
+                }
+                logger.drain()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, fanout);
+criterion_main!(benches);
@@ -0,0 +1,38 @@
+use std::net::UdpSocket;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use log::{Level, Log, Record};
+use udp_logger::{TimestampFormat, UdpLoggerBuilder};
+
+fn format(c: &mut Criterion) {
+    // Nothing reads from the receiver, the kernel discards datagrams once its buffer fills
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut group = c.benchmark_group("format");
+    for (name, timestamps) in [
+        ("rfc3339", TimestampFormat::default()),
+        ("no_timestamp", TimestampFormat::None),
+    ]
+    .iter()
+    {
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .manual()
+            .with_timestamp_format(*timestamps)
+            .build()
+            .unwrap();
+        group.bench_function(*name, |b| {
+            b.iter(|| {
+                logger.log(
+                    &Record::builder()
+                        .args(format_args!("message {}", 42))
+                        .level(Level::Info)
+                        .build(),
+                );
+                logger.drain()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, format);
+criterion_main!(benches);
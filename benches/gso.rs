@@ -0,0 +1,51 @@
+//! Compares draining fixed-size records one `send_to` at a time against UDP GSO
+//!
+//! With 64 segments per `sendmsg`, GSO drains `MESSAGES` records in `MESSAGES / 64`
+//! syscalls instead of `MESSAGES` (compare with `strace -c -e sendto,sendmsg`)
+use std::net::UdpSocket;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use udp_logger::UdpLoggerBuilder;
+
+const MESSAGES: usize = 1024;
+
+/// Each record is 63 bytes plus the newline added by `send_raw`
+const RECORD_SIZE: u16 = 64;
+
+#[cfg(target_os = "linux")]
+fn gso(c: &mut Criterion) {
+    // Nothing reads from the receiver, the kernel discards datagrams once its buffer fills
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let plain = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+        .manual()
+        .build()
+        .unwrap();
+    let segmented = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+        .manual()
+        .with_gso(RECORD_SIZE)
+        .build()
+        .unwrap();
+    let record = "x".repeat(RECORD_SIZE as usize - 1);
+
+    let mut group = c.benchmark_group("drain");
+    for (name, logger) in [("send_to", &plain), ("gso", &segmented)] {
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || {
+                    for _ in 0..MESSAGES {
+                        logger.send_raw(&record).unwrap();
+                    }
+                },
+                |()| logger.drain(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+#[cfg(not(target_os = "linux"))]
+fn gso(_c: &mut Criterion) {}
+
+criterion_group!(benches, gso);
+criterion_main!(benches);
@@ -0,0 +1,10 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "prost")]
+    {
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("Vendored protoc");
+        std::env::set_var("PROTOC", protoc);
+        prost_build::compile_protos(&["proto/log_record.proto"], &["proto/"])
+            .expect("Compile proto/log_record.proto");
+    }
+}
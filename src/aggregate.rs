@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::Record;
+
+use crate::throttle::KeyFn;
+
+/// Counts records by key over a window instead of sending each one
+pub(crate) struct Aggregator {
+    key: KeyFn,
+    interval: Duration,
+    window: Mutex<Window>,
+}
+
+/// The occurrences of each key since `started`
+struct Window {
+    started: Instant,
+    counts: BTreeMap<String, u64>,
+}
+
+impl Aggregator {
+    pub(crate) fn new(key: KeyFn, interval: Duration) -> Self {
+        Self {
+            key,
+            interval,
+            window: Mutex::new(Window {
+                started: Instant::now(),
+                counts: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Count `record` at `now`, returning the previous window's counts if it just ended
+    pub(crate) fn add(&self, record: &Record, now: Instant) -> BTreeMap<String, u64> {
+        let key = (self.key)(record);
+        let mut window = self.window.lock().unwrap();
+        let finished = if now.duration_since(window.started) >= self.interval {
+            window.started = now;
+            mem::take(&mut window.counts)
+        } else {
+            BTreeMap::new()
+        };
+        *window.counts.entry(key).or_default() += 1;
+        finished
+    }
+
+    /// End the current window if it's over at `now`, returning its counts, or how long
+    /// until it's over
+    pub(crate) fn take_due(&self, now: Instant) -> Result<BTreeMap<String, u64>, Duration> {
+        let mut window = self.window.lock().unwrap();
+        let elapsed = now.saturating_duration_since(window.started);
+        if elapsed < self.interval {
+            return Err(self.interval - elapsed);
+        }
+        window.started = now;
+        Ok(mem::take(&mut window.counts))
+    }
+
+    /// How long after `now` the current window ends
+    pub(crate) fn due_in(&self, now: Instant) -> Duration {
+        let window = self.window.lock().unwrap();
+        self.interval
+            .saturating_sub(now.saturating_duration_since(window.started))
+    }
+
+    /// End the current window early, returning its counts
+    pub(crate) fn take(&self, now: Instant) -> BTreeMap<String, u64> {
+        let mut window = self.window.lock().unwrap();
+        window.started = now;
+        mem::take(&mut window.counts)
+    }
+
+    /// The aggregate datagram for `count` occurrences of `key` in a window
+    pub(crate) fn render(&self, key: &str, count: u64, timestamp: &str) -> String {
+        format!(
+            "AGGREGATE [{}] count={} window={:?} {}\n",
+            timestamp, count, self.interval, key
+        )
+    }
+}
+
+/// The shortest time the timer waits between windows
+const MIN_WAIT: Duration = Duration::from_millis(1);
+
+/// Ends each aggregation window on time, even if no record arrives after it
+///
+/// Stopped when dropped, leaving the current window to whoever dropped it
+pub(crate) struct AggregateTimer {
+    shutdown: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl AggregateTimer {
+    /// Run `tick` whenever the window it last reported is due, starting now
+    ///
+    /// `tick` sends the window if it's over at the given time, and returns how long
+    /// until the next one is.
+    pub(crate) fn new(tick: impl Fn(Instant) -> Duration + Send + 'static) -> Self {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let stopping = Arc::clone(&shutdown);
+        let thread = thread::spawn(move || {
+            let mut wait = tick(Instant::now());
+            loop {
+                // Parked rather than slept so dropping the timer wakes it immediately, and
+                // never for no time at all so a zero interval doesn't spin
+                thread::park_timeout(wait.max(MIN_WAIT));
+                if stopping.load(Ordering::SeqCst) {
+                    return;
+                }
+                wait = tick(Instant::now());
+            }
+        });
+        Self {
+            shutdown,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for AggregateTimer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            thread.join().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    #[test]
+    fn test_windows() {
+        let aggregator = Aggregator::new(
+            Box::new(|record| record.args().to_string()),
+            Duration::from_secs(1),
+        );
+        let add = |message: &str, now| {
+            aggregator.add(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(Level::Info)
+                    .build(),
+                now,
+            )
+        };
+        let start = Instant::now();
+        assert!(add("a", start).is_empty());
+        assert!(add("b", start).is_empty());
+        assert!(add("a", start + Duration::from_millis(500)).is_empty());
+
+        let finished = add("b", start + Duration::from_secs(2));
+        assert_eq!(finished, BTreeMap::from([("a".into(), 2), ("b".into(), 1)]));
+        assert_eq!(
+            aggregator.take_due(start + Duration::from_millis(2500)),
+            Err(Duration::from_millis(500))
+        );
+        assert_eq!(
+            aggregator.due_in(start + Duration::from_millis(2500)),
+            Duration::from_millis(500)
+        );
+        let rest = aggregator.take(start + Duration::from_secs(2));
+        assert_eq!(rest, BTreeMap::from([("b".into(), 1)]));
+        assert_eq!(
+            aggregator.render("a", 2, "ts"),
+            "AGGREGATE [ts] count=2 window=1s a\n"
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use std::fmt::Debug;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use async_std::channel::{self, Sender};
+use async_std::task;
+
+use crate::queue::{Envelope, Payload};
+use crate::socket::SocketOptions;
+use crate::stats::Stats;
+use crate::{resolve, Writer};
+
+/// AsyncStdUdpWriter is a buffered writer backed by the `async-std` runtime
+///
+/// Messages are queued on an unbounded channel and sent from a spawned task
+/// via an `async_std::net::UdpSocket`. The task runs on async-std's global
+/// executor, which is started on demand, so the caller does not need to be
+/// inside an async context.
+pub(crate) struct AsyncStdUdpWriter {
+    messages: Sender<Payload>,
+    /// Messages pushed that the task hasn't finished sending, including the one it's
+    /// sending now
+    pending: Arc<AtomicUsize>,
+    local_addr: SocketAddr,
+    destination: SocketAddr,
+    socket: SocketOptions,
+}
+
+impl AsyncStdUdpWriter {
+    /// Create a new AsyncStdUdpWriter that sends messages to the given destination `SocketAddr`
+    pub fn new(
+        destination: impl ToSocketAddrs + Debug,
+        socket: &SocketOptions,
+        stats: Arc<Stats>,
+    ) -> io::Result<Self> {
+        let dest = socket.target(resolve(destination)?);
+        let out = socket.bind()?;
+        let local_addr = out.local_addr()?;
+        let out = async_std::net::UdpSocket::from(out);
+        let (messages, receiver) = channel::unbounded::<Payload>();
+        let options = socket.clone();
+        let socket = socket.clone();
+        let pending = Arc::new(AtomicUsize::new(0));
+        let sending = pending.clone();
+
+        task::spawn(async move {
+            while let Ok(message) = receiver.recv().await {
+                if socket.is_paused() {
+                    stats.record_drop();
+                    sending.fetch_sub(1, Ordering::SeqCst);
+                    continue;
+                }
+                let sent = match socket.seal(&message) {
+                    Ok(datagram) => out
+                        .send_to(&datagram, dest)
+                        .await
+                        .map(|_| socket.sent(&datagram)),
+                    Err(e) => Err(e),
+                };
+                match sent {
+                    Ok(()) => stats.record_send(),
+                    Err(e) => {
+                        stats.record_error();
+                        eprintln!("Error sending message: {}", e);
+                    }
+                }
+                sending.fetch_sub(1, Ordering::SeqCst);
+            }
+        });
+        Ok(Self {
+            messages,
+            pending,
+            local_addr,
+            destination: dest,
+            socket: options,
+        })
+    }
+}
+
+impl Writer for AsyncStdUdpWriter {
+    fn push(&self, message: Envelope) -> io::Result<()> {
+        // Counted before it's sent, so the task can never finish it first
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        self.messages.try_send(message.payload).map_err(|e| {
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+            io::Error::new(io::ErrorKind::BrokenPipe, e.to_string())
+        })
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+
+    /// The socket is owned by the sending task, so it can't be replaced
+    fn reconnect(&self) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "an async-std logger's socket can't be rebound",
+        ))
+    }
+
+    fn probe(&self, timeout: Duration) -> bool {
+        self.socket.probe(self.destination, timeout)
+    }
+
+    fn flush_timeout(&self, timeout: Duration) -> usize {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = self.pending.load(Ordering::SeqCst);
+            let now = Instant::now();
+            if remaining == 0 || now >= deadline {
+                return remaining;
+            }
+            thread::sleep((deadline - now).min(Duration::from_millis(5)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use log::{Level, Log, Record};
+
+    use crate::UdpLogger;
+
+    #[test]
+    fn test_async_std_send() {
+        async_std::task::block_on(async {
+            let receiver = async_std::net::UdpSocket::bind("127.0.0.1:0")
+                .await
+                .unwrap();
+            let logger = UdpLogger::new_async_std(receiver.local_addr().unwrap())
+                .expect("Can bind to localhost");
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("async testing"))
+                    .level(Level::Info)
+                    .build(),
+            );
+
+            let mut buf = [0; 1024];
+            let (len, _) =
+                async_std::future::timeout(Duration::from_secs(2), receiver.recv_from(&mut buf))
+                    .await
+                    .expect("Message arrives")
+                    .unwrap();
+            assert!(String::from_utf8_lossy(&buf[..len]).ends_with("] async testing\n"));
+        });
+    }
+
+    #[test]
+    fn test_async_std_flush() {
+        let receiver = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let logger = UdpLogger::new_async_std(receiver.local_addr().unwrap())
+            .expect("Can bind to localhost");
+        for i in 0..50 {
+            logger.send_raw(&format!("message {}", i)).unwrap();
+        }
+        // Waits for the last datagram to be sent, not just received by the task
+        assert_eq!(logger.flush_timeout(Duration::from_secs(2)), 0);
+        assert_eq!(logger.stats().sent, 50);
+    }
+}
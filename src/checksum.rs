@@ -0,0 +1,115 @@
+use std::io;
+
+/// The checksum `UdpLoggerBuilder::with_checksum` appends to every datagram
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Checksum {
+    /// CRC-32 (IEEE, as used by zlib and Ethernet), as a 4-byte big-endian trailer
+    #[default]
+    Crc32,
+}
+
+impl Checksum {
+    /// Bytes the trailer adds to a datagram
+    pub fn trailer_len(self) -> usize {
+        match self {
+            Checksum::Crc32 => 4,
+        }
+    }
+
+    /// The trailer for `datagram`
+    fn trailer(self, datagram: &[u8]) -> Vec<u8> {
+        match self {
+            Checksum::Crc32 => crc32fast::hash(datagram).to_be_bytes().to_vec(),
+        }
+    }
+
+    /// `datagram` followed by its trailer
+    pub(crate) fn append(self, datagram: &[u8]) -> Vec<u8> {
+        [datagram, &self.trailer(datagram)].concat()
+    }
+}
+
+/// Check the trailer of a datagram sent with `with_checksum`, returning the datagram
+/// without it
+///
+/// The trailer is the last `checksum.trailer_len()` bytes, computed over every byte
+/// before it (including any `with_magic_prefix`), so check it before anything else. A
+/// datagram too short to have one, or whose trailer doesn't match, is an `InvalidData`
+/// error.
+/// ```
+/// # use std::net::UdpSocket;
+/// use log::{Log, Record};
+/// use udp_logger::{Checksum, UdpLoggerBuilder};
+///
+/// let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+/// let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+///     .with_checksum(Checksum::Crc32)
+///     .build()
+///     .unwrap();
+/// logger.log(&Record::builder().args(format_args!("ok")).build());
+///
+/// let mut datagram = [0; 1024];
+/// let len = receiver.recv(&mut datagram).unwrap();
+/// let payload = udp_logger::verify_checksum(&datagram[..len], Checksum::Crc32).unwrap();
+/// assert!(payload.ends_with(b"ok\n"));
+/// ```
+pub fn verify_checksum(datagram: &[u8], checksum: Checksum) -> io::Result<&[u8]> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let split = datagram
+        .len()
+        .checked_sub(checksum.trailer_len())
+        .ok_or_else(|| invalid("datagram is shorter than its checksum"))?;
+    let (payload, trailer) = datagram.split_at(split);
+    if checksum.trailer(payload) != trailer {
+        return Err(invalid("checksum doesn't match"));
+    }
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A bitwise CRC-32 (reflected polynomial 0xedb88320), independent of crc32fast
+    fn crc32(bytes: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for byte in bytes {
+            crc ^= u32::from(*byte);
+            for _ in 0..8 {
+                crc = match crc & 1 {
+                    1 => (crc >> 1) ^ 0xedb8_8320,
+                    _ => crc >> 1,
+                };
+            }
+        }
+        !crc
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        let payload = b"INFO [ts] testing\n";
+        let datagram = Checksum::Crc32.append(payload);
+        assert_eq!(&datagram[..payload.len()], payload);
+        assert_eq!(&datagram[payload.len()..], crc32(payload).to_be_bytes());
+        assert_eq!(
+            verify_checksum(&datagram, Checksum::Crc32).unwrap(),
+            payload
+        );
+    }
+
+    #[test]
+    fn test_verify_checksum() {
+        let mut datagram = Checksum::Crc32.append(b"testing\n");
+        datagram[0] ^= 1;
+        let error = verify_checksum(&datagram, Checksum::Crc32).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let error = verify_checksum(&[1, 2, 3], Checksum::Crc32).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,111 @@
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+/// Dispatches every record to several loggers, e.g. a console logger alongside a UdpLogger
+///
+/// ```
+/// use log::LevelFilter;
+/// use udp_logger::{CombinedLogger, UdpLogger};
+///
+/// let udp = UdpLogger::new("127.0.0.1:1999").unwrap();
+/// CombinedLogger::new(vec![Box::new(udp)])
+///     .init(LevelFilter::Info)
+///     .unwrap();
+/// ```
+pub struct CombinedLogger {
+    loggers: Vec<Box<dyn Log>>,
+}
+
+impl CombinedLogger {
+    /// Combine the given loggers, each still filters records with its own `enabled`
+    pub fn new(loggers: Vec<Box<dyn Log>>) -> Self {
+        Self { loggers }
+    }
+
+    /// Add another logger
+    pub fn with(mut self, logger: impl Log + 'static) -> Self {
+        self.loggers.push(Box::new(logger));
+        self
+    }
+
+    /// Install the combined logger as the destination for `Log` macros
+    pub fn init(self, max_level: LevelFilter) -> Result<(), SetLoggerError> {
+        log::set_boxed_logger(Box::new(self)).map(|()| log::set_max_level(max_level))
+    }
+}
+
+impl Log for CombinedLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.loggers.iter().any(|logger| logger.enabled(metadata))
+    }
+
+    fn log(&self, record: &Record) {
+        for logger in &self.loggers {
+            if logger.enabled(record.metadata()) {
+                logger.log(record);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        for logger in &self.loggers {
+            logger.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use log::Level;
+
+    use super::*;
+    use crate::UdpLogger;
+
+    #[derive(Clone, Default)]
+    struct CaptureLogger(Arc<Mutex<Vec<String>>>);
+
+    impl Log for CaptureLogger {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn test_combined() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let capture = CaptureLogger::default();
+        let udp = UdpLogger::new(receiver.local_addr().unwrap()).expect("Can bind to localhost");
+        let logger = CombinedLogger::new(vec![Box::new(udp)]).with(capture.clone());
+
+        for level in [Level::Info, Level::Debug] {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("testing"))
+                    .level(level)
+                    .build(),
+            );
+        }
+
+        // The capture logger accepts everything, the UdpLogger only Info and above
+        assert_eq!(*capture.0.lock().unwrap(), ["testing", "testing"]);
+        let mut buf = [0; 1024];
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        assert!(String::from_utf8_lossy(&buf[..len]).ends_with("] testing\n"));
+        receiver
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        assert!(receiver.recv(&mut buf).is_err());
+    }
+}
@@ -0,0 +1,120 @@
+use std::convert::TryInto;
+use std::io;
+
+/// Flag byte starting a datagram sent as-is
+const UNCOMPRESSED: u8 = 0;
+
+/// Flag byte starting a datagram compressed as an LZ4 block
+const LZ4_BLOCK: u8 = 1;
+
+/// Largest payload `decompress` produces, bounding what a hostile datagram can expand to
+const MAX_DECOMPRESSED: usize = 1 << 24;
+
+/// Prefix `datagram` with a flag byte, compressing it first if it's over `threshold`
+/// bytes and compression makes it smaller
+pub(crate) fn compress_over(threshold: usize, datagram: &[u8]) -> Vec<u8> {
+    if datagram.len() > threshold {
+        let compressed = lz4_flex::block::compress_prepend_size(datagram);
+        if compressed.len() < datagram.len() {
+            return [&[LZ4_BLOCK][..], &compressed].concat();
+        }
+    }
+    [&[UNCOMPRESSED][..], datagram].concat()
+}
+
+/// Recover the payload of a datagram sent with `with_compression_threshold`
+///
+/// The first byte says whether the rest is compressed (1) or not (0). A compressed
+/// datagram carries the payload's length as a little-endian u32, then an LZ4 block, and
+/// payloads claiming more than 16 MiB are rejected. Strip any `with_magic_prefix` and
+/// decrypt the datagram first; a `with_payload_header` header is inside the compressed
+/// bytes.
+/// ```
+/// # use std::net::UdpSocket;
+/// use log::{Log, Record};
+/// use udp_logger::UdpLoggerBuilder;
+///
+/// let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+/// let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+///     .with_compression_threshold(256)
+///     .build()
+///     .unwrap();
+/// logger.log(&Record::builder().args(format_args!("{}", "ok ".repeat(100))).build());
+///
+/// let mut datagram = [0; 1024];
+/// let len = receiver.recv(&mut datagram).unwrap();
+/// let payload = udp_logger::decompress(&datagram[..len]).unwrap();
+/// assert!(payload.ends_with(b"ok ok \n"));
+/// ```
+pub fn decompress(datagram: &[u8]) -> io::Result<Vec<u8>> {
+    match datagram.split_first() {
+        Some((&UNCOMPRESSED, payload)) => Ok(payload.to_vec()),
+        Some((&LZ4_BLOCK, block)) => lz4_unblock(block),
+        Some((flag, _)) => Err(invalid(&format!("unknown compression flag {}", flag))),
+        None => Err(invalid("datagram is empty")),
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Decompress a length-prefixed LZ4 block, checking the length before allocating
+fn lz4_unblock(block: &[u8]) -> io::Result<Vec<u8>> {
+    if block.len() < 4 {
+        return Err(invalid("compressed block is truncated"));
+    }
+    let (len, block) = block.split_at(4);
+    let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+    if len > MAX_DECOMPRESSED {
+        return Err(invalid("compressed block is too large"));
+    }
+    let payload = lz4_flex::block::decompress(block, len).map_err(|e| invalid(&e.to_string()))?;
+    if payload.len() != len {
+        return Err(invalid("compressed block is truncated"));
+    }
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lz4_roundtrip() {
+        let long_run = vec![b'x'; 1000];
+        let mixed: Vec<u8> = (0..5000u32).map(|i| (i * i % 251) as u8).collect();
+        let text = "GET /index.html 200 ".repeat(50);
+        for input in [&long_run[..], &mixed, text.as_bytes()].iter() {
+            let datagram = compress_over(0, input);
+            assert_eq!(decompress(&datagram).unwrap(), *input);
+        }
+        assert!(compress_over(0, &long_run).len() < 30);
+        assert!(compress_over(0, text.as_bytes()).len() < 60);
+    }
+
+    #[test]
+    fn test_lz4_unblock() {
+        let block = lz4_flex::block::compress_prepend_size(&[b'a'; 16]);
+        assert_eq!(lz4_unblock(&block).unwrap(), vec![b'a'; 16]);
+        assert!(lz4_unblock(&block[..block.len() - 1]).is_err());
+        assert!(lz4_unblock(&[1, 0]).is_err());
+        // Claims 4 GiB, rejected before allocating
+        assert!(lz4_unblock(&[0xff, 0xff, 0xff, 0xff, 0x10, b'a']).is_err());
+        // Claims more than the block holds
+        assert!(lz4_unblock(&[100, 0, 0, 0, 0x10, b'a']).is_err());
+    }
+
+    #[test]
+    fn test_compress_over() {
+        let large = "a".repeat(100);
+        assert_eq!(compress_over(64, b"tiny"), b"\0tiny");
+        // Over the threshold, but incompressible
+        assert_eq!(compress_over(2, b"tiny"), b"\0tiny");
+        let compressed = compress_over(64, large.as_bytes());
+        assert_eq!(compressed[0], LZ4_BLOCK);
+        assert!(compressed.len() < 20);
+        assert_eq!(decompress(&compressed).unwrap(), large.as_bytes());
+        assert!(decompress(b"\x07oops").is_err());
+    }
+}
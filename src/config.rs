@@ -0,0 +1,170 @@
+use std::io;
+use std::time::Duration;
+
+use log::Level;
+use serde::Deserialize;
+
+use crate::{DropPolicy, Facility, Format, JsonFraming, JsonKeys, UdpLogger, UdpLoggerBuilder};
+
+/// Deserializable configuration for a UdpLogger, for embedding in an application's config file
+/// ```
+/// # #[cfg(feature = "serde")]
+/// # {
+/// use udp_logger::{UdpLogger, UdpLoggerConfig};
+///
+/// let config: UdpLoggerConfig =
+///     serde_json::from_str(r#"{"destination": "127.0.0.1:1999", "level": "debug"}"#).unwrap();
+/// let logger = UdpLogger::from_config(config).unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UdpLoggerConfig {
+    /// Where datagrams are sent, e.g. `"collector:1999"`
+    pub destination: String,
+    /// The maximum level that is logged (default == INFO)
+    #[serde(default = "default_level")]
+    pub level: Level,
+    /// Send from a background thread instead of inline
+    #[serde(default)]
+    pub buffered: bool,
+    /// How each record is encoded (default == text)
+    #[serde(default)]
+    pub format: Format,
+    /// How batched JSON records are combined
+    #[serde(default)]
+    pub json_framing: JsonFraming,
+    /// Coalesce queued messages into datagrams of up to this many bytes
+    pub batch_size: Option<usize>,
+    /// Coalesce at most this many messages into each datagram
+    pub max_batch_count: Option<usize>,
+    /// Bound the queue to this many messages
+    pub queue_limit: Option<usize>,
+    /// Bound the queue to this many bytes of messages
+    pub max_queue_bytes: Option<usize>,
+    /// Which message is dropped when the queue is full
+    #[serde(default)]
+    pub drop_policy: DropPolicy,
+    /// Send a heartbeat after this many idle milliseconds
+    pub heartbeat_ms: Option<u64>,
+    /// Wait at least this many microseconds between datagrams
+    pub pacing_us: Option<u64>,
+    /// Prefix each message with a syslog `<PRI>` using this facility
+    pub syslog_facility: Option<Facility>,
+    /// Rename the JSON fields (implies `format = "json"`), unset keys keep their defaults
+    pub json_keys: Option<JsonKeys>,
+}
+
+fn default_level() -> Level {
+    Level::Info
+}
+
+impl UdpLoggerConfig {
+    fn into_builder(self) -> UdpLoggerBuilder {
+        let mut builder = UdpLoggerBuilder::new(self.destination.as_str())
+            .level(self.level)
+            .with_format(self.format)
+            .with_json_framing(self.json_framing)
+            .with_drop_policy(self.drop_policy);
+        if self.buffered {
+            builder = builder.buffered();
+        }
+        if let Some(max_bytes) = self.batch_size {
+            builder = builder.with_batching(max_bytes);
+        }
+        if let Some(count) = self.max_batch_count {
+            builder = builder.with_max_batch_count(count);
+        }
+        if let Some(limit) = self.queue_limit {
+            builder = builder.with_queue_limit(limit);
+        }
+        if let Some(max_bytes) = self.max_queue_bytes {
+            builder = builder.with_max_queue_bytes(max_bytes);
+        }
+        if let Some(ms) = self.heartbeat_ms {
+            builder = builder.with_heartbeat(Duration::from_millis(ms));
+        }
+        if let Some(us) = self.pacing_us {
+            builder = builder.with_pacing(Duration::from_micros(us));
+        }
+        if let Some(facility) = self.syslog_facility {
+            builder = builder.with_syslog(facility);
+        }
+        if let Some(keys) = self.json_keys {
+            builder = builder.with_json_keys(keys);
+        }
+        builder
+    }
+}
+
+impl UdpLogger {
+    /// Create a new UdpLogger from a deserialized `UdpLoggerConfig`
+    pub fn from_config(config: UdpLoggerConfig) -> io::Result<Self> {
+        config.into_builder().build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_toml() {
+        let config: UdpLoggerConfig = toml::from_str(
+            r#"
+                destination = "127.0.0.1:1999"
+                level = "debug"
+                buffered = true
+                format = "json"
+                json_framing = "array"
+                batch_size = 1400
+                max_batch_count = 50
+                queue_limit = 1000
+                max_queue_bytes = 1048576
+                drop_policy = "drop_newest"
+                heartbeat_ms = 30000
+                syslog_facility = "local0"
+
+                [json_keys]
+                timestamp = "@timestamp"
+            "#,
+        )
+        .expect("Valid config");
+        assert_eq!(config.level, Level::Debug);
+        assert_eq!(config.format, Format::Json);
+        assert_eq!(config.json_framing, JsonFraming::Array);
+        assert_eq!(config.max_batch_count, Some(50));
+        assert_eq!(config.max_queue_bytes, Some(1 << 20));
+        assert_eq!(config.drop_policy, DropPolicy::DropNewest);
+        assert_eq!(config.syslog_facility, Some(Facility::Local0));
+        let keys = config.json_keys.as_ref().unwrap();
+        assert_eq!(
+            (keys.timestamp.as_str(), keys.message.as_str()),
+            ("@timestamp", "message")
+        );
+
+        let logger = UdpLogger::from_config(config).expect("Can bind to localhost");
+        assert_eq!(logger.level(), Level::Debug);
+        assert_eq!(logger.format, Format::Json);
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config: UdpLoggerConfig = toml::from_str(r#"destination = "127.0.0.1:1999""#).unwrap();
+        assert_eq!(config.level, Level::Info);
+        assert!(!config.buffered);
+        assert_eq!(config.format, Format::Text);
+        UdpLogger::from_config(config).expect("Can bind to localhost");
+    }
+
+    #[test]
+    fn test_unknown_field() {
+        assert!(toml::from_str::<UdpLoggerConfig>(
+            r#"
+                destination = "127.0.0.1:1999"
+                colour = "blue"
+            "#
+        )
+        .is_err());
+    }
+}
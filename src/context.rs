@@ -0,0 +1,84 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::marker::PhantomData;
+
+thread_local! {
+    /// Fields pushed on this thread, in order, until their guards are dropped
+    static CONTEXT: RefCell<Vec<(&'static str, String)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Removes a field added by `UdpLogger::push_context` when dropped
+///
+/// Dropping a guard also removes any fields pushed after it, so guards should be
+/// dropped in the reverse order they were created (as scopes do).
+#[must_use = "the field is removed as soon as the guard is dropped"]
+#[derive(Debug)]
+pub struct ContextGuard {
+    /// Context length before this guard's field was pushed
+    depth: usize,
+    /// The context is per thread, so the guard has to stay on this one
+    _thread: PhantomData<*const ()>,
+}
+
+pub(crate) fn push(key: &'static str, value: String) -> ContextGuard {
+    CONTEXT.with(|context| {
+        let mut context = context.borrow_mut();
+        context.push((key, value));
+        ContextGuard {
+            depth: context.len() - 1,
+            _thread: PhantomData,
+        }
+    })
+}
+
+/// Append this thread's context fields to `extra`
+pub(crate) fn extend(extra: &mut Vec<(Cow<'static, str>, String)>) {
+    CONTEXT.with(|context| {
+        let context = context.borrow();
+        extra.extend(
+            context
+                .iter()
+                .map(|(key, value)| (Cow::Borrowed(*key), value.clone())),
+        )
+    });
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        CONTEXT.with(|context| context.borrow_mut().truncate(self.depth));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn current() -> Vec<(String, String)> {
+        let mut extra = Vec::new();
+        extend(&mut extra);
+        extra
+            .into_iter()
+            .map(|(key, value)| (key.into_owned(), value))
+            .collect()
+    }
+
+    #[test]
+    fn test_nested_guards() {
+        let outer = push("request_id", "42".into());
+        {
+            let _inner = push("user", "ada".into());
+            assert_eq!(
+                current(),
+                [
+                    ("request_id".into(), "42".into()),
+                    ("user".into(), "ada".into())
+                ]
+            );
+        }
+        assert_eq!(current(), [("request_id".into(), "42".into())]);
+        // Other threads have their own context
+        assert!(std::thread::spawn(current).join().unwrap().is_empty());
+        drop(outer);
+        assert!(current().is_empty());
+    }
+}
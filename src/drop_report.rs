@@ -0,0 +1,146 @@
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::clock::Clock;
+use crate::stats::{Stats, StatsSnapshot};
+
+/// Periodically sends the logger's drops, by reason, to a separate destination
+///
+/// Stopped (after sending any drops not yet reported) when dropped
+pub(crate) struct DropReporter {
+    shutdown: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl DropReporter {
+    pub(crate) fn new(
+        out: UdpSocket,
+        destination: SocketAddr,
+        interval: Duration,
+        stats: Arc<Stats>,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let stopping = Arc::clone(&shutdown);
+        let thread = thread::spawn(move || {
+            let mut reported = Drops::default();
+            loop {
+                // Parked rather than slept so dropping the reporter wakes it immediately
+                thread::park_timeout(interval);
+                let stopped = stopping.load(Ordering::SeqCst);
+                let drops = Drops::from(&stats.snapshot());
+                let new = drops.since(&reported);
+                if new != Drops::default() {
+                    if let Err(e) =
+                        out.send_to(new.render(interval, clock.now()).as_bytes(), destination)
+                    {
+                        eprintln!("Error sending drop report: {}", e);
+                    }
+                }
+                reported = drops;
+                if stopped {
+                    return;
+                }
+            }
+        });
+        Self {
+            shutdown,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for DropReporter {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            thread.join().ok();
+        }
+    }
+}
+
+/// Cumulative drop counts by reason
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Drops {
+    queue_full: u64,
+    ttl_expired: u64,
+    send_error: u64,
+}
+
+impl From<&StatsSnapshot> for Drops {
+    fn from(stats: &StatsSnapshot) -> Self {
+        Self {
+            queue_full: stats.dropped,
+            ttl_expired: stats.expired,
+            send_error: stats.errors,
+        }
+    }
+}
+
+impl Drops {
+    /// The drops since `earlier`, which reads as zero if the stats were reset since
+    fn since(&self, earlier: &Drops) -> Drops {
+        Drops {
+            queue_full: self.queue_full.saturating_sub(earlier.queue_full),
+            ttl_expired: self.ttl_expired.saturating_sub(earlier.ttl_expired),
+            send_error: self.send_error.saturating_sub(earlier.send_error),
+        }
+    }
+
+    fn render(&self, interval: Duration, now: DateTime<Utc>) -> String {
+        format!(
+            "UDP_LOGGER_DROPS [{}] queue_full={} ttl_expired={} send_error={} interval={:?}\n",
+            now.to_rfc3339(),
+            self.queue_full,
+            self.ttl_expired,
+            self.send_error,
+            interval
+        )
+    }
+}
+
+/// Check that a drop report `interval` can be waited on
+pub(crate) fn check_interval(interval: Duration) -> io::Result<()> {
+    match interval.is_zero() {
+        true => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the drop report interval must be positive",
+        )),
+        false => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_since() {
+        use chrono::TimeZone;
+
+        let earlier = Drops {
+            queue_full: 2,
+            ttl_expired: 0,
+            send_error: 5,
+        };
+        let now = Drops {
+            queue_full: 7,
+            ttl_expired: 1,
+            send_error: 3,
+        };
+        let time = Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+        let report = now.since(&earlier).render(Duration::from_secs(10), time);
+        assert_eq!(
+            report,
+            "UDP_LOGGER_DROPS [2024-01-02T03:04:05+00:00] queue_full=5 ttl_expired=1 \
+             send_error=0 interval=10s\n"
+        );
+    }
+}
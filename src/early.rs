@@ -0,0 +1,159 @@
+use std::sync::{Mutex, OnceLock};
+
+use log::{Level, Log, Metadata, Record, SetLoggerError};
+
+/// Records captured before a logger is attached, beyond this they're discarded
+const CAPACITY: usize = 1024;
+
+/// The global early logger, set only once it has been installed with `log`
+static EARLY: OnceLock<&'static EarlyLogger> = OnceLock::new();
+
+/// Install an `EarlyLogger` as the global logger, capturing records up to `level`
+pub(crate) fn install(level: Level) -> Result<(), SetLoggerError> {
+    let early: &'static EarlyLogger = Box::leak(Box::new(EarlyLogger::new(level)));
+    log::set_logger(early)?;
+    log::set_max_level(level.to_level_filter());
+    let _ = EARLY.set(early);
+    Ok(())
+}
+
+/// The global early logger, if `install` succeeded
+pub(crate) fn installed() -> Option<&'static EarlyLogger> {
+    EARLY.get().copied()
+}
+
+/// An owned copy of a `Record`, so it can outlive the `log` call
+struct EarlyRecord {
+    level: Level,
+    target: String,
+    module_path: Option<String>,
+    file: Option<String>,
+    line: Option<u32>,
+    message: String,
+}
+
+/// Buffers records until a logger is attached, then replays them and forwards to it
+pub(crate) struct EarlyLogger {
+    level: Level,
+    buffer: Mutex<Vec<EarlyRecord>>,
+    logger: OnceLock<Box<dyn Log>>,
+}
+
+impl EarlyLogger {
+    pub(crate) fn new(level: Level) -> Self {
+        Self {
+            level,
+            buffer: Mutex::new(Vec::new()),
+            logger: OnceLock::new(),
+        }
+    }
+
+    /// Replay the captured records through `logger` and forward everything after to it
+    ///
+    /// Returns `logger` back if one was already attached
+    pub(crate) fn attach(&self, logger: Box<dyn Log>) -> Result<(), Box<dyn Log>> {
+        // Hold the buffer while attaching so no record lands in it after the replay
+        let mut buffer = self.buffer.lock().unwrap();
+        self.logger.set(logger)?;
+        let logger = self.logger.get().expect("Just attached");
+        for early in buffer.drain(..) {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", early.message))
+                    .level(early.level)
+                    .target(&early.target)
+                    .module_path(early.module_path.as_deref())
+                    .file(early.file.as_deref())
+                    .line(early.line)
+                    .build(),
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Log for EarlyLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match self.logger.get() {
+            Some(logger) => logger.enabled(metadata),
+            None => metadata.level() <= self.level,
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        if let Some(logger) = self.logger.get() {
+            return logger.log(record);
+        }
+        let mut buffer = self.buffer.lock().unwrap();
+        // Attached while waiting for the buffer
+        if let Some(logger) = self.logger.get() {
+            drop(buffer);
+            return logger.log(record);
+        }
+        if record.level() <= self.level && buffer.len() < CAPACITY {
+            buffer.push(EarlyRecord {
+                level: record.level(),
+                target: record.target().to_string(),
+                module_path: record.module_path().map(String::from),
+                file: record.file().map(String::from),
+                line: record.line(),
+                message: record.args().to_string(),
+            });
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(logger) = self.logger.get() {
+            logger.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    use crate::UdpLogger;
+
+    #[test]
+    fn test_replay_after_attach() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let early = EarlyLogger::new(Level::Info);
+        early.log(
+            &Record::builder()
+                .args(format_args!("before init"))
+                .level(Level::Info)
+                .build(),
+        );
+        early.log(
+            &Record::builder()
+                .args(format_args!("too verbose"))
+                .level(Level::Debug)
+                .build(),
+        );
+
+        let logger = UdpLogger::new(receiver.local_addr().unwrap()).unwrap();
+        assert!(early.attach(Box::new(logger)).is_ok());
+        early.log(
+            &Record::builder()
+                .args(format_args!("after init"))
+                .level(Level::Info)
+                .build(),
+        );
+
+        let mut buf = [0; 1024];
+        for expected in ["before init", "after init"] {
+            let len = receiver.recv(&mut buf).expect("Message arrives");
+            let message = std::str::from_utf8(&buf[..len]).unwrap();
+            assert!(message.ends_with(&format!("{}\n", expected)), "{}", message);
+        }
+
+        let again = UdpLogger::new(receiver.local_addr().unwrap()).unwrap();
+        assert!(early.attach(Box::new(again)).is_err());
+    }
+}
@@ -0,0 +1,82 @@
+use std::io;
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+/// Bytes of the random nonce prepended to each datagram
+const NONCE_LEN: usize = 12;
+
+/// Encrypts datagrams with ChaCha20-Poly1305 under a pre-shared key
+pub(crate) struct Sealer {
+    cipher: ChaCha20Poly1305,
+}
+
+impl std::fmt::Debug for Sealer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print the key
+        f.debug_struct("Sealer").finish_non_exhaustive()
+    }
+}
+
+impl Sealer {
+    pub(crate) fn new(key: &[u8; 32]) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(key)),
+        }
+    }
+
+    /// Encrypt a datagram as `nonce (12 bytes) || ciphertext || tag (16 bytes)`
+    pub(crate) fn seal(&self, datagram: &[u8]) -> io::Result<Vec<u8>> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, datagram)
+            .map_err(|_| io::Error::other("encryption failed"))?;
+        let mut out = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        out.extend_from_slice(&nonce);
+        out.extend(ciphertext);
+        Ok(out)
+    }
+}
+
+/// Decrypt a datagram sent by a logger built with `UdpLoggerBuilder::with_encryption`
+///
+/// Fails with `InvalidData` if the datagram was tampered with, truncated, or encrypted
+/// under a different key
+pub fn decrypt(key: &[u8; 32], datagram: &[u8]) -> io::Result<Vec<u8>> {
+    if datagram.len() < NONCE_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "datagram is shorter than a nonce",
+        ));
+    }
+    let (nonce, ciphertext) = datagram.split_at(NONCE_LEN);
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "datagram failed to decrypt"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let key = [7; 32];
+        let sealer = Sealer::new(&key);
+        let first = sealer.seal(b"INFO [ts] secret\n").unwrap();
+        let second = sealer.seal(b"INFO [ts] secret\n").unwrap();
+        // A fresh nonce means identical messages don't produce identical datagrams
+        assert_ne!(first, second);
+        assert_eq!(first.len(), NONCE_LEN + 17 + 16);
+
+        assert_eq!(decrypt(&key, &first).unwrap(), b"INFO [ts] secret\n");
+        assert_eq!(
+            decrypt(&[8; 32], &first).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        let mut tampered = first.clone();
+        tampered[NONCE_LEN] ^= 1;
+        assert!(decrypt(&key, &tampered).is_err());
+    }
+}
@@ -0,0 +1,42 @@
+use std::sync::Arc;
+
+/// A change in a logger's state, passed to the `with_event_listener` callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LoggerEvent {
+    /// `build` finished, and the logger is ready to send
+    Started,
+    /// The drain thread was replaced with a new one, see `with_thread_recycle`
+    ThreadRespawned,
+    /// Sending was held back by `UdpLogger::pause`
+    Paused,
+    /// Sending started again with `UdpLogger::resume`
+    Resumed,
+    /// The logger was dropped, and its writers have flushed and shut down
+    Shutdown,
+}
+
+/// A callback given each `LoggerEvent`
+pub(crate) type EventCallback = dyn Fn(LoggerEvent) + Send + Sync;
+
+/// The listener from `with_event_listener`, shared by the logger and its drain thread
+#[derive(Clone, Default)]
+pub(crate) struct Events(Option<Arc<EventCallback>>);
+
+impl Events {
+    pub(crate) fn new(listener: Box<EventCallback>) -> Self {
+        Self(Some(Arc::from(listener)))
+    }
+
+    /// Whether there's a listener to emit events to
+    pub(crate) fn is_listening(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Call the listener, if any, with `event`
+    pub(crate) fn emit(&self, event: LoggerEvent) {
+        if let Some(listener) = &self.0 {
+            listener(event);
+        }
+    }
+}
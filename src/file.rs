@@ -0,0 +1,113 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::queue::Envelope;
+use crate::Writer;
+
+/// Appends messages to a local file, rotating it once it reaches a size limit
+///
+/// Rotation renames `path` to `path.1`, `path.1` to `path.2`, and so on, deleting
+/// whatever would become `path.{max_files + 1}`.
+pub(crate) struct FileWriter {
+    file: Mutex<RotatingFile>,
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    /// Bytes in the current file
+    size: u64,
+    max_size: u64,
+    /// Rotated files kept besides the current one
+    max_files: usize,
+}
+
+impl FileWriter {
+    /// Append to `path` (creating it if needed), rotating before it exceeds `max_size` bytes
+    pub(crate) fn new(path: &Path, max_size: u64, max_files: usize) -> io::Result<Self> {
+        let file = File::options().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            file: Mutex::new(RotatingFile {
+                path: path.to_path_buf(),
+                file,
+                size,
+                max_size,
+                max_files,
+            }),
+        })
+    }
+}
+
+impl RotatingFile {
+    fn append(&mut self, message: &[u8]) -> io::Result<()> {
+        // A message larger than the limit still gets a file of its own
+        if self.size > 0 && self.size + message.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(message)?;
+        self.size += message.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        if self.max_files == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            for n in (1..self.max_files).rev() {
+                match fs::rename(rotated(n), rotated(n + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            fs::rename(&self.path, rotated(1))?;
+            self.file = File::options().create(true).append(true).open(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Writer for FileWriter {
+    fn push(&self, message: Envelope) -> io::Result<()> {
+        self.file.lock().unwrap().append(&message.payload)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Err(io::Error::new(
+            io::ErrorKind::NotConnected,
+            "file writer has no socket",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation() {
+        let dir = std::env::temp_dir().join(format!("udp_logger_file_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.log");
+        let writer = FileWriter::new(&path, 10, 2).unwrap();
+        for message in ["aaaaaa\n", "bbbbbb\n", "cccccc\n", "dddddd\n"] {
+            writer.push(message.to_string().into()).unwrap();
+        }
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("app.log"), "dddddd\n");
+        assert_eq!(read("app.log.1"), "cccccc\n");
+        assert_eq!(read("app.log.2"), "bbbbbb\n");
+        // Only `max_files` rotated files are kept
+        assert!(!dir.join("app.log.3").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
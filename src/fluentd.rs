@@ -0,0 +1,83 @@
+use log::Record;
+use rmp::encode;
+
+use crate::format::Fields;
+
+/// Encode a record as a Fluentd forward protocol event: `[tag, time, record]`
+///
+/// `time` is the integer epoch seconds and `record` is a map of `level`, `target`,
+/// `message`, `schema_version` if set, and any extra fields.
+pub(crate) fn encode(tag: &str, record: &Record, fields: &Fields) -> Vec<u8> {
+    let mut out = Vec::new();
+    // Writing into a Vec can't fail, so the encoder results are safe to ignore
+    let _ = encode::write_array_len(&mut out, 3);
+    let _ = encode::write_str(&mut out, tag);
+    let _ = encode::write_uint(
+        &mut out,
+        fields.time.map_or(0, |time| time.timestamp().max(0) as u64),
+    );
+    let len = 3 + fields.schema_version.is_some() as u32 + fields.extra.len() as u32;
+    let _ = encode::write_map_len(&mut out, len);
+    let _ = encode::write_str(&mut out, "level");
+    let _ = encode::write_str(&mut out, record.level().as_str());
+    let _ = encode::write_str(&mut out, "target");
+    let _ = encode::write_str(&mut out, record.target());
+    let _ = encode::write_str(&mut out, "message");
+    let _ = encode::write_str(&mut out, &record.args().to_string());
+    if let Some(version) = fields.schema_version {
+        let _ = encode::write_str(&mut out, "schema_version");
+        let _ = encode::write_uint(&mut out, version.into());
+    }
+    for (key, value) in &fields.extra {
+        let _ = encode::write_str(&mut out, key);
+        let _ = encode::write_str(&mut out, value);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use log::Level;
+    use rmpv::Value;
+
+    #[test]
+    fn test_event_array() {
+        let mut fields = Fields::new(Some(Utc.timestamp_opt(1_700_000_000, 0).unwrap()));
+        fields
+            .extra
+            .push(("run_id".into(), "00000000000000ff".into()));
+        let event = encode(
+            "app.logs",
+            &Record::builder()
+                .args(format_args!("testing"))
+                .level(Level::Warn)
+                .target("my_app")
+                .build(),
+            &fields,
+        );
+
+        let decoded = rmpv::decode::read_value(&mut &event[..]).expect("Valid MessagePack");
+        let event = decoded.as_array().expect("Event is an array");
+        assert_eq!(event.len(), 3);
+        assert_eq!(event[0].as_str(), Some("app.logs"));
+        assert_eq!(event[1].as_u64(), Some(1_700_000_000));
+
+        let record: Vec<(&str, &str)> = event[2]
+            .as_map()
+            .expect("Record is a map")
+            .iter()
+            .map(|(k, v): &(Value, Value)| (k.as_str().unwrap(), v.as_str().unwrap()))
+            .collect();
+        assert_eq!(
+            record,
+            [
+                ("level", "WARN"),
+                ("target", "my_app"),
+                ("message", "testing"),
+                ("run_id", "00000000000000ff"),
+            ]
+        );
+    }
+}
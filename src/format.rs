@@ -0,0 +1,1150 @@
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::convert::TryFrom;
+use std::fmt::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::{Level, Record};
+
+use crate::syslog;
+
+/// How JSON records are combined when several are batched into one datagram
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum JsonFraming {
+    /// Newline-delimited JSON, one object per line
+    #[default]
+    Lines,
+    /// A single JSON array containing every object in the datagram
+    Array,
+}
+
+impl JsonFraming {
+    /// Bytes added to a datagram beyond the records themselves
+    pub(crate) fn overhead(&self) -> usize {
+        match self {
+            JsonFraming::Lines => 0,
+            JsonFraming::Array => 1,
+        }
+    }
+
+    /// Combine formatted records (each ending with a newline) into a datagram payload
+    pub(crate) fn join(&self, records: &[impl AsRef<[u8]>]) -> Vec<u8> {
+        let records: Vec<&[u8]> = records.iter().map(AsRef::as_ref).collect();
+        match self {
+            JsonFraming::Lines => records.concat(),
+            JsonFraming::Array => {
+                let objects: Vec<&[u8]> = records
+                    .iter()
+                    .map(|r| r.strip_suffix(b"\n").unwrap_or(r))
+                    .collect();
+                let mut out = vec![b'['];
+                out.extend(objects.join(&b','));
+                out.push(b']');
+                out
+            }
+        }
+    }
+}
+
+/// The decoding codec of the [Vector](https://vector.dev) `socket` source, for
+/// `UdpLoggerBuilder::with_vector`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum VectorCodec {
+    /// `decoding.codec = "json"`: one JSON object per line, with Vector's default
+    /// `message` and `timestamp` keys
+    #[default]
+    Json,
+    /// `decoding.codec = "bytes"`: `Text` lines, each kept whole as the event's message
+    Bytes,
+}
+
+/// Combine formatted records (each ending with a newline) that share a target into
+/// one line, `target: [record, record]`
+pub(crate) fn group(target: &str, records: &[impl AsRef<[u8]>]) -> Vec<u8> {
+    let records: Vec<&[u8]> = records
+        .iter()
+        .map(|r| {
+            let r = r.as_ref();
+            r.strip_suffix(b"\n").unwrap_or(r)
+        })
+        .collect();
+    let mut out = Vec::new();
+    out.extend_from_slice(target.as_bytes());
+    out.extend_from_slice(b": [");
+    out.extend(records.join(&b", "[..]));
+    out.extend_from_slice(b"]\n");
+    out
+}
+
+/// The line starting a `with_batch_timestamps` datagram, which record offsets are from
+pub(crate) fn batch_header(base: DateTime<Utc>) -> String {
+    format!("@{}\n", base.to_rfc3339_opts(SecondsFormat::Micros, true))
+}
+
+/// The prefix of a record logged at `time`, in microseconds since the `batch_header`
+pub(crate) fn time_offset(base: DateTime<Utc>, time: DateTime<Utc>) -> String {
+    let micros = (time - base).num_microseconds().unwrap_or(i64::MAX);
+    format!("{:+} ", micros)
+}
+
+/// How `UdpLogger::send_raw_bytes` handles bytes that aren't valid UTF-8
+///
+/// Formatted records and custom formatter output are Rust strings, so they're always
+/// valid UTF-8 and aren't checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Utf8Policy {
+    /// Replace each invalid sequence with `U+FFFD`
+    #[default]
+    Lossy,
+    /// Drop the message, counting it in `StatsSnapshot::invalid_utf8`
+    Reject,
+}
+
+impl Utf8Policy {
+    /// Decode `bytes` per this policy, `None` if the message should be dropped
+    pub(crate) fn decode<'a>(&self, bytes: &'a [u8]) -> Option<Cow<'a, str>> {
+        match self {
+            Utf8Policy::Lossy => Some(String::from_utf8_lossy(bytes)),
+            Utf8Policy::Reject => std::str::from_utf8(bytes).ok().map(Cow::Borrowed),
+        }
+    }
+}
+
+/// The coarse date bucket prefixed to each message as a partition key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum DatePartition {
+    /// The calendar date, e.g. `2020-06-15`
+    Day,
+    /// The ISO 8601 week, e.g. `2020-W25`
+    IsoWeek,
+}
+
+impl DatePartition {
+    /// The bucket that `time` falls in
+    pub(crate) fn bucket(&self, time: DateTime<Utc>) -> String {
+        match self {
+            DatePartition::Day => time.format("%Y-%m-%d").to_string(),
+            DatePartition::IsoWeek => time.format("%G-W%V").to_string(),
+        }
+    }
+}
+
+/// Shorten `target` to at most `max_len` characters by replacing its front with `...`
+///
+/// The tail is kept since it's the most specific part of a module path
+pub(crate) fn truncate_front(target: &str, max_len: usize) -> Cow<'_, str> {
+    const ELLIPSIS: &str = "...";
+    let len = target.chars().count();
+    if len <= max_len {
+        return Cow::Borrowed(target);
+    }
+    let keep = max_len.saturating_sub(ELLIPSIS.len());
+    let (start, _) = target
+        .char_indices()
+        .nth(len - keep)
+        .unwrap_or((target.len(), ' '));
+    let ellipsis = &ELLIPSIS[..max_len.min(ELLIPSIS.len())];
+    Cow::Owned(format!("{}{}", ellipsis, &target[start..]))
+}
+
+/// The index of the `window`-long bucket of time since the epoch that `time` falls in
+///
+/// `window` must be at least a millisecond
+pub(crate) fn rotating_token(time: DateTime<Utc>, window: Duration) -> i64 {
+    let window = i64::try_from(window.as_millis()).unwrap_or(i64::MAX);
+    time.timestamp_millis().div_euclid(window)
+}
+
+/// How each record's timestamp is rendered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum TimestampFormat {
+    /// RFC 3339, e.g. `2020-06-15T03:15:39.500+00:00` (the default)
+    Rfc3339 {
+        /// How many fractional second digits are shown
+        #[cfg_attr(feature = "serde", serde(default))]
+        precision: TimestampPrecision,
+        /// End with `Z` rather than `+00:00`, for receivers that only accept one
+        #[cfg_attr(feature = "serde", serde(default))]
+        use_z: bool,
+    },
+    /// Decimal epoch seconds with this many fractional digits (at most 9), e.g.
+    /// `1700000000.123456`
+    ///
+    /// Digits beyond the precision are truncated rather than rounded.
+    EpochFloat(u8),
+    /// No timestamp, and the clock isn't read for each record
+    ///
+    /// The text format drops the `[timestamp]`, JSON and GELF drop the field, and the
+    /// fluentd and protobuf encodings send a zero time.
+    None,
+}
+
+impl Default for TimestampFormat {
+    fn default() -> Self {
+        TimestampFormat::Rfc3339 {
+            precision: TimestampPrecision::Auto,
+            use_z: false,
+        }
+    }
+}
+
+/// Fractional second digits of a `TimestampFormat::Rfc3339` timestamp
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum TimestampPrecision {
+    /// None, milli, micro, or nanosecond digits, whichever shows the time exactly
+    #[default]
+    Auto,
+    /// Whole seconds, e.g. `03:15:39`
+    Secs,
+    /// Three digits, e.g. `03:15:39.500`
+    Millis,
+    /// Six digits
+    Micros,
+    /// Nine digits
+    Nanos,
+}
+
+impl TimestampPrecision {
+    fn seconds_format(self) -> SecondsFormat {
+        match self {
+            TimestampPrecision::Auto => SecondsFormat::AutoSi,
+            TimestampPrecision::Secs => SecondsFormat::Secs,
+            TimestampPrecision::Millis => SecondsFormat::Millis,
+            TimestampPrecision::Micros => SecondsFormat::Micros,
+            TimestampPrecision::Nanos => SecondsFormat::Nanos,
+        }
+    }
+}
+
+impl TimestampFormat {
+    pub(crate) fn render(&self, time: DateTime<Utc>) -> String {
+        let precision = match self {
+            TimestampFormat::Rfc3339 { precision, use_z } => {
+                return time.to_rfc3339_opts(precision.seconds_format(), *use_z)
+            }
+            TimestampFormat::None => return String::new(),
+            TimestampFormat::EpochFloat(precision) => u32::from((*precision).min(9)),
+        };
+        // A leap second reports more than 1s of nanoseconds
+        let (mut secs, mut nanos) = (
+            time.timestamp(),
+            time.timestamp_subsec_nanos().min(999_999_999),
+        );
+        // Before the epoch, `timestamp` rounds down and the nanoseconds count up from it
+        let sign = if secs < 0 && nanos > 0 {
+            secs += 1;
+            nanos = 1_000_000_000 - nanos;
+            "-"
+        } else {
+            ""
+        };
+        if precision == 0 {
+            return format!("{}{}", sign, secs);
+        }
+        format!(
+            "{}{}.{:0width$}",
+            sign,
+            secs.abs(),
+            nanos / 10u32.pow(9 - precision),
+            width = precision as usize
+        )
+    }
+}
+
+/// How each record's level is labeled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum LevelLabel {
+    /// The level's name, e.g. `INFO`
+    #[default]
+    Name,
+    /// The first letter of the level and its syslog severity, e.g. `I6` for INFO or
+    /// `E3` for ERROR
+    LetterSeverity,
+}
+
+impl LevelLabel {
+    pub(crate) fn render(&self, level: Level) -> Cow<'static, str> {
+        match self {
+            LevelLabel::Name => Cow::Borrowed(level.as_str()),
+            LevelLabel::LetterSeverity => Cow::Owned(format!(
+                "{}{}",
+                &level.as_str()[..1],
+                syslog::severity(level)
+            )),
+        }
+    }
+}
+
+/// How records whose message renders empty are handled
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum EmptyMessagePolicy {
+    /// Send them as-is, e.g. `INFO [timestamp] `
+    #[default]
+    Keep,
+    /// Drop them, counting them in `StatsSnapshot::empty`
+    Skip,
+    /// Send them with this message instead, e.g. `<empty>`
+    Placeholder(String),
+}
+
+/// How newlines inside a record's message are handled, for line-delimited receivers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum NewlinePolicy {
+    /// Send them as-is, so a multi-line message spans several lines
+    #[default]
+    Keep,
+    /// Write each newline as a backslash then `n` (and each carriage return as `\r`), keeping
+    /// the message on one line
+    Escape,
+    /// Replace each line break (`\n`, `\r\n`, or a lone `\r`) with this character, e.g.
+    /// `' '` or `'⏎'`
+    Replace(char),
+    /// Log each line as its own record, with the same level and metadata, splitting at
+    /// the same line breaks as `Replace`
+    SplitRecords,
+}
+
+impl NewlinePolicy {
+    /// The messages to log in place of `message`, `None` if it's logged unchanged
+    pub(crate) fn apply(&self, message: &str) -> Option<Vec<String>> {
+        if *self == NewlinePolicy::Keep || !message.contains(&['\n', '\r'][..]) {
+            return None;
+        }
+        Some(match self {
+            NewlinePolicy::Keep => vec![message.to_string()],
+            NewlinePolicy::Escape => vec![message.replace('\r', "\\r").replace('\n', "\\n")],
+            NewlinePolicy::Replace(c) => vec![split_lines(message, false).join(&c.to_string())],
+            NewlinePolicy::SplitRecords => split_lines(message, true),
+        })
+    }
+}
+
+/// Split `message` at each `\r\n`, `\n`, and lone `\r`, which `str::lines` leaves in
+/// place, dropping a final empty line like it does if `trim_end` is set
+fn split_lines(message: &str, trim_end: bool) -> Vec<String> {
+    let mut lines: Vec<String> = message
+        .split("\r\n")
+        .flat_map(|line| line.split(&['\n', '\r'][..]))
+        .map(str::to_string)
+        .collect();
+    if trim_end && lines.len() > 1 && lines.last().is_some_and(String::is_empty) {
+        lines.pop();
+    }
+    lines
+}
+
+/// Whether `record`'s message renders as an empty string
+pub(crate) fn is_empty_message(record: &Record) -> bool {
+    match record.args().as_str() {
+        Some(message) => message.is_empty(),
+        None => record.args().to_string().is_empty(),
+    }
+}
+
+/// The version of the built-in structured formats, sent as their `schema_version` by default
+///
+/// Bumped whenever the fields of `Format::Json`, `Format::Gelf`, or the Fluentd
+/// encoding change in a way receivers could notice.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Renders a record into the payload that is sent
+pub(crate) type FormatterFn = Box<dyn Fn(&Record) -> String + Send + Sync>;
+
+/// What's sent for a record whose custom formatter panicked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum FormatterPanicPolicy {
+    /// Send the line the built-in `Format` renders instead
+    #[default]
+    Fallback,
+    /// Send nothing, as if the formatter returned an empty message
+    Skip,
+}
+
+thread_local! {
+    /// Set while a custom formatter runs, so the panic hook can tell its panic is caught
+    static FORMATTING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Run `formatter` on `record`, returning `None` instead of unwinding if it panics
+pub(crate) fn catch_panic(formatter: &FormatterFn, record: &Record) -> Option<String> {
+    let outer = FORMATTING.with(|formatting| formatting.replace(true));
+    let line = panic::catch_unwind(AssertUnwindSafe(|| formatter(record))).ok();
+    FORMATTING.with(|formatting| formatting.set(outer));
+    line
+}
+
+/// Whether this thread is running a custom formatter, see `catch_panic`
+pub(crate) fn is_formatting() -> bool {
+    FORMATTING.with(Cell::get)
+}
+
+/// The encoding used for each record
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Format {
+    /// `LEVEL [timestamp] message` lines
+    #[default]
+    Text,
+    /// JSON objects with `timestamp`, `level`, `target`, and `message` fields
+    Json,
+    /// [GELF 1.1](https://go2docs.graylog.org/current/getting_in_log_data/gelf.html)
+    /// JSON objects for Graylog, with the target and extra fields as `_` fields, one
+    /// per datagram
+    Gelf,
+    /// Only the message, for apps that format their own lines
+    ///
+    /// No level, timestamp, or fields are added. A newline ends each message unless it
+    /// already ends with one, since it's what separates batched records.
+    Raw,
+    /// `{"timestamp":<epoch ms>,"message":<line>}` objects, the shape of a CloudWatch
+    /// Logs (or Kinesis) log event, where the line is `Text` without its timestamp
+    CloudWatch,
+}
+
+/// Field names used by `Format::Json`, e.g. `@timestamp` for ELK or `ts` for Loki
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(default))]
+pub struct JsonKeys {
+    /// Key for the RFC 3339 timestamp (default == `timestamp`)
+    pub timestamp: String,
+    /// Key for the record's level (default == `level`)
+    pub level: String,
+    /// Key for the formatted message (default == `message`)
+    pub message: String,
+    /// Key for the record's target (default == `target`)
+    pub target: String,
+}
+
+impl Default for JsonKeys {
+    fn default() -> Self {
+        Self {
+            timestamp: "timestamp".into(),
+            level: "level".into(),
+            message: "message".into(),
+            target: "target".into(),
+        }
+    }
+}
+
+/// Values rendered alongside each record's level and message
+#[derive(Debug)]
+pub(crate) struct Fields {
+    /// When the record was logged, `None` if timestamps are disabled
+    pub time: Option<DateTime<Utc>>,
+    pub timestamp_format: TimestampFormat,
+    pub level_label: LevelLabel,
+    /// Additional `key=value` fields, in order
+    pub extra: Vec<(Cow<'static, str>, String)>,
+    /// A `schema_version` number for structured formats, see `with_schema_version`
+    pub schema_version: Option<u32>,
+}
+
+impl Fields {
+    pub(crate) fn new(time: Option<DateTime<Utc>>) -> Self {
+        Self {
+            time,
+            timestamp_format: TimestampFormat::default(),
+            level_label: LevelLabel::default(),
+            extra: Vec::new(),
+            schema_version: None,
+        }
+    }
+
+    /// `level` rendered per `level_label`
+    pub(crate) fn level(&self, level: Level) -> Cow<'static, str> {
+        self.level_label.render(level)
+    }
+
+    /// `time` rendered per `timestamp_format`, `None` if timestamps are disabled
+    pub(crate) fn timestamp(&self) -> Option<String> {
+        match (self.timestamp_format, self.time) {
+            (TimestampFormat::None, _) | (_, None) => None,
+            (format, Some(time)) => Some(format.render(time)),
+        }
+    }
+}
+
+impl Format {
+    /// Render a record with the given fields, terminated by a newline
+    ///
+    /// `keys` name the JSON fields and are ignored for `Text`
+    pub(crate) fn render(&self, record: &Record, fields: &Fields, keys: &JsonKeys) -> String {
+        match self {
+            Format::Text => {
+                let mut out = text_line(record, fields, fields.timestamp());
+                out.push('\n');
+                out
+            }
+            Format::Json => {
+                let mut out = String::from("{");
+                if let Some(timestamp) = fields.timestamp() {
+                    push_json_str(&mut out, &keys.timestamp);
+                    out.push(':');
+                    push_json_str(&mut out, &timestamp);
+                    out.push(',');
+                }
+                push_json_str(&mut out, &keys.level);
+                out.push(':');
+                push_json_str(&mut out, &fields.level(record.level()));
+                out.push(',');
+                push_json_str(&mut out, &keys.target);
+                out.push(':');
+                push_json_str(&mut out, record.target());
+                if let Some(version) = fields.schema_version {
+                    let _ = write!(out, ",\"schema_version\":{}", version);
+                }
+                for (key, value) in &fields.extra {
+                    out.push(',');
+                    push_json_str(&mut out, key);
+                    out.push(':');
+                    push_json_str(&mut out, value);
+                }
+                out.push(',');
+                push_json_str(&mut out, &keys.message);
+                out.push(':');
+                push_json_str(&mut out, &record.args().to_string());
+                out.push_str("}\n");
+                out
+            }
+            Format::CloudWatch => {
+                let mut out = String::from("{");
+                if let (Some(time), Some(_)) = (fields.time, fields.timestamp()) {
+                    let _ = write!(out, "\"timestamp\":{},", time.timestamp_millis());
+                }
+                out.push_str("\"message\":");
+                push_json_str(&mut out, &text_line(record, fields, None));
+                out.push_str("}\n");
+                out
+            }
+            Format::Raw => {
+                let mut out = record.args().to_string();
+                if !out.ends_with('\n') {
+                    out.push('\n');
+                }
+                out
+            }
+            Format::Gelf => {
+                let mut out = String::from("{\"version\":\"1.1\",\"host\":");
+                push_json_str(&mut out, hostname());
+                out.push_str(",\"short_message\":");
+                push_json_str(&mut out, &record.args().to_string());
+                if let (Some(time), Some(_)) = (fields.time, fields.timestamp()) {
+                    let timestamp = TimestampFormat::EpochFloat(6).render(time);
+                    let _ = write!(out, ",\"timestamp\":{}", timestamp);
+                }
+                let _ = write!(
+                    out,
+                    ",\"level\":{},\"_target\":",
+                    syslog::severity(record.level())
+                );
+                push_json_str(&mut out, record.target());
+                if let Some(version) = fields.schema_version {
+                    let _ = write!(out, ",\"_schema_version\":{}", version);
+                }
+                for (key, value) in &fields.extra {
+                    out.push(',');
+                    push_json_str(&mut out, &format!("_{}", key));
+                    out.push(':');
+                    push_json_str(&mut out, value);
+                }
+                out.push_str("}\n");
+                out
+            }
+        }
+    }
+}
+
+/// This machine's hostname, the GELF `host`, looked up once
+fn hostname() -> &'static str {
+    static HOSTNAME: OnceLock<String> = OnceLock::new();
+    HOSTNAME.get_or_init(|| {
+        #[cfg(unix)]
+        {
+            let mut buf = [0u8; 256];
+            // SAFETY: gethostname writes at most `buf.len()` bytes into `buf`
+            if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } == 0 {
+                let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+                return String::from_utf8_lossy(&buf[..len]).into_owned();
+            }
+        }
+        std::env::var("COMPUTERNAME").unwrap_or_else(|_| "localhost".into())
+    })
+}
+
+/// The kernel id of the current thread (gettid), as shown in `/proc` and by `perf`
+#[cfg(target_os = "linux")]
+pub(crate) fn os_thread_id() -> Option<u64> {
+    // SAFETY: gettid has no preconditions and always succeeds
+    u64::try_from(unsafe { libc::gettid() }).ok()
+}
+
+/// The Windows id of the current thread, as shown by debuggers and ETW
+#[cfg(windows)]
+pub(crate) fn os_thread_id() -> Option<u64> {
+    extern "system" {
+        fn GetCurrentThreadId() -> u32;
+    }
+    // SAFETY: GetCurrentThreadId has no preconditions and always succeeds
+    Some(unsafe { GetCurrentThreadId() }.into())
+}
+
+/// Other platforms have no thread id to report
+#[cfg(not(any(target_os = "linux", windows)))]
+pub(crate) fn os_thread_id() -> Option<u64> {
+    None
+}
+
+/// Substitute a formatted line into an envelope `template`
+///
+/// `{msg}` is the line without its trailing newline, `{level}`, `{target}`, and `{ts}`
+/// (per the `TimestampFormat`) come from the record. Values are inserted as-is, in a single pass so a
+/// message containing a placeholder is never expanded, and unknown placeholders are kept.
+pub(crate) fn wrap_envelope(
+    template: &str,
+    line: &str,
+    record: &Record,
+    fields: &Fields,
+) -> String {
+    let mut out = String::with_capacity(template.len() + line.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find('}') {
+            Some(end) => end,
+            None => break,
+        };
+        match &rest[1..end] {
+            "msg" => out.push_str(line.strip_suffix('\n').unwrap_or(line)),
+            "level" => out.push_str(&fields.level(record.level())),
+            "target" => out.push_str(record.target()),
+            "ts" => out.push_str(&fields.timestamp().unwrap_or_default()),
+            // Not a placeholder (e.g. a JSON brace), keep scanning after the brace
+            _ => {
+                out.push('{');
+                rest = &rest[1..];
+                continue;
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    out.push('\n');
+    out
+}
+
+/// Where a `with_footer` footer goes relative to the newline ending each message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum FooterPosition {
+    /// On the message's line, e.g. `INFO [...] message FOOTER\n`
+    #[default]
+    BeforeNewline,
+    /// After the newline, ending the datagram, e.g. `INFO [...] message\nFOOTER`
+    AfterNewline,
+}
+
+/// Bytes in the header prepended by `with_binary_header`
+pub(crate) const BINARY_HEADER_LEN: usize = 16;
+
+/// Set in a binary header's flags when the message is longer than its length field holds
+pub(crate) const FLAG_LENGTH_SATURATED: u8 = 1;
+
+/// Prepend the `with_binary_header` header to a record's `message`
+pub(crate) fn binary_header(level: Level, millis: i64, sequence: u32, message: &[u8]) -> Vec<u8> {
+    let (len, flags) = match u16::try_from(message.len()) {
+        Ok(len) => (len, 0),
+        Err(_) => (u16::MAX, FLAG_LENGTH_SATURATED),
+    };
+    let mut out = Vec::with_capacity(BINARY_HEADER_LEN + message.len());
+    out.push(level as u8);
+    out.push(flags);
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(&(millis.max(0) as u64).to_be_bytes());
+    out.extend_from_slice(&sequence.to_be_bytes());
+    out.extend_from_slice(message);
+    out
+}
+
+/// Append a footer rendered from `template` to `line`
+///
+/// `{crc32}` is the CRC-32 (IEEE) of everything before the footer, excluding the
+/// newline, as 8 lowercase hex digits. Other text is appended as-is.
+pub(crate) fn append_footer(mut line: String, template: &str, position: FooterPosition) -> String {
+    let had_newline = line.ends_with('\n');
+    if had_newline {
+        line.pop();
+    }
+    let footer = template.replace(
+        "{crc32}",
+        &format!("{:08x}", crc32fast::hash(line.as_bytes())),
+    );
+    match (position, had_newline) {
+        (FooterPosition::BeforeNewline, true) => {
+            line.push_str(&footer);
+            line.push('\n');
+        }
+        (FooterPosition::AfterNewline, true) => {
+            line.push('\n');
+            line.push_str(&footer);
+        }
+        (_, false) => line.push_str(&footer),
+    }
+    line
+}
+
+/// A `Text` line without its newline, with `timestamp` in brackets if there is one
+fn text_line(record: &Record, fields: &Fields, timestamp: Option<String>) -> String {
+    let mut out = fields.level(record.level()).into_owned();
+    if let Some(timestamp) = timestamp {
+        let _ = write!(out, " [{}]", timestamp);
+    }
+    for (key, value) in &fields.extra {
+        let _ = write!(out, " {}={}", key, value);
+    }
+    let _ = write!(out, " {}", record.args());
+    out
+}
+
+/// Append `value` to `out` as a quoted and escaped JSON string
+pub(crate) fn push_json_str(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use log::Level;
+
+    #[test]
+    fn test_split_lines() {
+        assert_eq!(split_lines("a\rb\r\nc\nd", true), ["a", "b", "c", "d"]);
+        assert_eq!(split_lines("a\n", true), ["a"]);
+        assert_eq!(split_lines("a\r", false), ["a", ""]);
+        assert_eq!(split_lines("a\n\nb", true), ["a", "", "b"]);
+    }
+
+    fn fields() -> Fields {
+        Fields::new(Some(
+            DateTime::parse_from_rfc3339("2020-06-15T03:15:39+00:00")
+                .unwrap()
+                .with_timezone(&Utc),
+        ))
+    }
+
+    #[test]
+    fn test_epoch_float() {
+        let time = Utc.timestamp_opt(1_700_000_000, 123_456_789).unwrap();
+        let render = |precision| TimestampFormat::EpochFloat(precision).render(time);
+        assert_eq!(render(6), "1700000000.123456");
+        assert_eq!(render(9), "1700000000.123456789");
+        assert_eq!(render(12), "1700000000.123456789");
+        assert_eq!(render(0), "1700000000");
+        let time = Utc.timestamp_opt(1_700_000_000, 5_000).unwrap();
+        assert_eq!(
+            TimestampFormat::EpochFloat(6).render(time),
+            "1700000000.000005"
+        );
+        let before_epoch = Utc.timestamp_opt(-2, 250_000_000).unwrap();
+        assert_eq!(
+            TimestampFormat::EpochFloat(3).render(before_epoch),
+            "-1.750"
+        );
+        let before_epoch = Utc.timestamp_opt(-1, 500_000_000).unwrap();
+        assert_eq!(TimestampFormat::EpochFloat(1).render(before_epoch), "-0.5");
+    }
+
+    #[test]
+    fn test_rfc3339_options() {
+        let time = Utc.timestamp_opt(1_592_190_939, 500_000_000).unwrap();
+        let render = |precision, use_z| TimestampFormat::Rfc3339 { precision, use_z }.render(time);
+        assert_eq!(
+            TimestampFormat::default().render(time),
+            "2020-06-15T03:15:39.500+00:00"
+        );
+        assert_eq!(
+            render(TimestampPrecision::Secs, true),
+            "2020-06-15T03:15:39Z"
+        );
+        assert_eq!(
+            render(TimestampPrecision::Millis, true),
+            "2020-06-15T03:15:39.500Z"
+        );
+        assert_eq!(
+            render(TimestampPrecision::Micros, false),
+            "2020-06-15T03:15:39.500000+00:00"
+        );
+    }
+
+    #[test]
+    fn test_json_render() {
+        let line = Format::Json.render(
+            &Record::builder()
+                .args(format_args!("testing"))
+                .level(Level::Warn)
+                .target("my_app")
+                .build(),
+            &fields(),
+            &JsonKeys::default(),
+        );
+        assert_eq!(
+            line,
+            "{\"timestamp\":\"2020-06-15T03:15:39+00:00\",\"level\":\"WARN\",\"target\":\"my_app\",\"message\":\"testing\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_json_escaping() {
+        let message = "say \"hi\"\\n\tC:\\logs\nnext line\u{1}";
+        let mut fields = fields();
+        fields.extra.push(("path".into(), "C:\\\"quoted\"".into()));
+        for format in [Format::Json, Format::Gelf].iter() {
+            let line = format.render(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(Level::Info)
+                    .target("my_app\n")
+                    .build(),
+                &fields,
+                &JsonKeys::default(),
+            );
+            // Only the terminating newline is a raw newline
+            assert_eq!(line.matches('\n').count(), 1, "{}", line);
+            let json: serde_json::Value = serde_json::from_str(&line).expect("Valid JSON");
+            let (message_key, target_key, path_key) = match format {
+                Format::Gelf => ("short_message", "_target", "_path"),
+                _ => ("message", "target", "path"),
+            };
+            assert_eq!(json[message_key], message);
+            assert_eq!(json[target_key], "my_app\n");
+            assert_eq!(json[path_key], "C:\\\"quoted\"");
+        }
+    }
+
+    #[test]
+    fn test_gelf_render() {
+        let mut fields = fields();
+        fields.extra.push(("app".into(), "my_app".into()));
+        let line = Format::Gelf.render(
+            &Record::builder()
+                .args(format_args!("testing"))
+                .level(Level::Warn)
+                .target("my_app::db")
+                .build(),
+            &fields,
+            &JsonKeys::default(),
+        );
+        let expected = format!(
+            "{{\"version\":\"1.1\",\"host\":\"{}\",\"short_message\":\"testing\",\
+             \"timestamp\":1592190939.000000,\"level\":4,\"_target\":\"my_app::db\",\
+             \"_app\":\"my_app\"}}\n",
+            hostname()
+        );
+        assert_eq!(line, expected);
+        assert!(!hostname().is_empty());
+    }
+
+    #[test]
+    fn test_level_letter_severity() {
+        let label = |level| LevelLabel::LetterSeverity.render(level);
+        assert_eq!(label(Level::Error), "E3");
+        assert_eq!(label(Level::Warn), "W4");
+        assert_eq!(label(Level::Info), "I6");
+        assert_eq!(label(Level::Debug), "D7");
+        assert_eq!(label(Level::Trace), "T7");
+        assert_eq!(LevelLabel::Name.render(Level::Info), "INFO");
+
+        let mut fields = fields();
+        fields.level_label = LevelLabel::LetterSeverity;
+        let record = Record::builder()
+            .args(format_args!("testing"))
+            .level(Level::Warn)
+            .build();
+        assert_eq!(
+            Format::Text.render(&record, &fields, &JsonKeys::default()),
+            "W4 [2020-06-15T03:15:39+00:00] testing\n"
+        );
+        let json = Format::Json.render(&record, &fields, &JsonKeys::default());
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(json["level"], "W4");
+    }
+
+    #[test]
+    fn test_json_keys() {
+        let keys = JsonKeys {
+            timestamp: "@timestamp".into(),
+            message: "msg".into(),
+            ..Default::default()
+        };
+        let line = Format::Json.render(
+            &Record::builder()
+                .args(format_args!("testing"))
+                .level(Level::Warn)
+                .target("my_app")
+                .build(),
+            &fields(),
+            &keys,
+        );
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["@timestamp"], "2020-06-15T03:15:39+00:00");
+        assert_eq!(json["msg"], "testing");
+        assert_eq!(json["level"], "WARN");
+        assert_eq!(json["target"], "my_app");
+        assert!(json.get("timestamp").is_none() && json.get("message").is_none());
+    }
+
+    #[test]
+    fn test_extra_fields() {
+        let record = |format: Format| {
+            let mut fields = fields();
+            fields
+                .extra
+                .push(("run_id".into(), "00000000000000ff".into()));
+            format.render(
+                &Record::builder()
+                    .args(format_args!("testing"))
+                    .level(Level::Info)
+                    .target("my_app")
+                    .build(),
+                &fields,
+                &JsonKeys::default(),
+            )
+        };
+        assert_eq!(
+            record(Format::Text),
+            "INFO [2020-06-15T03:15:39+00:00] run_id=00000000000000ff testing\n"
+        );
+        let json: serde_json::Value = serde_json::from_str(&record(Format::Json)).unwrap();
+        assert_eq!(json["run_id"], "00000000000000ff");
+    }
+
+    #[test]
+    fn test_truncate_front() {
+        assert_eq!(truncate_front("my_app::db", 10), "my_app::db");
+        assert_eq!(truncate_front("my_app::db::pool", 10), "...b::pool");
+        assert_eq!(truncate_front("my_app::db::pool", 10).chars().count(), 10);
+        assert_eq!(truncate_front("my_app::dé::pool", 9), "...::pool");
+        assert_eq!(truncate_front("my_app::dé::pool", 10), "...é::pool");
+        assert_eq!(truncate_front("my_app", 2), "..");
+        assert_eq!(truncate_front("my_app", 0), "");
+    }
+
+    #[test]
+    fn test_rotating_token() {
+        let window = Duration::from_secs(300);
+        let time = |secs| Utc.timestamp_opt(secs, 0).unwrap();
+        assert_eq!(rotating_token(time(0), window), 0);
+        assert_eq!(rotating_token(time(299), window), 0);
+        assert_eq!(rotating_token(time(300), window), 1);
+        assert_eq!(rotating_token(time(-1), window), -1);
+    }
+
+    #[test]
+    fn test_date_partition() {
+        assert_eq!(
+            DatePartition::Day.bucket(fields().time.unwrap()),
+            "2020-06-15"
+        );
+        assert_eq!(
+            DatePartition::IsoWeek.bucket(fields().time.unwrap()),
+            "2020-W25"
+        );
+        // The ISO week-based year can differ from the calendar year
+        let new_year = DateTime::parse_from_rfc3339("2021-01-01T00:00:00+00:00")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(DatePartition::IsoWeek.bucket(new_year), "2020-W53");
+    }
+
+    #[test]
+    fn test_binary_header() {
+        let datagram = binary_header(Level::Warn, 1_700_000_000_123, 7, b"hi\n");
+        assert_eq!(datagram.len(), BINARY_HEADER_LEN + 3);
+        assert_eq!(&datagram[..4], &[2, 0, 0, 3]);
+        assert_eq!(&datagram[4..12], &1_700_000_000_123u64.to_be_bytes());
+        assert_eq!(&datagram[12..16], &[0, 0, 0, 7]);
+        assert_eq!(&datagram[16..], b"hi\n");
+
+        let long = vec![b'x'; 70_000];
+        let datagram = binary_header(Level::Info, 0, 0, &long);
+        assert_eq!(&datagram[..4], &[3, FLAG_LENGTH_SATURATED, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn test_append_footer() {
+        let footer =
+            |line: &str, template, position| append_footer(line.to_string(), template, position);
+        assert_eq!(
+            footer("message\n", " END", FooterPosition::BeforeNewline),
+            "message END\n"
+        );
+        assert_eq!(
+            footer("message\n", "END", FooterPosition::AfterNewline),
+            "message\nEND"
+        );
+        // The CRC-32 of "123456789" is the standard check value
+        assert_eq!(
+            footer("123456789\n", " crc={crc32}", FooterPosition::BeforeNewline),
+            "123456789 crc=cbf43926\n"
+        );
+        assert_eq!(
+            footer("123456789", "|{crc32}", FooterPosition::AfterNewline),
+            "123456789|cbf43926"
+        );
+    }
+
+    #[test]
+    fn test_wrap_envelope() {
+        let record = Record::builder()
+            .args(format_args!("testing"))
+            .level(Level::Info)
+            .target("my_app")
+            .build();
+        assert_eq!(
+            wrap_envelope(
+                r#"{"source":"app","level":"{level}","payload":"{msg}"}"#,
+                "INFO [ts] {level} testing\n",
+                &record,
+                &fields(),
+            ),
+            "{\"source\":\"app\",\"level\":\"INFO\",\"payload\":\"INFO [ts] {level} testing\"}\n"
+        );
+        assert_eq!(
+            wrap_envelope("{ts} {target}: {msg} {unknown", "hi\n", &record, &fields()),
+            "2020-06-15T03:15:39+00:00 my_app: hi {unknown\n"
+        );
+    }
+
+    #[test]
+    fn test_is_empty_message() {
+        let empty = "";
+        for (record, empty) in [
+            (Record::builder().args(format_args!("")).build(), true),
+            (
+                Record::builder().args(format_args!("{}", empty)).build(),
+                true,
+            ),
+            (Record::builder().args(format_args!(" ")).build(), false),
+        ] {
+            assert_eq!(is_empty_message(&record), empty);
+        }
+    }
+
+    #[test]
+    fn test_utf8_policy() {
+        let invalid = b"bad \xF0\x28 bytes";
+        assert_eq!(
+            Utf8Policy::Lossy.decode(invalid).unwrap(),
+            "bad \u{FFFD}( bytes"
+        );
+        assert_eq!(Utf8Policy::Reject.decode(invalid), None);
+        assert_eq!(Utf8Policy::Reject.decode(b"fine").unwrap(), "fine");
+    }
+
+    #[test]
+    fn test_json_framing() {
+        let records: Vec<Vec<u8>> = (0..3)
+            .map(|i| {
+                Format::Json
+                    .render(
+                        &Record::builder()
+                            .args(format_args!("record {}", i))
+                            .level(Level::Info)
+                            .build(),
+                        &fields(),
+                        &JsonKeys::default(),
+                    )
+                    .into_bytes()
+            })
+            .collect();
+
+        let lines = String::from_utf8(JsonFraming::Lines.join(&records)).unwrap();
+        let parsed: Vec<serde_json::Value> = lines
+            .lines()
+            .map(|l| serde_json::from_str(l).expect("Each line is valid JSON"))
+            .collect();
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(parsed[2]["message"], "record 2");
+
+        let array = JsonFraming::Array.join(&records);
+        let parsed: serde_json::Value = serde_json::from_slice(&array).expect("Valid JSON array");
+        assert_eq!(parsed.as_array().unwrap().len(), 3);
+        assert_eq!(parsed[0]["message"], "record 0");
+    }
+}
@@ -0,0 +1,65 @@
+use std::borrow::Cow;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Hex digits kept from each hash, 64 bits, plenty to correlate values without collisions
+const HASH_HEX_LEN: usize = 16;
+
+/// Replaces the values of fields named `field` with a salted hash
+pub(crate) struct FieldHasher {
+    pub(crate) field: String,
+    pub(crate) salt: String,
+}
+
+impl FieldHasher {
+    /// The first 16 hex digits of HMAC-SHA256 over the value, keyed by the salt
+    pub(crate) fn hash(&self, value: &str) -> String {
+        // HMAC takes a key of any length, so this never fails
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.salt.as_bytes())
+            .expect("HMAC takes keys of any length");
+        mac.update(value.as_bytes());
+        let mut hex: String = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        hex.truncate(HASH_HEX_LEN);
+        hex
+    }
+}
+
+/// Replace the value of every field matching one of `hashers`, in place
+pub(crate) fn apply(hashers: &[FieldHasher], extra: &mut [(Cow<'static, str>, String)]) {
+    for (key, value) in extra {
+        if let Some(hasher) = hashers.iter().find(|hasher| hasher.field == *key) {
+            *value = hasher.hash(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash() {
+        let hasher = |salt: &str| FieldHasher {
+            field: "ip".to_string(),
+            salt: salt.to_string(),
+        };
+        // RFC 4231 test case 2
+        assert_eq!(
+            hasher("Jefe").hash("what do ya want for nothing?"),
+            "5bdcc146bf60754e"
+        );
+        let hash = hasher("pepper").hash("10.0.0.1");
+        assert_eq!(hash.len(), HASH_HEX_LEN);
+        assert_eq!(hash, hasher("pepper").hash("10.0.0.1"));
+        assert_ne!(hash, hasher("pepper").hash("10.0.0.2"));
+        assert_ne!(hash, hasher("paprika").hash("10.0.0.1"));
+        // The salt is a key, not a prefix, so moving bytes between them changes the hash
+        assert_ne!(hasher("pepper1").hash("0.0.0.1"), hash);
+    }
+}
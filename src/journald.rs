@@ -0,0 +1,150 @@
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+
+use log::Record;
+
+use crate::format::Fields;
+use crate::queue::Envelope;
+use crate::syslog;
+use crate::Writer;
+
+/// Where journald listens for its native protocol
+pub(crate) const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Sends messages already in journald's native format to its AF_UNIX datagram socket
+pub(crate) struct JournaldWriter {
+    out: UnixDatagram,
+    path: PathBuf,
+}
+
+impl JournaldWriter {
+    pub(crate) fn new(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            out: UnixDatagram::unbound()?,
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Writer for JournaldWriter {
+    fn push(&self, message: Envelope) -> io::Result<()> {
+        self.out.send_to(&message.payload, &self.path).map(|_| ())
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Err(io::Error::new(
+            io::ErrorKind::NotConnected,
+            "journald writer has no UDP socket",
+        ))
+    }
+}
+
+/// Encode a record as journald native protocol fields
+///
+/// `MESSAGE`, `PRIORITY` (the syslog severity), and `TARGET` are always present, along
+/// with the record's `CODE_MODULE`, `CODE_FILE`, and `CODE_LINE` when known. An `app`
+/// field becomes `SYSLOG_IDENTIFIER`, and the other extra fields are upper-cased.
+pub(crate) fn encode(record: &Record, fields: &Fields) -> Vec<u8> {
+    let mut out = Vec::new();
+    push_field(&mut out, "MESSAGE", &record.args().to_string());
+    let severity = syslog::severity(record.level()).to_string();
+    push_field(&mut out, "PRIORITY", &severity);
+    push_field(&mut out, "TARGET", record.target());
+    if let Some(module) = record.module_path() {
+        push_field(&mut out, "CODE_MODULE", module);
+    }
+    if let Some(file) = record.file() {
+        push_field(&mut out, "CODE_FILE", file);
+    }
+    if let Some(line) = record.line() {
+        push_field(&mut out, "CODE_LINE", &line.to_string());
+    }
+    for (key, value) in &fields.extra {
+        let name = match key.as_ref() {
+            "app" => "SYSLOG_IDENTIFIER".to_string(),
+            key => field_name(key),
+        };
+        push_field(&mut out, &name, value);
+    }
+    out
+}
+
+/// Append `NAME=value\n`, or for a value with newlines `NAME\n<u64 LE length>value\n`
+fn push_field(out: &mut Vec<u8>, name: &str, value: &str) {
+    out.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        out.push(b'\n');
+        out.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        out.push(b'=');
+    }
+    out.extend_from_slice(value.as_bytes());
+    out.push(b'\n');
+}
+
+/// A journald field name for `key`: upper-case letters, digits, and underscores,
+/// starting with a letter and at most 64 bytes
+fn field_name(key: &str) -> String {
+    let mut name: String = key
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_uppercase(),
+            false => '_',
+        })
+        .collect();
+    if !name.starts_with(|c: char| c.is_ascii_uppercase()) {
+        name.insert_str(0, "X_");
+    }
+    name.truncate(64);
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    #[test]
+    fn test_encode() {
+        let mut fields = Fields::new(None);
+        fields.extra.push(("app".into(), "myapp".to_string()));
+        fields.extra.push(("request-id".into(), "42".to_string()));
+        fields.extra.push(("_hidden".into(), "x".to_string()));
+        let record = Record::builder()
+            .args(format_args!("first\nsecond"))
+            .level(Level::Warn)
+            .target("my_app::db")
+            .line(Some(7))
+            .build();
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&12u64.to_le_bytes());
+        expected.extend_from_slice(
+            b"first\nsecond\nPRIORITY=4\nTARGET=my_app::db\nCODE_LINE=7\n\
+              SYSLOG_IDENTIFIER=myapp\nREQUEST_ID=42\nX__HIDDEN=x\n",
+        );
+        assert_eq!(encode(&record, &fields), expected);
+    }
+
+    #[test]
+    fn test_writer() {
+        let dir = std::env::temp_dir().join(format!("udp_logger_journald_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("socket");
+        let journal = UnixDatagram::bind(&path).unwrap();
+        let writer = JournaldWriter::new(&path).unwrap();
+        let record = Record::builder()
+            .args(format_args!("testing"))
+            .level(Level::Error)
+            .target("my_app")
+            .build();
+        writer
+            .push(encode(&record, &Fields::new(None)).into())
+            .unwrap();
+        let mut buf = [0; 128];
+        let len = journal.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"MESSAGE=testing\nPRIORITY=3\nTARGET=my_app\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+use std::borrow::Cow;
+
+use chrono::{DateTime, Utc};
+use log::kv::{self, Key, Value, VisitSource};
+use log::Record;
+use serde_json::Value as Json;
+
+/// Append a record's key-values to `extra`, in order
+///
+/// Each value is serialized with serde: strings are kept as-is and anything else is
+/// rendered as JSON, e.g. `user={"id":42}`. With `flatten`, objects and arrays are
+/// instead expanded into a field per leaf with a dotted path, e.g. `user.id=42` and
+/// `items.0=a`.
+///
+/// Past `max_fields` (counting flattened leaves), the rest are dropped and marked with
+/// `fields_truncated=true total_fields=N`.
+pub(crate) fn extend(
+    record: &Record,
+    flatten: bool,
+    max_fields: Option<usize>,
+    extra: &mut Vec<(Cow<'static, str>, String)>,
+) {
+    let start = extra.len();
+    let mut visitor = Collect {
+        flatten,
+        extra: &mut *extra,
+    };
+    // Collecting can't fail, so there's no error to report
+    let _ = record.key_values().visit(&mut visitor);
+    let total = extra.len() - start;
+    match max_fields {
+        Some(max_fields) if total > max_fields => {
+            extra.truncate(start + max_fields);
+            extra.push(("fields_truncated".into(), "true".to_string()));
+            extra.push(("total_fields".into(), total.to_string()));
+        }
+        _ => {}
+    }
+}
+
+/// The time in a record's `key` key-value, if it's there and parses as RFC 3339
+pub(crate) fn timestamp(record: &Record, key: &str) -> Option<DateTime<Utc>> {
+    let value = record.key_values().get(Key::from_str(key))?;
+    let time = DateTime::parse_from_rfc3339(&value.to_string()).ok()?;
+    Some(time.with_timezone(&Utc))
+}
+
+struct Collect<'a> {
+    flatten: bool,
+    extra: &'a mut Vec<(Cow<'static, str>, String)>,
+}
+
+impl<'kvs> VisitSource<'kvs> for Collect<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let key = key.as_str().to_string();
+        match serde_json::to_value(&value) {
+            Ok(json) if self.flatten => push_flattened(key, &json, self.extra),
+            Ok(json) => self.extra.push((key.into(), render(&json))),
+            // e.g. a map with non-string keys, which JSON can't represent
+            Err(_) => self.extra.push((key.into(), value.to_string())),
+        }
+        Ok(())
+    }
+}
+
+/// Push a field for each leaf of `json`, at `path` joined by dots with its keys or indexes
+fn push_flattened(path: String, json: &Json, extra: &mut Vec<(Cow<'static, str>, String)>) {
+    match json {
+        Json::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                push_flattened(format!("{}.{}", path, key), value, extra);
+            }
+        }
+        Json::Array(items) if !items.is_empty() => {
+            for (index, value) in items.iter().enumerate() {
+                push_flattened(format!("{}.{}", path, index), value, extra);
+            }
+        }
+        // Including empty objects and arrays, so the key isn't silently lost
+        leaf => extra.push((path.into(), render(leaf))),
+    }
+}
+
+/// A string as-is, or anything else as JSON
+fn render(json: &Json) -> String {
+    match json {
+        Json::String(string) => string.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+    use serde::Serialize;
+
+    #[derive(Serialize)]
+    struct User {
+        id: u32,
+        name: &'static str,
+        roles: Vec<&'static str>,
+    }
+
+    fn fields(flatten: bool) -> Vec<(String, String)> {
+        let user = User {
+            id: 42,
+            name: "bob",
+            roles: vec!["admin", "ops"],
+        };
+        let tags: Vec<&str> = vec![];
+        let key_values = [
+            ("user", Value::from_serde(&user)),
+            ("attempt", Value::from(3)),
+            ("tags", Value::from_serde(&tags)),
+        ];
+        let record = Record::builder()
+            .args(format_args!("login"))
+            .level(Level::Info)
+            .key_values(&key_values)
+            .build();
+        let mut extra = Vec::new();
+        extend(&record, flatten, None, &mut extra);
+        extra
+            .into_iter()
+            .map(|(key, value)| (key.into_owned(), value))
+            .collect()
+    }
+
+    #[test]
+    fn test_flatten() {
+        let expected = [
+            ("user.id", "42"),
+            ("user.name", "bob"),
+            ("user.roles.0", "admin"),
+            ("user.roles.1", "ops"),
+            ("attempt", "3"),
+            ("tags", "[]"),
+        ];
+        let expected: Vec<(String, String)> = expected
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        assert_eq!(fields(true), expected);
+    }
+
+    #[test]
+    fn test_nested_as_json() {
+        let fields = fields(false);
+        assert_eq!(
+            fields[0],
+            (
+                "user".to_string(),
+                r#"{"id":42,"name":"bob","roles":["admin","ops"]}"#.to_string()
+            )
+        );
+        assert_eq!(fields[1], ("attempt".to_string(), "3".to_string()));
+    }
+}
@@ -0,0 +1,247 @@
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{Arc, PoisonError, RwLock};
+
+use log::{Level, LevelFilter};
+
+use crate::is_module_prefix;
+
+/// A UdpLogger's level, shared with any `LevelHandle`s
+#[derive(Debug)]
+struct Shared {
+    /// Encoded as `LevelFilter as u8` (0 == Off, 1 == Error, ... 5 == Trace)
+    level: Arc<AtomicU8>,
+    /// Owned by the caller via `with_shared_level`, who may change it at any time, or
+    /// replaced by a `with_scheduled_level` schedule
+    external: bool,
+    /// Installed as the global logger, so `log::max_level` must follow `level`
+    installed: AtomicBool,
+    targets: RwLock<Targets>,
+}
+
+/// Per-target settings changed at runtime through a `LevelHandle`
+#[derive(Debug, Default)]
+struct Targets {
+    /// Module prefixes logged at their own level instead of the logger's
+    levels: Vec<(String, LevelFilter)>,
+    /// Module prefixes whose records are all dropped
+    suppressed: Vec<String>,
+}
+
+/// Changes a UdpLogger's level at runtime, even after it has been installed globally
+///
+/// Targets can be given their own level or suppressed too, taking effect from the next
+/// record.
+///
+/// While the logger is installed, setting the level also updates `log::max_level`, so
+/// the `log` macros skip disabled records before any formatting happens.
+#[derive(Debug, Clone)]
+pub struct LevelHandle {
+    shared: Arc<Shared>,
+}
+
+impl LevelHandle {
+    pub(crate) fn new(level: Level) -> Self {
+        Self::from_atomic(Arc::new(AtomicU8::new(level as u8)), false)
+    }
+
+    /// Follow a level owned by the caller, see `UdpLoggerBuilder::with_shared_level`
+    pub(crate) fn shared(level: Arc<AtomicU8>) -> Self {
+        Self::from_atomic(level, true)
+    }
+
+    /// Follow `level` outside the windows of a `UdpLoggerBuilder::with_scheduled_level` schedule
+    pub(crate) fn scheduled(level: Level) -> Self {
+        Self::from_atomic(Arc::new(AtomicU8::new(level as u8)), true)
+    }
+
+    fn from_atomic(level: Arc<AtomicU8>, external: bool) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                level,
+                external,
+                installed: AtomicBool::new(false),
+                targets: RwLock::default(),
+            }),
+        }
+    }
+
+    /// The maximum level that is logged
+    ///
+    /// A shared level set to 0 (Off) reads as Error, see `filter` for the exact value
+    pub fn level(&self) -> Level {
+        self.filter().to_level().unwrap_or(Level::Error)
+    }
+
+    /// The maximum level that is logged, `LevelFilter::Off` if nothing is
+    pub fn filter(&self) -> LevelFilter {
+        match self.shared.level.load(Ordering::Relaxed) {
+            0 => LevelFilter::Off,
+            1 => LevelFilter::Error,
+            2 => LevelFilter::Warn,
+            3 => LevelFilter::Info,
+            4 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    }
+
+    /// Change the maximum level that is logged
+    pub fn set_level(&self, level: Level) {
+        self.shared.level.store(level as u8, Ordering::Relaxed);
+        self.sync_max_level();
+    }
+
+    /// Change the maximum level that is logged, where `LevelFilter::Off` logs nothing
+    pub fn set_filter(&self, filter: LevelFilter) {
+        self.shared
+            .level
+            .store(filter as usize as u8, Ordering::Relaxed);
+        self.sync_max_level();
+    }
+
+    /// Log targets under `prefix` (e.g. `my_app::db`) at `level` instead of the logger's
+    /// level, e.g. to enable Debug for one module on demand
+    ///
+    /// The most specific prefix set applies, replacing the logger's level (scheduled or
+    /// not) for its targets. Floors from `with_target_floor` still apply on top.
+    pub fn set_target_level(&self, prefix: impl Into<String>, level: LevelFilter) {
+        let prefix = prefix.into();
+        let mut targets = self.write_targets();
+        targets.levels.retain(|(existing, _)| *existing != prefix);
+        targets.levels.push((prefix, level));
+        drop(targets);
+        self.sync_max_level();
+    }
+
+    /// Log targets under `prefix` at the logger's level again
+    pub fn clear_target_level(&self, prefix: &str) {
+        self.write_targets()
+            .levels
+            .retain(|(existing, _)| existing != prefix);
+        self.sync_max_level();
+    }
+
+    /// Drop every record for targets under `prefix`, whatever its level
+    pub fn add_suppressed_target(&self, prefix: impl Into<String>) {
+        let prefix = prefix.into();
+        let mut targets = self.write_targets();
+        if !targets.suppressed.contains(&prefix) {
+            targets.suppressed.push(prefix);
+        }
+    }
+
+    /// Stop suppressing targets under `prefix`
+    pub fn remove_suppressed_target(&self, prefix: &str) {
+        self.write_targets()
+            .suppressed
+            .retain(|existing| existing != prefix);
+    }
+
+    /// The level set for `target` through the handle, `Off` if it's suppressed
+    pub(crate) fn target_level(&self, target: &str) -> Option<LevelFilter> {
+        let targets = self
+            .shared
+            .targets
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        if targets
+            .suppressed
+            .iter()
+            .any(|prefix| is_module_prefix(prefix, target))
+        {
+            return Some(LevelFilter::Off);
+        }
+        targets
+            .levels
+            .iter()
+            .filter(|(prefix, _)| is_module_prefix(prefix, target))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, level)| *level)
+    }
+
+    fn write_targets(&self) -> std::sync::RwLockWriteGuard<'_, Targets> {
+        self.shared
+            .targets
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The most verbose level any record may be logged at, the logger's or a target's
+    fn max_filter(&self) -> LevelFilter {
+        let targets = self
+            .shared
+            .targets
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        targets
+            .levels
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.filter(), Ord::max)
+    }
+
+    /// Keep `log::max_level` in step with the levels, if the logger is installed
+    fn sync_max_level(&self) {
+        if self.shared.installed.load(Ordering::Relaxed) && !self.shared.external {
+            log::set_max_level(self.max_filter());
+        }
+    }
+
+    /// Record that the logger is now the global logger and sync `log::max_level`
+    ///
+    /// A shared or scheduled level can change without going through the handle, so
+    /// `log::max_level` stays at `Trace` and every record is checked by the logger instead
+    pub(crate) fn install(&self) {
+        self.shared.installed.store(true, Ordering::Relaxed);
+        log::set_max_level(match self.shared.external {
+            true => LevelFilter::Trace,
+            false => self.max_filter(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_level() {
+        let level = Arc::new(AtomicU8::new(Level::Info as u8));
+        let handle = LevelHandle::shared(level.clone());
+        assert_eq!(handle.filter(), LevelFilter::Info);
+
+        level.store(Level::Trace as u8, Ordering::Relaxed);
+        assert_eq!(handle.level(), Level::Trace);
+        level.store(0, Ordering::Relaxed);
+        assert_eq!(handle.filter(), LevelFilter::Off);
+
+        // Changes through the handle are visible to the owner
+        handle.set_level(Level::Warn);
+        assert_eq!(level.load(Ordering::Relaxed), 2);
+        handle.set_filter(LevelFilter::Off);
+        assert_eq!(level.load(Ordering::Relaxed), 0);
+        handle.set_filter(LevelFilter::Debug);
+        assert_eq!(handle.level(), Level::Debug);
+    }
+
+    #[test]
+    fn test_target_level() {
+        let handle = LevelHandle::new(Level::Info);
+        assert_eq!(handle.target_level("app::db"), None);
+        handle.set_target_level("app", LevelFilter::Warn);
+        handle.set_target_level("app::db", LevelFilter::Debug);
+        assert_eq!(
+            handle.target_level("app::db::pool"),
+            Some(LevelFilter::Debug)
+        );
+        assert_eq!(handle.target_level("app::http"), Some(LevelFilter::Warn));
+        assert_eq!(handle.target_level("application"), None);
+        assert_eq!(handle.max_filter(), LevelFilter::Debug);
+
+        handle.add_suppressed_target("app::db");
+        assert_eq!(handle.target_level("app::db"), Some(LevelFilter::Off));
+        handle.remove_suppressed_target("app::db");
+        handle.clear_target_level("app::db");
+        assert_eq!(handle.target_level("app::db"), Some(LevelFilter::Warn));
+        assert_eq!(handle.max_filter(), LevelFilter::Info);
+    }
+}
@@ -1,63 +1,1238 @@
-use std::collections::VecDeque;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Write};
 use std::io;
-use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
-use std::sync::{Arc, Mutex};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
 use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-use chrono::Utc;
-use log::{self, Level, Metadata, Record, SetLoggerError};
+use arc_swap::ArcSwap;
+use chrono::{DateTime, NaiveTime, Utc};
+use log::{self, Level, LevelFilter, Metadata, Record, SetLoggerError};
+
+mod aggregate;
+#[cfg(feature = "async-std")]
+mod async_std_writer;
+mod checksum;
+mod clock;
+mod combined;
+#[cfg(feature = "compression")]
+mod compress;
+#[cfg(feature = "serde")]
+mod config;
+mod context;
+mod drop_report;
+mod early;
+#[cfg(feature = "encryption")]
+mod encrypt;
+mod events;
+mod file;
+#[cfg(feature = "msgpack")]
+mod fluentd;
+mod format;
+#[cfg(feature = "hashing")]
+mod hash;
+#[cfg(target_os = "linux")]
+mod journald;
+#[cfg(feature = "kv")]
+mod kv;
+mod level;
+mod multi;
+mod notice;
+mod panic_hook;
+#[cfg(windows)]
+mod pipe;
+#[cfg(feature = "prost")]
+mod protobuf;
+mod queue;
+mod resolver;
+mod schedule;
+mod self_test;
+mod sink;
+mod socket;
+mod spill;
+mod stats;
+mod syslog;
+mod tcp;
+mod template;
+mod throttle;
+#[cfg(target_os = "linux")]
+mod unix;
+mod url;
+mod weighted;
+mod writer_handle;
+
+use aggregate::{AggregateTimer, Aggregator};
+pub use checksum::{verify_checksum, Checksum};
+pub use clock::{Clock, FixedClock, SystemClock};
+pub use combined::CombinedLogger;
+#[cfg(feature = "compression")]
+pub use compress::decompress;
+#[cfg(feature = "serde")]
+pub use config::UdpLoggerConfig;
+pub use context::ContextGuard;
+use drop_report::DropReporter;
+#[cfg(feature = "encryption")]
+pub use encrypt::decrypt;
+pub use events::LoggerEvent;
+use events::{EventCallback, Events};
+use file::FileWriter;
+pub use format::{
+    DatePartition, EmptyMessagePolicy, FooterPosition, Format, FormatterPanicPolicy, JsonFraming,
+    JsonKeys, LevelLabel, NewlinePolicy, TimestampFormat, TimestampPrecision, Utf8Policy,
+    VectorCodec, SCHEMA_VERSION,
+};
+use format::{Fields, FormatterFn};
+#[cfg(feature = "hashing")]
+use hash::FieldHasher;
+#[cfg(target_os = "linux")]
+use journald::JournaldWriter;
+pub use level::LevelHandle;
+pub use multi::{MultiOutputBuilder, MultiOutputLogger};
+use notice::DropNotice;
+#[cfg(feature = "prost")]
+pub use protobuf::LogRecord;
+use queue::BoundedQueue;
+use queue::Payload;
+pub use queue::{DropPolicy, Envelope, MessageQueue};
+pub use resolver::{Resolver, SystemResolver};
+use schedule::LevelSchedule;
+pub use self_test::SelfTestReport;
+use self_test::{SELF_TEST_MESSAGE, SELF_TEST_TIMEOUT};
+pub use sink::Sink;
+use socket::{LastSent, SentCallback, SentFn, SharedSocket, SocketOptions};
+use spill::SpillQueue;
+use stats::Stats;
+pub use stats::{DestinationStats, SendLatency, StatsSnapshot};
+pub use syslog::Facility;
+use syslog::FacilityFn;
+use tcp::TcpWriter;
+use template::Template;
+pub use throttle::RateLimit;
+use throttle::{RateLimiter, RateMeter, SendCap, Throttle};
+use weighted::WeightedWriter;
+pub use writer_handle::WriterHandle;
 
 /// UdpLogger is a Log adaptor for sending messages as UDP datagrams
 ///
 /// It is backed by two UDP sending strategies: unbuffered and buffered
 pub struct UdpLogger {
-    writer: Box<dyn Writer>,
-    level: Level,
+    writer: Arc<ArcSwap<Box<dyn Writer>>>,
+    level: LevelHandle,
+    format: Format,
+    formatter: Option<FormatterFn>,
+    facility: Option<FacilityFn>,
+    priority: Option<PriorityFn>,
+    run_id: Option<u64>,
+    journald_priority: bool,
+    #[cfg(feature = "msgpack")]
+    fluentd_tag: Option<String>,
+    #[cfg(feature = "prost")]
+    protobuf: bool,
+    clock: Arc<dyn Clock>,
+    /// Additional destinations and the limits on every datagram, shared with
+    /// `aggregation`
+    fanout: Arc<Fanout>,
+    throttle: Option<Throttle>,
+    aggregation: Option<Arc<Aggregation>>,
+    /// Sends each `with_aggregation` window as it ends
+    aggregate_timer: Option<AggregateTimer>,
+    filter: Option<FilterFn>,
+    empty_message: EmptyMessagePolicy,
+    formatter_panic: FormatterPanicPolicy,
+    newline_policy: NewlinePolicy,
+    utf8_policy: Utf8Policy,
+    json_keys: JsonKeys,
+    /// Stricter levels for targets under each prefix, never loosening `level`
+    target_floors: Vec<(String, Level)>,
+    /// Nothing more verbose is logged, whatever else allows it
+    hard_min_level: Option<Level>,
+    /// Levels replacing `level` during windows of the day
+    schedule: LevelSchedule,
+    /// Shared with `aggregation`
+    last_error: Arc<Mutex<Option<io::Error>>>,
+    crate_field: bool,
+    app_name: Option<String>,
+    /// Included as `version`, see `with_version`
+    version: Option<String>,
+    /// The local address added as a `source` field, see `with_source_field`
+    source: Option<String>,
+    /// Add a `schema_version` to structured formats
+    schema_version: Option<u32>,
+    /// Truncate longer targets from the front to this many characters
+    target_max_len: Option<usize>,
+    date_partition: Option<DatePartition>,
+    /// Prefix each message with the index of its window of time, see `with_rotating_token`
+    rotating_token: Option<Duration>,
+    timestamp_format: TimestampFormat,
+    level_label: LevelLabel,
+    /// Renders records in place of the format, see `with_template`
+    template: Option<Template>,
+    /// Template each formatted line is substituted into
+    envelope: Option<String>,
+    /// Appended to each formatted message, see `with_footer`
+    footer: Option<(String, FooterPosition)>,
+    /// Applied to each fully formatted message, see `with_transform`
+    transform: Option<Transform>,
+    shutdown_summary: bool,
+    /// Wake the writers right after queueing a record at or above this level
+    flush_on_level: Option<Level>,
+    created_at: Instant,
+    /// When the logger was built, per its clock
+    started_at: DateTime<Utc>,
+    /// Add `uptime_ms` and `started_at` fields to every message
+    uptime: bool,
+    /// Add a `mono_ms` field, milliseconds since `created_at`, to every message
+    monotonic: bool,
+    /// Add a `tid` field, the logging thread's OS id, to every message
+    os_thread_id: bool,
+    /// The next `with_binary_header` sequence number, if enabled
+    binary_header: Option<AtomicU32>,
+    /// Expand nested record key-values into dotted keys, see `with_flatten_kv`
+    #[cfg(feature = "kv")]
+    flatten_kv: bool,
+    /// Keep at most this many record key-values, see `with_max_fields`
+    #[cfg(feature = "kv")]
+    max_fields: Option<usize>,
+    /// Take each record's time from this key-value, see `with_timestamp_field`
+    #[cfg(feature = "kv")]
+    timestamp_field: Option<String>,
+    /// Replace the values of these fields with salted hashes, see `with_field_hasher`
+    #[cfg(feature = "hashing")]
+    field_hashers: Vec<FieldHasher>,
+    /// Keep each record's target in its envelope, see `with_grouped_batches`
+    group_by_target: bool,
+    /// Keep each record's time in its envelope instead of its line, see
+    /// `with_batch_timestamps`
+    batch_timestamps: bool,
+    /// Run once when the logger is dropped, after its writers have shut down
+    shutdown_hook: Mutex<Option<ShutdownHook>>,
+    strict: bool,
+    stats: Arc<Stats>,
+    /// Each UDP writer's own counters, for `destinations`
+    destination_stats: Vec<(SocketAddr, Arc<Stats>)>,
+    /// Shared with every writer's `SocketOptions`, see `pause`
+    paused: Arc<AtomicBool>,
+    events: Events,
+    /// Last so its final report includes drops while the writers shut down
+    _drop_reporter: Option<DropReporter>,
+}
+
+/// How an additional destination encodes records instead of using the primary's payload
+enum Reformat {
+    Formatter(FormatterFn),
+    /// journald's native protocol, see `UdpLoggerBuilder::with_journald_native`
+    #[cfg(target_os = "linux")]
+    Journald,
+}
+
+/// The destinations every message is sent to besides the primary writer, and the limits
+/// on each datagram sent
+struct Fanout {
+    /// Additional destinations, each with its own encoding or the primary's payload
+    destinations: Vec<(Box<dyn Writer>, Option<Reformat>)>,
+    /// Caps the datagrams per second across the primary writer and every destination
+    rate_limit: Option<RateLimiter>,
+    send_cap: Option<SendCap>,
+}
+
+impl Fanout {
+    /// Whether another datagram fits under `with_global_rate_limit` and `with_send_cap`,
+    /// counting it if not
+    fn allow(&self, stats: &Stats) -> bool {
+        match &self.rate_limit {
+            Some(limiter) if !limiter.allow(Instant::now()) => {
+                stats.record_rate_limited();
+                return false;
+            }
+            _ => {}
+        }
+        match &self.send_cap {
+            Some(cap) if !cap.allow() => {
+                stats.record_capped();
+                false
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Sends the windows of `with_aggregation`, from logging threads and its timer alike
+struct Aggregation {
+    aggregator: Aggregator,
+    writer: Arc<ArcSwap<Box<dyn Writer>>>,
+    fanout: Arc<Fanout>,
+    clock: Arc<dyn Clock>,
+    stats: Arc<Stats>,
+    last_error: Arc<Mutex<Option<io::Error>>>,
+}
+
+impl Aggregation {
+    /// Send one datagram per key with its count in a finished window, to the primary
+    /// writer and every destination
+    ///
+    /// Aggregates aren't records, so destinations with their own formatter get them as-is.
+    fn send(&self, counts: BTreeMap<String, u64>) {
+        if counts.is_empty() {
+            return;
+        }
+        let timestamp = self.clock.now().to_rfc3339();
+        let priority = queue::default_priority(Level::Info);
+        let messages: Vec<Payload> = counts
+            .into_iter()
+            .map(|(key, count)| {
+                queue::payload(self.aggregator.render(&key, count, &timestamp).into_bytes())
+            })
+            .collect();
+        let primary = self.writer.load();
+        let writers = std::iter::once(&**primary)
+            .chain(self.fanout.destinations.iter().map(|(writer, _)| writer));
+        for writer in writers {
+            let batch = messages
+                .iter()
+                .filter(|_| self.fanout.allow(&self.stats))
+                .map(|message| Envelope::new(message.clone(), priority))
+                .collect();
+            if let Err(e) = writer.push_batch(batch) {
+                *self.last_error.lock().unwrap() = Some(e);
+            }
+        }
+    }
 }
 
+/// Decides whether an enabled record is logged
+type FilterFn = Box<dyn Fn(&Record) -> bool + Send + Sync>;
+
+/// Custom cleanup run when a UdpLogger is dropped
+type ShutdownHook = Box<dyn FnOnce() + Send>;
+
+/// Chooses the queue priority for each record, higher values are more important
+type PriorityFn = Box<dyn Fn(&Record) -> u8 + Send + Sync>;
+
+/// Generates ids for `UdpLoggerBuilder::with_id_source`
+pub type IdSource = Box<dyn Fn() -> u64 + Send + Sync>;
+
+/// Rewrites each formatted message for `UdpLoggerBuilder::with_transform`
+pub type Transform = Box<dyn Fn(Vec<u8>) -> Vec<u8> + Send + Sync>;
+
 impl UdpLogger {
     /// Create a new, unbuffered UdpLogger that sends datagrams to the given destination
-    pub fn new(destination: impl ToSocketAddrs) -> io::Result<Self> {
-        let writer = UdpWriter::new(destination)?;
-        Ok(Self {
-            writer: Box::new(writer),
-            level: Level::Info,
-        })
+    pub fn new(destination: impl ToSocketAddrs + Debug) -> io::Result<Self> {
+        UdpLoggerBuilder::new(destination).build()
     }
 
     /// Create a new, buffered UdpLogger that sends datagrams to the given destination
-    pub fn new_buffered(destination: impl ToSocketAddrs) -> io::Result<Self> {
-        let writer = UdpBufferedWriter::new(destination)?;
-        Ok(Self {
-            writer: Box::new(writer),
-            level: Level::Info,
-        })
+    pub fn new_buffered(destination: impl ToSocketAddrs + Debug) -> io::Result<Self> {
+        UdpLoggerBuilder::new(destination).buffered().build()
+    }
+
+    /// A buffered UdpLogger logging up to `filter`, for `try_buffered_init_filtered`
+    fn buffered_filtered(
+        destination: impl ToSocketAddrs + Debug,
+        filter: LevelFilter,
+    ) -> io::Result<Self> {
+        let logger = UdpLogger::new_buffered(destination)?;
+        logger.level.set_filter(filter);
+        Ok(logger)
+    }
+
+    /// Create a new UdpLogger that sends length-prefixed messages over a TCP connection,
+    /// see `UdpLoggerBuilder::tcp`
+    pub fn new_tcp(destination: impl ToSocketAddrs + Debug) -> io::Result<Self> {
+        UdpLoggerBuilder::new(destination).tcp().build()
+    }
+
+    /// Create a new UdpLogger that sends length-prefixed messages over the Windows named
+    /// pipe `name`, see `UdpLoggerBuilder::pipe`
+    #[cfg(windows)]
+    pub fn new_pipe(name: &str) -> io::Result<Self> {
+        // The destination is only used by other writers, so any will do
+        UdpLoggerBuilder::new("127.0.0.1:0").pipe(name).build()
+    }
+
+    /// Create a new UdpLogger that sends datagrams from an `async-std` task
+    #[cfg(feature = "async-std")]
+    pub fn new_async_std(destination: impl ToSocketAddrs + Debug) -> io::Result<Self> {
+        UdpLoggerBuilder::new(destination).async_std().build()
     }
 
     /// Modify the log level (default == INFO)
+    ///
+    /// If the logger is installed globally, `log::max_level` is updated to match
     pub fn set_level(&mut self, level: Level) -> &mut Self {
-        self.level = level;
+        self.level.set_level(level);
         self
     }
+
+    /// The maximum level that is logged
+    pub fn level(&self) -> Level {
+        self.level.level()
+    }
+
+    /// The most verbose level logged for `target`, after applying the level, any target
+    /// levels or suppression set through the `LevelHandle`, target floors, and the hard
+    /// minimum level
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        let level = match (self.level.target_level(target), self.scheduled_level()) {
+            (Some(level), _) => level,
+            (None, Some(level)) => level.to_level_filter(),
+            (None, None) => self.level.filter(),
+        };
+        let level = match self.hard_min_level {
+            Some(hard_min) => level.min(hard_min.to_level_filter()),
+            None => level,
+        };
+        self.target_floors
+            .iter()
+            .filter(|(prefix, _)| is_module_prefix(prefix, target))
+            .map(|(_, floor)| floor.to_level_filter())
+            .fold(level, LevelFilter::min)
+    }
+
+    /// The `with_scheduled_level` level in effect now, if any
+    fn scheduled_level(&self) -> Option<Level> {
+        match self.schedule.is_empty() {
+            true => None,
+            false => self.schedule.level_at(self.clock.now()),
+        }
+    }
+
+    /// Count a record `would_log` rejected, against its target's rules if it has any
+    /// and they rejected it, otherwise against the level
+    fn record_filtered(&self, metadata: &Metadata) {
+        let level = metadata.level();
+        let global = match self.scheduled_level() {
+            Some(scheduled) => scheduled.to_level_filter(),
+            None => self.level.filter(),
+        };
+        let by_level = match (
+            self.hard_min_level,
+            self.level.target_level(metadata.target()),
+        ) {
+            (Some(hard_min), _) if level > hard_min => true,
+            // A target level replaces the level, so it or a floor rejected the record
+            (_, Some(_)) => false,
+            (_, None) => level > global,
+        };
+        match by_level {
+            true => self.stats.record_filtered_by_level(),
+            false => self.stats.record_filtered_by_target(),
+        }
+    }
+
+    /// Whether a record for `target` at `level` would be logged
+    pub fn would_log(&self, target: &str, level: Level) -> bool {
+        level <= self.level_for(target)
+    }
+
+    /// A handle to change the level later, e.g. after installing the logger with `log`
+    pub fn level_handle(&self) -> LevelHandle {
+        self.level.clone()
+    }
+
+    /// Add `key=value` to the fields of every record logged on this thread until the guard drops
+    ///
+    /// Useful for request-scoped metadata like a request id. The fields follow any
+    /// `run_id` and `crate` fields, in the order they were pushed.
+    /// ```
+    /// use udp_logger::UdpLogger;
+    ///
+    /// let _request = UdpLogger::push_context("request_id", 42);
+    /// log::info!("handled"); // INFO [...] request_id=42 handled
+    /// ```
+    pub fn push_context(key: &'static str, value: impl ToString) -> ContextGuard {
+        context::push(key, value.to_string())
+    }
+
+    /// An `io::Write` that sends each line written to it as a message, e.g. to pipe
+    /// another library's output through the logger
+    pub fn sink(&self) -> Sink {
+        Sink::new(self.writer.clone(), self.stats.clone())
+    }
+
+    /// Log every panic through the installed logger before running the existing panic hook
+    ///
+    /// The panic is logged at Error level with the `panic` target, formatted like the
+    /// default hook's output (`thread 'main' panicked at src/main.rs:2:5: boom`). The hook
+    /// then waits up to a second for an installed UdpLogger to send what's queued, so
+    /// messages aren't lost if the panic ends the process. Call this after installing
+    /// the logger (e.g. with `init`), since it logs through `log::logger()`; each call
+    /// adds another hook around the current one.
+    /// ```
+    /// use udp_logger::{UdpLogger, UdpLoggerBuilder};
+    ///
+    /// UdpLoggerBuilder::new("127.0.0.1:1999").init().unwrap();
+    /// UdpLogger::install_panic_hook();
+    /// ```
+    pub fn install_panic_hook() {
+        panic_hook::install(log::logger());
+    }
+
+    /// A handle to replace the primary writer later, e.g. to point an installed logger elsewhere
+    pub fn writer_handle(&self) -> WriterHandle {
+        WriterHandle::new(self.writer.clone(), self.stats.clone(), self.paused.clone())
+    }
+
+    /// Stop sending until `resume`, e.g. during a known-noisy operation
+    ///
+    /// Buffered and manual loggers keep queueing while paused, up to the queue limit
+    /// (evicting per `with_drop_policy`), and send the queue after `resume`; heartbeats
+    /// and self-metrics are held back too. Unbuffered and `async_std` loggers have no
+    /// queue to hold messages in, so they drop them, counted in `StatsSnapshot::dropped`.
+    /// Every UDP destination pauses together, while `with_file` and journald
+    /// destinations keep writing. A paused buffered logger still sends its queue
+    /// when dropped (see `with_flush_on_drop`).
+    /// ```
+    /// use udp_logger::UdpLogger;
+    ///
+    /// let logger = UdpLogger::new_buffered("127.0.0.1:1999").unwrap();
+    /// logger.pause();
+    /// // ... run the noisy migration, whose messages wait in the queue
+    /// logger.resume();
+    /// ```
+    pub fn pause(&self) {
+        if !self.paused.swap(true, Ordering::SeqCst) {
+            self.events.emit(LoggerEvent::Paused);
+        }
+    }
+
+    /// Start sending again after `pause`
+    ///
+    /// The drain thread picks the held queue back up when it next wakes, within 50ms
+    /// (or the next second with `with_aligned_flush`).
+    pub fn resume(&self) {
+        if self.paused.swap(false, Ordering::SeqCst) {
+            self.events.emit(LoggerEvent::Resumed);
+        }
+    }
+
+    /// Whether sending is paused by `pause`
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Wait up to `timeout` for queued messages to be sent, returning how many remain undelivered
+    ///
+    /// Unbuffered loggers send inline, so this always returns 0 immediately for them
+    pub fn flush_timeout(&self, timeout: Duration) -> usize {
+        flush_writers(&self.writer, &self.fanout, timeout)
+    }
+
+    /// `flush_timeout` for the panic hook, which only sees the installed logger as a `dyn Log`
+    fn flusher(&self) -> panic_hook::Flush {
+        let writer = self.writer.clone();
+        let fanout = self.fanout.clone();
+        Box::new(move |timeout| flush_writers(&writer, &fanout, timeout))
+    }
+
+    /// Send everything queued by a `manual` logger, returning how many messages were sent
+    ///
+    /// Other loggers send on their own, so this returns 0 for them
+    pub fn drain(&self) -> usize {
+        let primary = self.writer.load();
+        let writers =
+            std::iter::once(&**primary).chain(self.fanout.destinations.iter().map(|(w, _)| w));
+        writers.map(|writer| writer.drain()).sum()
+    }
+
+    /// Replace the socket of every destination with a newly bound one
+    ///
+    /// For recovering from a socket left in a bad state, e.g. by an interface flap, so
+    /// that every send fails until it's rebound. Call it from a watchdog or after
+    /// `last_error` reports a failure. Queued messages are kept and sent from the new
+    /// socket, which has a new local port. Each new socket is counted in
+    /// `StatsSnapshot::rebinds`. A buffered `async_std` logger can't be rebound and
+    /// returns an `Unsupported` error. On error, the sockets that failed to rebind are
+    /// left in place and the first error is returned.
+    pub fn reconnect(&self) -> io::Result<()> {
+        let primary = self.writer.load();
+        let writers =
+            std::iter::once(&**primary).chain(self.fanout.destinations.iter().map(|(w, _)| w));
+        // Every writer is rebound even if an earlier one fails
+        let mut result = Ok(());
+        for writer in writers {
+            let rebound = writer.reconnect();
+            if result.is_ok() {
+                result = rebound;
+            }
+        }
+        result
+    }
+
+    /// Block until a probe datagram reaches the primary destination, returning whether
+    /// one did within `timeout`, e.g. as a readiness check at startup
+    ///
+    /// Probes (`UDP_LOGGER_PROBE [<timestamp>]`) are sent from a separate socket
+    /// connected to the destination, so ICMP errors like port unreachable surface, and
+    /// retried until one goes out with no error reported within about 50ms. UDP has no
+    /// acknowledgements, so a `true` means no error came back, not that a collector
+    /// read the probe. A TCP logger checks its connection, reconnecting if needed,
+    /// and a `with_dry_run` logger is always ready.
+    /// ```
+    /// # use std::net::UdpSocket;
+    /// # use std::time::Duration;
+    /// use udp_logger::UdpLogger;
+    ///
+    /// # let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let collector = collector.local_addr().unwrap();
+    /// let logger = UdpLogger::new(collector).unwrap();
+    /// assert!(logger.wait_ready(Duration::from_secs(1)));
+    /// ```
+    pub fn wait_ready(&self, timeout: Duration) -> bool {
+        self.writer.load().probe(timeout)
+    }
+
+    /// Send a probe record through the whole pipeline to the primary destination and
+    /// report how it went, e.g. as a health check at startup or in a diagnostic command
+    ///
+    /// The probe (`UDP_LOGGER_SELF_TEST` at `Info`, with target `udp_logger::self_test`)
+    /// skips level filtering but is otherwise formatted, sealed, and sent like any other
+    /// record. A buffered logger waits up to a second for its queue to send, and a manual
+    /// one drains its queue, sending anything queued ahead of the probe too. The report
+    /// then waits about 50ms for an error to come back, which only a
+    /// `with_connected_socket` socket reports for an unreachable port. As with
+    /// `wait_ready`, a sent probe means no error came back, not that a collector read it.
+    /// ```
+    /// # use std::net::UdpSocket;
+    /// use udp_logger::UdpLogger;
+    ///
+    /// # let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let collector = collector.local_addr().unwrap();
+    /// let logger = UdpLogger::new(collector).unwrap();
+    /// let report = logger.self_test();
+    /// assert!(report.is_ok(), "{:?}", report.error);
+    /// assert_eq!(report.destination, Some(collector));
+    /// ```
+    pub fn self_test(&self) -> SelfTestReport {
+        let writer = self.writer.load();
+        let sent = || -> io::Result<()> {
+            if self.is_paused() {
+                return Err(io::Error::other("the logger is paused"));
+            }
+            let payload = self.format(
+                &Record::builder()
+                    .args(format_args!("{}", SELF_TEST_MESSAGE))
+                    .level(Level::Info)
+                    .target("udp_logger::self_test")
+                    .build(),
+            );
+            if payload.is_empty() {
+                return Err(io::Error::other("the formatter skipped the probe"));
+            }
+            writer.push(Envelope::new(payload, queue::default_priority(Level::Info)))?;
+            writer.drain();
+            if writer.flush_timeout(SELF_TEST_TIMEOUT) > 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "the probe is still queued",
+                ));
+            }
+            thread::sleep(socket::PROBE_GRACE);
+            match writer.take_error()? {
+                Some(e) => Err(e),
+                None => Ok(()),
+            }
+        }();
+        SelfTestReport {
+            destination: writer.destination(),
+            local_addr: writer.local_addr().ok(),
+            sent: sent.is_ok(),
+            error: sent.err(),
+        }
+    }
+
+    /// Up to `max` messages queued for the primary destination, oldest first, without
+    /// removing them
+    ///
+    /// For debugging a backlog, e.g. a stuck sender. Only buffered and manual loggers
+    /// queue messages, so this is empty for other loggers. The queue is locked only
+    /// while the messages are copied, and they're decoded after it's released, with
+    /// invalid UTF-8 replaced. A custom `MessageQueue` may only show its next message,
+    /// and `with_spill_file` only shows messages still in memory.
+    pub fn peek_queue(&self, max: usize) -> Vec<String> {
+        self.writer
+            .load()
+            .peek_queue(max)
+            .into_iter()
+            .map(|message| String::from_utf8_lossy(&message.payload).into_owned())
+            .collect()
+    }
+
+    /// Send a pre-formatted message through the writer, bypassing the `log` macros
+    ///
+    /// The message isn't formatted, but is queued, batched, and framed like any other.
+    /// A trailing newline is added if missing.
+    pub fn send_raw(&self, msg: &str) -> io::Result<()> {
+        if msg.is_empty() {
+            self.stats.record_empty();
+            return Ok(());
+        }
+        let mut payload = msg.as_bytes().to_vec();
+        if !payload.ends_with(b"\n") {
+            payload.push(b'\n');
+        }
+        self.writer.load().push(payload.into())
+    }
+
+    /// Send pre-formatted message bytes, decoded per the builder's `Utf8Policy`
+    ///
+    /// With `Utf8Policy::Reject`, invalid UTF-8 is counted in `StatsSnapshot::invalid_utf8`
+    /// and returns an `InvalidData` error without sending anything
+    pub fn send_raw_bytes(&self, msg: &[u8]) -> io::Result<()> {
+        match self.utf8_policy.decode(msg) {
+            Some(msg) => self.send_raw(&msg),
+            None => {
+                self.stats.record_invalid_utf8();
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "message is not valid UTF-8",
+                ))
+            }
+        }
+    }
+
+    /// Send many pre-formatted messages at once, as if by `send_raw` for each
+    ///
+    /// Buffered and manual loggers queue the whole batch under a single lock acquisition,
+    /// which cuts overhead for bulk producers. Unbuffered loggers send each message in
+    /// turn, stopping at the first failure.
+    pub fn log_batch(&self, messages: impl IntoIterator<Item = String>) -> io::Result<()> {
+        let mut batch = Vec::new();
+        for msg in messages {
+            if msg.is_empty() {
+                self.stats.record_empty();
+                continue;
+            }
+            let mut payload = msg.into_bytes();
+            if !payload.ends_with(b"\n") {
+                payload.push(b'\n');
+            }
+            batch.push(payload.into());
+        }
+        self.writer.load().push_batch(batch)
+    }
+
+    /// Send previously captured payloads again, byte for byte, e.g. after a collector outage
+    ///
+    /// Each payload is treated as an already-formatted message: nothing is added, not
+    /// even a newline, but it's still batched, sealed (`with_payload_header`,
+    /// compression, encryption), and counted like any other. Only the primary writer
+    /// sends them. Empty payloads are counted in `StatsSnapshot::empty` and skipped.
+    /// ```
+    /// use udp_logger::UdpLogger;
+    ///
+    /// let logger = UdpLogger::new("127.0.0.1:1999").unwrap();
+    /// let captured = vec![b"INFO [2020-06-15T03:15:39+00:00] started\n".to_vec()];
+    /// logger.replay(captured).unwrap();
+    /// ```
+    pub fn replay(&self, messages: impl IntoIterator<Item = Vec<u8>>) -> io::Result<()> {
+        let mut batch = Vec::new();
+        for payload in messages {
+            if payload.is_empty() {
+                self.stats.record_empty();
+                continue;
+            }
+            batch.push(payload.into());
+        }
+        self.writer.load().push_batch(batch)
+    }
+
+    /// The most recent error from handing a logged record to the writer, if any
+    ///
+    /// Only errors surfaced by `Log::log` are kept: the unbuffered writer reports failed
+    /// sends, while the buffered writer's background sends are only counted in `stats()`
+    pub fn last_error(&self) -> Option<io::Error> {
+        self.last_error
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|e| match e.raw_os_error() {
+                Some(code) => io::Error::from_raw_os_error(code),
+                None => io::Error::new(e.kind(), e.to_string()),
+            })
+    }
+
+    /// The id included in every message to identify this process lifetime, if enabled
+    pub fn run_id(&self) -> Option<u64> {
+        self.run_id
+    }
+
+    /// A snapshot of the logger's counters
+    pub fn stats(&self) -> StatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    /// Each UDP destination's address and its own counters, to find which collector in a
+    /// fan-out is unhealthy
+    ///
+    /// The primary destination comes first, then any `add_weighted_destination`s, then
+    /// the `add_destination_with_formatter` destinations, in the order they were added.
+    /// Files, journald, and a pipe or Unix socket in place of the destination aren't
+    /// listed, and nor is a writer swapped in by `WriterHandle::set_writer`.
+    pub fn destinations(&self) -> Vec<DestinationStats> {
+        self.destination_stats
+            .iter()
+            .map(|(address, stats)| stats.destination(*address))
+            .collect()
+    }
+
+    /// A snapshot of the logger's counters, zeroing them so the next call reports only
+    /// what happened since this one
+    ///
+    /// Each counter is read-and-reset atomically, so concurrent events are never lost,
+    /// though one racing the call may land in the following snapshot.
+    pub fn take_stats(&self) -> StatsSnapshot {
+        self.stats.take()
+    }
+
+    /// Zero the logger's counters
+    pub fn reset_stats(&self) {
+        self.stats.reset()
+    }
+
+    /// The local address (system determined IP & port) datagrams are sent from
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.writer.load().local_addr()
+    }
+
+    /// The time bytes were last successfully put on the wire, if ever
+    pub fn last_send_at(&self) -> Option<SystemTime> {
+        self.stats.last_send_at()
+    }
+
+    /// Format a record into the payload that is sent as a datagram
+    ///
+    /// An empty line from the formatter is returned as-is so it can be skipped
+    fn format(&self, record: &Record) -> Vec<u8> {
+        let payload = match (self.encode(record), &self.binary_header) {
+            (payload, Some(sequence)) if !payload.is_empty() => format::binary_header(
+                record.level(),
+                self.clock.now().timestamp_millis(),
+                sequence.fetch_add(1, Ordering::Relaxed),
+                &payload,
+            ),
+            (payload, _) => payload,
+        };
+        match &self.transform {
+            Some(transform) if !payload.is_empty() => transform(payload),
+            _ => payload,
+        }
+    }
+
+    /// Encode a record with the configured format, before any `with_transform`
+    fn encode(&self, record: &Record) -> Vec<u8> {
+        if let Some(max_len) = self.target_max_len {
+            if let Cow::Owned(target) = format::truncate_front(record.target(), max_len) {
+                return self.encode(
+                    &Record::builder()
+                        .args(*record.args())
+                        .metadata(record.metadata().clone())
+                        .target(&target)
+                        .module_path(record.module_path())
+                        .file(record.file())
+                        .line(record.line())
+                        .build(),
+                );
+            }
+        }
+        let fields = self.fields(record);
+        #[cfg(feature = "msgpack")]
+        if let (None, Some(tag)) = (&self.formatter, &self.fluentd_tag) {
+            return fluentd::encode(tag, record, &fields);
+        }
+        #[cfg(feature = "prost")]
+        if self.formatter.is_none() && self.protobuf {
+            return protobuf::encode(record, &fields);
+        }
+        let line = match (&self.formatter, &self.template) {
+            (Some(formatter), _) => self.custom_format(formatter, record),
+            (None, Some(template)) => {
+                let time = fields.time.unwrap_or_else(|| self.clock.now());
+                template.render(record, &fields, time)
+            }
+            (None, None) => self.format.render(record, &fields, &self.json_keys),
+        };
+        if line.is_empty() {
+            return line.into_bytes();
+        }
+        let line = match &self.envelope {
+            Some(template) => format::wrap_envelope(template, &line, record, &fields),
+            None => line,
+        };
+        let line = match &self.date_partition {
+            Some(partition) => {
+                let time = fields.time.unwrap_or_else(|| self.clock.now());
+                format!("{} {}", partition.bucket(time), line)
+            }
+            None => line,
+        };
+        let line = match self.rotating_token {
+            Some(window) => {
+                let time = fields.time.unwrap_or_else(|| self.clock.now());
+                format!("{} {}", format::rotating_token(time, window), line)
+            }
+            None => line,
+        };
+        let line = if self.journald_priority {
+            format!("PRIORITY={} {}", syslog::severity(record.level()), line)
+        } else {
+            line
+        };
+        let line = match &self.facility {
+            Some(facility) => format!(
+                "<{}>{}",
+                syslog::priority(facility(record), record.level()),
+                line
+            ),
+            None => line,
+        };
+        match &self.footer {
+            Some((template, position)) => {
+                format::append_footer(line, template, *position).into_bytes()
+            }
+            None => line.into_bytes(),
+        }
+    }
+
+    /// Render a record with a custom formatter, per the `FormatterPanicPolicy` if it panics
+    fn custom_format(&self, formatter: &FormatterFn, record: &Record) -> String {
+        if let Some(line) = format::catch_panic(formatter, record) {
+            return line;
+        }
+        self.stats.record_formatter_panic();
+        match self.formatter_panic {
+            FormatterPanicPolicy::Fallback => {
+                self.format
+                    .render(record, &self.fields(record), &self.json_keys)
+            }
+            FormatterPanicPolicy::Skip => String::new(),
+        }
+    }
+
+    /// Collect the fields rendered alongside a record
+    fn fields(&self, record: &Record) -> Fields {
+        // Batched timestamps come from the envelope and leave the line without one
+        let timestamp_format = match self.batch_timestamps {
+            true => TimestampFormat::None,
+            false => self.timestamp_format,
+        };
+        // Reading the clock is skipped entirely when nothing renders the time
+        let time = match (timestamp_format, &self.date_partition, self.rotating_token) {
+            (TimestampFormat::None, None, None) => None,
+            _ => Some(self.clock.now()),
+        };
+        #[cfg(feature = "kv")]
+        let time = match (time, &self.timestamp_field) {
+            (Some(now), Some(key)) => Some(kv::timestamp(record, key).unwrap_or(now)),
+            _ => time,
+        };
+        let mut fields = Fields::new(time);
+        fields.timestamp_format = timestamp_format;
+        fields.level_label = self.level_label;
+        fields.schema_version = self.schema_version;
+        if let Some(run_id) = self.run_id {
+            fields
+                .extra
+                .push(("run_id".into(), format!("{:016x}", run_id)));
+        }
+        if self.crate_field {
+            if let Some(module) = record.module_path() {
+                let name = module.split("::").next().unwrap_or(module);
+                fields.extra.push(("crate".into(), name.to_string()));
+            }
+        }
+        if let Some(app) = &self.app_name {
+            fields.extra.push(("app".into(), app.clone()));
+        }
+        if let Some(version) = &self.version {
+            fields.extra.push(("version".into(), version.clone()));
+        }
+        if let Some(source) = &self.source {
+            fields.extra.push(("source".into(), source.clone()));
+        }
+        if self.uptime {
+            let uptime = self.created_at.elapsed().as_millis();
+            fields.extra.push(("uptime_ms".into(), uptime.to_string()));
+            if self.timestamp_format != TimestampFormat::None {
+                let started_at = self.timestamp_format.render(self.started_at);
+                fields.extra.push(("started_at".into(), started_at));
+            }
+        }
+        if self.monotonic {
+            let elapsed = self.created_at.elapsed().as_millis();
+            fields.extra.push(("mono_ms".into(), elapsed.to_string()));
+        }
+        if self.os_thread_id {
+            if let Some(tid) = format::os_thread_id() {
+                fields.extra.push(("tid".into(), tid.to_string()));
+            }
+        }
+        context::extend(&mut fields.extra);
+        #[cfg(feature = "kv")]
+        kv::extend(record, self.flatten_kv, self.max_fields, &mut fields.extra);
+        #[cfg(feature = "hashing")]
+        hash::apply(&self.field_hashers, &mut fields.extra);
+        fields
+    }
+
+    /// Format a record and attach the metadata used by the writer
+    fn envelope(&self, record: &Record) -> Envelope {
+        self.with_priority(record, self.format(record))
+    }
+
+    /// Send the counts of the current aggregation window
+    fn flush_aggregates(&self) {
+        if let Some(aggregation) = &self.aggregation {
+            aggregation.send(aggregation.aggregator.take(Instant::now()));
+        }
+    }
+
+    /// The end-of-session datagram sent by `with_shutdown_summary`
+    fn summary(&self) -> String {
+        let stats = self.stats.snapshot();
+        let mut summary = format!(
+            "UDP_LOGGER_SUMMARY [{}] sent={} dropped={} errors={} pending={} duration={:?}",
+            self.clock.now().to_rfc3339(),
+            stats.sent,
+            stats.dropped,
+            stats.errors,
+            self.writer.load().flush_timeout(Duration::ZERO),
+            self.created_at.elapsed(),
+        );
+        for (level, count) in stats.by_level() {
+            let _ = write!(summary, " {}={}", level.as_str().to_lowercase(), count);
+        }
+        summary.push('\n');
+        summary
+    }
+
+    /// The startup datagram sent by `with_build_info`
+    fn handshake(&self, version: &str, commit: Option<&str>) -> String {
+        let mut handshake = format!(
+            "UDP_LOGGER_HANDSHAKE [{}] version={}",
+            self.clock.now().to_rfc3339(),
+            version
+        );
+        if let Some(commit) = commit {
+            let _ = write!(handshake, " commit={}", commit);
+        }
+        if let Some(app) = &self.app_name {
+            let _ = write!(handshake, " app={}", app);
+        }
+        if let Some(run_id) = self.run_id {
+            let _ = write!(handshake, " run_id={:016x}", run_id);
+        }
+        handshake.push('\n');
+        handshake
+    }
+
+    /// Count a record that formatted to nothing, which is an error in strict mode
+    fn formatted_empty(&self) {
+        self.stats.record_empty();
+        if self.strict {
+            self.capture(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "strict: formatter produced an empty message",
+            )));
+        }
+    }
+
+    /// Log `record` once it's passed the level and custom filters, applying the
+    /// `NewlinePolicy` only if `split_lines` is set
+    ///
+    /// The records a policy splits a message into are logged with it unset, so they're
+    /// never split again.
+    fn log_checked(&self, record: &Record, split_lines: bool) {
+        match &self.empty_message {
+            EmptyMessagePolicy::Keep => {}
+            _ if !format::is_empty_message(record) => {}
+            EmptyMessagePolicy::Skip => return self.stats.record_empty(),
+            EmptyMessagePolicy::Placeholder(text) if !text.is_empty() => {
+                let mut builder = Record::builder();
+                builder
+                    .metadata(record.metadata().clone())
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line());
+                #[cfg(feature = "kv")]
+                builder.key_values(record.key_values());
+                return self
+                    .log_checked(&builder.args(format_args!("{}", text)).build(), split_lines);
+            }
+            EmptyMessagePolicy::Placeholder(_) => {}
+        }
+        if split_lines && self.newline_policy != NewlinePolicy::Keep {
+            let message = match record.args().as_str() {
+                Some(message) => Cow::Borrowed(message),
+                None => Cow::Owned(record.args().to_string()),
+            };
+            if let Some(messages) = self.newline_policy.apply(&message) {
+                for message in messages {
+                    self.log_as(record, &message);
+                }
+                return;
+            }
+        }
+        if let Some(throttle) = &self.throttle {
+            if !throttle.allow(record, Instant::now()) {
+                self.stats.record_throttled();
+                return;
+            }
+        }
+        if let Some(aggregation) = &self.aggregation {
+            self.stats.record_level(record.level());
+            return aggregation.send(aggregation.aggregator.add(record, Instant::now()));
+        }
+        let message = self.envelope(record);
+        if message.payload.is_empty() {
+            self.formatted_empty();
+            return;
+        }
+        self.stats.record_level(record.level());
+        let payload = match self.fanout.destinations.is_empty() {
+            true => Payload::new(),
+            false => message.payload.clone(),
+        };
+        if self.fanout.allow(&self.stats) {
+            self.capture(self.writer.load().push(message));
+        }
+        for (writer, formatter) in &self.fanout.destinations {
+            if !self.fanout.allow(&self.stats) {
+                continue;
+            }
+            let payload: Payload = match formatter {
+                Some(Reformat::Formatter(formatter)) => {
+                    queue::payload(self.custom_format(formatter, record).into_bytes())
+                }
+                #[cfg(target_os = "linux")]
+                Some(Reformat::Journald) => {
+                    queue::payload(journald::encode(record, &self.fields(record)))
+                }
+                None => payload.clone(),
+            };
+            if payload.is_empty() {
+                self.formatted_empty();
+                continue;
+            }
+            self.capture(writer.push(self.with_priority(record, payload)));
+        }
+        if self
+            .flush_on_level
+            .is_some_and(|level| record.level() <= level)
+        {
+            self.writer.load().wake();
+            for (writer, _) in &self.fanout.destinations {
+                writer.wake();
+            }
+        }
+    }
+
+    /// Log `record` again, with `message` in place of its own and no `NewlinePolicy`
+    fn log_as(&self, record: &Record, message: &str) {
+        let mut builder = Record::builder();
+        builder
+            .metadata(record.metadata().clone())
+            .module_path(record.module_path())
+            .file(record.file())
+            .line(record.line());
+        #[cfg(feature = "kv")]
+        builder.key_values(record.key_values());
+        self.log_checked(&builder.args(format_args!("{}", message)).build(), false);
+    }
+
+    /// Remember a failed push for `last_error`, since `Log::log` can't return it
+    fn capture(&self, result: io::Result<()>) {
+        if let Err(e) = result {
+            *self.last_error.lock().unwrap() = Some(e);
+        }
+    }
+
+    /// Wrap a formatted payload with the record's queue priority
+    fn with_priority(&self, record: &Record, payload: impl Into<Payload>) -> Envelope {
+        let priority = match &self.priority {
+            Some(priority) => priority(record),
+            None => queue::default_priority(record.level()),
+        };
+        let mut envelope = Envelope::new(payload, priority);
+        envelope.level = Some(record.level());
+        if self.group_by_target {
+            envelope.target = Some(record.target().into());
+        }
+        if self.batch_timestamps {
+            envelope.time = Some(self.clock.now());
+        }
+        envelope
+    }
+}
+
+impl Drop for UdpLogger {
+    fn drop(&mut self) {
+        // Stopped first so the final window is only sent once
+        self.aggregate_timer.take();
+        self.flush_aggregates();
+        if self.shutdown_summary {
+            let summary = self.summary();
+            let priority = queue::default_priority(Level::Error);
+            self.capture(
+                self.writer
+                    .load()
+                    .push(Envelope::new(summary.into_bytes(), priority)),
+            );
+        }
+        let hook = self.shutdown_hook.get_mut().unwrap().take();
+        if hook.is_none() && !self.events.is_listening() {
+            return;
+        }
+        // Shut the writers down now rather than after this returns, so the hook runs last
+        self.aggregation.take();
+        if let Some(fanout) = Arc::get_mut(&mut self.fanout) {
+            fanout.destinations.clear();
+        }
+        self.writer.store(Arc::new(Box::new(DryRunWriter {
+            stats: self.stats.clone(),
+        })));
+        self.events.emit(LoggerEvent::Shutdown);
+        if let Some(hook) = hook {
+            hook();
+        }
+    }
 }
 
 impl log::Log for UdpLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= self.level
+        self.would_log(metadata.target(), metadata.level())
     }
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
-            let _ = self.writer.push(format!(
-                "{} [{}] {}\n",
-                record.level(),
-                Utc::now().to_rfc3339(),
-                record.args()
-            ));
+            if let Some(filter) = &self.filter {
+                if !filter(record) {
+                    self.stats.record_filtered_by_filter();
+                    return;
+                }
+            }
+            self.log_checked(record, true);
+        } else {
+            self.record_filtered(record.metadata());
         }
     }
 
-    fn flush(&self) {}
+    fn flush(&self) {
+        self.flush_aggregates();
+    }
+}
+
+/// Wait up to `timeout` for the primary writer and every destination to send what's queued
+fn flush_writers(writer: &ArcSwap<Box<dyn Writer>>, fanout: &Fanout, timeout: Duration) -> usize {
+    let deadline = Instant::now() + timeout;
+    let primary = writer.load();
+    let writers = std::iter::once(&**primary).chain(fanout.destinations.iter().map(|(w, _)| w));
+    writers
+        .map(|writer| writer.flush_timeout(deadline.saturating_duration_since(Instant::now())))
+        .sum()
 }
 
 /// Easily initialize the UdpLogger adapter with `Log` using this UdpLogger builder
@@ -70,131 +1245,7679 @@ impl log::Log for UdpLogger {
 ///
 /// info!("This will get sent via UDP!");
 /// ```
-pub struct UdpLoggerBuilder;
+///
+/// The builder can also be used to configure optional behavior before init:
+/// ```no_run
+/// use std::time::Duration;
+/// use udp_logger::UdpLoggerBuilder;
+///
+/// UdpLoggerBuilder::new("127.0.0.1:1999")
+///     .level(log::Level::Debug)
+///     .buffered()
+///     .with_heartbeat(Duration::from_secs(30))
+///     .init()
+///     .unwrap();
+/// ```
+pub struct UdpLoggerBuilder {
+    destination: io::Result<SocketAddr>,
+    /// A host to look up with a resolver when building, replacing `destination`
+    lookup: Option<(String, Box<dyn Resolver>)>,
+    /// Attempts at the lookup and the delay between them
+    resolve_retry: (u32, Duration),
+    level: Level,
+    kind: WriterKind,
+    /// Set by `with_ordered`, checked against `kind` by `build`
+    ordered: bool,
+    options: BufferedOptions,
+    socket: SocketOptions,
+    format: Format,
+    json_framing: JsonFraming,
+    formatter: Option<FormatterFn>,
+    facility: Option<FacilityFn>,
+    priority: Option<PriorityFn>,
+    run_id: Option<u64>,
+    /// Generate `run_id` with `id_source` when building
+    generate_run_id: bool,
+    id_source: Option<IdSource>,
+    journald_priority: bool,
+    #[cfg(feature = "msgpack")]
+    fluentd_tag: Option<String>,
+    #[cfg(feature = "prost")]
+    protobuf: bool,
+    clock: Arc<dyn Clock>,
+    dry_run: bool,
+    destinations: Vec<(io::Result<SocketAddr>, FormatterFn)>,
+    /// Destinations sharing the primary writer's messages, see `add_weighted_destination`
+    weighted: Vec<(io::Result<SocketAddr>, u32)>,
+    /// Each UDP writer's own counters, filled in while building the writers
+    destination_stats: Vec<(SocketAddr, Arc<Stats>)>,
+    files: Vec<(PathBuf, u64, usize)>,
+    /// The socket path for `with_journald_native`
+    #[cfg(target_os = "linux")]
+    journald: Option<PathBuf>,
+    throttle: Option<Throttle>,
+    rate_limit: Option<RateLimit>,
+    send_cap: Option<usize>,
+    send_cap_callback: Option<Box<dyn FnOnce() + Send>>,
+    aggregator: Option<Aggregator>,
+    filter: Option<FilterFn>,
+    empty_message: EmptyMessagePolicy,
+    formatter_panic: FormatterPanicPolicy,
+    newline_policy: NewlinePolicy,
+    utf8_policy: Utf8Policy,
+    json_keys: JsonKeys,
+    target_floors: Vec<(String, Level)>,
+    hard_min_level: Option<Level>,
+    schedule: LevelSchedule,
+    crate_field: bool,
+    app_name: Option<String>,
+    version: Option<String>,
+    source_field: bool,
+    schema_version: Option<u32>,
+    target_max_len: Option<usize>,
+    uptime: bool,
+    monotonic: bool,
+    os_thread_id: bool,
+    binary_header: bool,
+    #[cfg(feature = "kv")]
+    flatten_kv: bool,
+    #[cfg(feature = "kv")]
+    max_fields: Option<usize>,
+    #[cfg(feature = "kv")]
+    timestamp_field: Option<String>,
+    #[cfg(feature = "hashing")]
+    field_hashers: Vec<FieldHasher>,
+    queue: Option<Box<dyn MessageQueue>>,
+    spill: Option<(PathBuf, u64)>,
+    shutdown_hook: Option<ShutdownHook>,
+    strict: bool,
+    date_partition: Option<DatePartition>,
+    rotating_token: Option<Duration>,
+    timestamp_format: TimestampFormat,
+    level_label: LevelLabel,
+    drop_reporting: Option<(io::Result<SocketAddr>, Duration)>,
+    template: Option<io::Result<Template>>,
+    envelope: Option<String>,
+    footer: Option<String>,
+    footer_position: FooterPosition,
+    transform: Option<Transform>,
+    shutdown_summary: bool,
+    /// The crate version and commit sent in the startup handshake
+    build_info: Option<(String, Option<String>)>,
+    /// Wake the writers right after queueing a record at or above this level
+    flush_on_level: Option<Level>,
+    shared_level: Option<Arc<AtomicU8>>,
+}
 
 impl UdpLoggerBuilder {
-    /// Initialize an unbuffered UdpLogger as a destination for `Log` macros
-    pub fn try_init(
-        destination: impl ToSocketAddrs,
-        level: Level,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut logger = UdpLogger::new(destination).unwrap();
-        logger.set_level(level);
-        UdpLoggerBuilder::init(logger).map_err(|e| e.into())
+    /// Create a builder for a UdpLogger that sends datagrams to the given destination
+    pub fn new(destination: impl ToSocketAddrs + Debug) -> Self {
+        Self {
+            destination: resolve(destination),
+            lookup: None,
+            resolve_retry: (1, Duration::ZERO),
+            level: Level::Info,
+            kind: WriterKind::Unbuffered,
+            ordered: false,
+            options: BufferedOptions::default(),
+            socket: SocketOptions::default(),
+            format: Format::default(),
+            json_framing: JsonFraming::default(),
+            formatter: None,
+            facility: None,
+            priority: None,
+            run_id: None,
+            generate_run_id: false,
+            id_source: None,
+            journald_priority: false,
+            #[cfg(feature = "msgpack")]
+            fluentd_tag: None,
+            #[cfg(feature = "prost")]
+            protobuf: false,
+            clock: Arc::new(SystemClock),
+            dry_run: false,
+            destinations: Vec::new(),
+            weighted: Vec::new(),
+            destination_stats: Vec::new(),
+            files: Vec::new(),
+            #[cfg(target_os = "linux")]
+            journald: None,
+            throttle: None,
+            rate_limit: None,
+            send_cap: None,
+            send_cap_callback: None,
+            aggregator: None,
+            filter: None,
+            empty_message: EmptyMessagePolicy::default(),
+            formatter_panic: FormatterPanicPolicy::default(),
+            newline_policy: NewlinePolicy::default(),
+            utf8_policy: Utf8Policy::default(),
+            json_keys: JsonKeys::default(),
+            target_floors: Vec::new(),
+            hard_min_level: None,
+            schedule: LevelSchedule::default(),
+            crate_field: false,
+            app_name: None,
+            version: None,
+            source_field: false,
+            schema_version: Some(SCHEMA_VERSION),
+            target_max_len: None,
+            uptime: false,
+            monotonic: false,
+            os_thread_id: false,
+            binary_header: false,
+            #[cfg(feature = "kv")]
+            flatten_kv: false,
+            #[cfg(feature = "kv")]
+            max_fields: None,
+            #[cfg(feature = "kv")]
+            timestamp_field: None,
+            #[cfg(feature = "hashing")]
+            field_hashers: Vec::new(),
+            queue: None,
+            spill: None,
+            shutdown_hook: None,
+            strict: false,
+            date_partition: None,
+            rotating_token: None,
+            timestamp_format: TimestampFormat::default(),
+            level_label: LevelLabel::default(),
+            drop_reporting: None,
+            template: None,
+            envelope: None,
+            footer: None,
+            transform: None,
+            footer_position: FooterPosition::default(),
+            shutdown_summary: false,
+            build_info: None,
+            flush_on_level: None,
+            shared_level: None,
+        }
     }
 
-    /// Initialize a buffered UdpLogger as a destination for `Log` macros
-    pub fn try_buffered_init(
-        destination: impl ToSocketAddrs,
-        level: Level,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut logger = UdpLogger::new(destination)?;
-        logger.set_level(level);
-        UdpLoggerBuilder::init(logger).map_err(|e| e.into())
+    /// Resolve the destination `host` (e.g. `"collector:1999"`) with `resolver` instead
+    ///
+    /// Replaces the destination given to `new`. `host` is looked up by `build`, and the
+    /// first address returned is used.
+    pub fn with_resolver(mut self, host: &str, resolver: impl Resolver + 'static) -> Self {
+        self.lookup = Some((host.to_string(), Box::new(resolver)));
+        self
     }
 
-    fn init(logger: UdpLogger) -> Result<(), SetLoggerError> {
-        let level_filter = logger.level.to_level_filter();
-        let r = log::set_boxed_logger(Box::new(logger)).map(|()| log::set_max_level(level_filter));
-        r
+    /// Try the `with_resolver` lookup up to `attempts` times, waiting `delay` between them
+    ///
+    /// For a collector whose DNS record may not exist yet at startup. `build` blocks
+    /// while retrying, and returns the last error if every attempt fails. Addresses that
+    /// can never be sent to (e.g. port 0) aren't retried. The destination given to `new`
+    /// is resolved immediately, so use `with_resolver(host, SystemResolver)` to retry
+    /// the system lookup; without a resolver there's nothing to retry, which
+    /// `with_strict` rejects.
+    pub fn with_resolve_retry(mut self, attempts: u32, delay: Duration) -> Self {
+        self.resolve_retry = (attempts.max(1), delay);
+        self
     }
-}
 
-/// Writer is used by UdpLogger to send UDP datagrams
-trait Writer: Send + Sync {
-    fn push(&self, message: String) -> io::Result<()>;
-}
+    /// Try binding each writer's socket up to `attempts` times, waiting `delay` between
+    /// them
+    ///
+    /// For containers where binding can briefly fail at startup, e.g. while the network
+    /// namespace is set up. `build` (and `UdpLogger::reconnect`) blocks while retrying,
+    /// and returns the last error if every attempt fails. Errors from the socket options
+    /// applied after binding aren't retried.
+    pub fn with_bind_retry(mut self, attempts: u32, delay: Duration) -> Self {
+        self.socket.bind_retry = (attempts.max(1), delay);
+        self
+    }
 
-/// UdpWriter is an unbuffered writer and datagrams will be sent immediately
-/// via a UdpSocket (system determined IP & port)
-struct UdpWriter {
-    out: UdpSocket,
-    destination: SocketAddr,
-}
+    /// Read the level from an atomic owned by the caller, replacing `level`
+    ///
+    /// The value is `LevelFilter as u8`: 0 == Off, 1 == Error, 2 == Warn, 3 == Info,
+    /// 4 == Debug, 5 (or more) == Trace. It's read for every record, so storing a new
+    /// value changes the verbosity immediately. When installed globally, `log::max_level`
+    /// is left at `Trace` so every record reaches the logger to be checked.
+    pub fn with_shared_level(mut self, level: Arc<AtomicU8>) -> Self {
+        self.shared_level = Some(level);
+        self
+    }
 
-impl UdpWriter {
-    /// Create a new UdpWriter that sends messages to the given destination `SocketAddr`
-    pub fn new(destination: impl ToSocketAddrs) -> io::Result<Self> {
-        let dest = destination
-            .to_socket_addrs()?
-            .into_iter()
-            .next()
-            .ok_or_else(|| io::Error::new(io::ErrorKind::AddrNotAvailable, ""))?;
-        Ok(Self {
-            destination: dest,
-            out: UdpSocket::bind("0.0.0.0:0")?,
-        })
+    /// Modify the log level (default == INFO)
+    pub fn level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
     }
-}
 
-impl Writer for UdpWriter {
-    /// This is used by `Log` to write the message as a datagram
-    fn push(&self, message: String) -> io::Result<()> {
-        self.out
-            .send_to(message.as_bytes(), self.destination)
-            .map(|_| ())
+    /// Never log below `floor` for targets under `prefix` (e.g. `my_app::noisy`)
+    ///
+    /// The stricter of the global level and the floor applies, so a floor can quiet a
+    /// noisy module but never enables records the global level would reject
+    pub fn with_target_floor(mut self, prefix: impl Into<String>, floor: Level) -> Self {
+        self.target_floors.push((prefix.into(), floor));
+        self
     }
-}
 
-/// UdpBufferedWriter is an alternate UdpWriter that buffers submitted messages
-/// and sends in a background thread
-struct UdpBufferedWriter {
-    messages: Arc<Mutex<VecDeque<String>>>,
-}
+    /// Never log anything more verbose than `level`, e.g. `Info` to drop `Debug` and
+    /// `Trace` in release builds
+    ///
+    /// Unlike the level, this can't be loosened later: it applies after the schedule,
+    /// target floors, and any target levels set through the `LevelHandle`.
+    pub fn with_hard_min_level(mut self, level: Level) -> Self {
+        self.hard_min_level = Some(level);
+        self
+    }
 
-impl UdpBufferedWriter {
-    /// Create a new UdpBufferedWriter that sends messages to the given destination `SocketAddr`
-    pub fn new(destination: impl ToSocketAddrs) -> io::Result<Self> {
-        let messages: Arc<Mutex<VecDeque<String>>> = Arc::new(Mutex::new(VecDeque::new()));
-        {
-            let m_clone = messages.clone();
-            let dest = destination
-                .to_socket_addrs()?
-                .into_iter()
-                .next()
-                .ok_or_else(|| io::Error::new(io::ErrorKind::AddrNotAvailable, ""))?;
-
-            let out = UdpSocket::bind("0.0.0.0:0")?;
-            thread::spawn(move || loop {
-                if let Ok(mut messages) = m_clone.lock() {
-                    while let Some(message) = messages.pop_front() {
-                        out.send_to(message.as_bytes(), dest)
-                            .map_err(|e| eprintln!("Error sending message: {}", e))
-                            .ok();
-                    }
-                }
-                std::thread::sleep(std::time::Duration::from_millis(50));
-            });
-        }
-        Ok(Self { messages })
+    /// Replace the level during daily windows of local time, e.g. `Debug` during a
+    /// maintenance window
+    ///
+    /// Each window is `(start, end, level)` and includes `start` but not `end`. A window
+    /// whose `end` is before its `start` wraps past midnight. The first window containing
+    /// the current time (read from `with_clock`) wins, and outside every window the
+    /// normal level applies. Target floors still apply on top. When installed globally,
+    /// `log::max_level` is left at `Trace` so every record reaches the logger to be checked.
+    /// ```
+    /// use chrono::NaiveTime;
+    /// use log::Level;
+    /// use udp_logger::UdpLoggerBuilder;
+    ///
+    /// let at = |hour| NaiveTime::from_hms_opt(hour, 0, 0).unwrap();
+    /// let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+    ///     .level(Level::Warn)
+    ///     .with_scheduled_level(vec![(at(2), at(4), Level::Debug)])
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn with_scheduled_level(
+        mut self,
+        schedule: impl IntoIterator<Item = (NaiveTime, NaiveTime, Level)>,
+    ) -> Self {
+        self.schedule = LevelSchedule::new(schedule.into_iter().collect());
+        self
     }
-}
 
-impl Writer for UdpBufferedWriter {
-    fn push(&self, message: String) -> io::Result<()> {
-        self.messages.lock().unwrap().push_back(message);
+    /// Buffer messages and send them from a background thread
+    ///
+    /// If the thread dies (e.g. a custom queue panics), queued messages are sent from
+    /// the logging thread instead, counted once in `StatsSnapshot::drain_failures`
+    pub fn buffered(mut self) -> Self {
+        self.kind = WriterKind::Buffered;
+        self
+    }
+
+    /// Buffer messages until `UdpLogger::drain` is called, without a background thread
+    ///
+    /// This suits single-threaded or event-driven programs that want to schedule sends
+    /// from their own loop. Queue, batching, and pacing options apply, heartbeats don't.
+    pub fn manual(mut self) -> Self {
+        self.kind = WriterKind::Manual;
+        self
+    }
+
+    /// Send messages over a TCP connection instead of as UDP datagrams, for collectors
+    /// that only accept TCP
+    ///
+    /// TCP is a stream, so each message is framed with its length as a 4-byte big-endian
+    /// integer. The connection is opened by `build` (an unreachable collector is an
+    /// error) and messages are written inline, so a slow collector slows logging down
+    /// (see `with_send_timeout`). Unlike UDP, messages arrive in order and aren't
+    /// silently lost in the network, but a write only means the kernel accepted the
+    /// bytes: messages written just before the connection breaks can be lost. A failed
+    /// write reconnects (counted in `StatsSnapshot::rebinds`) and resends that message
+    /// once, so the collector may see it twice. Queue and batching options don't apply.
+    /// ```
+    /// # use std::net::TcpListener;
+    /// use udp_logger::UdpLoggerBuilder;
+    ///
+    /// # let collector = TcpListener::bind("127.0.0.1:0").unwrap();
+    /// # let addr = collector.local_addr().unwrap();
+    /// let logger = UdpLoggerBuilder::new(addr).tcp().build().unwrap();
+    /// ```
+    pub fn tcp(mut self) -> Self {
+        self.kind = WriterKind::Tcp;
+        self
+    }
+
+    /// Send length-prefixed messages to a local collector over the Windows named pipe
+    /// `name` (e.g. `collector` for `\\.\pipe\collector`) instead of the destination
+    ///
+    /// The collector must create the pipe before the logger is built. Messages are
+    /// framed like `tcp`, since pipes are byte streams too, and a failed write reopens
+    /// the pipe (counted in `StatsSnapshot::rebinds`) and resends that message once.
+    /// Other destinations are still sent to over UDP, and queue and batching options
+    /// don't apply.
+    #[cfg(windows)]
+    pub fn pipe(mut self, name: &str) -> Self {
+        self.kind = WriterKind::Pipe(pipe::pipe_path(name));
+        self
+    }
+
+    /// Send each message as a datagram to a local collector's Unix socket `name` instead
+    /// of the destination
+    ///
+    /// A `name` starting with `@` or `\0` is in Linux's abstract namespace (e.g.
+    /// `@collector`), which leaves no file behind; any other is a filesystem path. The
+    /// collector must be bound before messages are sent. Other destinations are still
+    /// sent to over UDP, and queue and batching options don't apply.
+    #[cfg(target_os = "linux")]
+    pub fn unix_datagram(mut self, name: &str) -> Self {
+        self.kind = WriterKind::UnixDatagram(name.to_string());
+        self
+    }
+
+    /// Buffer messages and send them from a task on the `async-std` runtime
+    ///
+    /// The task is spawned on async-std's global executor, which starts on demand
+    #[cfg(feature = "async-std")]
+    pub fn async_std(mut self) -> Self {
+        self.kind = WriterKind::AsyncStd;
+        self
+    }
+
+    /// Send a small keepalive datagram whenever nothing has been sent for `interval`,
+    /// so receivers can tell a quiet sender from a dead one
+    ///
+    /// Heartbeats are sent by the drain thread, so this only applies to buffered loggers
+    pub fn with_heartbeat(mut self, interval: Duration) -> Self {
+        self.options.heartbeat = Some(interval);
+        self
+    }
+
+    /// Send the logger's own stats every `interval`, for monitoring the logger itself
+    ///
+    /// Each report is a datagram like
+    /// `UDP_LOGGER_METRICS [timestamp] sent=10 dropped=0 errors=0 queue_depth=2`, tagged
+    /// so it can be filtered downstream. Reports are sent by the drain thread of a
+    /// buffered logger, or after a `manual` logger's `drain` once `interval` has passed,
+    /// so this only applies to loggers that queue. Each report counts towards `sent`.
+    pub fn with_self_metrics(mut self, interval: Duration) -> Self {
+        self.options.self_metrics = Some(interval);
+        self
+    }
+
+    /// Coalesce queued messages into datagrams of up to `max_bytes` (buffered only)
+    ///
+    /// A message is never split across datagrams: one that doesn't fit starts the next
+    /// datagram, and one larger than `max_bytes` on its own is sent alone, unsplit.
+    /// GELF receivers read one record per datagram, so `build` fails with
+    /// `InvalidInput` if this is combined with `Format::Gelf`.
+    pub fn with_batching(mut self, max_bytes: usize) -> Self {
+        self.options.batch_size = Some(max_bytes);
+        self
+    }
+
+    /// Drop the newline after the last record of each batched datagram (default == false)
+    ///
+    /// Records are still separated by newlines, but the datagram doesn't end with one,
+    /// for parsers that read a trailing newline as an empty record. Only applies with
+    /// `with_batching`, and `JsonFraming::Array` datagrams never end with one.
+    pub fn with_strip_trailing_newline(mut self, strip: bool) -> Self {
+        self.options.strip_trailing_newline = strip;
+        self
+    }
+
+    /// Send consecutive batched records with the same target as one line, with the target
+    /// only once, e.g. `my_app::db: [INFO opened, INFO migrated]` (default == false)
+    ///
+    /// This saves bytes for bursts of logging from one module. Each record loses its
+    /// trailing newline inside the brackets, and a record with a different target (or
+    /// with none, like `send_raw` messages) ends the group, since batching never
+    /// reorders the queue. Only applies with `with_batching`, where the whole group
+    /// counts towards `max_bytes`, and to line formats (`Text`, `Raw`, templates, and
+    /// custom formatters): structured records are batched ungrouped, so they stay valid.
+    /// It makes most sense with `Text`, which doesn't include the target itself.
+    pub fn with_grouped_batches(mut self, grouped: bool) -> Self {
+        self.options.group_by_target = grouped;
+        self
+    }
+
+    /// Send each batched datagram's timestamp once, in a header line, with each record
+    /// offset from it in microseconds (default == false)
+    ///
+    /// A datagram looks like `@2020-06-15T03:15:39.000000Z\n+0 INFO a\n+1500 INFO b\n`,
+    /// so dense batches don't repeat a full timestamp per line. A line without an
+    /// offset, like a `send_raw` message, has no time of its own. Only applies with
+    /// `with_batching` on a buffered logger and not with `with_grouped_batches`, and
+    /// only to line formats (`Text`, `Raw`, a template, or a custom formatter): an offset
+    /// in front of a JSON or GELF object would break its parsing, so structured formats
+    /// keep their own timestamps. Otherwise every line keeps its `with_timestamp_format`
+    /// timestamp.
+    pub fn with_batch_timestamps(mut self, enabled: bool) -> Self {
+        self.options.batch_timestamps = enabled;
+        self
+    }
+
+    /// Send runs of `segment_size`-byte messages with one syscall using UDP GSO (Linux, buffered only)
+    ///
+    /// The kernel splits each send into separate `segment_size`-byte datagrams, so
+    /// receivers see exactly what they would otherwise, from far fewer `sendmsg` calls.
+    /// A run ends after the first message of another size (which may be shorter), so this
+    /// pays off with fixed-size records. Batching takes precedence when both are set,
+    /// encrypted datagrams are sent one at a time, and sends fall back to one datagram
+    /// per syscall if the kernel doesn't support GSO (before Linux 4.18).
+    #[cfg(target_os = "linux")]
+    pub fn with_gso(mut self, segment_size: u16) -> Self {
+        self.options.gso = Some(segment_size);
+        self
+    }
+
+    /// Coalesce at most `count` messages into each batched datagram (buffered only)
+    ///
+    /// Applies alongside `with_batching`, whichever limit is reached first ends the
+    /// datagram. Without `with_batching` every message is already sent on its own.
+    pub fn with_max_batch_count(mut self, count: usize) -> Self {
+        self.options.max_batch_count = Some(count);
+        self
+    }
+
+    /// Replace the drain thread with a new one every `lifetime` (buffered only)
+    ///
+    /// A guard against slow leaks in a long-running thread (e.g. thread-local state in a
+    /// custom queue). Between drains the thread spawns its successor, which takes over
+    /// the queue and the rest of the drain's state, then exits, so nothing queued is
+    /// lost or reordered.
+    pub fn with_thread_recycle(mut self, lifetime: Duration) -> Self {
+        self.options.thread_recycle = Some(lifetime);
+        self
+    }
+
+    /// Wait at least `gap` between consecutive datagrams sent by the drain thread (buffered only)
+    ///
+    /// Pacing smooths out micro-bursts by spacing every send evenly, whereas rate
+    /// limiting caps the total number of sends over a period but still allows bursts
+    pub fn with_pacing(mut self, gap: Duration) -> Self {
+        self.options.pacing = Some(gap);
+        self
+    }
+
+    /// Send at most `count` datagrams each time the queue is drained, leaving the rest
+    /// for the next drain (buffered only)
+    ///
+    /// Without this a single wake sends the whole backlog as a burst of syscalls. With
+    /// it, a backlog is spread over the drains the thread makes every 50ms (or each
+    /// second with `with_aligned_flush`), so this also caps the sustained send rate;
+    /// pacing still spaces the sends within a drain. A GSO send counts once, and the
+    /// cap is lifted to flush the queue on shutdown.
+    pub fn with_max_sends_per_flush(mut self, count: usize) -> Self {
+        self.options.max_sends_per_flush = Some(count);
+        self
+    }
+
+    /// Guarantee that datagrams are sent in the order messages were logged (implies `buffered`)
+    ///
+    /// Every message then goes through the buffered writer's single FIFO queue and drain
+    /// thread. Batching, pacing, and priority eviction never reorder the queue, though
+    /// evicted messages are still dropped. A `manual` logger's single drain keeps the
+    /// order too. Choosing any other writer (e.g. `tcp` or `async_std`) after this, or
+    /// `with_requeue`, which sends failed messages after those queued behind them, makes
+    /// `build` fail with `InvalidInput` rather than silently dropping the guarantee.
+    pub fn with_ordered(mut self, enabled: bool) -> Self {
+        self.ordered = enabled;
+        if enabled {
+            self.kind = WriterKind::Buffered;
+        }
+        self
+    }
+
+    /// Connect each UDP writer's socket to its destination, so errors reported back by
+    /// ICMP (e.g. `ConnectionRefused` from a closed port) surface as send errors
+    ///
+    /// The error from a datagram arrives after it was sent, so it fails a later send
+    /// instead, counted in `StatsSnapshot::errors` and `UdpLogger::destinations`. The
+    /// connection is kept when the socket is replaced by `UdpLogger::reconnect`.
+    pub fn with_connected_socket(mut self, connected: bool) -> Self {
+        self.socket.connected = connected;
+        self
+    }
+
+    /// Put the socket in non-blocking mode so `send_to` can never stall the caller
+    ///
+    /// A send that would block is treated as transient congestion, like a full OS send
+    /// buffer (`ENOBUFS`) with any socket: the unbuffered writer drops the message
+    /// immediately, while the drain thread retries it after pauses of 1, 2, 4, 8, and
+    /// 16ms (counted in `StatsSnapshot::congestion_pauses`) before dropping it. Either
+    /// way a dropped message is counted in `StatsSnapshot::dropped`.
+    pub fn with_nonblocking_socket(mut self, nonblocking: bool) -> Self {
+        self.socket.nonblocking = nonblocking;
+        self
+    }
+
+    /// Give up on a send that blocks for longer than `timeout` (`SO_SNDTIMEO`)
+    ///
+    /// A timed-out send is handled like congestion on a non-blocking socket: the
+    /// unbuffered writer drops the message, and the drain thread retries briefly before
+    /// dropping it. This only matters for a blocking socket, since a non-blocking send
+    /// never waits. A zero `timeout` is rejected by `build`.
+    pub fn with_send_timeout(mut self, timeout: Duration) -> Self {
+        self.socket.send_timeout = Some(timeout);
+        self
+    }
+
+    /// Mark every datagram with the DSCP `dscp` (0-63), e.g. `8` (CS1) for low-priority traffic
+    ///
+    /// This sets `IP_TOS`, or `IPV6_TCLASS` for a `with_dual_stack` socket, to the DSCP
+    /// with the ECN bits clear, so routers that honor it can let log traffic yield to
+    /// production traffic. A DSCP over 63 is rejected by `build`.
+    pub fn with_dscp(mut self, dscp: u8) -> Self {
+        self.socket.dscp = Some(dscp);
+        self
+    }
+
+    /// Check that every unbuffered send put the whole datagram on the wire
+    ///
+    /// `send_to` reports how many bytes it sent, and a UDP datagram is normally sent
+    /// whole or not at all. With this set, a short send is retried with the whole
+    /// datagram a couple of times, then handled like any other send error and counted
+    /// in `StatsSnapshot::errors`. This only applies to the unbuffered writer.
+    pub fn with_confirm_send(mut self, confirm: bool) -> Self {
+        self.socket.confirm_send = confirm;
+        self
+    }
+
+    /// Skip sending a datagram that's byte-for-byte identical to the one sent just
+    /// before it, less than a second earlier (default == false)
+    ///
+    /// Unlike throttling, this looks at the finished payload, so it catches an exact
+    /// repeat from any source, e.g. a retry of a send that already went through. Only a
+    /// hash of the last datagram is kept, and it's compared before encryption. A run of
+    /// identical datagrams is still sent once a second, and suppressed ones are counted
+    /// in `StatsSnapshot::suppressed`. This applies to the UDP writers, each comparing
+    /// against its own last datagram.
+    pub fn with_suppress_identical_consecutive(mut self, suppress: bool) -> Self {
+        self.socket.suppress_identical = suppress;
+        self
+    }
+
+    /// Call `callback` with the bytes of every datagram after it's been sent
+    ///
+    /// The bytes are exactly what was put on the wire, including any batching,
+    /// header, or encryption, and heartbeats and other datagrams sent by the drain
+    /// thread. It's called on the sending thread, so it should be quick. Failed
+    /// sends aren't reported to it.
+    pub fn with_sent_callback(mut self, callback: Box<SentCallback>) -> Self {
+        self.socket.on_sent = Some(SentFn(Arc::from(callback)));
+        self
+    }
+
+    /// Call `listener` with each `LoggerEvent`, as the logger starts, pauses and
+    /// resumes, replaces its drain thread, and shuts down
+    ///
+    /// `Shutdown` is emitted when the logger is dropped, once its writers have flushed,
+    /// and before any `with_shutdown_hook`. `ThreadRespawned` is emitted from the drain
+    /// thread being replaced, the others from the thread causing them, so `listener`
+    /// should be quick and must not log through this logger.
+    /// ```
+    /// use udp_logger::{LoggerEvent, UdpLoggerBuilder};
+    ///
+    /// let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+    ///     .with_event_listener(Box::new(|event| {
+    ///         if event == LoggerEvent::Shutdown {
+    ///             eprintln!("logger shut down");
+    ///         }
+    ///     }))
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn with_event_listener(mut self, listener: Box<EventCallback>) -> Self {
+        self.options.events = Events::new(listener);
+        self
+    }
+
+    /// Prepend `header` to every datagram, e.g. `V1;utf-8;` for receivers that need an encoding marker
+    ///
+    /// The header comes before the whole datagram, so a batch of records or a
+    /// JSON array gets it once, and it's encrypted along with the datagram under
+    /// `with_encryption`.
+    pub fn with_payload_header(mut self, header: &str) -> Self {
+        self.socket.header = Some(header.as_bytes().to_vec());
+        self
+    }
+
+    /// Start each record with a fixed 16-byte binary header, for bandwidth-minimal
+    /// receivers that parse it instead of the text
+    ///
+    /// Multi-byte fields are big-endian (network byte order):
+    ///
+    /// | Offset | Size | Field                                                          |
+    /// |--------|------|----------------------------------------------------------------|
+    /// | 0      | 1    | Level: 1 = Error, 2 = Warn, 3 = Info, 4 = Debug, 5 = Trace     |
+    /// | 1      | 1    | Flags: bit 0 is set when the message is over 65535 bytes       |
+    /// | 2      | 2    | Length of the message in bytes, 65535 if it's longer           |
+    /// | 4      | 8    | When the record was formatted, in milliseconds since the epoch |
+    /// | 12     | 4    | Sequence number, counting from 0 and wrapping after `u32::MAX` |
+    ///
+    /// The other flag bits are reserved and zero. The UTF-8 message follows at offset
+    /// 16, formatted as usual, so pair this with `TimestampFormat::None` to avoid
+    /// sending the time twice. Each record in a batched datagram has its own header,
+    /// so the length finds the next one. `with_transform` is given the header along
+    /// with the message, and a `with_payload_header` header comes before it.
+    pub fn with_binary_header(mut self, enabled: bool) -> Self {
+        self.binary_header = enabled;
+        self
+    }
+
+    /// Compress datagrams larger than `bytes`, sending smaller ones as they are
+    ///
+    /// Compressing a short message costs more than it saves, so only datagrams (a
+    /// single record or a whole batch) over the threshold are compressed, as an LZ4
+    /// block, and only if that makes them smaller. Every datagram then starts with a
+    /// flag byte, 1 if it's compressed and 0 if not, and receivers can read either with
+    /// `udp_logger::decompress`. A `with_payload_header` header is compressed along with
+    /// the datagram, before `with_encryption`, and `with_magic_prefix` stays uncompressed.
+    #[cfg(feature = "compression")]
+    pub fn with_compression_threshold(mut self, bytes: usize) -> Self {
+        self.socket.compression_threshold = Some(bytes);
+        self
+    }
+
+    /// Prepend the `magic` bytes to every datagram, e.g. to pick out log datagrams on a
+    /// port shared with other protocols
+    ///
+    /// Receivers must strip the prefix before parsing the rest. It comes before
+    /// everything else in the datagram, including a `with_payload_header` header, and
+    /// isn't encrypted under `with_encryption`, so strip it before decrypting.
+    pub fn with_magic_prefix(mut self, magic: Vec<u8>) -> Self {
+        self.socket.magic = Some(magic);
+        self
+    }
+
+    /// Append a `checksum` of every datagram as a fixed-length trailer, so receivers can
+    /// detect corruption with `udp_logger::verify_checksum`
+    ///
+    /// The trailer is added last, after `with_encryption` and `with_magic_prefix`, and
+    /// covers every byte before it: for `Checksum::Crc32`, the final 4 bytes are the
+    /// big-endian CRC-32 of the rest. This only detects accidental damage; anyone can
+    /// recompute it, so use `with_encryption` to detect tampering.
+    pub fn with_checksum(mut self, checksum: Checksum) -> Self {
+        self.socket.checksum = Some(checksum);
+        self
+    }
+
+    /// Send from one IPv6 socket (`[::]:0`) that reaches both IPv6 and IPv4 destinations
+    ///
+    /// `IPV6_V6ONLY` is cleared before binding, since the default differs by platform
+    /// (off on Linux and macOS, on for Windows). IPv4 destinations are sent to as
+    /// IPv4-mapped addresses (`::ffff:a.b.c.d`). OpenBSD doesn't support dual-stack
+    /// sockets, so building the logger fails there.
+    pub fn with_dual_stack(mut self, enabled: bool) -> Self {
+        self.socket.dual_stack = enabled;
+        self
+    }
+
+    /// Send multicast datagrams from the IPv4 address of the named interface (e.g. `eth1`)
+    ///
+    /// The name is resolved when the logger is built, which fails with `NotFound` if the
+    /// interface doesn't exist or has no IPv4 address
+    #[cfg(unix)]
+    pub fn with_multicast_interface_name(mut self, name: &str) -> Self {
+        self.socket.multicast_interface = Some(name.to_string());
+        self
+    }
+
+    /// Buffer messages in a custom `MessageQueue` instead of the default bounded FIFO
+    ///
+    /// The queue decides what to drop when full, so `with_queue_limit` and
+    /// `with_drop_policy` don't apply to it. Extra destinations keep the default queue.
+    pub fn with_queue(mut self, queue: Box<dyn MessageQueue>) -> Self {
+        self.queue = Some(queue);
+        self
+    }
+
+    /// Spill messages that overflow the `with_queue_limit` queue to a file instead of
+    /// dropping them, replaying them as the queue drains (buffered only)
+    ///
+    /// The file is truncated when the logger is built, and holds at most `max_bytes`;
+    /// messages beyond that are dropped. Priorities and the `DropPolicy` don't apply
+    /// while spilling, since order is kept by spilling everything behind the first
+    /// spilled message, and replayed messages keep the time they were queued for
+    /// `with_ttl`. Spilled and replayed messages are counted in `stats()`. `build` fails
+    /// with `InvalidInput` without `with_queue_limit`, since there's nothing to spill
+    /// beyond.
+    pub fn with_spill(mut self, path: impl Into<PathBuf>, max_bytes: u64) -> Self {
+        self.spill = Some((path.into(), max_bytes));
+        self
+    }
+
+    /// Encrypt every datagram with ChaCha20-Poly1305 under the pre-shared `key`
+    ///
+    /// Each datagram on the wire is `nonce (12 bytes) || ciphertext || tag (16 bytes)`,
+    /// and receivers can open it with `udp_logger::decrypt`. Nonces are random, so keep
+    /// well under 2^32 datagrams per key: a repeated nonce under the same key breaks
+    /// confidentiality for both datagrams. Rotate keys for long-lived, high-volume senders.
+    #[cfg(feature = "encryption")]
+    pub fn with_encryption(mut self, key: [u8; 32]) -> Self {
+        self.socket.sealer = Some(Arc::new(encrypt::Sealer::new(&key)));
+        self
+    }
+
+    /// Fail loudly on misconfiguration instead of silently degrading, e.g. for CI and dev builds
+    ///
+    /// Strict mode makes `build` fail with `InvalidInput` when:
+    /// - The queue limit, queue byte limit, batch size, or batch count is zero
+    /// - `with_resolve_retry` is used without `with_resolver`, so nothing is retried
+    /// - Queue, batching, heartbeat, self-metrics, pacing, TTL, or drop notice options
+    ///   are set on a logger that doesn't queue (unbuffered or async-std), where they're
+    ///   ignored
+    ///
+    /// While logging, a record whose formatter produces an empty message is still
+    /// skipped, but is also reported through `UdpLogger::last_error`.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Bound the buffered queue to `limit` messages, dropping per the `DropPolicy` when full
+    pub fn with_queue_limit(mut self, limit: usize) -> Self {
+        self.options.queue_limit = Some(limit);
+        self
+    }
+
+    /// Bound the buffered queue to `max_bytes` of queued payloads
+    ///
+    /// Bounds memory when message sizes vary widely. Independent of `with_queue_limit`:
+    /// whichever limit a new message would exceed first evicts per the `DropPolicy`,
+    /// possibly several smaller messages for a large one. A message larger than
+    /// `max_bytes` is dropped. Each dropped message is counted in
+    /// `StatsSnapshot::dropped`. Has no effect with `with_queue` or `with_spill`.
+    pub fn with_max_queue_bytes(mut self, max_bytes: usize) -> Self {
+        self.options.max_queue_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Pre-allocate the buffered queue with room for `capacity` messages
+    ///
+    /// Avoids reallocating (while holding the queue lock) as bursts grow the queue.
+    /// The capacity never exceeds the queue limit, and has no effect with `with_queue`.
+    pub fn with_queue_capacity(mut self, capacity: usize) -> Self {
+        self.options.queue_capacity = capacity;
+        self
+    }
+
+    /// Discard queued messages older than `ttl` instead of sending them (buffered only)
+    ///
+    /// Expiry is checked when the queue is drained, so after an outage fresh messages are
+    /// sent rather than a stale backlog. Expired messages are counted in
+    /// `StatsSnapshot::expired`.
+    pub fn with_message_ttl(mut self, ttl: Duration) -> Self {
+        self.options.ttl = Some(ttl);
+        self
+    }
+
+    /// Discard queued records at `level` older than `ttl`, in place of the
+    /// `with_message_ttl` TTL (buffered only)
+    ///
+    /// Once any level has its own TTL, `Error` records never expire unless given one
+    /// too, so critical records survive a backlog that low-priority ones expire from.
+    /// Other levels, and messages that aren't records (like `send_raw` ones), keep the
+    /// `with_message_ttl` TTL, if any. Expiry then checks the whole queue on each drain
+    /// rather than only its oldest messages.
+    pub fn with_level_ttl(mut self, level: Level, ttl: Duration) -> Self {
+        self.options.level_ttls.retain(|(other, _)| *other != level);
+        self.options.level_ttls.push((level, ttl));
+        self
+    }
+
+    /// Put messages back at the end of the queue when their send fails, up to
+    /// `max_requeues` times each, instead of dropping them (buffered only)
+    ///
+    /// This smooths over brief outages: a failed send ends the drain pass, and the
+    /// message is tried again on a later one, after the messages queued before the
+    /// failure (so requeued messages can arrive out of order, and `build` fails with
+    /// `InvalidInput` alongside `with_ordered`). Each failed attempt is still counted
+    /// in `StatsSnapshot::errors` (or `dropped` for a congested non-blocking socket),
+    /// and each requeue in `StatsSnapshot::requeued`. Requeued messages count towards
+    /// the queue limit, and still expire per `with_message_ttl`. When a GSO send fails
+    /// part way, only the segments that failed are requeued.
+    pub fn with_requeue(mut self, max_requeues: u32) -> Self {
+        self.options.max_requeues = Some(max_requeues);
+        self
+    }
+
+    /// Send a synthetic `level` message whenever messages were dropped (buffered only)
+    ///
+    /// `{count}` and `{interval}` in `template` are replaced with the number of drops and
+    /// the notice interval, e.g. `"dropped {count} messages in {interval}"`. At most one
+    /// notice is sent per interval (default == 10s).
+    pub fn with_drop_notice(mut self, level: Level, template: impl Into<String>) -> Self {
+        let notice = self
+            .options
+            .drop_notice
+            .get_or_insert_with(DropNotice::default);
+        notice.level = level;
+        notice.template = template.into();
+        self
+    }
+
+    /// Send drop notices at most once per `interval`, enabling the default WARN notice
+    /// if `with_drop_notice` wasn't used (buffered only)
+    pub fn with_drop_notice_interval(mut self, interval: Duration) -> Self {
+        self.options
+            .drop_notice
+            .get_or_insert_with(DropNotice::default)
+            .interval = interval;
+        self
+    }
+
+    /// Report drops to `destination` every `interval`, separately from the logs
+    ///
+    /// Each report is a datagram like
+    /// `UDP_LOGGER_DROPS [timestamp] queue_full=3 ttl_expired=0 send_error=1 interval=10s`
+    /// counting the messages lost since the previous report: evicted from a full queue,
+    /// expired by `with_message_ttl`, or failed to send. Reports are only sent when
+    /// something was lost, plus a final one when the logger is dropped, so alerting can
+    /// watch this stream without parsing every log. A background thread sends them, so
+    /// this works with every writer, and a zero `interval` is rejected by `build`.
+    pub fn with_drop_reporting(
+        mut self,
+        destination: impl ToSocketAddrs + Debug,
+        interval: Duration,
+    ) -> Self {
+        self.drop_reporting = Some((resolve(destination), interval));
+        self
+    }
+
+    /// Send queued messages on each wall-clock second boundary instead of every 50ms (buffered only)
+    ///
+    /// Each datagram then holds the records logged during the previous second, for
+    /// receivers that bucket by second. Records wait up to a second longer to be sent.
+    pub fn with_aligned_flush(mut self, aligned: bool) -> Self {
+        self.options.aligned_flush = aligned;
+        self
+    }
+
+    /// Send queued messages once nothing new has been queued for `idle`, without waiting
+    /// for the next interval (buffered only)
+    ///
+    /// A quiet logger's lone message then goes out after `idle`, while a busy one still
+    /// batches everything logged between intervals. Most useful with
+    /// `with_aligned_flush`; the drain checks a queue that's empty once per `idle`.
+    pub fn with_idle_flush(mut self, idle: Duration) -> Self {
+        self.options.idle_flush = Some(idle);
+        self
+    }
+
+    /// Send queued messages as soon as a record at or above `level` is logged (buffered only)
+    ///
+    /// The drain thread is woken right after the record is queued, rather than at the
+    /// next interval, so e.g. an Error isn't held back by `with_aligned_flush`. Logging
+    /// doesn't wait for the send. Everything queued before the record goes with it.
+    pub fn with_flush_on_level(mut self, level: Level) -> Self {
+        self.flush_on_level = Some(level);
+        self
+    }
+
+    /// Whether dropping a buffered logger blocks until queued messages are sent (default == true)
+    ///
+    /// Disable this for a fast exit, e.g. when crashing, at the cost of losing whatever
+    /// is still queued. Either way the drain thread is stopped.
+    pub fn with_flush_on_drop(mut self, flush: bool) -> Self {
+        self.options.flush_on_drop = flush;
+        self
+    }
+
+    /// Give up flushing on drop after `timeout`, rather than waiting for the queue to drain
+    ///
+    /// With `with_flush_on_drop`, dropping a buffered logger waits for every queued
+    /// message to be sent, which can hang exit if sends stall. Past `timeout` the drain
+    /// thread is told to stop, left to finish the send it's stuck in, and the number of
+    /// messages left unsent is printed to stderr. Without this, drop waits indefinitely.
+    pub fn with_drop_flush_timeout(mut self, timeout: Duration) -> Self {
+        self.options.drop_flush_timeout = Some(timeout);
+        self
+    }
+
+    /// Send a summary of the session when the logger is dropped, as an end-of-session marker
+    ///
+    /// The summary is a datagram like `UDP_LOGGER_SUMMARY [timestamp] sent=10 dropped=0
+    /// errors=0 pending=2 duration=1.5s error=1 warn=0 info=9 debug=0 trace=0`, where
+    /// `pending` counts messages still queued behind it and the levels count records
+    /// logged. It's queued at the highest priority before the writers shut down, so a
+    /// buffered logger only sends it if `with_flush_on_drop` is enabled.
+    pub fn with_shutdown_summary(mut self, enabled: bool) -> Self {
+        self.shutdown_summary = enabled;
+        self
+    }
+
+    /// Send a handshake with the binary's build metadata when the logger is built, so the
+    /// collector knows exactly which build is talking
+    ///
+    /// The handshake is a datagram like `UDP_LOGGER_HANDSHAKE [timestamp] version=1.4.2
+    /// commit=3f9c2ab app=myapp run_id=00c0ffee00c0ffee`, where `commit` is left out when
+    /// it's `None`, and `app` and `run_id` are only included when enabled. It's queued at
+    /// the highest priority before any record. Both values usually come from the build:
+    /// ```
+    /// # use udp_logger::UdpLoggerBuilder;
+    /// let builder = UdpLoggerBuilder::new("127.0.0.1:1999")
+    ///     .with_build_info(env!("CARGO_PKG_VERSION"), option_env!("GIT_SHA"));
+    /// ```
+    pub fn with_build_info(mut self, version: impl Into<String>, commit: Option<&str>) -> Self {
+        self.build_info = Some((version.into(), commit.map(String::from)));
+        self
+    }
+
+    /// Run `hook` once when the logger is dropped, after the final flush
+    ///
+    /// Every writer is shut down first, so for a buffered logger the hook runs after the
+    /// queue is drained (per `with_flush_on_drop`) and the drain thread has exited. A
+    /// logger installed with `init` is never dropped, so its hook never runs.
+    pub fn with_shutdown_hook(mut self, hook: Box<dyn FnOnce() + Send>) -> Self {
+        self.shutdown_hook = Some(hook);
+        self
+    }
+
+    /// Choose which message is dropped when the queue limit is reached (default == DropOldest)
+    pub fn with_drop_policy(mut self, policy: DropPolicy) -> Self {
+        self.options.drop_policy = policy;
+        self
+    }
+
+    /// Override the queue priority of each record, higher values are more important
+    ///
+    /// By default priority is derived from the level (Error == 5, Trace == 1). When
+    /// the queue is full, the lowest-priority messages are always dropped first.
+    pub fn with_priority_fn(
+        mut self,
+        priority: impl Fn(&Record) -> u8 + Send + Sync + 'static,
+    ) -> Self {
+        self.priority = Some(Box::new(priority));
+        self
+    }
+
+    /// Choose how each record is encoded (default == Text)
+    pub fn with_format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Encode each record as a CloudWatch Logs style event, e.g.
+    /// `{"timestamp":1592190939500,"message":"WARN app=relay disk full"}`
+    ///
+    /// A preset of `Format::CloudWatch`: the timestamp is epoch milliseconds, the message
+    /// is the `Text` line without its timestamp, and batched events are newline-delimited.
+    /// With `TimestampFormat::None` the `timestamp` key is left out.
+    pub fn with_cloudwatch(self) -> Self {
+        self.with_format(Format::CloudWatch)
+    }
+
+    /// Encode each record as a JSON object with `timestamp`, `level`, `target`, and `message`
+    pub fn with_json(self) -> Self {
+        self.with_format(Format::Json)
+    }
+
+    /// Encode each record as JSON using the given field names instead of the defaults
+    pub fn with_json_keys(mut self, keys: JsonKeys) -> Self {
+        self.json_keys = keys;
+        self.with_json()
+    }
+
+    /// Render each record with a custom closure instead of the built-in `Format`
+    ///
+    /// The returned string is sent as-is, so include a trailing newline if the receiver
+    /// expects one. Records that format to an empty string are skipped and counted in
+    /// `StatsSnapshot::empty`. A panic in `formatter` is caught rather than unwinding
+    /// into the logging call, see `with_formatter_panic_policy`.
+    pub fn with_formatter(
+        mut self,
+        formatter: impl Fn(&Record) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.formatter = Some(Box::new(formatter));
+        self
+    }
+
+    /// Render records with `verbose`, switching to `compact` while more than `threshold`
+    /// records per second are logged, e.g. from verbose JSON to a short text line
+    ///
+    /// The rate is counted over one-second windows: the record that exceeds the
+    /// threshold and the rest of that second are compact, as is the next second, and
+    /// `verbose` resumes once a second stays under the threshold. Like `with_formatter`,
+    /// this replaces the built-in `Format`.
+    /// ```
+    /// use udp_logger::UdpLoggerBuilder;
+    ///
+    /// let builder = UdpLoggerBuilder::new("127.0.0.1:1999").with_adaptive_format(
+    ///     1000,
+    ///     |r| format!("{} [{}] {}\n", r.level(), r.target(), r.args()),
+    ///     |r| format!("{}\n", r.args()),
+    /// );
+    /// ```
+    pub fn with_adaptive_format(
+        self,
+        threshold: u32,
+        verbose: impl Fn(&Record) -> String + Send + Sync + 'static,
+        compact: impl Fn(&Record) -> String + Send + Sync + 'static,
+    ) -> Self {
+        let meter = RateMeter::new(threshold, Instant::now());
+        self.with_formatter(move |record| match meter.record(Instant::now()) {
+            true => compact(record),
+            false => verbose(record),
+        })
+    }
+
+    /// Share messages between the destination and `destination`, sending each message to
+    /// one of them in proportion to their weights
+    ///
+    /// The builder's own destination has a weight of 1, so after
+    /// `add_weighted_destination(big, 3)` three in four messages go to `big`. Messages
+    /// are spread by smooth weighted round-robin (with `3`: `big big dest big`), so each
+    /// share is exact over every cycle of the total weight, rather than random.
+    /// Each destination gets a writer of the configured kind and options, with its own
+    /// queue, and a weight of zero is an `InvalidInput` error from `build`. This only
+    /// applies to the primary writer, not to `add_destination_with_formatter`.
+    pub fn add_weighted_destination(
+        mut self,
+        destination: impl ToSocketAddrs + Debug,
+        weight: u32,
+    ) -> Self {
+        self.weighted.push((resolve(destination), weight));
+        self
+    }
+
+    /// Also send every record to `destination`, rendered with its own `formatter`
+    ///
+    /// Extra destinations use the same writer mode and options as the primary one, so
+    /// one logger can e.g. send JSON to a collector and text to a human-readable sink.
+    /// `UdpLogger::send_raw` only sends to the primary destination. For destinations
+    /// that also need their own framing, transform, encryption, or rate limit, make each
+    /// an output of a `MultiOutputBuilder` instead.
+    pub fn add_destination_with_formatter(
+        mut self,
+        destination: impl ToSocketAddrs + Debug,
+        formatter: impl Fn(&Record) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.destinations
+            .push((resolve(destination), Box::new(formatter)));
+        self
+    }
+
+    /// Also append every message to a local file at `path`, for retention without the network
+    ///
+    /// The file gets the same bytes as the primary destination. Before it would exceed
+    /// `max_size` bytes it's rotated to `path.1` (shifting older files up to
+    /// `path.{max_files}`, dropping the oldest). Writes happen inline, even for a
+    /// buffered logger, and failures are reported through `UdpLogger::last_error`.
+    pub fn with_file(mut self, path: impl Into<PathBuf>, max_size: u64, max_files: usize) -> Self {
+        self.files.push((path.into(), max_size, max_files));
+        self
+    }
+
+    /// Also send every record to journald over its native protocol socket
+    /// (`/run/systemd/journal/socket`), for systemd hosts
+    ///
+    /// Records are encoded as journald fields rather than with the logger's format:
+    /// `MESSAGE`, `PRIORITY` (the syslog severity of the level), `TARGET`, the
+    /// `CODE_MODULE`, `CODE_FILE`, and `CODE_LINE` when known, `SYSLOG_IDENTIFIER` from
+    /// `with_app_name`, and any other fields (e.g. `run_id`, context) upper-cased.
+    /// Multiline messages use the protocol's length-prefixed framing. Sends happen
+    /// inline, even for a buffered logger, and failures (including a message too large
+    /// for one datagram) are reported through `UdpLogger::last_error`.
+    #[cfg(target_os = "linux")]
+    pub fn with_journald_native(mut self) -> Self {
+        self.journald = Some(PathBuf::from(journald::JOURNALD_SOCKET));
+        self
+    }
+
+    /// Encode each record as a MessagePack Fluentd forward event `[tag, time, record]`
+    ///
+    /// Fluentd's forward input listens on TCP, so this targets setups that accept the
+    /// event format over UDP (e.g. a relay or a UDP-enabled input plugin). Syslog
+    /// prefixes aren't applied to these binary payloads.
+    #[cfg(feature = "msgpack")]
+    pub fn with_fluentd(mut self, tag: impl Into<String>) -> Self {
+        self.fluentd_tag = Some(tag.into());
+        self
+    }
+
+    /// Encode each record as the protobuf `LogRecord` in `proto/log_record.proto`
+    ///
+    /// Decode payloads with `udp_logger::LogRecord`. Like `with_fluentd`, syslog
+    /// prefixes and envelopes aren't applied to these binary payloads.
+    #[cfg(feature = "prost")]
+    pub fn with_protobuf(mut self) -> Self {
+        self.protobuf = true;
+        self
+    }
+
+    /// Send records in the shape a [Vector](https://vector.dev) `socket` source with
+    /// `mode = "udp"` and the given `codec` decodes out of the box
+    ///
+    /// This is a preset of `with_json_keys` (the default keys, which match Vector's
+    /// `message` and `timestamp`) or the `Text` format, with newline-delimited framing
+    /// and every record's newline kept. Batched datagrams then need the source's
+    /// `framing.method = "newline_delimited"` to split into one event per record.
+    pub fn with_vector(mut self, codec: VectorCodec) -> Self {
+        self.options.strip_trailing_newline = false;
+        let builder = self.with_json_framing(JsonFraming::Lines);
+        match codec {
+            VectorCodec::Json => builder.with_json_keys(JsonKeys::default()),
+            VectorCodec::Bytes => builder.with_format(Format::Text),
+        }
+    }
+
+    /// Choose how batched JSON records are combined into a datagram (default == Lines)
+    ///
+    /// Framing only applies when batching is enabled; otherwise every datagram carries
+    /// a single JSON object
+    pub fn with_json_framing(mut self, framing: JsonFraming) -> Self {
+        self.json_framing = framing;
+        self
+    }
+
+    /// Include a random id (as `run_id`, 16 hex digits) generated once at construction
+    /// in every message, so receivers can group messages by process instance
+    ///
+    /// The id comes from `with_id_source` if one is given
+    pub fn with_run_id(mut self, enabled: bool) -> Self {
+        self.generate_run_id = enabled;
+        self.run_id = None;
+        self
+    }
+
+    /// Generate ids (e.g. the `with_run_id` id) with `source` instead of randomly
+    ///
+    /// Together with `with_clock`, this makes the output fully deterministic for tests
+    pub fn with_id_source(mut self, source: IdSource) -> Self {
+        self.id_source = Some(source);
+        self
+    }
+
+    /// Render each record from a `template` like `"{level} {ts:%H:%M:%S} {target} {msg}"`
+    /// instead of the format
+    ///
+    /// The tokens are `{level}` (per `with_level_label`), `{ts}` (RFC 3339 from the
+    /// logger's clock, or `{ts:spec}` with a strftime spec), `{target}`, `{module}`,
+    /// `{file}`, `{line}`, `{msg}`, and `{thread}` (the logging thread's name), and `{{`
+    /// and `}}` are literal braces. A record without a module, file, or line shows `?`.
+    /// The template is parsed once, and `build` fails with `InvalidInput` on an unknown
+    /// token or an invalid spec. A custom formatter takes precedence, extra fields
+    /// aren't included, and a newline ends each line.
+    pub fn with_template(mut self, template: &str) -> Self {
+        self.template = Some(Template::parse(template));
+        self
+    }
+
+    /// Embed each formatted line in a fixed `template`, e.g. `{"source":"app","payload":"{msg}"}`
+    ///
+    /// `{msg}` is the formatted line (text, JSON, or a custom formatter's output) without
+    /// its trailing newline, and `{level}`, `{target}`, and `{ts}` (per `with_timestamp_format`) come
+    /// from the record. Values aren't escaped. Date partitions and priorities are still prefixed
+    /// to the result.
+    pub fn with_envelope(mut self, template: impl Into<String>) -> Self {
+        self.envelope = Some(template.into());
+        self
+    }
+
+    /// Append a footer rendered from `template` to each message, e.g. ` crc={crc32}`
+    ///
+    /// The footer goes last, after any envelope and priority, and before the message's
+    /// newline unless `with_footer_position` says otherwise. `{crc32}` is the CRC-32
+    /// (IEEE) of the message before the footer, without its newline, as 8 lowercase hex
+    /// digits, so receivers can check it after stripping the footer. The fluentd and
+    /// protobuf encodings don't get a footer.
+    pub fn with_footer(mut self, template: impl Into<String>) -> Self {
+        self.footer = Some(template.into());
+        self
+    }
+
+    /// Rewrite each fully formatted message with `transform`, e.g. to redact PII
+    ///
+    /// The transform is the last step of formatting: it gets the bytes of each message
+    /// after any envelope, priority, and footer (and fluentd or protobuf encoding), and
+    /// what it returns is sent. It runs before messages are batched, and before the
+    /// `with_payload_header`, encryption, and `with_magic_prefix` are applied to the
+    /// datagram. Only logged records are transformed, not `send_raw` payloads.
+    /// ```
+    /// use udp_logger::UdpLoggerBuilder;
+    ///
+    /// let builder = UdpLoggerBuilder::new("127.0.0.1:1999")
+    ///     .with_transform(Box::new(|message| message.to_ascii_uppercase()));
+    /// ```
+    pub fn with_transform(mut self, transform: Transform) -> Self {
+        self.transform = Some(transform);
+        self
+    }
+
+    /// Choose whether the `with_footer` footer goes before or after the message's newline
+    /// (default == before)
+    pub fn with_footer_position(mut self, position: FooterPosition) -> Self {
+        self.footer_position = position;
+        self
+    }
+
+    /// Choose how record timestamps are rendered (default == RFC 3339)
+    ///
+    /// Applies to the text and JSON formats and the envelope `{ts}`. A JSON epoch
+    /// timestamp is still a string, e.g. `"1700000000.123456"`. `TimestampFormat::None`
+    /// leaves timestamps out and skips reading the clock, which is the cheapest option
+    /// when the receiver stamps arrivals itself.
+    pub fn with_timestamp_format(mut self, format: TimestampFormat) -> Self {
+        self.timestamp_format = format;
+        self
+    }
+
+    /// Choose how record levels are labeled (default == the name, e.g. `INFO`)
+    ///
+    /// Applies to the text and JSON formats and the envelope `{level}`, e.g.
+    /// `LevelLabel::LetterSeverity` for a compact `I6`. GELF always uses the numeric
+    /// severity.
+    pub fn with_level_label(mut self, label: LevelLabel) -> Self {
+        self.level_label = label;
+        self
+    }
+
+    /// Prefix each message with the index of the `window`-long bucket of time it was
+    /// logged in (e.g. `5897196 `), as a token for receivers that shard by time window
+    ///
+    /// The index is the record's time since the Unix epoch divided by `window`, so every
+    /// sender agrees on it without coordinating. It's the leading token of the message,
+    /// before any `with_date_partition` bucket and after any syslog or journald priority.
+    /// A `window` under a millisecond is treated as a millisecond.
+    pub fn with_rotating_token(mut self, window: Duration) -> Self {
+        self.rotating_token = Some(window.max(Duration::from_millis(1)));
+        self
+    }
+
+    /// Prefix each message with its date bucket (e.g. `2020-06-15 ` or `2020-W25 `) as a
+    /// partition key
+    ///
+    /// The bucket comes from the record's timestamp in UTC, and is the leading token of
+    /// the message, after any syslog or journald priority
+    pub fn with_date_partition(mut self, granularity: DatePartition) -> Self {
+        self.date_partition = Some(granularity);
+        self
+    }
+
+    /// Include the record's originating crate (as `crate`), the first segment of its
+    /// module path, in every message
+    pub fn with_crate_field(mut self, enabled: bool) -> Self {
+        self.crate_field = enabled;
+        self
+    }
+
+    /// Shorten targets longer than `max_len` characters to their last characters, with
+    /// a `...` prefix, e.g. `...db::pool`
+    ///
+    /// Deeply nested module paths can make up much of each datagram, and the tail is
+    /// usually the most specific part. Only the rendered target is shortened: level
+    /// floors and filters still see the full target.
+    pub fn with_target_max_len(mut self, max_len: usize) -> Self {
+        self.target_max_len = Some(max_len);
+        self
+    }
+
+    /// Include `version` as the `schema_version` number in every structured message, so
+    /// receivers can tell which fields to expect
+    ///
+    /// Applies to `Format::Json` (after `target`), `Format::Gelf` (as `_schema_version`),
+    /// and the Fluentd encoding, but not to `Text` or custom formatters. Defaults to
+    /// `SCHEMA_VERSION`, the version of the formats in this release; override it with a
+    /// version of your own if you also shape records with fields and envelopes.
+    pub fn with_schema_version(mut self, version: u32) -> Self {
+        self.schema_version = Some(version);
+        self
+    }
+
+    /// Include the application's name (as `app`) in every message
+    pub fn with_app_name(mut self, name: impl Into<String>) -> Self {
+        self.app_name = Some(name.into());
+        self
+    }
+
+    /// Include the emitting service's version (as `version`) in every message
+    ///
+    /// Pass `env!("CARGO_PKG_VERSION")` for the version of the crate calling this, since
+    /// `env!` is expanded where it's written.
+    pub fn with_version(mut self, version: impl Into<String>) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+
+    /// Include the version in the environment variable `var` (as `version`) in every
+    /// message, e.g. one set by the deployment
+    ///
+    /// The variable is read once, here. If it isn't set (or isn't valid Unicode), any
+    /// version from an earlier `with_version` is kept, so
+    /// `.with_version(env!("CARGO_PKG_VERSION")).with_version_from_env("SERVICE_VERSION")`
+    /// falls back to the crate's version.
+    pub fn with_version_from_env(mut self, var: &str) -> Self {
+        if let Ok(version) = std::env::var(var) {
+            self.version = Some(version);
+        }
+        self
+    }
+
+    /// Include the address datagrams are sent from (as `source`) in every message
+    ///
+    /// For receivers behind NAT, where the source address on arrival has been
+    /// rewritten. It's read once, when the logger is built: the local port, with the IP
+    /// the OS routes the destination through when the socket is bound to every
+    /// interface, e.g. `source=192.168.1.20:53011`. It's the primary writer's address,
+    /// even in messages to other destinations, and writers without a UDP socket add none.
+    pub fn with_source_field(mut self, enabled: bool) -> Self {
+        self.source_field = enabled;
+        self
+    }
+
+    /// Include the logger's uptime (as `uptime_ms`) and when it was built (as
+    /// `started_at`) in every message
+    ///
+    /// `started_at` comes from the logger's clock and is rendered like the record's
+    /// timestamp. Build the logger early in `main` so these track the process itself.
+    pub fn with_uptime(mut self, enabled: bool) -> Self {
+        self.uptime = enabled;
+        self
+    }
+
+    /// Include a monotonic timestamp (as `mono_ms`), the milliseconds since the logger
+    /// was built, in every message
+    ///
+    /// Unlike the wall clock it never goes backwards (e.g. when NTP steps the clock), so
+    /// receivers can order one logger's messages by it. It's alongside the wall clock
+    /// timestamp, or instead of it with `TimestampFormat::None`.
+    pub fn with_monotonic_timestamp(mut self, enabled: bool) -> Self {
+        self.monotonic = enabled;
+        self
+    }
+
+    /// Include the OS id of the logging thread (as `tid`) in every message
+    ///
+    /// This is the kernel TID from `gettid` on Linux, as in `/proc/<pid>/task` and
+    /// `perf`, or `GetCurrentThreadId` on Windows, rather than the Rust thread name. It's
+    /// read while the record is formatted, on the thread that logged it, so a buffered
+    /// logger's drain thread doesn't replace it. Other platforms have no id to add.
+    pub fn with_os_thread_id(mut self, enabled: bool) -> Self {
+        self.os_thread_id = enabled;
+        self
+    }
+
+    /// Expand nested record key-values into a field per leaf with a dotted path, e.g.
+    /// `user.id=42 user.name=bob`, for receivers that don't handle nested objects
+    ///
+    /// With the `kv` feature, each key-value of a record (e.g. `info!(user:serde; "login")`)
+    /// becomes a field after any context fields, in every format. Strings are kept as-is
+    /// and other values are rendered as JSON, e.g. `user={"id":42,"name":"bob"}`. With
+    /// this set, objects are expanded by key (in key order) and arrays by index, e.g.
+    /// `items.0=a items.1=b`, while empty ones stay `{}` and `[]`.
+    #[cfg(feature = "kv")]
+    pub fn with_flatten_kv(mut self, enabled: bool) -> Self {
+        self.flatten_kv = enabled;
+        self
+    }
+
+    /// Keep at most `max_fields` of each record's key-values, dropping the rest
+    ///
+    /// A record with more is marked by two extra fields, `fields_truncated=true` and
+    /// `total_fields=N` with the number it had, so the receiver can tell fields were
+    /// lost. With `with_flatten_kv`, each leaf counts as a field. Context and other
+    /// fields added by the logger aren't counted or dropped.
+    #[cfg(feature = "kv")]
+    pub fn with_max_fields(mut self, max_fields: usize) -> Self {
+        self.max_fields = Some(max_fields);
+        self
+    }
+
+    /// Timestamp each record with its `key` key-value instead of the clock, e.g. to keep
+    /// the original event times of relayed logs
+    ///
+    /// The value must be an RFC 3339 string like `2020-06-15T03:15:39Z`; a record
+    /// without the key, or with a value that doesn't parse, gets the clock's time. The
+    /// key-value is still rendered as a field like any other.
+    #[cfg(feature = "kv")]
+    pub fn with_timestamp_field(mut self, key: impl Into<String>) -> Self {
+        self.timestamp_field = Some(key.into());
+        self
+    }
+
+    /// Replace the value of every `field_name` field with a salted hash, so values can
+    /// still be correlated across messages but not read, e.g. for IPs or user ids
+    ///
+    /// The hash is the first 16 hex digits of HMAC-SHA256 over the value, keyed by
+    /// `salt`, so the same value always hashes the same with the same salt. Values from
+    /// a small space (like IPv4 addresses) can be recovered by hashing every candidate,
+    /// so keep the salt secret. Call once per field; this applies to the fields of the
+    /// built-in formats (context fields, record key-values, `app`, etc.), not to the
+    /// message.
+    #[cfg(feature = "hashing")]
+    pub fn with_field_hasher(
+        mut self,
+        field_name: impl Into<String>,
+        salt: impl Into<String>,
+    ) -> Self {
+        self.field_hashers.push(FieldHasher {
+            field: field_name.into(),
+            salt: salt.into(),
+        });
+        self
+    }
+
+    /// Include the given id (as `run_id`) in every message
+    pub fn with_custom_run_id(mut self, run_id: u64) -> Self {
+        self.generate_run_id = false;
+        self.run_id = Some(run_id);
+        self
+    }
+
+    /// Prefix each message with a journald-style `PRIORITY=<n>` field, where `n` is the
+    /// syslog severity of the record's level (e.g. `PRIORITY=3` for `Error`)
+    pub fn with_journald_priority(mut self, enabled: bool) -> Self {
+        self.journald_priority = enabled;
+        self
+    }
+
+    /// Choose how `UdpLogger::send_raw_bytes` handles invalid UTF-8 (default == `Lossy`)
+    ///
+    /// Only raw bytes are checked: formatted records, including custom formatter and
+    /// template output, are built as strings and so are always valid UTF-8.
+    pub fn with_utf8_policy(mut self, policy: Utf8Policy) -> Self {
+        self.utf8_policy = policy;
+        self
+    }
+
+    /// Only log records for which `filter` returns true, for filtering beyond levels and targets
+    ///
+    /// `filter` is called after the level checks and before anything else (throttling,
+    /// aggregation, formatting), so a rejected record costs nothing more
+    pub fn with_filter(mut self, filter: impl Fn(&Record) -> bool + Send + Sync + 'static) -> Self {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Choose how records with an empty message are handled (default == Keep)
+    pub fn with_empty_message_policy(mut self, policy: EmptyMessagePolicy) -> Self {
+        self.empty_message = policy;
+        self
+    }
+
+    /// Choose how newlines inside a message are handled (default == Keep)
+    ///
+    /// A multi-line message, e.g. an error with a backtrace, otherwise splits one record
+    /// across lines for line-delimited receivers. The policy is applied to the message
+    /// before it's formatted, so the record's own delimiter is unaffected, and with
+    /// `SplitRecords` each line passes through throttling, rate limits, and the
+    /// `EmptyMessagePolicy` as a record of its own.
+    pub fn with_newline_policy(mut self, policy: NewlinePolicy) -> Self {
+        self.newline_policy = policy;
+        self
+    }
+
+    /// Choose what's sent when a custom formatter panics (default == Fallback)
+    ///
+    /// The panic never reaches the code that logged the record. It's counted in
+    /// `StatsSnapshot::formatter_panics`, and the record is either rendered with the
+    /// built-in `Format` or skipped. This applies to `with_formatter`,
+    /// `with_adaptive_format`, and `add_destination_with_formatter`. The standard panic
+    /// hook still prints the panic, but `install_panic_hook` doesn't log it.
+    pub fn with_formatter_panic_policy(mut self, policy: FormatterPanicPolicy) -> Self {
+        self.formatter_panic = policy;
+        self
+    }
+
+    /// Send records sharing a key at most once per `min_interval`, dropping repeats
+    ///
+    /// `key` identifies a message, e.g. by its text or call site
+    /// (`|r| format!("{}:{:?}", r.target(), r.line())`). The most recently seen keys are
+    /// remembered, and suppressed records are counted in `StatsSnapshot::throttled`.
+    pub fn with_throttle(
+        mut self,
+        key: impl Fn(&Record) -> String + Send + Sync + 'static,
+        min_interval: Duration,
+    ) -> Self {
+        self.throttle = Some(Throttle::new(Box::new(key), min_interval));
+        self
+    }
+
+    /// Send at most `limit.per_sec` datagrams per second in total, across the primary
+    /// destination and every `add_destination_with_formatter` (and `with_file`) destination
+    ///
+    /// Each copy of a record sent to a destination counts once, so adding destinations
+    /// doesn't raise the total. After a quiet period, a burst of up to `limit.burst`
+    /// datagrams goes out at once, then sends are paced at the steady rate. A plain
+    /// number is a `RateLimit` with a burst of one second's worth. Copies over the limit
+    /// are dropped and counted in `StatsSnapshot::rate_limited`. Only logged records
+    /// are limited: `send_raw` and `log_batch` aren't.
+    /// ```
+    /// use udp_logger::{RateLimit, UdpLoggerBuilder};
+    ///
+    /// let builder = UdpLoggerBuilder::new("127.0.0.1:1999")
+    ///     .with_global_rate_limit(RateLimit { per_sec: 100, burst: 1000 });
+    /// ```
+    pub fn with_global_rate_limit(mut self, limit: impl Into<RateLimit>) -> Self {
+        self.rate_limit = Some(limit.into());
+        self
+    }
+
+    /// Send at most `max` datagrams over the logger's lifetime, then silently stop, e.g.
+    /// for a bounded diagnostic capture
+    ///
+    /// Counted like `with_global_rate_limit`, once per copy of a record sent to a
+    /// destination, and copies held back by the rate limit don't use up the cap. Once
+    /// it's reached, records are dropped and counted in `StatsSnapshot::capped`. A
+    /// batching writer may pack the capped records into fewer datagrams.
+    pub fn with_send_cap(mut self, max: usize) -> Self {
+        self.send_cap = Some(max);
+        self
+    }
+
+    /// Run `callback` once when `with_send_cap` is reached, as the last datagram under
+    /// the cap is sent
+    pub fn with_send_cap_callback(mut self, callback: Box<dyn FnOnce() + Send>) -> Self {
+        self.send_cap_callback = Some(callback);
+        self
+    }
+
+    /// Send periodic counts of records by `key` instead of each record, for repetitive logs
+    ///
+    /// Every `interval`, one datagram like `AGGREGATE [timestamp] count=42 window=10s key`
+    /// is sent per distinct key seen in the window. A background thread sends each
+    /// window as it ends, and `Log::flush` or dropping the logger sends the current one
+    /// early. Aggregated records are still counted in `StatsSnapshot::per_level`, and
+    /// aggregates go to every destination under the global rate limit and send cap,
+    /// as-is even to destinations with their own formatter.
+    pub fn with_aggregation(
+        mut self,
+        key: impl Fn(&Record) -> String + Send + Sync + 'static,
+        interval: Duration,
+    ) -> Self {
+        self.aggregator = Some(Aggregator::new(Box::new(key), interval));
+        self
+    }
+
+    /// Format and count every message (as `would_send` in `stats()`) without sending it
+    ///
+    /// Useful to validate configuration and estimate volume before going live. The
+    /// destination is still resolved, but no socket is bound.
+    pub fn with_dry_run(mut self, enabled: bool) -> Self {
+        self.dry_run = enabled;
+        self
+    }
+
+    /// Take record timestamps from the given clock instead of the system clock
+    ///
+    /// The clock also timestamps heartbeats, self-metrics, drop notices, and
+    /// `with_drop_reporting` reports, and sets the second boundaries of
+    /// `with_aligned_flush`.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Prefix each message with a syslog `<PRI>` value using a single facility
+    pub fn with_syslog(self, facility: Facility) -> Self {
+        self.with_syslog_facility(move |_| facility)
+    }
+
+    /// Prefix each message with a syslog `<PRI>` value, choosing the facility per-record
+    /// ```
+    /// use udp_logger::{Facility, UdpLoggerBuilder};
+    ///
+    /// let builder = UdpLoggerBuilder::new("127.0.0.1:514").with_syslog_facility(|record| {
+    ///     if record.target().starts_with("auth") {
+    ///         Facility::Auth
+    ///     } else {
+    ///         Facility::User
+    ///     }
+    /// });
+    /// ```
+    pub fn with_syslog_facility(
+        mut self,
+        facility: impl Fn(&Record) -> Facility + Send + Sync + 'static,
+    ) -> Self {
+        self.facility = Some(Box::new(facility));
+        self
+    }
+
+    /// Build the configured UdpLogger without installing it
+    pub fn build(mut self) -> io::Result<UdpLogger> {
+        if self.strict {
+            self.check_strict()?;
+        }
+        if self.ordered && !matches!(self.kind, WriterKind::Buffered | WriterKind::Manual) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "with_ordered needs the buffered or manual writer",
+            ));
+        }
+        if self.ordered && self.options.max_requeues.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "with_requeue can reorder the queue, which with_ordered forbids",
+            ));
+        }
+        if self.spill.is_some() && self.options.queue_limit.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "with_spill needs with_queue_limit to spill beyond",
+            ));
+        }
+        let gelf = self.format == Format::Gelf && !self.is_line_format();
+        if gelf && self.options.batch_size.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Format::Gelf sends one record per datagram, so it can't be batched",
+            ));
+        }
+        self.options.clock = self.clock.clone();
+        // Grouping would turn structured records into text
+        let line_format = self.is_line_format();
+        self.options.group_by_target &= line_format;
+        let template = self.template.take().transpose()?;
+        let batch_timestamps = self.options.batch_timestamps
+            && self.options.batch_size.is_some()
+            && !self.options.group_by_target
+            && line_format
+            && matches!(self.kind, WriterKind::Buffered | WriterKind::Manual);
+        let stats = Arc::new(Stats::default());
+        let writer = self.primary_writer(&stats)?;
+        // The primary writer's destination is listed first, already resolved
+        let primary = self.destination_stats.first().map(|(address, _)| *address);
+        let source = match (self.source_field, writer.local_addr()) {
+            (true, Ok(local)) => Some(Self::source_addr(local, primary).to_string()),
+            _ => None,
+        };
+        let mut destinations = Vec::new();
+        for (destination, formatter) in std::mem::take(&mut self.destinations) {
+            let destination = destination?;
+            let scoped = self.destination_stats(destination, &stats);
+            let writer = self.writer(destination, &scoped, None)?;
+            destinations.push((writer, Some(Reformat::Formatter(formatter))));
+        }
+        for (path, max_size, max_files) in &self.files {
+            let writer: Box<dyn Writer> = Box::new(FileWriter::new(path, *max_size, *max_files)?);
+            destinations.push((writer, None));
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(path) = &self.journald {
+            let writer: Box<dyn Writer> = Box::new(JournaldWriter::new(path)?);
+            destinations.push((writer, Some(Reformat::Journald)));
+        }
+        let drop_reporter = match self.drop_reporting.take() {
+            Some((destination, interval)) => {
+                drop_report::check_interval(interval)?;
+                let destination = self.socket.target(destination?);
+                let out = self.socket.bind()?;
+                Some(DropReporter::new(
+                    out,
+                    destination,
+                    interval,
+                    stats.clone(),
+                    self.clock.clone(),
+                ))
+            }
+            None => None,
+        };
+        let started_at = self.clock.now();
+        let run_id = match (self.generate_run_id, &self.id_source) {
+            (true, Some(source)) => Some(source()),
+            (true, None) => Some(random_id()),
+            (false, _) => self.run_id,
+        };
+        let writer = Arc::new(ArcSwap::from_pointee(writer));
+        let fanout = Arc::new(Fanout {
+            destinations,
+            rate_limit: self
+                .rate_limit
+                .map(|limit| RateLimiter::new(limit, Instant::now())),
+            send_cap: match self.send_cap {
+                Some(max) => Some(SendCap::new(max, self.send_cap_callback)),
+                None => None,
+            },
+        });
+        let last_error = Arc::new(Mutex::new(None));
+        let clock = self.clock.clone();
+        let aggregation = self.aggregator.take().map(|aggregator| {
+            Arc::new(Aggregation {
+                aggregator,
+                writer: writer.clone(),
+                fanout: fanout.clone(),
+                clock,
+                stats: stats.clone(),
+                last_error: last_error.clone(),
+            })
+        });
+        let aggregate_timer = aggregation.clone().map(|aggregation| {
+            AggregateTimer::new(move |now| match aggregation.aggregator.take_due(now) {
+                Ok(counts) => {
+                    aggregation.send(counts);
+                    aggregation.aggregator.due_in(now)
+                }
+                Err(due_in) => due_in,
+            })
+        });
+        let logger = UdpLogger {
+            writer,
+            level: match self.shared_level {
+                Some(level) => LevelHandle::shared(level),
+                None if !self.schedule.is_empty() => LevelHandle::scheduled(self.level),
+                None => LevelHandle::new(self.level),
+            },
+            format: self.format,
+            formatter: self.formatter,
+            facility: self.facility,
+            priority: self.priority,
+            run_id,
+            journald_priority: self.journald_priority,
+            #[cfg(feature = "msgpack")]
+            fluentd_tag: self.fluentd_tag,
+            #[cfg(feature = "prost")]
+            protobuf: self.protobuf,
+            clock: self.clock,
+            fanout,
+            throttle: self.throttle,
+            aggregation,
+            aggregate_timer,
+            filter: self.filter,
+            empty_message: self.empty_message,
+            formatter_panic: self.formatter_panic,
+            newline_policy: self.newline_policy,
+            utf8_policy: self.utf8_policy,
+            json_keys: self.json_keys,
+            target_floors: self.target_floors,
+            hard_min_level: self.hard_min_level,
+            schedule: self.schedule,
+            last_error,
+            crate_field: self.crate_field,
+            app_name: self.app_name,
+            version: self.version,
+            source,
+            schema_version: self.schema_version,
+            target_max_len: self.target_max_len,
+            shutdown_hook: Mutex::new(self.shutdown_hook),
+            strict: self.strict,
+            date_partition: self.date_partition,
+            rotating_token: self.rotating_token,
+            timestamp_format: self.timestamp_format,
+            level_label: self.level_label,
+            template,
+            envelope: self.envelope,
+            footer: self.footer.zip(Some(self.footer_position)),
+            transform: self.transform,
+            shutdown_summary: self.shutdown_summary,
+            flush_on_level: self.flush_on_level,
+            created_at: Instant::now(),
+            started_at,
+            uptime: self.uptime,
+            monotonic: self.monotonic,
+            os_thread_id: self.os_thread_id,
+            binary_header: self.binary_header.then(|| AtomicU32::new(0)),
+            #[cfg(feature = "kv")]
+            flatten_kv: self.flatten_kv,
+            #[cfg(feature = "kv")]
+            max_fields: self.max_fields,
+            #[cfg(feature = "kv")]
+            timestamp_field: self.timestamp_field,
+            #[cfg(feature = "hashing")]
+            field_hashers: self.field_hashers,
+            group_by_target: self.options.group_by_target,
+            batch_timestamps,
+            stats,
+            destination_stats: self.destination_stats,
+            paused: self.socket.paused.clone(),
+            events: self.options.events.clone(),
+            _drop_reporter: drop_reporter,
+        };
+        if let Some((version, commit)) = &self.build_info {
+            let handshake = logger.handshake(version, commit.as_deref());
+            let priority = queue::default_priority(Level::Error);
+            logger.capture(
+                logger
+                    .writer
+                    .load()
+                    .push(Envelope::new(handshake.into_bytes(), priority)),
+            );
+        }
+        logger.events.emit(LoggerEvent::Started);
+        Ok(logger)
+    }
+
+    /// Create the writer for the primary destination, with the configured queue
+    fn primary_writer(&mut self, stats: &Arc<Stats>) -> io::Result<Box<dyn Writer>> {
+        #[cfg(windows)]
+        if let (WriterKind::Pipe(path), false) = (&self.kind, self.dry_run) {
+            return Ok(Box::new(pipe::PipeWriter::new(
+                path,
+                &self.socket,
+                stats.clone(),
+            )?));
+        }
+        #[cfg(target_os = "linux")]
+        if let (WriterKind::UnixDatagram(name), false) = (&self.kind, self.dry_run) {
+            return Ok(Box::new(unix::UnixDatagramWriter::new(
+                name,
+                &self.socket,
+                stats.clone(),
+            )?));
+        }
+        let destination = self.resolve_destination()?;
+        if self.format == Format::Json {
+            self.options.framing = self.json_framing;
+        }
+        let queue = match (self.queue.take(), &self.spill, self.options.queue_limit) {
+            (Some(queue), _, _) => Some(queue),
+            (None, Some((path, max_bytes)), Some(limit)) => {
+                Some(
+                    Box::new(SpillQueue::new(limit, path, *max_bytes, stats.clone())?)
+                        as Box<dyn MessageQueue>,
+                )
+            }
+            (None, _, _) => None,
+        };
+        let scoped = self.destination_stats(destination, stats);
+        let writer = self.writer(destination, &scoped, queue)?;
+        if self.weighted.is_empty() {
+            return Ok(writer);
+        }
+        let mut writers = vec![(writer, 1)];
+        for (destination, weight) in std::mem::take(&mut self.weighted) {
+            let destination = destination?;
+            let scoped = self.destination_stats(destination, stats);
+            writers.push((self.writer(destination, &scoped, None)?, weight));
+        }
+        Ok(Box::new(WeightedWriter::new(writers)?))
+    }
+
+    /// Counters for the writer sending to `destination`, listed by `UdpLogger::destinations`
+    fn destination_stats(&mut self, destination: SocketAddr, stats: &Arc<Stats>) -> Arc<Stats> {
+        let scoped = Stats::scoped(stats);
+        self.destination_stats.push((destination, scoped.clone()));
+        scoped
+    }
+
+    /// The address datagrams from `local` to `destination` appear to come from, before any NAT
+    fn source_addr(local: SocketAddr, destination: Option<SocketAddr>) -> SocketAddr {
+        if !local.ip().is_unspecified() {
+            return local;
+        }
+        // Bound to every interface, so the IP is the one the OS routes the destination by
+        match destination.map(socket::route_ip) {
+            Some(Ok(ip)) => SocketAddr::new(ip, local.port()),
+            _ => local,
+        }
+    }
+
+    /// The primary destination, looked up with the `with_resolver` resolver if there is one
+    fn resolve_destination(&self) -> io::Result<SocketAddr> {
+        let (host, resolver) = match (&self.lookup, &self.destination) {
+            (Some(lookup), _) => lookup,
+            (None, Ok(destination)) => return Ok(*destination),
+            (None, Err(e)) => return Err(io::Error::new(e.kind(), e.to_string())),
+        };
+        let (attempts, delay) = self.resolve_retry;
+        let mut attempt = 1;
+        loop {
+            match first_destination(host, resolver.resolve(host)) {
+                Err(e) if attempt < attempts && e.kind() != io::ErrorKind::InvalidInput => {
+                    attempt += 1;
+                    thread::sleep(delay);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Whether records are encoded as plain lines rather than a structured or binary
+    /// format, so a line can be prefixed without breaking it
+    fn is_line_format(&self) -> bool {
+        if self.formatter.is_some() || self.template.is_some() {
+            return true;
+        }
+        #[cfg(feature = "msgpack")]
+        if self.fluentd_tag.is_some() {
+            return false;
+        }
+        #[cfg(feature = "prost")]
+        if self.protobuf {
+            return false;
+        }
+        matches!(self.format, Format::Text | Format::Raw)
+    }
+
+    /// Reject the misconfigurations documented on `with_strict`
+    fn check_strict(&self) -> io::Result<()> {
+        let invalid = |reason: &str| {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("strict: {}", reason),
+            ))
+        };
+        let options = &self.options;
+        if options.queue_limit == Some(0) || options.max_queue_bytes == Some(0) {
+            return invalid("queue limit is zero, so every message is dropped");
+        }
+        if options.batch_size == Some(0) || options.max_batch_count == Some(0) {
+            return invalid("batch size and count must be greater than zero");
+        }
+        if self.resolve_retry.0 > 1 && self.lookup.is_none() {
+            return invalid("resolve retries need with_resolver, new resolves immediately");
+        }
+        let queued = matches!(self.kind, WriterKind::Buffered | WriterKind::Manual);
+        let queue_options = self.queue.is_some()
+            || self.spill.is_some()
+            || options.queue_limit.is_some()
+            || options.max_queue_bytes.is_some()
+            || options.batch_size.is_some()
+            || options.max_batch_count.is_some()
+            || options.gso.is_some()
+            || options.heartbeat.is_some()
+            || options.thread_recycle.is_some()
+            || options.self_metrics.is_some()
+            || options.pacing.is_some()
+            || options.max_sends_per_flush.is_some()
+            || options.aligned_flush
+            || options.idle_flush.is_some()
+            || options.strip_trailing_newline
+            || options.group_by_target
+            || options.batch_timestamps
+            || options.ttl.is_some()
+            || !options.level_ttls.is_empty()
+            || options.max_requeues.is_some()
+            || options.drop_flush_timeout.is_some()
+            || self.flush_on_level.is_some()
+            || options.drop_notice.is_some();
+        if queue_options && !queued {
+            return invalid("queue options are ignored by a writer that doesn't queue");
+        }
+        Ok(())
+    }
+
+    /// Create the configured kind of writer for a destination, buffering in `queue` if given
+    fn writer(
+        &self,
+        destination: SocketAddr,
+        stats: &Arc<Stats>,
+        queue: Option<Box<dyn MessageQueue>>,
+    ) -> io::Result<Box<dyn Writer>> {
+        let queue = queue.unwrap_or_else(|| {
+            Box::new(
+                BoundedQueue::new(self.options.queue_limit, self.options.drop_policy)
+                    .with_max_bytes(self.options.max_queue_bytes)
+                    .with_capacity(self.options.queue_capacity),
+            )
+        });
+        Ok(match self.kind {
+            _ if self.dry_run => Box::new(DryRunWriter {
+                stats: stats.clone(),
+            }),
+            WriterKind::Unbuffered => {
+                Box::new(UdpWriter::new(destination, &self.socket, stats.clone())?)
+            }
+            // Only the primary writer uses the pipe, other destinations are sent to directly
+            #[cfg(windows)]
+            WriterKind::Pipe(_) => {
+                Box::new(UdpWriter::new(destination, &self.socket, stats.clone())?)
+            }
+            // Likewise for the Unix socket
+            #[cfg(target_os = "linux")]
+            WriterKind::UnixDatagram(_) => {
+                Box::new(UdpWriter::new(destination, &self.socket, stats.clone())?)
+            }
+            WriterKind::Buffered => Box::new(UdpBufferedWriter::new(
+                destination,
+                &self.options,
+                queue,
+                &self.socket,
+                stats.clone(),
+            )?),
+            WriterKind::Manual => Box::new(ManualWriter::new(
+                destination,
+                &self.options,
+                queue,
+                &self.socket,
+                stats.clone(),
+            )?),
+            WriterKind::Tcp => Box::new(TcpWriter::new(destination, &self.socket, stats.clone())?),
+            #[cfg(feature = "async-std")]
+            WriterKind::AsyncStd => Box::new(async_std_writer::AsyncStdUdpWriter::new(
+                destination,
+                &self.socket,
+                stats.clone(),
+            )?),
+        })
+    }
+
+    /// Build the configured UdpLogger and install it as the destination for `Log` macros
+    pub fn init(self) -> Result<(), Box<dyn std::error::Error>> {
+        self.init_with_handle().map(|_| ())
+    }
+
+    /// Like `init`, returning a handle to change the level of the installed logger
+    ///
+    /// `log::max_level` is kept in sync with the level set through the handle
+    pub fn init_with_handle(self) -> Result<LevelHandle, Box<dyn std::error::Error>> {
+        let logger = self.build()?;
+        let handle = logger.level_handle();
+        UdpLoggerBuilder::set_logger(logger)?;
+        Ok(handle)
+    }
+
+    /// Like `init_with_handle`, storing the logger in `slot` instead of a `Box`
+    ///
+    /// `log::set_boxed_logger` leaks a heap allocation to get a `'static` logger, which
+    /// this avoids by installing a reference into a static declared with
+    /// `static_logger!`. The logger itself still allocates while it's built and while
+    /// it logs, and (like a boxed logger) it's never dropped, so shutdown hooks and
+    /// summaries never run.
+    ///
+    /// A static can only be set once, so `slot` keeps its logger even if installing it
+    /// fails because another logger is already installed. Calling this again with the
+    /// same `slot` doesn't build this builder's logger, but retries installing the one
+    /// already in `slot`, returning the same `SetLoggerError` while another logger holds
+    /// its place.
+    /// ```
+    /// use udp_logger::{static_logger, UdpLoggerBuilder};
+    ///
+    /// static_logger!(LOGGER);
+    ///
+    /// UdpLoggerBuilder::new("127.0.0.1:1999")
+    ///     .init_static(&LOGGER)
+    ///     .unwrap();
+    /// log::info!("Sent by the logger in LOGGER");
+    /// assert!(LOGGER.get().is_some());
+    /// ```
+    pub fn init_static(
+        self,
+        slot: &'static OnceLock<UdpLogger>,
+    ) -> Result<LevelHandle, Box<dyn std::error::Error>> {
+        if slot.get().is_none() {
+            // Another thread may fill the slot first, and then its logger is installed
+            let _ = slot.set(self.build()?);
+        }
+        let logger = slot.get().expect("Just initialized");
+        log::set_logger(logger)?;
+        let handle = logger.level_handle();
+        handle.install();
+        panic_hook::register(logger.flusher());
+        Ok(handle)
+    }
+
+    /// Capture records logged before the real logger is ready, for `try_init_with_replay`
+    ///
+    /// Installs a placeholder global logger that buffers up to 1024 records at or above
+    /// `level`. Call this as early as possible, since `log` can only install one logger.
+    pub fn capture_early(level: Level) -> Result<(), SetLoggerError> {
+        early::install(level)
+    }
+
+    /// Like `init`, first replaying any records captured by `capture_early`
+    ///
+    /// Replayed records are timestamped when they're replayed. Without `capture_early`
+    /// this is the same as `init`.
+    pub fn try_init_with_replay(self) -> Result<(), Box<dyn std::error::Error>> {
+        let logger = self.build()?;
+        let early = match early::installed() {
+            Some(early) => early,
+            None => return UdpLoggerBuilder::set_logger(logger).map_err(|e| e.into()),
+        };
+        let handle = logger.level_handle();
+        let flush = logger.flusher();
+        early
+            .attach(Box::new(logger))
+            .map_err(|_| "a logger was already initialized")?;
+        handle.install();
+        panic_hook::register(flush);
+        Ok(())
+    }
+
+    /// Initialize an unbuffered UdpLogger as a destination for `Log` macros
+    pub fn try_init(
+        destination: impl ToSocketAddrs + Debug,
+        level: Level,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut logger = UdpLogger::new(destination)?;
+        logger.set_level(level);
+        UdpLoggerBuilder::set_logger(logger).map_err(|e| e.into())
+    }
+
+    /// Initialize a buffered UdpLogger as a destination for `Log` macros
+    pub fn try_buffered_init(
+        destination: impl ToSocketAddrs + Debug,
+        level: Level,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        UdpLoggerBuilder::try_buffered_init_filtered(destination, level.to_level_filter())
+            .map(|_| ())
+    }
+
+    /// Initialize a buffered UdpLogger as a destination for `Log` macros, logging up to
+    /// `filter`, returning a handle to change its level
+    ///
+    /// Unlike `try_buffered_init`, this takes a `LevelFilter`, so `LevelFilter::Off`
+    /// installs a logger that logs nothing until its level is raised through the
+    /// returned `LevelHandle`.
+    pub fn try_buffered_init_filtered(
+        destination: impl ToSocketAddrs + Debug,
+        filter: LevelFilter,
+    ) -> Result<LevelHandle, Box<dyn std::error::Error>> {
+        let logger = UdpLogger::buffered_filtered(destination, filter)?;
+        let handle = logger.level_handle();
+        UdpLoggerBuilder::set_logger(logger)?;
+        Ok(handle)
+    }
+
+    fn set_logger(logger: UdpLogger) -> Result<(), SetLoggerError> {
+        let handle = logger.level_handle();
+        let flush = logger.flusher();
+        log::set_boxed_logger(Box::new(logger))?;
+        handle.install();
+        panic_hook::register(flush);
+        Ok(())
+    }
+}
+
+/// Declare a static to install a UdpLogger into with `UdpLoggerBuilder::init_static`
+///
+/// `static_logger!(pub LOGGER)` declares `pub static LOGGER: OnceLock<UdpLogger>`
+#[macro_export]
+macro_rules! static_logger {
+    ($vis:vis $name:ident) => {
+        $vis static $name: ::std::sync::OnceLock<$crate::UdpLogger> = ::std::sync::OnceLock::new();
+    };
+}
+
+/// The sending strategy a UdpLoggerBuilder will construct
+enum WriterKind {
+    Unbuffered,
+    Buffered,
+    Manual,
+    Tcp,
+    /// To the named pipe at this path, in place of the destination
+    #[cfg(windows)]
+    Pipe(PathBuf),
+    /// To the Unix datagram socket with this name, in place of the destination
+    #[cfg(target_os = "linux")]
+    UnixDatagram(String),
+    #[cfg(feature = "async-std")]
+    AsyncStd,
+}
+
+/// Whether `target` is the module `prefix` or one of its submodules
+fn is_module_prefix(prefix: &str, target: &str) -> bool {
+    target
+        .strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+}
+
+/// Generate a random id from the standard library's randomly-seeded hasher
+fn random_id() -> u64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    hasher.write_u32(std::process::id());
+    hasher.finish()
+}
+
+/// Resolve the first `SocketAddr` for the given destination
+fn resolve(destination: impl ToSocketAddrs + Debug) -> io::Result<SocketAddr> {
+    let addrs = destination.to_socket_addrs().map(Iterator::collect);
+    first_destination(&destination, addrs)
+}
+
+/// The first of the `addrs` looked up for `destination`, if it can be sent to
+fn first_destination(
+    destination: &dyn Debug,
+    addrs: io::Result<Vec<SocketAddr>>,
+) -> io::Result<SocketAddr> {
+    let failed = |kind, reason: &dyn std::fmt::Display| {
+        io::Error::new(
+            kind,
+            format!("can't resolve destination {:?}: {}", destination, reason),
+        )
+    };
+    let addr = addrs
+        .map_err(|e| failed(e.kind(), &e))?
+        .into_iter()
+        .next()
+        .ok_or_else(|| failed(io::ErrorKind::AddrNotAvailable, &"no addresses found"))?;
+    // Nothing can ever be delivered to these, so catch the misconfiguration early
+    if addr.ip().is_unspecified() {
+        return Err(failed(
+            io::ErrorKind::InvalidInput,
+            &format!("{} is an unspecified address", addr),
+        ));
+    }
+    if addr.port() == 0 {
+        return Err(failed(
+            io::ErrorKind::InvalidInput,
+            &format!(
+                "{} has an unspecified port, port 0 only makes sense when binding",
+                addr
+            ),
+        ));
+    }
+    Ok(addr)
+}
+
+/// Writer is used by UdpLogger to send UDP datagrams
+trait Writer: Send + Sync {
+    fn push(&self, message: Envelope) -> io::Result<()>;
+
+    /// Push several messages, writers with a queue override this to lock it only once
+    fn push_batch(&self, messages: Vec<Envelope>) -> io::Result<()> {
+        messages
+            .into_iter()
+            .try_for_each(|message| self.push(message))
+    }
+
+    /// The local address of the socket datagrams are sent from
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    /// Wait up to `timeout` for queued messages to be sent, returning how many remain
+    fn flush_timeout(&self, _timeout: Duration) -> usize {
+        0
+    }
+
+    /// Send queued messages from the calling thread, returning how many were sent
+    fn drain(&self) -> usize {
+        0
+    }
+
+    /// Start sending queued messages now rather than at the next interval, without waiting
+    fn wake(&self) {}
+
+    /// Copies of up to `max` queued messages, oldest first
+    fn peek_queue(&self, _max: usize) -> Vec<Envelope> {
+        Vec::new()
+    }
+
+    /// Replace the socket with a newly bound one, writers without a socket have nothing to do
+    fn reconnect(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Whether a probe reaches the destination within `timeout`, see `UdpLogger::wait_ready`
+    fn probe(&self, _timeout: Duration) -> bool {
+        true
+    }
+
+    /// The resolved address datagrams are sent to, writers without a single UDP
+    /// destination have none
+    fn destination(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    /// Take an error left pending on the socket by an earlier send, e.g. an ICMP port
+    /// unreachable on a connected socket
+    fn take_error(&self) -> io::Result<Option<io::Error>> {
+        Ok(None)
+    }
+}
+
+/// DryRunWriter counts messages but never sends them
+struct DryRunWriter {
+    stats: Arc<Stats>,
+}
+
+impl Writer for DryRunWriter {
+    fn push(&self, _message: Envelope) -> io::Result<()> {
+        self.stats.record_would_send();
+        Ok(())
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Err(io::Error::new(
+            io::ErrorKind::NotConnected,
+            "dry-run logger has no socket",
+        ))
+    }
+}
+
+/// UdpWriter is an unbuffered writer and datagrams will be sent immediately
+/// via a UdpSocket (system determined IP & port)
+struct UdpWriter {
+    out: SharedSocket,
+    destination: SocketAddr,
+    socket: SocketOptions,
+    stats: Arc<Stats>,
+    last_sent: LastSent,
+}
+
+impl UdpWriter {
+    /// Create a new UdpWriter that sends messages to the given destination `SocketAddr`
+    pub fn new(
+        destination: impl ToSocketAddrs + Debug,
+        socket: &SocketOptions,
+        stats: Arc<Stats>,
+    ) -> io::Result<Self> {
+        let destination = socket.target(resolve(destination)?);
+        Ok(Self {
+            destination,
+            out: socket.bind_shared(destination)?,
+            socket: socket.clone(),
+            stats,
+            last_sent: LastSent::default(),
+        })
+    }
+}
+
+impl Writer for UdpWriter {
+    /// This is used by `Log` to write the message as a datagram
+    fn push(&self, message: Envelope) -> io::Result<()> {
+        // There's no queue to hold messages in, so they're dropped until resumed
+        if self.socket.is_paused() {
+            self.stats.record_drop();
+            return Ok(());
+        }
+        let now = Instant::now();
+        if self.socket.suppress_identical && self.last_sent.is_repeat(&message.payload, now) {
+            self.stats.record_suppressed();
+            return Ok(());
+        }
+        let datagram = self.socket.seal(&message.payload)?;
+        let out = self.out.load();
+        match socket::send_confirmed(&datagram, self.socket.confirm_send, |bytes| {
+            self.stats
+                .timed(|| self.socket.send(&out, bytes, self.destination))
+        }) {
+            Ok(()) => {
+                self.stats.record_send();
+                self.socket.sent(&datagram);
+                if self.socket.suppress_identical {
+                    self.last_sent.record(&message.payload, now);
+                }
+                Ok(())
+            }
+            Err(e) if socket::is_congested(&e) => {
+                self.stats.record_drop();
+                Err(e)
+            }
+            Err(e) => {
+                self.stats.record_error();
+                Err(e)
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.out.load().local_addr()
+    }
+
+    fn reconnect(&self) -> io::Result<()> {
+        self.socket.rebind(&self.out, self.destination)?;
+        self.stats.record_rebind();
+        Ok(())
+    }
+
+    fn probe(&self, timeout: Duration) -> bool {
+        self.socket.probe(self.destination, timeout)
+    }
+
+    fn destination(&self) -> Option<SocketAddr> {
+        Some(self.destination)
+    }
+
+    fn take_error(&self) -> io::Result<Option<io::Error>> {
+        self.out.load().take_error()
+    }
+}
+
+/// Options for the background thread of a UdpBufferedWriter
+#[derive(Clone)]
+struct BufferedOptions {
+    heartbeat: Option<Duration>,
+    /// Replace the drain thread with a new one this often
+    thread_recycle: Option<Duration>,
+    self_metrics: Option<Duration>,
+    interval: Duration,
+    batch_size: Option<usize>,
+    max_batch_count: Option<usize>,
+    /// Send runs of equal-sized messages as UDP GSO segments of this size
+    gso: Option<u16>,
+    framing: JsonFraming,
+    queue_limit: Option<usize>,
+    max_queue_bytes: Option<usize>,
+    queue_capacity: usize,
+    drop_policy: DropPolicy,
+    pacing: Option<Duration>,
+    /// Send at most this many datagrams per drain pass, until shutdown
+    max_sends_per_flush: Option<usize>,
+    ttl: Option<Duration>,
+    /// TTLs replacing `ttl` for records at these levels, see `with_level_ttl`
+    level_ttls: Vec<(Level, Duration)>,
+    /// Put a message back in the queue after a failed send, up to this many times
+    max_requeues: Option<u32>,
+    drop_notice: Option<DropNotice>,
+    flush_on_drop: bool,
+    /// Stop waiting for the flush on drop after this long
+    drop_flush_timeout: Option<Duration>,
+    /// Wait for the next wall-clock second rather than `interval` between drains
+    aligned_flush: bool,
+    /// Drain early once nothing has been queued for this long
+    idle_flush: Option<Duration>,
+    /// Drop the newline ending the last record of each batched datagram
+    strip_trailing_newline: bool,
+    /// Combine batched records that share a target into `target: [record, ...]`
+    group_by_target: bool,
+    /// Start batched datagrams with a timestamp header, see `with_batch_timestamps`
+    batch_timestamps: bool,
+    events: Events,
+    /// Timestamps heartbeats, self-metrics, and drop notices, and aligns flushes
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for BufferedOptions {
+    fn default() -> Self {
+        Self {
+            heartbeat: None,
+            thread_recycle: None,
+            self_metrics: None,
+            interval: Duration::from_millis(50),
+            batch_size: None,
+            max_batch_count: None,
+            gso: None,
+            framing: JsonFraming::Lines,
+            queue_limit: None,
+            max_queue_bytes: None,
+            queue_capacity: 0,
+            drop_policy: DropPolicy::default(),
+            pacing: None,
+            max_sends_per_flush: None,
+            ttl: None,
+            level_ttls: Vec::new(),
+            max_requeues: None,
+            drop_notice: None,
+            flush_on_drop: true,
+            drop_flush_timeout: None,
+            aligned_flush: false,
+            idle_flush: None,
+            strip_trailing_newline: false,
+            group_by_target: false,
+            batch_timestamps: false,
+            events: Events::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+impl BufferedOptions {
+    /// The TTL of a queued message at `level`, `None` if it never expires
+    fn ttl_for(&self, level: Option<Level>) -> Option<Duration> {
+        let explicit = self
+            .level_ttls
+            .iter()
+            .find(|(other, _)| Some(*other) == level)
+            .map(|(_, ttl)| *ttl);
+        match (explicit, level) {
+            (Some(ttl), _) => Some(ttl),
+            (None, Some(Level::Error)) => None,
+            (None, _) => self.ttl,
+        }
+    }
+
+    /// How long the drain waits after draining at `now`
+    fn wait(&self, now: DateTime<Utc>) -> Duration {
+        if !self.aligned_flush {
+            return self.interval;
+        }
+        // A leap second reports more than 1s of nanoseconds, so wait until it's over
+        let elapsed = Duration::from_nanos(now.timestamp_subsec_nanos().into());
+        Duration::from_secs(1)
+            .checked_sub(elapsed)
+            .unwrap_or(Duration::from_secs(1))
+    }
+
+    /// Pop the next datagram's worth of messages from the queue, along with how many
+    /// messages it contains
+    fn next_datagram(&self, messages: &mut dyn MessageQueue) -> Option<(Payload, usize)> {
+        let first = messages.pop()?;
+        let max_bytes = match (self.batch_size, self.gso) {
+            (Some(max_bytes), _) => max_bytes,
+            (None, Some(segment)) => {
+                let (buffer, count) =
+                    segments(queue::into_vec(first.payload), messages, segment.into());
+                return Some((queue::payload(buffer), count));
+            }
+            (None, None) => return Some((first.payload, 1)),
+        };
+        if let (true, Some(target)) = (self.group_by_target, first.target.clone()) {
+            let (datagram, count) = self.next_group(target, first.payload, messages, max_bytes);
+            return Some((queue::payload(datagram), count));
+        }
+        // With `with_batch_timestamps`, the first timed record sets the header's time
+        let mut base = None;
+        // Each record ends with a newline, which becomes the separator for `Array` framing
+        // and leaves only the surrounding brackets as overhead
+        let mut size = first.payload.len() + stamp_len(base, &first) + self.framing.overhead();
+        let max_count = self.max_batch_count.unwrap_or(usize::MAX);
+        let mut batch = vec![stamp(&mut base, first)];
+        // An oversized message is never split or combined: the batch before it ended at
+        // the peek below, and it goes out alone
+        if size > max_bytes {
+            return Some((queue::payload(self.finish_batch(&batch, base)), 1));
+        }
+        while let Some(next) = messages.peek() {
+            let len = next.payload.len() + stamp_len(base, next);
+            if size + len > max_bytes || batch.len() >= max_count {
+                break;
+            }
+            size += len;
+            batch.extend(messages.pop().map(|m| stamp(&mut base, m)));
+        }
+        Some((queue::payload(self.finish_batch(&batch, base)), batch.len()))
+    }
+
+    /// Pop the queued messages following `first` with the same target, grouped into a
+    /// datagram of up to `max_bytes`, along with how many messages it contains
+    fn next_group(
+        &self,
+        target: Arc<str>,
+        first: Payload,
+        messages: &mut dyn MessageQueue,
+        max_bytes: usize,
+    ) -> (Vec<u8>, usize) {
+        // `target: [` then each record with its newline replaced by `, `, or by `]\n` for
+        // the last, so one more byte per record
+        let mut size = target.len() + 3 + first.len() + 1;
+        let max_count = self.max_batch_count.unwrap_or(usize::MAX);
+        let mut batch = vec![first];
+        while let Some(next) = messages.peek() {
+            if next.target.as_ref() != Some(&target)
+                || size + next.payload.len() + 1 > max_bytes
+                || batch.len() >= max_count
+            {
+                break;
+            }
+            size += next.payload.len() + 1;
+            batch.extend(messages.pop().map(|m| m.payload));
+        }
+        let mut datagram = format::group(&target, &batch);
+        if self.strip_trailing_newline {
+            datagram.pop();
+        }
+        (datagram, batch.len())
+    }
+
+    /// Frame a batch of messages into a datagram, after a header line if the batch has
+    /// a `with_batch_timestamps` time
+    fn finish_batch(&self, batch: &[Payload], base: Option<DateTime<Utc>>) -> Vec<u8> {
+        let mut datagram = match base {
+            Some(base) => format::batch_header(base).into_bytes(),
+            None => Vec::new(),
+        };
+        datagram.extend(self.framing.join(batch));
+        if self.strip_trailing_newline && datagram.ends_with(b"\n") {
+            datagram.pop();
+        }
+        datagram
+    }
+}
+
+/// Most segments the kernel accepts in one GSO send
+const GSO_MAX_SEGMENTS: usize = 64;
+
+/// Largest UDP payload over IPv4, which bounds a GSO send as a whole
+const GSO_MAX_BYTES: usize = 65_507;
+
+/// Append queued messages to `first` as GSO segments, along with how many there are
+///
+/// Every segment but the last must be exactly `segment` bytes, so this stops after the
+/// first message of any other size. A lone message is sent normally, even if it's larger.
+fn segments(first: Vec<u8>, messages: &mut dyn MessageQueue, segment: usize) -> (Vec<u8>, usize) {
+    let mut buffer = first;
+    let mut count = 1;
+    while buffer.len() == count * segment && count < GSO_MAX_SEGMENTS {
+        match messages.peek() {
+            Some(next)
+                if next.payload.len() <= segment
+                    && buffer.len() + next.payload.len() <= GSO_MAX_BYTES => {}
+            _ => break,
+        }
+        if let Some(message) = messages.pop() {
+            buffer.extend_from_slice(&message.payload);
+        }
+        count += 1;
+    }
+    (buffer, count)
+}
+
+/// Prefix a message with its offset from the batch's `with_batch_timestamps` time, which
+/// the first timed message sets
+fn stamp(base: &mut Option<DateTime<Utc>>, message: Envelope) -> Payload {
+    match message.time {
+        Some(time) => {
+            let base = *base.get_or_insert(time);
+            let mut line = format::time_offset(base, time).into_bytes();
+            line.extend_from_slice(&message.payload);
+            queue::payload(line)
+        }
+        None => message.payload,
+    }
+}
+
+/// The bytes `stamp` adds to `message`, including the header line if it sets the time
+fn stamp_len(base: Option<DateTime<Utc>>, message: &Envelope) -> usize {
+    match (base, message.time) {
+        (_, None) => 0,
+        (Some(base), Some(time)) => format::time_offset(base, time).len(),
+        (None, Some(time)) => {
+            format::batch_header(time).len() + format::time_offset(time, time).len()
+        }
+    }
+}
+
+/// The handle of a UdpBufferedWriter's drain thread, shared with the thread to replace itself
+type DrainThread = Arc<Mutex<Option<thread::JoinHandle<()>>>>;
+
+/// Lock a drain thread handle, even if a thread panicked while holding it
+fn lock_thread(thread: &DrainThread) -> MutexGuard<'_, Option<thread::JoinHandle<()>>> {
+    thread.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The message queue shared between a UdpBufferedWriter and its drain thread
+struct SharedQueue {
+    messages: Mutex<Box<dyn MessageQueue>>,
+    /// Messages popped from the queue that the drain thread is still sending
+    in_flight: AtomicUsize,
+    /// Set when the writer is dropped to stop the drain thread
+    shutdown: AtomicBool,
+    /// Set when the writer stops waiting for the flush on drop, to stop it early
+    abandoned: AtomicBool,
+    /// When a message was last queued since the last drain started, for
+    /// `with_idle_flush`, as nanoseconds after `created` plus one, or 0 if none was
+    last_push: AtomicU64,
+    created: Instant,
+    /// Drain threads running, only 0 once the last one has exited (e.g. panicked)
+    drains: AtomicUsize,
+}
+
+/// Counts a drain thread as running until it exits, even by panicking
+struct RunningDrain(Arc<SharedQueue>);
+
+impl Drop for RunningDrain {
+    fn drop(&mut self) {
+        self.0.drains.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl SharedQueue {
+    fn new(messages: Box<dyn MessageQueue>) -> Self {
+        Self {
+            messages: Mutex::new(messages),
+            in_flight: AtomicUsize::new(0),
+            shutdown: AtomicBool::new(false),
+            abandoned: AtomicBool::new(false),
+            last_push: AtomicU64::new(0),
+            created: Instant::now(),
+            drains: AtomicUsize::new(0),
+        }
+    }
+
+    /// Lock the queue, even if a drain thread panicked while holding it
+    fn lock(&self) -> MutexGuard<'_, Box<dyn MessageQueue>> {
+        self.messages.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Copy up to `max` queued messages, holding the lock only while copying
+    fn peek(&self, max: usize) -> Vec<Envelope> {
+        self.lock().peek_many(max)
+    }
+
+    /// Queue messages, counting a drop each time the queue evicts one
+    fn push(&self, batch: impl IntoIterator<Item = Envelope>, stats: &Stats) {
+        let mut messages = self.lock();
+        for message in batch {
+            for _ in 0..messages.push_counted(message) {
+                stats.record_drop();
+            }
+        }
+        let since = self.created.elapsed().as_nanos() as u64;
+        self.last_push.store(since + 1, Ordering::SeqCst);
+    }
+
+    /// Forget earlier pushes as a drain starts, so messages it leaves queued (e.g. past
+    /// `with_max_sends_per_flush`) wait for the next interval rather than counting as idle
+    fn start_drain(&self) {
+        self.last_push.store(0, Ordering::SeqCst);
+    }
+
+    /// How long messages queued since the last drain started have waited for another
+    /// to join them, `None` if none were queued or the queue is empty
+    fn idle_for(&self, now: Instant) -> Option<Duration> {
+        let messages = self.lock();
+        let last_push = match self.last_push.load(Ordering::SeqCst) {
+            0 => return None,
+            _ if messages.is_empty() => return None,
+            since => self.created + Duration::from_nanos(since - 1),
+        };
+        Some(now.saturating_duration_since(last_push))
+    }
+
+    /// Messages that have not yet been handed to the socket
+    fn pending(&self) -> usize {
+        // `in_flight` is set while holding the lock, so reading it under the lock
+        // can't miss a datagram between being popped and being sent
+        let messages = self.lock();
+        messages.len() + self.in_flight.load(Ordering::SeqCst)
+    }
+}
+
+/// UdpBufferedWriter is an alternate UdpWriter that buffers submitted messages
+/// and sends in a background thread
+struct UdpBufferedWriter {
+    queue: Arc<SharedQueue>,
+    /// Shared with the drain thread and `fallback`
+    out: SharedSocket,
+    destination: SocketAddr,
+    socket: SocketOptions,
+    options: BufferedOptions,
+    stats: Arc<Stats>,
+    /// The current drain thread, replaced by the thread itself with `with_thread_recycle`
+    drain: DrainThread,
+    /// Set once the drain thread is found dead and `fallback` has taken over
+    failed: AtomicBool,
+    /// Sends from the logging thread if the drain thread dies, sharing its socket
+    fallback: Mutex<Drain>,
+}
+
+impl UdpBufferedWriter {
+    /// If the drain thread has died (i.e. panicked), send everything queued from this thread
+    ///
+    /// The drain thread only exits on its own after `shutdown` is set
+    fn fall_back_if_dead(&self) {
+        if self.queue.drains.load(Ordering::SeqCst) > 0 {
+            return;
+        }
+        if !self.failed.swap(true, Ordering::SeqCst) {
+            self.stats.record_drain_failure();
+            // Whatever the drain thread was sending when it died is gone
+            self.queue.in_flight.store(0, Ordering::SeqCst);
+            eprintln!("Drain thread died, sending from the logging thread instead");
+        }
+        self.fallback
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .drain();
+    }
+}
+
+impl UdpBufferedWriter {
+    /// Create a new UdpBufferedWriter that sends messages to the given destination `SocketAddr`
+    pub fn new(
+        destination: impl ToSocketAddrs + Debug,
+        options: &BufferedOptions,
+        queue: Box<dyn MessageQueue>,
+        socket: &SocketOptions,
+        stats: Arc<Stats>,
+    ) -> io::Result<Self> {
+        let queue = Arc::new(SharedQueue::new(queue));
+        let destination = socket.target(resolve(destination)?);
+        let out = socket.bind_shared(destination)?;
+        let fallback = Drain::new(
+            out.clone(),
+            socket,
+            destination,
+            options,
+            queue.clone(),
+            stats.clone(),
+        );
+        let drain = Drain::new(
+            out.clone(),
+            socket,
+            destination,
+            options,
+            queue.clone(),
+            stats.clone(),
+        );
+        let thread = DrainThread::default();
+        drain.spawn(&thread);
+        Ok(Self {
+            queue,
+            out,
+            destination,
+            socket: socket.clone(),
+            options: options.clone(),
+            stats,
+            drain: thread,
+            failed: AtomicBool::new(false),
+            fallback: Mutex::new(fallback),
+        })
+    }
+}
+
+impl Drop for UdpBufferedWriter {
+    fn drop(&mut self) {
+        if self.options.flush_on_drop {
+            self.fall_back_if_dead();
+        }
+        self.queue.shutdown.store(true, Ordering::SeqCst);
+        let deadline = self
+            .options
+            .drop_flush_timeout
+            .map(|timeout| Instant::now() + timeout);
+        // A recycling thread may have handed over to a new one by the time it's joined
+        while let Some(drain) = lock_thread(&self.drain).take() {
+            drain.thread().unpark();
+            if !self.options.flush_on_drop {
+                return;
+            }
+            if let Some(deadline) = deadline {
+                while !drain.is_finished() {
+                    if Instant::now() >= deadline {
+                        return self.abandon_flush();
+                    }
+                    thread::sleep(Duration::from_millis(1));
+                }
+            }
+            drain.join().ok();
+        }
+    }
+}
+
+impl UdpBufferedWriter {
+    /// Stop the drain thread flushing on drop, reporting what's left unsent
+    fn abandon_flush(&self) {
+        self.queue.abandoned.store(true, Ordering::SeqCst);
+        eprintln!(
+            "udp_logger: gave up flushing on drop, {} messages unsent",
+            self.queue.pending()
+        );
+    }
+}
+
+impl Writer for UdpBufferedWriter {
+    fn push(&self, message: Envelope) -> io::Result<()> {
+        self.queue.push([message], &self.stats);
+        self.fall_back_if_dead();
+        Ok(())
+    }
+
+    fn push_batch(&self, messages: Vec<Envelope>) -> io::Result<()> {
+        self.queue.push(messages, &self.stats);
+        self.fall_back_if_dead();
+        Ok(())
+    }
+
+    fn peek_queue(&self, max: usize) -> Vec<Envelope> {
+        self.queue.peek(max)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.out.load().local_addr()
+    }
+
+    fn reconnect(&self) -> io::Result<()> {
+        self.socket.rebind(&self.out, self.destination)?;
+        self.stats.record_rebind();
+        Ok(())
+    }
+
+    fn probe(&self, timeout: Duration) -> bool {
+        self.socket.probe(self.destination, timeout)
+    }
+
+    fn destination(&self) -> Option<SocketAddr> {
+        Some(self.destination)
+    }
+
+    fn take_error(&self) -> io::Result<Option<io::Error>> {
+        self.out.load().take_error()
+    }
+
+    fn wake(&self) {
+        if let Some(drain) = lock_thread(&self.drain).as_ref() {
+            drain.thread().unpark();
+        }
+    }
+
+    fn flush_timeout(&self, timeout: Duration) -> usize {
+        self.fall_back_if_dead();
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = self.queue.pending();
+            let now = Instant::now();
+            if remaining == 0 || now >= deadline {
+                return remaining;
+            }
+            thread::sleep((deadline - now).min(Duration::from_millis(5)));
+        }
+    }
+}
+
+/// ManualWriter buffers submitted messages until they're drained by the caller
+struct ManualWriter {
+    queue: Arc<SharedQueue>,
+    drain: Mutex<Drain>,
+    /// Shared with `drain`
+    out: SharedSocket,
+    destination: SocketAddr,
+    socket: SocketOptions,
+    stats: Arc<Stats>,
+}
+
+impl ManualWriter {
+    /// Create a new ManualWriter that sends messages to the given destination `SocketAddr`
+    pub fn new(
+        destination: impl ToSocketAddrs + Debug,
+        options: &BufferedOptions,
+        queue: Box<dyn MessageQueue>,
+        socket: &SocketOptions,
+        stats: Arc<Stats>,
+    ) -> io::Result<Self> {
+        let queue = Arc::new(SharedQueue::new(queue));
+        let destination = socket.target(resolve(destination)?);
+        let out = socket.bind_shared(destination)?;
+        let drain = Drain::new(
+            out.clone(),
+            socket,
+            destination,
+            options,
+            queue.clone(),
+            stats.clone(),
+        );
+        Ok(Self {
+            queue,
+            drain: Mutex::new(drain),
+            out,
+            destination,
+            socket: socket.clone(),
+            stats,
+        })
+    }
+}
+
+impl Writer for ManualWriter {
+    fn push(&self, message: Envelope) -> io::Result<()> {
+        self.queue.push([message], &self.stats);
+        Ok(())
+    }
+
+    fn push_batch(&self, messages: Vec<Envelope>) -> io::Result<()> {
+        self.queue.push(messages, &self.stats);
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use log::info;
+    fn peek_queue(&self, max: usize) -> Vec<Envelope> {
+        self.queue.peek(max)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.out.load().local_addr()
+    }
+
+    fn reconnect(&self) -> io::Result<()> {
+        self.socket.rebind(&self.out, self.destination)?;
+        self.stats.record_rebind();
+        Ok(())
+    }
+
+    fn probe(&self, timeout: Duration) -> bool {
+        self.socket.probe(self.destination, timeout)
+    }
+
+    fn destination(&self) -> Option<SocketAddr> {
+        Some(self.destination)
+    }
+
+    fn take_error(&self) -> io::Result<Option<io::Error>> {
+        self.out.load().take_error()
+    }
+
+    /// Nothing is sent without `drain`, so this returns how many are queued immediately
+    fn flush_timeout(&self, _timeout: Duration) -> usize {
+        self.queue.pending()
+    }
+
+    fn drain(&self) -> usize {
+        let mut drain = self.drain.lock().unwrap();
+        let sent = drain.drain();
+        drain.report_metrics();
+        sent
+    }
+}
+
+/// How many times the drain thread retries a congested send before dropping it
+const CONGESTED_RETRIES: u32 = 5;
+
+/// The drain thread's first pause before retrying a congested send, doubling each time
+const CONGESTED_BACKOFF: Duration = Duration::from_millis(1);
+
+/// The background thread of a UdpBufferedWriter
+///
+/// A single drain sends every queued message in FIFO order, which is what upholds
+/// the `with_ordered` guarantee
+struct Drain {
+    out: SharedSocket,
+    socket: SocketOptions,
+    destination: SocketAddr,
+    options: BufferedOptions,
+    queue: Arc<SharedQueue>,
+    stats: Arc<Stats>,
+    /// When any datagram (including heartbeats) was last successfully sent
+    last_send: Instant,
+    /// When the last queued datagram was sent, used for pacing
+    last_datagram: Option<Instant>,
+    /// When the last drop notice was sent
+    last_notice: Option<Instant>,
+    /// When self-metrics were last reported
+    last_metrics: Instant,
+    /// Cleared if a GSO send fails because the kernel or device doesn't support it
+    gso_supported: bool,
+    last_sent: LastSent,
+}
+
+impl Drain {
+    fn new(
+        out: SharedSocket,
+        socket: &SocketOptions,
+        destination: SocketAddr,
+        options: &BufferedOptions,
+        queue: Arc<SharedQueue>,
+        stats: Arc<Stats>,
+    ) -> Self {
+        Self {
+            out,
+            socket: socket.clone(),
+            destination,
+            options: options.clone(),
+            queue,
+            stats,
+            last_send: Instant::now(),
+            last_datagram: None,
+            last_notice: None,
+            last_metrics: Instant::now(),
+            gso_supported: true,
+            last_sent: LastSent::default(),
+        }
+    }
+
+    /// Run on a new thread, stored in `thread`
+    fn spawn(self, thread: &DrainThread) {
+        // Locked until the handle is stored, in case the new thread recycles right away
+        let mut current = lock_thread(thread);
+        let successor = thread.clone();
+        // Counted before this thread exits, so a recycle never looks like a dead drain
+        self.queue.drains.fetch_add(1, Ordering::SeqCst);
+        *current = Some(thread::spawn(move || self.run(successor)));
+    }
+
+    fn run(mut self, thread: DrainThread) {
+        let _running = RunningDrain(self.queue.clone());
+        let started = Instant::now();
+        loop {
+            if self.queue.shutdown.load(Ordering::SeqCst) {
+                if self.options.flush_on_drop {
+                    self.drain();
+                }
+                return;
+            }
+            if self.socket.is_paused() {
+                thread::park_timeout(self.options.wait(self.options.clock.now()));
+                continue;
+            }
+            self.drain();
+            if let Some(interval) = self.options.heartbeat {
+                if self.last_send.elapsed() >= interval {
+                    let now = self.options.clock.now();
+                    let message = format!("HEARTBEAT [{}]\n", now.to_rfc3339());
+                    self.send(message.as_bytes())
+                        .map_err(|e| eprintln!("Error sending heartbeat: {}", e))
+                        .ok();
+                    self.last_send = Instant::now();
+                }
+            }
+            self.report_metrics();
+            if let Some(lifetime) = self.options.thread_recycle {
+                if started.elapsed() >= lifetime {
+                    self.options.events.emit(LoggerEvent::ThreadRespawned);
+                    return self.spawn(&thread);
+                }
+            }
+            // Parked rather than slept so a dropped writer can wake the drain immediately
+            self.park();
+        }
+    }
+
+    /// Wait until the next drain, or until queued messages have been idle for
+    /// `with_idle_flush`, whichever comes first
+    fn park(&self) {
+        let wait = self.options.wait(self.options.clock.now());
+        let idle = match self.options.idle_flush {
+            Some(idle) => idle,
+            None => return thread::park_timeout(wait),
+        };
+        let deadline = Instant::now() + wait;
+        loop {
+            let now = Instant::now();
+            if now >= deadline || self.queue.shutdown.load(Ordering::SeqCst) {
+                return;
+            }
+            // Nothing queued yet is checked on again after a full idle period
+            let next = match self.queue.idle_for(now) {
+                Some(idle_for) if idle_for >= idle => return,
+                Some(idle_for) => idle - idle_for,
+                None => idle,
+            };
+            thread::park_timeout(next.min(deadline - now));
+        }
+    }
+
+    /// Send the logger's stats if the self-metrics interval has elapsed
+    fn report_metrics(&mut self) {
+        match self.options.self_metrics {
+            Some(interval) if self.last_metrics.elapsed() >= interval => {}
+            _ => return,
+        }
+        let stats = self.stats.snapshot();
+        let message = format!(
+            "UDP_LOGGER_METRICS [{}] sent={} dropped={} errors={} queue_depth={}\n",
+            self.options.clock.now().to_rfc3339(),
+            stats.sent,
+            stats.dropped,
+            stats.errors,
+            self.queue.pending()
+        );
+        self.send(message.as_bytes())
+            .map_err(|e| eprintln!("Error sending self-metrics: {}", e))
+            .ok();
+        self.last_metrics = Instant::now();
+    }
+
+    /// Send a drop notice if messages were dropped and the notice interval has elapsed
+    fn notify_drops(&mut self) {
+        let notice = match &self.options.drop_notice {
+            Some(notice) => notice,
+            None => return,
+        };
+        if self
+            .last_notice
+            .is_some_and(|last| last.elapsed() < notice.interval)
+        {
+            return;
+        }
+        let count = self.stats.take_unreported_drops();
+        if count == 0 {
+            return;
+        }
+        let message = notice.render(count, &self.options.clock.now().to_rfc3339());
+        self.send(message.as_bytes())
+            .map_err(|e| eprintln!("Error sending drop notice: {}", e))
+            .ok();
+        self.last_notice = Some(Instant::now());
+    }
+
+    /// Send everything currently queued, returning how many messages were sent
+    fn drain(&mut self) -> usize {
+        self.queue.start_drain();
+        self.notify_drops();
+        let mut sent = 0;
+        let mut sends = 0;
+        loop {
+            let shutdown = self.queue.shutdown.load(Ordering::SeqCst);
+            if shutdown
+                && (!self.options.flush_on_drop || self.queue.abandoned.load(Ordering::SeqCst))
+            {
+                return sent;
+            }
+            match self.options.max_sends_per_flush {
+                Some(max) if sends >= max && !shutdown => return sent,
+                _ => {}
+            }
+            // Queued messages are held while paused, but still flushed on shutdown
+            if self.socket.is_paused() && !shutdown {
+                return sent;
+            }
+            let datagram = {
+                let mut messages = self.queue.lock();
+                let expired = match (self.options.ttl, self.options.level_ttls.is_empty()) {
+                    (Some(ttl), true) => queue::expire(messages.as_mut(), ttl, Instant::now()),
+                    (None, true) => 0,
+                    (_, false) => queue::expire_each(
+                        messages.as_mut(),
+                        |m| self.options.ttl_for(m.level),
+                        Instant::now(),
+                    ),
+                };
+                self.stats.record_expired(expired);
+                // Copies are only kept if a failed send may requeue them
+                let next = match self.options.max_requeues {
+                    Some(_) => {
+                        let mut recording = queue::Recording::new(messages.as_mut());
+                        let next = self.options.next_datagram(&mut recording);
+                        next.map(|(datagram, count)| (datagram, count, recording.popped))
+                    }
+                    None => self
+                        .options
+                        .next_datagram(messages.as_mut())
+                        .map(|(datagram, count)| (datagram, count, Vec::new())),
+                };
+                if let Some((_, count, _)) = &next {
+                    self.queue.in_flight.store(*count, Ordering::SeqCst);
+                }
+                next
+            };
+            let (datagram, count, mut popped) = match datagram {
+                Some(next) => next,
+                None => return sent,
+            };
+            if let (Some(pacing), Some(last)) = (self.options.pacing, self.last_datagram) {
+                thread::sleep(pacing.saturating_sub(last.elapsed()));
+            }
+            let (delivered, result) = match self.options.gso {
+                Some(segment) if count > 1 && self.options.batch_size.is_none() => {
+                    self.send_segments(&datagram, segment, count, &mut popped)
+                }
+                _ => {
+                    let result = self.send(&datagram);
+                    (if result.is_ok() { count } else { 0 }, result)
+                }
+            };
+            self.last_datagram = Some(Instant::now());
+            sends += 1;
+            sent += delivered;
+            let requeued = match result {
+                Ok(()) => 0,
+                Err(e) => {
+                    eprintln!("Error sending message: {}", e);
+                    self.requeue(popped)
+                }
+            };
+            // Cleared once a failed send is back in the queue, so `pending` never misses it
+            self.queue.in_flight.store(0, Ordering::SeqCst);
+            // Retried on a later pass rather than straight away
+            if requeued > 0 {
+                return sent;
+            }
+        }
+    }
+
+    /// Put messages from a failed send back in the queue if they have requeues left,
+    /// returning how many were requeued
+    fn requeue(&self, popped: Vec<Envelope>) -> usize {
+        let max_requeues = match self.options.max_requeues {
+            Some(max_requeues) => max_requeues,
+            None => return 0,
+        };
+        let requeued: Vec<Envelope> = popped
+            .into_iter()
+            .filter(|message| message.requeues < max_requeues)
+            .map(|mut message| {
+                message.requeues += 1;
+                self.stats.record_requeue();
+                message
+            })
+            .collect();
+        let count = requeued.len();
+        self.queue.push(requeued, &self.stats);
+        count
+    }
+
+    /// Send `count` messages laid out as `segment`-byte segments, in one syscall if possible,
+    /// returning how many were sent
+    ///
+    /// Sent one at a time, the segments that were sent are removed from `popped`, so only
+    /// the failed ones can be requeued.
+    fn send_segments(
+        &mut self,
+        buffer: &[u8],
+        segment: u16,
+        count: usize,
+        popped: &mut Vec<Envelope>,
+    ) -> (usize, io::Result<()>) {
+        // Sealing changes each datagram's size, so sealed datagrams are sent one at a time
+        #[cfg(target_os = "linux")]
+        if self.gso_supported && !self.socket.seals() {
+            let out = self.out.load();
+            match self
+                .stats
+                .timed(|| socket::send_segments(&out, buffer, segment, self.destination))
+            {
+                Ok(()) => {
+                    (0..count).for_each(|_| self.stats.record_send());
+                    if self.socket.on_sent.is_some() {
+                        buffer
+                            .chunks(segment.into())
+                            .for_each(|datagram| self.socket.sent(datagram));
+                    }
+                    self.last_send = Instant::now();
+                    return (count, Ok(()));
+                }
+                Err(e) if socket::is_gso_unsupported(&e) => self.gso_supported = false,
+                // Sent one at a time below, which retries or counts the failure
+                Err(_) => {}
+            }
+        }
+        let mut result = Ok(());
+        let mut failed = Vec::with_capacity(count);
+        for datagram in buffer.chunks(segment.into()) {
+            let sent = self.send(datagram);
+            failed.push(sent.is_err());
+            result = result.and(sent);
+        }
+        let sent = failed.iter().filter(|failed| !**failed).count();
+        let mut failed = failed.into_iter();
+        popped.retain(|_| failed.next().unwrap_or(false));
+        (sent, result)
+    }
+
+    fn send(&mut self, datagram: &[u8]) -> io::Result<()> {
+        let now = Instant::now();
+        if self.socket.suppress_identical && self.last_sent.is_repeat(datagram, now) {
+            self.stats.record_suppressed();
+            return Ok(());
+        }
+        let sealed = self.socket.seal(datagram)?;
+        let mut retries = 0;
+        loop {
+            let out = self.out.load();
+            match self
+                .stats
+                .timed(|| self.socket.send(&out, &sealed, self.destination))
+            {
+                Ok(_) => break,
+                // A full send buffer drains by itself, so the same datagram is retried
+                Err(e) if socket::is_congested(&e) && retries < CONGESTED_RETRIES => {
+                    self.stats.record_congestion_pause();
+                    thread::sleep(CONGESTED_BACKOFF * 2u32.pow(retries));
+                    retries += 1;
+                }
+                Err(e) if socket::is_congested(&e) => {
+                    self.stats.record_drop();
+                    return Err(e);
+                }
+                Err(e) => {
+                    self.stats.record_error();
+                    return Err(e);
+                }
+            }
+        }
+        self.stats.record_send();
+        self.socket.sent(&sealed);
+        if self.socket.suppress_identical {
+            self.last_sent.record(datagram, now);
+        }
+        self.last_send = Instant::now();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::{info, Log};
+    use std::collections::VecDeque;
+    use std::net::UdpSocket;
+
+    #[test]
+    fn test_new() {
+        UdpLogger::new("127.0.0.1:1999")
+            .expect("Can bind to localhost")
+            .set_level(Level::Debug);
+    }
+
+    #[test]
+    fn test_message_queue() {
+        let _ = UdpLogger::new("127.0.0.1:1999")
+            .expect("Can bind to localhost")
+            .set_level(Level::Debug);
+
+        info!("testing");
+    }
+
+    #[test]
+    fn test_try_init_returns_err() {
+        // A destination without a port can never be resolved
+        assert!(UdpLoggerBuilder::try_init("not-an-address", Level::Info).is_err());
+    }
+
+    #[test]
+    fn test_resolve_error_names_input() {
+        let err = UdpLogger::new("not-an-address").err().unwrap();
+        assert!(err.to_string().contains("\"not-an-address\""));
+
+        let none: &[SocketAddr] = &[];
+        let err = UdpLogger::new_buffered(none).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AddrNotAvailable);
+        assert!(err.to_string().contains("[]"));
+    }
+
+    #[test]
+    fn test_unspecified_destination() {
+        for destination in ["0.0.0.0:0", "0.0.0.0:1999", "127.0.0.1:0", "[::]:1999"] {
+            let err = UdpLogger::new(destination).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert!(err.to_string().contains("unspecified"));
+        }
+    }
+
+    #[test]
+    fn test_zero_port_destination() {
+        for destination in ["127.0.0.1:0", "[::1]:0", "localhost:0"] {
+            let err = UdpLoggerBuilder::new(destination)
+                .build()
+                .err()
+                .expect("Port 0 is rejected");
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert!(err.to_string().contains("unspecified port"), "{}", err);
+        }
+        let err = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .add_destination_with_formatter("127.0.0.1:0", |r| r.args().to_string())
+            .build()
+            .err()
+            .expect("Port 0 is rejected");
+        assert!(err.to_string().contains("unspecified port"), "{}", err);
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let _logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .buffered()
+            .with_heartbeat(Duration::from_millis(100))
+            .build()
+            .expect("Can bind to localhost");
+
+        let mut buf = [0; 1024];
+        let len = receiver.recv(&mut buf).expect("Heartbeat arrives");
+        assert!(String::from_utf8_lossy(&buf[..len]).starts_with("HEARTBEAT ["));
+    }
+
+    #[test]
+    fn test_drop_reporting() {
+        let recv = || {
+            let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+            receiver
+                .set_read_timeout(Some(Duration::from_secs(2)))
+                .unwrap();
+            receiver
+        };
+        let (logs, reports) = (recv(), recv());
+        let logger = UdpLoggerBuilder::new(logs.local_addr().unwrap())
+            .manual()
+            .with_queue_limit(1)
+            .with_drop_reporting(reports.local_addr().unwrap(), Duration::from_millis(50))
+            .build()
+            .expect("Can bind to localhost");
+        for i in 0..3 {
+            logger.send_raw(&format!("message {}", i)).unwrap();
+        }
+
+        let mut buf = [0; 1024];
+        let len = reports.recv(&mut buf).expect("Report arrives");
+        let report = String::from_utf8_lossy(&buf[..len]);
+        assert!(report.starts_with("UDP_LOGGER_DROPS ["), "{}", report);
+        assert!(
+            report.ends_with("] queue_full=2 ttl_expired=0 send_error=0 interval=50ms\n"),
+            "{}",
+            report
+        );
+        // Nothing more is reported until something else is lost
+        reports
+            .set_read_timeout(Some(Duration::from_millis(150)))
+            .unwrap();
+        assert!(reports.recv(&mut buf).is_err());
+        assert_eq!(logger.drain(), 1);
+        assert!(logs.recv(&mut buf).is_ok());
+
+        let err = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_drop_reporting(reports.local_addr().unwrap(), Duration::ZERO)
+            .build()
+            .err()
+            .expect("Zero interval");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_self_metrics() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .buffered()
+            .with_self_metrics(Duration::from_millis(200))
+            .build()
+            .expect("Can bind to localhost");
+        logger.send_raw("testing").unwrap();
+
+        let mut buf = [0; 1024];
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        assert_eq!(&buf[..len], b"testing\n");
+        let len = receiver.recv(&mut buf).expect("Metrics arrive");
+        let metrics = String::from_utf8_lossy(&buf[..len]);
+        assert!(metrics.starts_with("UDP_LOGGER_METRICS ["), "{}", metrics);
+        assert!(
+            metrics.ends_with(" sent=1 dropped=0 errors=0 queue_depth=0\n"),
+            "{}",
+            metrics
+        );
+    }
+
+    #[test]
+    fn test_flush_timeout() {
+        let logger = UdpLogger::new("127.0.0.1:1999").expect("Can bind to localhost");
+        assert_eq!(logger.flush_timeout(Duration::from_secs(1)), 0);
+
+        let logger = UdpLogger::new_buffered("127.0.0.1:1999").expect("Can bind to localhost");
+        logger
+            .writer
+            .load()
+            .push("testing\n".to_string().into())
+            .unwrap();
+        assert_eq!(logger.flush_timeout(Duration::from_secs(1)), 0);
+    }
+
+    #[test]
+    fn test_flush_timeout_stalled() {
+        // A drain thread that won't wake up for an hour leaves everything queued
+        let options = BufferedOptions {
+            interval: Duration::from_secs(3600),
+            ..Default::default()
+        };
+        let writer = UdpBufferedWriter::new(
+            "127.0.0.1:1999",
+            &options,
+            Box::new(VecDeque::new()),
+            &SocketOptions::default(),
+            Arc::default(),
+        )
+        .unwrap();
+        thread::sleep(Duration::from_millis(50));
+        for _ in 0..3 {
+            writer.push("testing\n".to_string().into()).unwrap();
+        }
+        assert_eq!(writer.flush_timeout(Duration::from_millis(50)), 3);
+    }
+
+    #[test]
+    fn test_json_batching() {
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_json()
+            .build()
+            .expect("Can bind to localhost");
+        let records: VecDeque<Envelope> = (0..3)
+            .map(|i| {
+                logger
+                    .format(
+                        &Record::builder()
+                            .args(format_args!("record {}", i))
+                            .level(Level::Info)
+                            .build(),
+                    )
+                    .into()
+            })
+            .collect();
+
+        let options = BufferedOptions {
+            batch_size: Some(1024),
+            ..Default::default()
+        };
+        let datagram = options.next_datagram(&mut records.clone()).unwrap().0;
+        let datagram = String::from_utf8(datagram.to_vec()).unwrap();
+        assert_eq!(datagram.lines().count(), 3);
+        for line in datagram.lines() {
+            serde_json::from_str::<serde_json::Value>(line).expect("Valid JSON line");
+        }
+
+        let options = BufferedOptions {
+            batch_size: Some(1024),
+            framing: JsonFraming::Array,
+            ..Default::default()
+        };
+        let datagram = options.next_datagram(&mut records.clone()).unwrap().0;
+        let parsed: serde_json::Value = serde_json::from_slice(&datagram).expect("Valid JSON");
+        assert_eq!(parsed.as_array().unwrap().len(), 3);
+
+        // Batches never exceed the size limit
+        let options = BufferedOptions {
+            batch_size: Some(records[0].payload.len() + records[1].payload.len()),
+            ..Default::default()
+        };
+        let mut queue = records;
+        let (datagram, count) = options.next_datagram(&mut queue).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(datagram.split(|b| *b == b'\n').count() - 1, 2);
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn test_oversized_batch_boundaries() {
+        let mut queue: VecDeque<Envelope> = ["aaaa", "bbbb", &"x".repeat(30), "cccc", "dddd"]
+            .iter()
+            .map(|m| Envelope::from(format!("{}\n", m)))
+            .collect();
+        let options = BufferedOptions {
+            batch_size: Some(12),
+            ..Default::default()
+        };
+        let mut datagrams = Vec::new();
+        while let Some((datagram, count)) = options.next_datagram(&mut queue) {
+            datagrams.push((String::from_utf8(datagram.to_vec()).unwrap(), count));
+        }
+        assert_eq!(
+            datagrams,
+            [
+                ("aaaa\nbbbb\n".to_string(), 2),
+                (format!("{}\n", "x".repeat(30)), 1),
+                ("cccc\ndddd\n".to_string(), 2),
+            ]
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_gso() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .manual()
+            .with_gso(8)
+            .build()
+            .expect("Can bind to localhost");
+        let messages = ["record1", "record2", "last", "oversized message", "record3"];
+        for message in messages {
+            logger.send_raw(message).unwrap();
+        }
+        assert_eq!(logger.drain(), messages.len());
+        assert_eq!(logger.stats().sent, messages.len() as u64);
+
+        let mut buf = [0; 1024];
+        for expected in messages {
+            let len = receiver.recv(&mut buf).expect("Message arrives");
+            assert_eq!(&buf[..len], format!("{}\n", expected).as_bytes());
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_gso_partial_failure() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        // The prefix sends segments one at a time, and pushes the first past the UDP limit
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .manual()
+            .with_gso(u16::MAX)
+            .with_magic_prefix(b"UL".to_vec())
+            .build()
+            .expect("Can bind to localhost");
+        logger
+            .send_raw(&"x".repeat(usize::from(u16::MAX) - 1))
+            .unwrap();
+        logger.send_raw("short").unwrap();
+        assert_eq!(logger.drain(), 1);
+        let stats = logger.stats();
+        assert_eq!((stats.sent, stats.errors), (1, 1));
+
+        let mut buf = [0; 1024];
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        assert_eq!(&buf[..len], b"ULshort\n");
+    }
+
+    #[test]
+    fn test_gso_segments() {
+        let mut queue: VecDeque<Envelope> = ["aaaa", "bbbb", "cc", "dddd"]
+            .iter()
+            .map(|m| Envelope::from(m.to_string()))
+            .collect();
+        assert_eq!(
+            segments(queue::into_vec(queue.pop().unwrap().payload), &mut queue, 4),
+            (b"aaaabbbbcc".to_vec(), 3)
+        );
+        assert_eq!(segments(b"toolong".to_vec(), &mut queue, 4).1, 1);
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn test_sent_callback() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let unbuffered = {
+            let sent = sent.clone();
+            UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+                .with_formatter(|record| format!("{}\n", record.args()))
+                .with_sent_callback(Box::new(move |datagram| {
+                    sent.lock().unwrap().push(datagram.to_vec())
+                }))
+                .build()
+                .expect("Can bind to localhost")
+        };
+        let manual = {
+            let sent = sent.clone();
+            UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+                .manual()
+                .with_formatter(|record| format!("{}\n", record.args()))
+                .with_sent_callback(Box::new(move |datagram| {
+                    sent.lock().unwrap().push(datagram.to_vec())
+                }))
+                .build()
+                .expect("Can bind to localhost")
+        };
+        for logger in [&unbuffered, &manual].iter() {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("testing"))
+                    .level(Level::Info)
+                    .build(),
+            );
+        }
+        assert_eq!(sent.lock().unwrap().len(), 1, "manual hasn't sent yet");
+        assert_eq!(manual.drain(), 1);
+        assert_eq!(*sent.lock().unwrap(), [b"testing\n", b"testing\n"]);
+    }
+
+    #[test]
+    fn test_binary_header() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .with_timestamp_format(TimestampFormat::None)
+            .with_format(Format::Raw)
+            .with_binary_header(true)
+            .build()
+            .expect("Can bind to localhost");
+        let before = Utc::now().timestamp_millis() as u64;
+        for level in [Level::Warn, Level::Debug, Level::Error].iter() {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("compact"))
+                    .level(*level)
+                    .build(),
+            );
+        }
+
+        let mut buf = [0; 1024];
+        for (sequence, level) in [(0u32, 2u8), (1, 1)].iter() {
+            let len = receiver.recv(&mut buf).unwrap();
+            let datagram = &buf[..len];
+            assert_eq!(datagram[0], *level);
+            assert_eq!(datagram[1], 0);
+            let message_len = u16::from_be_bytes([datagram[2], datagram[3]]);
+            assert_eq!(usize::from(message_len), len - 16);
+            let mut millis = [0; 8];
+            millis.copy_from_slice(&datagram[4..12]);
+            let millis = u64::from_be_bytes(millis);
+            assert!(millis >= before && millis < before + 2000, "{}", millis);
+            let mut seq = [0; 4];
+            seq.copy_from_slice(&datagram[12..16]);
+            assert_eq!(u32::from_be_bytes(seq), *sequence);
+            assert_eq!(&datagram[16..], b"compact\n");
+        }
+    }
+
+    #[test]
+    fn test_payload_header() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .manual()
+            .with_batching(1024)
+            .with_formatter(|record| format!("{}\n", record.args()))
+            .with_payload_header("V1;utf-8;")
+            .build()
+            .expect("Can bind to localhost");
+        for message in ["first", "second"].iter() {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(Level::Info)
+                    .build(),
+            );
+        }
+        assert_eq!(logger.drain(), 2);
+
+        let mut buf = [0; 1024];
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        assert_eq!(&buf[..len], b"V1;utf-8;first\nsecond\n");
+    }
+
+    #[test]
+    fn test_aligned_flush() {
+        let at = |time: &str| {
+            DateTime::parse_from_rfc3339(time)
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+        let options = BufferedOptions {
+            aligned_flush: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            options.wait(at("2020-06-15T03:15:39.250+00:00")),
+            Duration::from_millis(750)
+        );
+        assert_eq!(
+            options.wait(at("2020-06-15T03:15:39.999+00:00")),
+            Duration::from_millis(1)
+        );
+        // Just flushed on the boundary, so wait for the next one
+        assert_eq!(
+            options.wait(at("2020-06-15T03:15:40+00:00")),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            BufferedOptions::default().wait(at("2020-06-15T03:15:39.250+00:00")),
+            Duration::from_millis(50)
+        );
+    }
+
+    #[test]
+    fn test_strip_trailing_newline() {
+        let queue: VecDeque<Envelope> = (0..3)
+            .map(|i| Envelope::from(format!("record {}\n", i)))
+            .collect();
+        let mut options = BufferedOptions {
+            batch_size: Some(1024),
+            ..Default::default()
+        };
+        let datagram = options.next_datagram(&mut queue.clone()).unwrap().0;
+        assert_eq!(&datagram[..], b"record 0\nrecord 1\nrecord 2\n");
+
+        options.strip_trailing_newline = true;
+        let datagram = options.next_datagram(&mut queue.clone()).unwrap().0;
+        assert_eq!(&datagram[..], b"record 0\nrecord 1\nrecord 2");
+    }
+
+    #[test]
+    fn test_grouped_batches() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .manual()
+            .with_timestamp_format(TimestampFormat::None)
+            .with_batching(1024)
+            .with_grouped_batches(true)
+            .build()
+            .expect("Can bind to localhost");
+        for message in ["opened", "migrated", "ready"].iter() {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(Level::Info)
+                    .target("my_app::db")
+                    .build(),
+            );
+        }
+        assert_eq!(logger.drain(), 3);
+        let mut buf = [0; 1024];
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        assert_eq!(
+            &buf[..len],
+            b"my_app::db: [INFO opened, INFO migrated, INFO ready]\n"
+        );
+
+        // JSON records stay valid JSON lines
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .manual()
+            .with_json()
+            .with_timestamp_format(TimestampFormat::None)
+            .with_batching(1024)
+            .with_grouped_batches(true)
+            .build()
+            .expect("Can bind to localhost");
+        for message in ["opened", "migrated"].iter() {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(Level::Info)
+                    .target("my_app::db")
+                    .build(),
+            );
+        }
+        assert_eq!(logger.drain(), 2);
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        let lines: Vec<serde_json::Value> = String::from_utf8_lossy(&buf[..len])
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("Valid JSON"))
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["message"], "migrated");
+    }
+
+    #[test]
+    fn test_vector() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .manual()
+            .with_batching(1024)
+            .with_vector(VectorCodec::Json)
+            .build()
+            .expect("Can bind to localhost");
+        for message in ["opened", "ready"].iter() {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(Level::Info)
+                    .target("my_app")
+                    .build(),
+            );
+        }
+        assert_eq!(logger.drain(), 2);
+        let mut buf = [0; 1024];
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        let datagram = std::str::from_utf8(&buf[..len]).unwrap();
+        // Vector's `newline_delimited` framing splits on each newline, then the `json`
+        // codec decodes each frame as an object
+        assert!(datagram.ends_with('\n'));
+        let events: Vec<serde_json::Value> = datagram
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events.len(), 2);
+        for (event, message) in events.iter().zip(["opened", "ready"].iter()) {
+            assert_eq!(event["message"], *message);
+            assert_eq!(event["level"], "INFO");
+            assert_eq!(event["target"], "my_app");
+            let timestamp = event["timestamp"].as_str().unwrap();
+            DateTime::parse_from_rfc3339(timestamp).unwrap();
+        }
+
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_json()
+            .with_timestamp_format(TimestampFormat::None)
+            .with_vector(VectorCodec::Bytes)
+            .build()
+            .unwrap();
+        let record = Record::builder()
+            .args(format_args!("ready"))
+            .level(Level::Info)
+            .build();
+        assert_eq!(logger.format(&record), b"INFO ready\n");
+    }
+
+    /// Runs with `Vec<u8>` payloads by default and `Bytes` with the `bytes` feature, which
+    /// must send the same datagrams
+    #[test]
+    fn test_payload_output() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        for requeues in [None, Some(3)].iter() {
+            let mut builder = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+                .manual()
+                .with_timestamp_format(TimestampFormat::None)
+                .with_batching(16);
+            if let Some(requeues) = requeues {
+                builder = builder.with_requeue(*requeues);
+            }
+            let logger = builder.build().expect("Can bind to localhost");
+            for message in ["a", "b", "oversized record"].iter() {
+                logger.log(
+                    &Record::builder()
+                        .args(format_args!("{}", message))
+                        .level(Level::Info)
+                        .build(),
+                );
+            }
+            logger.send_raw("raw\n").unwrap();
+            assert_eq!(logger.drain(), 4);
+            let mut buf = [0; 1024];
+            let mut received = Vec::new();
+            for _ in 0..3 {
+                let len = receiver.recv(&mut buf).expect("Message arrives");
+                received.push(String::from_utf8_lossy(&buf[..len]).to_string());
+            }
+            assert_eq!(
+                received,
+                ["INFO a\nINFO b\n", "INFO oversized record\n", "raw\n"]
+            );
+        }
+        let payload = queue::payload(b"bytes".to_vec());
+        assert_eq!(&payload[..], b"bytes");
+        assert_eq!(queue::into_vec(payload), b"bytes");
+    }
+
+    #[test]
+    fn test_cloudwatch() {
+        use chrono::TimeZone;
+
+        let time = Utc.timestamp_opt(1_592_190_939, 500_000_000).unwrap();
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_clock(FixedClock(time))
+            .with_app_name("relay")
+            .with_cloudwatch()
+            .build()
+            .expect("Can bind to localhost");
+        let record = Record::builder()
+            .args(format_args!("disk \"sda\" full"))
+            .level(Level::Warn)
+            .build();
+        assert_eq!(
+            String::from_utf8(logger.format(&record)).unwrap(),
+            "{\"timestamp\":1592190939500,\"message\":\"WARN app=relay disk \\\"sda\\\" full\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_batch_timestamps() {
+        /// Steps forward 1.5ms every time it's read
+        struct SteppingClock(Mutex<DateTime<Utc>>);
+        impl Clock for SteppingClock {
+            fn now(&self) -> DateTime<Utc> {
+                let mut time = self.0.lock().unwrap();
+                let now = *time;
+                *time = now + chrono::Duration::microseconds(1500);
+                now
+            }
+        }
+        use chrono::TimeZone;
+        let start = Utc.with_ymd_and_hms(2020, 6, 15, 3, 15, 39).unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .manual()
+            .with_clock(SteppingClock(Mutex::new(start)))
+            .with_batching(1024)
+            .with_batch_timestamps(true)
+            .build()
+            .expect("Can bind to localhost");
+        // `build` reads the clock once for the logger's start time
+        let start = start + chrono::Duration::microseconds(1500);
+        for message in ["a", "b", "c"].iter() {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(Level::Info)
+                    .build(),
+            );
+        }
+        assert_eq!(logger.drain(), 3);
+        let mut buf = [0; 1024];
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        let datagram = std::str::from_utf8(&buf[..len]).unwrap();
+        assert_eq!(
+            datagram,
+            "@2020-06-15T03:15:39.001500Z\n+0 INFO a\n+1500 INFO b\n+3000 INFO c\n"
+        );
+
+        let mut lines = datagram.lines();
+        let header = lines.next().unwrap().strip_prefix('@').unwrap();
+        let base = DateTime::parse_from_rfc3339(header).unwrap();
+        let times: Vec<_> = lines
+            .map(|line| {
+                let offset = line.split(' ').next().unwrap().parse().unwrap();
+                base + chrono::Duration::microseconds(offset)
+            })
+            .collect();
+        let expected: Vec<_> = (0..3)
+            .map(|i| start + chrono::Duration::microseconds(1500 * i))
+            .collect();
+        assert_eq!(times, expected);
+    }
+
+    #[test]
+    fn test_batch_timestamps_need_batching() {
+        use chrono::TimeZone;
+        let time = Utc.with_ymd_and_hms(2020, 6, 15, 3, 15, 39).unwrap();
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_clock(FixedClock(time))
+            .with_batch_timestamps(true)
+            .build()
+            .unwrap();
+        let record = Record::builder()
+            .args(format_args!("a"))
+            .level(Level::Info)
+            .build();
+        assert_eq!(
+            logger.format(&record),
+            b"INFO [2020-06-15T03:15:39+00:00] a\n"
+        );
+
+        // An offset in front of a JSON object would break it
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .manual()
+            .with_json()
+            .with_clock(FixedClock(time))
+            .with_batching(1024)
+            .with_batch_timestamps(true)
+            .build()
+            .unwrap();
+        assert!(!logger.batch_timestamps);
+        let line = logger.format(&record);
+        let json: serde_json::Value = serde_json::from_slice(&line).unwrap();
+        assert!(json.get("timestamp").is_some(), "{}", json);
+    }
+
+    #[test]
+    fn test_group_ends_at_other_target() {
+        let targeted = |target: &str, payload: &str| {
+            let mut envelope = Envelope::from(payload.to_string());
+            envelope.target = Some(target.into());
+            envelope
+        };
+        let mut queue: VecDeque<Envelope> = vec![
+            targeted("db", "a\n"),
+            targeted("db", "b\n"),
+            targeted("http", "c\n"),
+            Envelope::from("raw\n".to_string()),
+            targeted("db", "d\n"),
+        ]
+        .into_iter()
+        .collect();
+        let options = BufferedOptions {
+            batch_size: Some(1024),
+            group_by_target: true,
+            ..Default::default()
+        };
+        let mut datagrams = Vec::new();
+        while let Some((datagram, _)) = options.next_datagram(&mut queue) {
+            datagrams.push(String::from_utf8(datagram.to_vec()).unwrap());
+        }
+        // The untargeted message is batched normally, along with what follows it
+        assert_eq!(datagrams, ["db: [a, b]\n", "http: [c]\n", "raw\nd\n"]);
+
+        // A group that's exactly `max_bytes` long still fits
+        let mut queue: VecDeque<Envelope> = vec![targeted("db", "a\n"), targeted("db", "b\n")]
+            .into_iter()
+            .collect();
+        let options = BufferedOptions {
+            batch_size: Some(11),
+            ..options
+        };
+        assert_eq!(
+            options.next_datagram(&mut queue).unwrap(),
+            (queue::payload(b"db: [a, b]\n".to_vec()), 2)
+        );
+    }
+
+    #[test]
+    fn test_magic_prefix() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .with_json()
+            .with_magic_prefix(b"\x00LOG".to_vec())
+            .build()
+            .expect("Can bind to localhost");
+        logger.send_raw("{}").unwrap();
+
+        let mut buf = [0; 1024];
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        assert_eq!(&buf[..len], b"\x00LOG{}\n");
+    }
+
+    #[test]
+    fn test_max_batch_count() {
+        let mut queue: VecDeque<Envelope> = (0..7)
+            .map(|i| Envelope::from(format!("record {}\n", i)))
+            .collect();
+        let options = BufferedOptions {
+            batch_size: Some(1024),
+            max_batch_count: Some(3),
+            ..Default::default()
+        };
+        let mut counts = Vec::new();
+        while let Some((datagram, count)) = options.next_datagram(&mut queue) {
+            assert_eq!(datagram.split(|b| *b == b'\n').count() - 1, count);
+            counts.push(count);
+        }
+        assert_eq!(counts, [3, 3, 1]);
+
+        // The size limit still applies when it's reached first
+        let mut queue: VecDeque<Envelope> = (0..4)
+            .map(|i| Envelope::from(format!("record {}\n", i)))
+            .collect();
+        let options = BufferedOptions {
+            batch_size: Some(18),
+            max_batch_count: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(options.next_datagram(&mut queue).unwrap().1, 2);
+    }
+
+    #[test]
+    fn test_last_send_at() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let logger = UdpLogger::new(receiver.local_addr().unwrap()).expect("Can bind to localhost");
+        assert!(logger.last_send_at().is_none());
+
+        let record = |logger: &UdpLogger| {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("testing"))
+                    .level(Level::Info)
+                    .build(),
+            )
+        };
+        record(&logger);
+        let first = logger.last_send_at().expect("Sent once");
+        thread::sleep(Duration::from_millis(10));
+        record(&logger);
+        assert!(logger.last_send_at().unwrap() > first);
+    }
+
+    #[test]
+    fn test_queue_priority() {
+        let options = BufferedOptions {
+            interval: Duration::from_secs(3600),
+            queue_limit: Some(2),
+            ..Default::default()
+        };
+        let writer = UdpBufferedWriter::new(
+            "127.0.0.1:1999",
+            &options,
+            Box::new(BoundedQueue::new(Some(2), DropPolicy::default())),
+            &SocketOptions::default(),
+            Arc::default(),
+        )
+        .unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_priority_fn(|record| {
+                if record.target() == "important" {
+                    10
+                } else {
+                    0
+                }
+            })
+            .build()
+            .unwrap();
+        for target in ["important", "noise", "important", "noise"] {
+            writer
+                .push(
+                    logger.envelope(
+                        &Record::builder()
+                            .args(format_args!("{}", target))
+                            .level(Level::Info)
+                            .target(target)
+                            .build(),
+                    ),
+                )
+                .unwrap();
+        }
+
+        assert_eq!(writer.stats.snapshot().dropped, 2);
+        let mut queued = writer.queue.messages.lock().unwrap();
+        assert_eq!(queued.len(), 2);
+        while let Some(m) = queued.pop() {
+            assert!(m.payload.ends_with(b"] important\n"));
+        }
+    }
+
+    #[test]
+    fn test_pacing() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let gap = Duration::from_millis(20);
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .buffered()
+            .with_pacing(gap)
+            .build()
+            .expect("Can bind to localhost");
+
+        let start = Instant::now();
+        for _ in 0..5 {
+            logger
+                .writer
+                .load()
+                .push("testing\n".to_string().into())
+                .unwrap();
+        }
+        let mut buf = [0; 1024];
+        for _ in 0..5 {
+            receiver.recv(&mut buf).expect("Message arrives");
+        }
+        assert!(start.elapsed() >= gap * 4);
+    }
+
+    #[test]
+    fn test_empty_payload_skipped() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .with_formatter(|_| String::new())
+            .build()
+            .expect("Can bind to localhost");
+        logger.log(
+            &Record::builder()
+                .args(format_args!("testing"))
+                .level(Level::Info)
+                .build(),
+        );
+
+        let mut buf = [0; 1024];
+        assert!(receiver.recv(&mut buf).is_err());
+        let stats = logger.stats();
+        assert_eq!(stats.empty, 1);
+        assert_eq!(stats.sent, 0);
+    }
+
+    #[test]
+    fn test_send_raw() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let logger =
+            UdpLogger::new_buffered(receiver.local_addr().unwrap()).expect("Can bind to localhost");
+        logger.send_raw("shutting down").unwrap();
+
+        let mut buf = [0; 1024];
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        assert_eq!(&buf[..len], b"shutting down\n");
+    }
+
+    #[test]
+    fn test_send_raw_bytes_policy() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let invalid = b"bad \xFF byte";
+
+        let logger = UdpLogger::new(receiver.local_addr().unwrap()).expect("Can bind to localhost");
+        logger.send_raw_bytes(invalid).unwrap();
+        let mut buf = [0; 1024];
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        assert_eq!(&buf[..len], "bad \u{FFFD} byte\n".as_bytes());
+
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .with_utf8_policy(Utf8Policy::Reject)
+            .build()
+            .expect("Can bind to localhost");
+        let err = logger.send_raw_bytes(invalid).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let stats = logger.stats();
+        assert_eq!((stats.invalid_utf8, stats.sent), (1, 0));
+    }
+
+    #[test]
+    fn test_nonblocking_socket() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for builder in [
+            UdpLoggerBuilder::new(receiver.local_addr().unwrap()),
+            UdpLoggerBuilder::new(receiver.local_addr().unwrap()).buffered(),
+        ] {
+            let logger = builder
+                .with_nonblocking_socket(true)
+                .build()
+                .expect("Can bind to localhost");
+            for _ in 0..1000 {
+                logger.log(
+                    &Record::builder()
+                        .args(format_args!("testing"))
+                        .level(Level::Info)
+                        .build(),
+                );
+            }
+            assert_eq!(logger.flush_timeout(Duration::from_secs(2)), 0);
+            let stats = logger.stats();
+            // Any sends that would have blocked are dropped rather than stalling
+            assert_eq!(stats.sent + stats.dropped, 1000);
+            assert_eq!(stats.errors, 0);
+        }
+    }
+
+    #[test]
+    fn test_pause() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let log = |logger: &UdpLogger, message: &str| {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(Level::Info)
+                    .build(),
+            )
+        };
+        let mut buf = [0; 1024];
+
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .manual()
+            .with_formatter(|r| format!("{}\n", r.args()))
+            .build()
+            .expect("Can bind to localhost");
+        logger.pause();
+        assert!(logger.is_paused());
+        for message in ["first", "second"] {
+            log(&logger, message);
+        }
+        // Held in the queue rather than sent
+        assert_eq!(logger.drain(), 0);
+        assert_eq!(logger.flush_timeout(Duration::ZERO), 2);
+        assert!(receiver.recv(&mut buf).is_err());
+
+        logger.resume();
+        assert_eq!(logger.drain(), 2);
+        for expected in ["first\n", "second\n"] {
+            let len = receiver.recv(&mut buf).expect("Message arrives");
+            assert_eq!(&buf[..len], expected.as_bytes());
+        }
+
+        // Without a queue, messages logged while paused are dropped
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .with_formatter(|r| format!("{}\n", r.args()))
+            .build()
+            .expect("Can bind to localhost");
+        logger.writer_handle().pause();
+        log(&logger, "dropped");
+        assert!(receiver.recv(&mut buf).is_err());
+        logger.writer_handle().resume();
+        log(&logger, "sent");
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        assert_eq!(&buf[..len], b"sent\n");
+        let stats = logger.stats();
+        assert_eq!((stats.sent, stats.dropped), (1, 1));
+    }
+
+    #[test]
+    fn test_tcp() {
+        use std::io::Read;
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let logger = UdpLoggerBuilder::new(listener.local_addr().unwrap())
+            .tcp()
+            .with_formatter(|r| format!("{}\n", r.args()))
+            .build()
+            .expect("Can connect to localhost");
+        let (mut collector, _) = listener.accept().unwrap();
+        for message in ["first", "second"] {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(Level::Info)
+                    .build(),
+            );
+        }
+        let mut received = vec![0; 2 * 4 + 13];
+        collector.read_exact(&mut received).unwrap();
+        assert_eq!(received, b"\0\0\0\x06first\n\0\0\0\x07second\n");
+        assert_eq!(logger.stats().sent, 2);
+
+        // Nothing listening is an error when building
+        drop(listener);
+        let closed = collector.local_addr().unwrap();
+        drop(collector);
+        assert!(UdpLogger::new_tcp(closed).is_err());
+    }
+
+    #[test]
+    fn test_adaptive_format() {
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_adaptive_format(
+                3,
+                |r| format!("{{\"level\":\"{}\",\"msg\":\"{}\"}}\n", r.level(), r.args()),
+                |r| format!("{}\n", r.args()),
+            )
+            .build()
+            .expect("Can bind to localhost");
+        let lines: Vec<String> = (0..5)
+            .map(|i| {
+                let line = logger.format(
+                    &Record::builder()
+                        .args(format_args!("message {}", i))
+                        .level(Level::Info)
+                        .build(),
+                );
+                String::from_utf8(line).unwrap()
+            })
+            .collect();
+        assert_eq!(lines[2], "{\"level\":\"INFO\",\"msg\":\"message 2\"}\n");
+        // Past 3 records in a second, the compact formatter takes over
+        assert_eq!(lines[3..], ["message 3\n", "message 4\n"]);
+    }
+
+    #[test]
+    fn test_wait_ready() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        for builder in [
+            UdpLoggerBuilder::new(receiver.local_addr().unwrap()),
+            UdpLoggerBuilder::new(receiver.local_addr().unwrap()).buffered(),
+        ] {
+            let logger = builder.build().expect("Can bind to localhost");
+            assert!(logger.wait_ready(Duration::from_secs(1)));
+            let mut buf = [0; 1024];
+            let len = receiver.recv(&mut buf).expect("Probe arrives");
+            assert!(String::from_utf8_lossy(&buf[..len]).starts_with("UDP_LOGGER_PROBE ["));
+        }
+
+        // Nothing listening, so the loopback port is unreachable
+        let dead = UdpSocket::bind("127.0.0.1:0")
+            .and_then(|socket| socket.local_addr())
+            .unwrap();
+        let logger = UdpLogger::new(dead).expect("Can bind to localhost");
+        let start = Instant::now();
+        assert!(!logger.wait_ready(Duration::from_millis(300)));
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn test_timestamp_field() {
+        use chrono::TimeZone;
+
+        let now = Utc.with_ymd_and_hms(2020, 6, 15, 3, 15, 39).unwrap();
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_clock(FixedClock(now))
+            .with_timestamp_field("ts")
+            .build()
+            .expect("Can bind to localhost");
+        let format = |ts: &str| {
+            let key_values = [("ts", ts)];
+            let line = logger.format(
+                &Record::builder()
+                    .args(format_args!("relayed"))
+                    .level(Level::Info)
+                    .key_values(&key_values)
+                    .build(),
+            );
+            String::from_utf8(line).unwrap()
+        };
+        assert_eq!(
+            format("2019-01-02T03:04:05.500+02:00"),
+            "INFO [2019-01-02T01:04:05.500+00:00] ts=2019-01-02T03:04:05.500+02:00 relayed\n"
+        );
+        assert_eq!(
+            format("yesterday"),
+            "INFO [2020-06-15T03:15:39+00:00] ts=yesterday relayed\n"
+        );
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn test_flatten_kv() {
+        use log::kv::Value;
+
+        let user = serde_json::json!({"id": 42, "name": "bob", "items": ["a", "b"]});
+        let key_values = [("user", Value::from_serde(&user))];
+        let format = |flatten| {
+            let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+                .with_timestamp_format(TimestampFormat::None)
+                .with_flatten_kv(flatten)
+                .build()
+                .expect("Can bind to localhost");
+            let line = logger.format(
+                &Record::builder()
+                    .args(format_args!("login"))
+                    .level(Level::Info)
+                    .key_values(&key_values)
+                    .build(),
+            );
+            String::from_utf8(line).unwrap()
+        };
+        assert_eq!(
+            format(true),
+            "INFO user.id=42 user.items.0=a user.items.1=b user.name=bob login\n"
+        );
+        assert_eq!(
+            format(false),
+            "INFO user={\"id\":42,\"items\":[\"a\",\"b\"],\"name\":\"bob\"} login\n"
+        );
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn test_max_fields() {
+        let key_values = [("a", 1), ("b", 2), ("c", 3)];
+        let format = |max_fields| {
+            let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+                .with_timestamp_format(TimestampFormat::None)
+                .with_max_fields(max_fields)
+                .build()
+                .expect("Can bind to localhost");
+            let line = logger.format(
+                &Record::builder()
+                    .args(format_args!("sync"))
+                    .level(Level::Info)
+                    .key_values(&key_values)
+                    .build(),
+            );
+            String::from_utf8(line).unwrap()
+        };
+        assert_eq!(
+            format(2),
+            "INFO a=1 b=2 fields_truncated=true total_fields=3 sync\n"
+        );
+        assert_eq!(format(3), "INFO a=1 b=2 c=3 sync\n");
+    }
+
+    #[test]
+    #[cfg(feature = "hashing")]
+    fn test_field_hasher() {
+        let format = |salt: &str| {
+            let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+                .with_timestamp_format(TimestampFormat::None)
+                .with_field_hasher("client_ip", salt)
+                .with_field_hasher("user", salt)
+                .build()
+                .expect("Can bind to localhost");
+            let _ip = UdpLogger::push_context("client_ip", "10.0.0.1");
+            let _user = UdpLogger::push_context("user", "bob");
+            let _path = UdpLogger::push_context("path", "/login");
+            let line = logger.format(
+                &Record::builder()
+                    .args(format_args!("login"))
+                    .level(Level::Info)
+                    .build(),
+            );
+            String::from_utf8(line).unwrap()
+        };
+        let line = format("pepper");
+        let ip = FieldHasher {
+            field: "client_ip".to_string(),
+            salt: "pepper".to_string(),
+        }
+        .hash("10.0.0.1");
+        assert!(
+            line.starts_with(&format!("INFO client_ip={} user=", ip)),
+            "{}",
+            line
+        );
+        assert!(line.ends_with(" path=/login login\n"), "{}", line);
+        assert!(!line.contains("bob"), "{}", line);
+        assert_eq!(line, format("pepper"));
+        assert_ne!(line, format("paprika"));
+    }
+
+    #[test]
+    fn test_confirm_send() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .with_confirm_send(true)
+            .build()
+            .expect("Can bind to localhost");
+        logger.log(
+            &Record::builder()
+                .args(format_args!("testing"))
+                .level(Level::Info)
+                .build(),
+        );
+        let mut buf = [0; 128];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert!(buf[..len].ends_with(b"testing\n"));
+        let stats = logger.stats();
+        assert_eq!((stats.sent, stats.errors), (1, 0));
+    }
+
+    #[test]
+    fn test_suppress_identical_consecutive() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .with_suppress_identical_consecutive(true)
+            .build()
+            .expect("Can bind to localhost");
+        for msg in ["same", "same", "other", "same"].iter() {
+            logger.send_raw(msg).unwrap();
+        }
+        let mut buf = [0; 128];
+        let mut received = Vec::new();
+        while let Ok(len) = receiver.recv(&mut buf) {
+            received.push(String::from_utf8_lossy(&buf[..len]).to_string());
+        }
+        assert_eq!(received, ["same\n", "other\n", "same\n"]);
+        let stats = logger.stats();
+        assert_eq!((stats.sent, stats.suppressed), (3, 1));
+    }
+
+    #[test]
+    fn test_formatter_panic() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let formatter = |record: &Record| match record.args().to_string().as_str() {
+            "boom" => panic!("formatter bug"),
+            message => format!("custom {}\n", message),
+        };
+        for policy in [FormatterPanicPolicy::Fallback, FormatterPanicPolicy::Skip].iter() {
+            let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+                .with_formatter(formatter)
+                .with_formatter_panic_policy(*policy)
+                .with_timestamp_format(TimestampFormat::None)
+                .build()
+                .expect("Can bind to localhost");
+            for message in ["before", "boom", "after"].iter() {
+                logger.log(
+                    &Record::builder()
+                        .args(format_args!("{}", message))
+                        .level(Level::Warn)
+                        .build(),
+                );
+            }
+            let mut buf = [0; 128];
+            let mut received = Vec::new();
+            while let Ok(len) = receiver.recv(&mut buf) {
+                received.push(String::from_utf8_lossy(&buf[..len]).to_string());
+            }
+            let (first, last) = ("custom before\n".to_string(), "custom after\n".to_string());
+            match policy {
+                FormatterPanicPolicy::Fallback => {
+                    assert_eq!(received.len(), 3);
+                    assert!(received[1].ends_with("boom\n"), "{:?}", received[1]);
+                    assert_eq!((&received[0], &received[2]), (&first, &last));
+                }
+                FormatterPanicPolicy::Skip => assert_eq!(received, [first, last]),
+            }
+            assert_eq!(logger.stats().formatter_panics, 1);
+        }
+    }
+
+    #[test]
+    fn test_event_listener() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let logger = {
+            let events = events.clone();
+            UdpLoggerBuilder::new("127.0.0.1:1999")
+                .buffered()
+                .with_thread_recycle(Duration::from_millis(20))
+                .with_event_listener(Box::new(move |event| events.lock().unwrap().push(event)))
+                .build()
+                .expect("Can bind to localhost")
+        };
+        assert_eq!(*events.lock().unwrap(), [LoggerEvent::Started]);
+        logger.pause();
+        logger.pause();
+        logger.resume();
+        thread::sleep(Duration::from_millis(100));
+        drop(logger);
+
+        let events = events.lock().unwrap();
+        assert_eq!(
+            events[..3],
+            [
+                LoggerEvent::Started,
+                LoggerEvent::Paused,
+                LoggerEvent::Resumed
+            ]
+        );
+        assert!(events.contains(&LoggerEvent::ThreadRespawned));
+        assert_eq!(events.last(), Some(&LoggerEvent::Shutdown));
+    }
+
+    #[test]
+    fn test_raw_format() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .with_format(Format::Raw)
+            .with_run_id(true)
+            .build()
+            .expect("Can bind to localhost");
+        let _request = UdpLogger::push_context("request", "42");
+        for message in ["GET /index.html 200", "already ended\n"].iter() {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(Level::Info)
+                    .build(),
+            );
+        }
+        let mut buf = [0; 128];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"GET /index.html 200\n");
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"already ended\n");
+    }
+
+    #[test]
+    fn test_source_field() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .with_source_field(true)
+            .with_timestamp_format(TimestampFormat::None)
+            .build()
+            .expect("Can bind to localhost");
+        logger.log(
+            &Record::builder()
+                .args(format_args!("testing"))
+                .level(Level::Info)
+                .build(),
+        );
+        let mut buf = [0; 128];
+        let (len, from) = receiver.recv_from(&mut buf).unwrap();
+        assert_eq!(logger.local_addr().unwrap().port(), from.port());
+        let expected = format!("INFO source={} testing\n", from);
+        assert_eq!(String::from_utf8_lossy(&buf[..len]), expected);
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn test_compression_threshold() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .with_compression_threshold(64)
+            .build()
+            .expect("Can bind to localhost");
+        let large = "0123456789".repeat(20);
+        let mut buf = [0; 1024];
+        for message in ["small", large.as_str()].iter() {
+            logger.send_raw(message).unwrap();
+            let len = receiver.recv(&mut buf).unwrap();
+            let datagram = &buf[..len];
+            let compressed = message.len() > 64;
+            assert_eq!(datagram[0], u8::from(compressed));
+            assert_eq!(len < message.len(), compressed);
+            let payload = decompress(datagram).unwrap();
+            assert_eq!(payload, format!("{}\n", message).as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_replay() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .buffered()
+            .build()
+            .expect("Can bind to localhost");
+        let captured = vec![
+            b"INFO first\n".to_vec(),
+            b"{\"level\":\"WARN\"}".to_vec(),
+            Vec::new(),
+            b"no newline".to_vec(),
+        ];
+        logger.replay(captured.clone()).unwrap();
+        let mut buf = [0; 128];
+        for payload in captured.iter().filter(|payload| !payload.is_empty()) {
+            let len = receiver.recv(&mut buf).unwrap();
+            assert_eq!(&buf[..len], payload.as_slice());
+        }
+        assert_eq!(logger.stats().empty, 1);
+    }
+
+    #[test]
+    fn test_run_id() {
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_run_id(true)
+            .build()
+            .expect("Can bind to localhost");
+        let run_id = format!("run_id={:016x} ", logger.run_id().unwrap());
+        for _ in 0..2 {
+            let line = logger.format(
+                &Record::builder()
+                    .args(format_args!("testing"))
+                    .level(Level::Info)
+                    .build(),
+            );
+            assert!(String::from_utf8(line).unwrap().contains(&run_id));
+        }
+
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_custom_run_id(42)
+            .build()
+            .expect("Can bind to localhost");
+        assert_eq!(logger.run_id(), Some(42));
+    }
+
+    /// A clock that counts how often it's read
+    struct CountingClock(Arc<AtomicUsize>);
+
+    impl Clock for CountingClock {
+        fn now(&self) -> DateTime<Utc> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Utc::now()
+        }
+    }
+
+    #[test]
+    fn test_timestamp_none() {
+        let reads = Arc::new(AtomicUsize::new(0));
+        let mut logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_clock(CountingClock(Arc::clone(&reads)))
+            .with_timestamp_format(TimestampFormat::None)
+            .with_uptime(true)
+            .build()
+            .expect("Can bind to localhost");
+        let before = reads.load(Ordering::Relaxed);
+        let mut record = |format| {
+            logger.format = format;
+            let line = logger.format(
+                &Record::builder()
+                    .args(format_args!("testing"))
+                    .level(Level::Info)
+                    .build(),
+            );
+            String::from_utf8(line).unwrap()
+        };
+        let line = record(Format::Text);
+        assert!(line.starts_with("INFO uptime_ms="), "{}", line);
+        assert!(
+            line.ends_with(" testing\n") && !line.contains('['),
+            "{}",
+            line
+        );
+        let json: serde_json::Value = serde_json::from_str(&record(Format::Json)).unwrap();
+        assert!(json.get("timestamp").is_none() && json.get("started_at").is_none());
+        let gelf: serde_json::Value = serde_json::from_str(&record(Format::Gelf)).unwrap();
+        assert!(gelf.get("timestamp").is_none());
+        assert_eq!(reads.load(Ordering::Relaxed), before);
+    }
+
+    #[test]
+    fn test_scheduled_level() {
+        use chrono::{Local, NaiveDate, TimeZone};
+
+        let at = |hour, min| NaiveTime::from_hms_opt(hour, min, 0).unwrap();
+        let local = |hour, min| {
+            let time = NaiveDate::from_ymd_opt(2020, 6, 15)
+                .unwrap()
+                .and_time(at(hour, min));
+            FixedClock(
+                Local
+                    .from_local_datetime(&time)
+                    .unwrap()
+                    .with_timezone(&Utc),
+            )
+        };
+        let mut logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .level(Level::Warn)
+            .with_scheduled_level(vec![
+                (at(2, 0), at(4, 0), Level::Debug),
+                (at(23, 0), at(1, 0), Level::Error),
+            ])
+            .with_target_floor("my_app::noisy", Level::Info)
+            .build()
+            .expect("Can bind to localhost");
+
+        logger.clock = Arc::new(local(1, 59));
+        assert!(!logger.would_log("my_app", Level::Info));
+        assert!(logger.would_log("my_app", Level::Warn));
+        logger.clock = Arc::new(local(2, 0));
+        assert!(logger.would_log("my_app", Level::Debug));
+        assert!(!logger.would_log("my_app::noisy", Level::Debug));
+        logger.clock = Arc::new(local(4, 0));
+        assert!(!logger.would_log("my_app", Level::Debug));
+        // Wraps past midnight
+        logger.clock = Arc::new(local(0, 30));
+        assert!(!logger.would_log("my_app", Level::Warn));
+        assert!(logger.would_log("my_app", Level::Error));
+    }
+
+    #[test]
+    fn test_id_source() {
+        let time = chrono::DateTime::parse_from_rfc3339("2020-06-15T03:15:39+00:00").unwrap();
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_clock(FixedClock(time.with_timezone(&Utc)))
+            .with_run_id(true)
+            .with_id_source(Box::new(|| 0xfeed))
+            .build()
+            .expect("Can bind to localhost");
+        let line = logger.format(
+            &Record::builder()
+                .args(format_args!("testing"))
+                .level(Level::Info)
+                .build(),
+        );
+        assert_eq!(
+            String::from_utf8(line).unwrap(),
+            "INFO [2020-06-15T03:15:39+00:00] run_id=000000000000feed testing\n"
+        );
+    }
+
+    #[test]
+    fn test_take_stats() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let logger = UdpLogger::new(receiver.local_addr().unwrap()).expect("Can bind to localhost");
+        for _ in 0..3 {
+            logger.send_raw("testing").unwrap();
+        }
+        assert_eq!(logger.take_stats().sent, 3);
+        assert_eq!(logger.stats().sent, 0);
+
+        logger.send_raw("testing").unwrap();
+        assert_eq!(logger.take_stats().sent, 1);
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_fluentd() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .with_fluentd("app.logs")
+            .with_syslog(Facility::User)
+            .build()
+            .expect("Can bind to localhost");
+        logger.log(
+            &Record::builder()
+                .args(format_args!("testing"))
+                .level(Level::Info)
+                .build(),
+        );
+
+        let mut buf = [0; 1024];
+        let (len, _) = receiver.recv_from(&mut buf).expect("Message arrives");
+        let event = rmpv::decode::read_value(&mut &buf[..len]).expect("Valid MessagePack");
+        assert_eq!(event[0].as_str(), Some("app.logs"));
+        assert_eq!(event[2]["message"].as_str(), Some("testing"));
+    }
+
+    #[test]
+    fn test_push_context() {
+        let time = chrono::DateTime::parse_from_rfc3339("2020-06-15T03:15:39+00:00").unwrap();
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_clock(FixedClock(time.with_timezone(&Utc)))
+            .build()
+            .expect("Can bind to localhost");
+        let format = |logger: &UdpLogger| {
+            String::from_utf8(
+                logger.format(
+                    &Record::builder()
+                        .args(format_args!("testing"))
+                        .level(Level::Info)
+                        .build(),
+                ),
+            )
+            .unwrap()
+        };
+        {
+            let _request = UdpLogger::push_context("request_id", 42);
+            assert_eq!(
+                format(&logger),
+                "INFO [2020-06-15T03:15:39+00:00] request_id=42 testing\n"
+            );
+        }
+        assert_eq!(
+            format(&logger),
+            "INFO [2020-06-15T03:15:39+00:00] testing\n"
+        );
+    }
+
+    #[test]
+    fn test_uptime() {
+        let time = chrono::DateTime::parse_from_rfc3339("2020-06-15T03:15:39+00:00").unwrap();
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_clock(FixedClock(time.with_timezone(&Utc)))
+            .with_uptime(true)
+            .build()
+            .expect("Can bind to localhost");
+        let uptime = || {
+            let line = logger.format(
+                &Record::builder()
+                    .args(format_args!("testing"))
+                    .level(Level::Info)
+                    .build(),
+            );
+            let line = String::from_utf8(line).unwrap();
+            assert!(
+                line.contains(" started_at=2020-06-15T03:15:39+00:00 testing"),
+                "{}",
+                line
+            );
+            let uptime = line.split("uptime_ms=").nth(1).unwrap();
+            uptime.split(' ').next().unwrap().parse::<u64>().unwrap()
+        };
+        let first = uptime();
+        thread::sleep(Duration::from_millis(20));
+        let second = uptime();
+        assert!(second >= first + 20, "{} then {}", first, second);
+    }
+
+    #[test]
+    fn test_monotonic_timestamp() {
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_monotonic_timestamp(true)
+            .with_timestamp_format(TimestampFormat::None)
+            .build()
+            .expect("Can bind to localhost");
+        let mono_ms = || {
+            let line = logger.format(
+                &Record::builder()
+                    .args(format_args!("testing"))
+                    .level(Level::Info)
+                    .build(),
+            );
+            let line = String::from_utf8(line).unwrap();
+            let mono_ms = line.strip_prefix("INFO mono_ms=").expect("No wall clock");
+            mono_ms.split(' ').next().unwrap().parse::<u64>().unwrap()
+        };
+        let mut last = mono_ms();
+        for _ in 0..5 {
+            thread::sleep(Duration::from_millis(2));
+            let next = mono_ms();
+            assert!(next >= last, "{} then {}", last, next);
+            last = next;
+        }
+        assert!(last >= 10, "{}", last);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_os_thread_id() {
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_os_thread_id(true)
+            .with_timestamp_format(TimestampFormat::None)
+            .build()
+            .expect("Can bind to localhost");
+        let tid = || {
+            let line = logger.format(
+                &Record::builder()
+                    .args(format_args!("testing"))
+                    .level(Level::Info)
+                    .build(),
+            );
+            let line = String::from_utf8(line).unwrap();
+            let tid = line.strip_prefix("INFO tid=").expect("Has a tid field");
+            tid.split(' ').next().unwrap().parse::<u64>().unwrap()
+        };
+        let here = tid();
+        assert_eq!(here, tid());
+        let there = thread::scope(|scope| scope.spawn(tid).join().unwrap());
+        assert_ne!(here, there);
+    }
+
+    #[test]
+    fn test_fixed_clock() {
+        let time = chrono::DateTime::parse_from_rfc3339("2020-06-15T03:15:39+00:00").unwrap();
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_clock(FixedClock(time.with_timezone(&Utc)))
+            .with_syslog(Facility::Local0)
+            .build()
+            .expect("Can bind to localhost");
+        let line = logger.format(
+            &Record::builder()
+                .args(format_args!("testing"))
+                .level(Level::Warn)
+                .build(),
+        );
+        assert_eq!(line, b"<132>WARN [2020-06-15T03:15:39+00:00] testing\n");
+    }
+
+    #[test]
+    fn test_date_partition() {
+        let time = chrono::DateTime::parse_from_rfc3339("2020-06-15T03:15:39+00:00").unwrap();
+        for (granularity, expected) in [
+            (
+                DatePartition::Day,
+                "2020-06-15 WARN [2020-06-15T03:15:39+00:00] testing\n",
+            ),
+            (
+                DatePartition::IsoWeek,
+                "2020-W25 WARN [2020-06-15T03:15:39+00:00] testing\n",
+            ),
+        ] {
+            let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+                .with_clock(FixedClock(time.with_timezone(&Utc)))
+                .with_date_partition(granularity)
+                .build()
+                .expect("Can bind to localhost");
+            let line = logger.format(
+                &Record::builder()
+                    .args(format_args!("testing"))
+                    .level(Level::Warn)
+                    .build(),
+            );
+            assert_eq!(String::from_utf8(line).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_target_max_len() {
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_format(Format::Json)
+            .with_target_max_len(17)
+            .build()
+            .expect("Can bind to localhost");
+        let target = |target| {
+            let line = logger.format(
+                &Record::builder()
+                    .args(format_args!("testing"))
+                    .level(Level::Warn)
+                    .target(target)
+                    .build(),
+            );
+            let json: serde_json::Value = serde_json::from_slice(&line).unwrap();
+            json["target"].as_str().unwrap().to_string()
+        };
+        assert_eq!(target("my_app::db::pool::conn"), "...db::pool::conn");
+        assert_eq!(target("my_app::db"), "my_app::db");
+    }
+
+    #[test]
+    fn test_rotating_token() {
+        let time = |s| {
+            let time = chrono::DateTime::parse_from_rfc3339(s).unwrap();
+            FixedClock(time.with_timezone(&Utc))
+        };
+        let mut logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_rotating_token(Duration::from_secs(15 * 60))
+            .with_timestamp_format(TimestampFormat::None)
+            .build()
+            .expect("Can bind to localhost");
+        let mut token = |clock| {
+            logger.clock = Arc::new(clock);
+            let line = logger.format(
+                &Record::builder()
+                    .args(format_args!("testing"))
+                    .level(Level::Warn)
+                    .build(),
+            );
+            String::from_utf8(line).unwrap()
+        };
+        // 2020-06-15T03:00:00Z is 1592190000s, the start of window 1769100
+        assert_eq!(
+            token(time("2020-06-15T03:14:59.999+00:00")),
+            "1769100 WARN testing\n"
+        );
+        assert_eq!(
+            token(time("2020-06-15T03:15:00+00:00")),
+            "1769101 WARN testing\n"
+        );
+        assert_eq!(
+            token(time("2020-06-15T03:29:59+00:00")),
+            "1769101 WARN testing\n"
+        );
+    }
+
+    #[test]
+    fn test_footer() {
+        let time = chrono::DateTime::parse_from_rfc3339("2020-06-15T03:15:39+00:00").unwrap();
+        let format = |builder: UdpLoggerBuilder| {
+            let logger = builder
+                .with_clock(FixedClock(time.with_timezone(&Utc)))
+                .with_syslog(Facility::Local0)
+                .build()
+                .expect("Can bind to localhost");
+            let line = logger.format(
+                &Record::builder()
+                    .args(format_args!("testing"))
+                    .level(Level::Warn)
+                    .build(),
+            );
+            String::from_utf8(line).unwrap()
+        };
+        let line = format(UdpLoggerBuilder::new("127.0.0.1:1999").with_footer(" crc={crc32}"));
+        let message = "<132>WARN [2020-06-15T03:15:39+00:00] testing";
+        assert_eq!(
+            line,
+            format!(
+                "{} crc={:08x}\n",
+                message,
+                crc32fast::hash(message.as_bytes())
+            )
+        );
+
+        let line = format(
+            UdpLoggerBuilder::new("127.0.0.1:1999")
+                .with_footer("--END--")
+                .with_footer_position(FooterPosition::AfterNewline),
+        );
+        assert_eq!(line, format!("{}\n--END--", message));
+    }
+
+    #[test]
+    fn test_transform() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        // Redact anything that looks like an email address
+        let redact = |message: Vec<u8>| {
+            let line = String::from_utf8(message).unwrap();
+            let redacted = line
+                .split(' ')
+                .map(|word| match word.contains('@') {
+                    true => "<redacted>",
+                    false => word,
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+            redacted.into_bytes()
+        };
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .with_timestamp_format(TimestampFormat::None)
+            .with_transform(Box::new(redact))
+            .with_payload_header("V1;")
+            .build()
+            .expect("Can bind to localhost");
+        logger.log(
+            &Record::builder()
+                .args(format_args!("signup from alice@example.com ok"))
+                .level(Level::Info)
+                .build(),
+        );
+        let mut buf = [0; 128];
+        let len = receiver.recv(&mut buf).unwrap();
+        // The header is added after the transform, so it's untouched
+        assert_eq!(&buf[..len], b"V1;INFO signup from <redacted> ok\n");
+    }
+
+    #[test]
+    fn test_envelope() {
+        let time = chrono::DateTime::parse_from_rfc3339("2020-06-15T03:15:39+00:00").unwrap();
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_clock(FixedClock(time.with_timezone(&Utc)))
+            .with_envelope("app[{level}]: {msg}")
+            .with_syslog(Facility::Local0)
+            .build()
+            .expect("Can bind to localhost");
+        let line = logger.format(
+            &Record::builder()
+                .args(format_args!("testing"))
+                .level(Level::Info)
+                .build(),
+        );
+        assert_eq!(
+            String::from_utf8(line).unwrap(),
+            "<134>app[INFO]: INFO [2020-06-15T03:15:39+00:00] testing\n"
+        );
+    }
+
+    #[test]
+    fn test_template() {
+        let time = chrono::DateTime::parse_from_rfc3339("2020-06-15T03:15:39+00:00").unwrap();
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_clock(FixedClock(time.with_timezone(&Utc)))
+            .with_template("{level} {ts:%H:%M:%S} {target} {file}:{line} {msg}")
+            .build()
+            .expect("Can bind to localhost");
+        let line = logger.format(
+            &Record::builder()
+                .args(format_args!("testing"))
+                .level(Level::Warn)
+                .target("my_app::db")
+                .file(Some("src/db.rs"))
+                .line(Some(7))
+                .build(),
+        );
+        assert_eq!(
+            String::from_utf8(line).unwrap(),
+            "WARN 03:15:39 my_app::db src/db.rs:7 testing\n"
+        );
+
+        let error = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_template("{level} {message}")
+            .build()
+            .err()
+            .expect("Unknown tokens are rejected");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("{message}"), "{}", error);
+    }
+
+    #[test]
+    fn test_journald_priority() {
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_journald_priority(true)
+            .build()
+            .expect("Can bind to localhost");
+        for (level, priority) in [
+            (Level::Error, "PRIORITY=3 ERROR ["),
+            (Level::Warn, "PRIORITY=4 WARN ["),
+            (Level::Info, "PRIORITY=6 INFO ["),
+            (Level::Debug, "PRIORITY=7 DEBUG ["),
+        ] {
+            let line = logger.format(
+                &Record::builder()
+                    .args(format_args!("testing"))
+                    .level(level)
+                    .build(),
+            );
+            assert!(line.starts_with(priority.as_bytes()));
+        }
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .with_dry_run(true)
+            .build()
+            .expect("Can build a dry-run logger");
+        for _ in 0..3 {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("testing"))
+                    .level(Level::Info)
+                    .build(),
+            );
+        }
+        let stats = logger.stats();
+        assert_eq!(stats.would_send, 3);
+        assert_eq!(stats.sent, 0);
+
+        let mut buf = [0; 1024];
+        assert!(receiver.recv_from(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_ordered() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .with_ordered(true)
+            .with_pacing(Duration::from_micros(100))
+            .build()
+            .expect("Can bind to localhost");
+        for i in 0..20 {
+            logger.send_raw(&format!("message {}", i)).unwrap();
+        }
+
+        let mut buf = [0; 1024];
+        for i in 0..20 {
+            let len = receiver.recv(&mut buf).expect("Message arrives");
+            assert_eq!(&buf[..len], format!("message {}\n", i).as_bytes());
+        }
+
+        let error = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_ordered(true)
+            .tcp()
+            .build()
+            .err()
+            .expect("TCP writer breaks the ordering guarantee");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        let error = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_ordered(true)
+            .with_requeue(3)
+            .build()
+            .err()
+            .expect("Requeues break the ordering guarantee");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_ordered(true)
+            .manual()
+            .build()
+            .expect("Manual writer keeps the order");
+    }
+
+    #[test]
+    fn test_destination_with_formatter() {
+        let recv = || {
+            let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+            receiver
+                .set_read_timeout(Some(Duration::from_secs(2)))
+                .unwrap();
+            receiver
+        };
+        let (text, json) = (recv(), recv());
+        let logger = UdpLoggerBuilder::new(text.local_addr().unwrap())
+            .add_destination_with_formatter(json.local_addr().unwrap(), |record| {
+                serde_json::json!({ "level": record.level().as_str(), "message": record.args().to_string() })
+                    .to_string()
+            })
+            .build()
+            .expect("Can bind to localhost");
+        logger.log(
+            &Record::builder()
+                .args(format_args!("testing"))
+                .level(Level::Warn)
+                .build(),
+        );
+
+        let mut buf = [0; 1024];
+        let len = text.recv(&mut buf).expect("Text message arrives");
+        let line = String::from_utf8_lossy(&buf[..len]);
+        assert!(line.starts_with("WARN [") && line.ends_with("] testing\n"));
+
+        let len = json.recv(&mut buf).expect("JSON message arrives");
+        let parsed: serde_json::Value = serde_json::from_slice(&buf[..len]).expect("Valid JSON");
+        assert_eq!(parsed["level"], "WARN");
+        assert_eq!(parsed["message"], "testing");
+        assert_eq!(logger.stats().sent, 2);
+    }
+
+    #[test]
+    fn test_destination_stats() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        // Bound then closed, so nothing listens on the port
+        let dead = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let live = receiver.local_addr().unwrap();
+        let logger = UdpLoggerBuilder::new(live)
+            .add_destination_with_formatter(dead, |record| format!("{}\n", record.args()))
+            .with_connected_socket(true)
+            .build()
+            .expect("Can bind to localhost");
+        for i in 0..5 {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("message {}", i))
+                    .level(Level::Info)
+                    .build(),
+            );
+            // Give the port unreachable error time to come back
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        let destinations = logger.destinations();
+        assert_eq!(destinations.len(), 2);
+        assert_eq!(destinations[0].address, live);
+        assert_eq!((destinations[0].sent, destinations[0].errors), (5, 0));
+        assert_eq!(destinations[1].address, dead);
+        assert!(destinations[1].errors >= 2, "{:?}", destinations[1]);
+        assert_eq!(destinations[1].sent + destinations[1].errors, 5);
+        let stats = logger.stats();
+        assert_eq!(stats.sent, 5 + destinations[1].sent);
+        assert_eq!(stats.errors, destinations[1].errors);
+    }
+
+    #[test]
+    fn test_self_test() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let live = receiver.local_addr().unwrap();
+        let logger = UdpLoggerBuilder::new(live)
+            .level(Level::Error)
+            .with_timestamp_format(TimestampFormat::None)
+            .build()
+            .expect("Can bind to localhost");
+        let report = logger.self_test();
+        assert!(report.is_ok(), "{:?}", report.error);
+        assert!(report.error.is_none());
+        assert_eq!(report.destination, Some(live));
+        assert_eq!(report.local_addr, logger.local_addr().ok());
+        let mut buf = [0; 1024];
+        let len = receiver.recv(&mut buf).expect("Probe arrives");
+        assert_eq!(&buf[..len], b"INFO UDP_LOGGER_SELF_TEST\n");
+
+        // Bound then closed, so nothing listens on the port
+        let dead = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(dead)
+            .with_connected_socket(true)
+            .build()
+            .expect("Can bind to localhost");
+        let report = logger.self_test();
+        assert!(!report.is_ok());
+        assert_eq!(report.destination, Some(dead));
+        assert!(report.local_addr.is_some());
+        let error = report.error.expect("Port unreachable is reported");
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+    }
+
+    #[test]
+    fn test_global_rate_limit() {
+        let recv = || {
+            let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+            receiver
+                .set_read_timeout(Some(Duration::from_millis(200)))
+                .unwrap();
+            receiver
+        };
+        let (first, second) = (recv(), recv());
+        let logger = UdpLoggerBuilder::new(first.local_addr().unwrap())
+            .add_destination_with_formatter(second.local_addr().unwrap(), |record| {
+                format!("{}\n", record.args())
+            })
+            .with_global_rate_limit(10)
+            .build()
+            .expect("Can bind to localhost");
+        for i in 0..30 {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("message {}", i))
+                    .level(Level::Info)
+                    .build(),
+            );
+        }
+
+        let received = |receiver: &UdpSocket| {
+            let mut buf = [0; 1024];
+            std::iter::from_fn(|| receiver.recv(&mut buf).ok()).count() as u64
+        };
+        let total = received(&first) + received(&second);
+        // A burst of one second's worth, plus a token if logging was slow
+        assert!((10..=11).contains(&total), "{} sent", total);
+        let stats = logger.stats();
+        assert_eq!(stats.sent, total);
+        assert_eq!(stats.sent + stats.rate_limited, 60);
+    }
+
+    #[test]
+    fn test_send_cap() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let reached = Arc::new(AtomicUsize::new(0));
+        let counter = reached.clone();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .with_timestamp_format(TimestampFormat::None)
+            .with_send_cap(3)
+            .with_send_cap_callback(Box::new(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }))
+            .build()
+            .expect("Can bind to localhost");
+        for i in 0..5 {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("message {}", i))
+                    .level(Level::Info)
+                    .build(),
+            );
+        }
+
+        let mut buf = [0; 1024];
+        let mut received = Vec::new();
+        while let Ok(len) = receiver.recv(&mut buf) {
+            received.push(String::from_utf8_lossy(&buf[..len]).to_string());
+        }
+        assert_eq!(
+            received,
+            ["INFO message 0\n", "INFO message 1\n", "INFO message 2\n"]
+        );
+        assert_eq!(reached.load(Ordering::SeqCst), 1);
+        let stats = logger.stats();
+        assert_eq!((stats.sent, stats.capped), (3, 2));
+    }
+
+    #[test]
+    fn test_peek_queue() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .manual()
+            .build()
+            .expect("Can bind to localhost");
+        assert!(logger.peek_queue(10).is_empty());
+        for i in 0..3 {
+            logger.send_raw(&format!("message {}", i)).unwrap();
+        }
+        assert_eq!(logger.peek_queue(2), ["message 0\n", "message 1\n"]);
+        assert_eq!(logger.peek_queue(10).len(), 3);
+
+        // Nothing was consumed
+        assert_eq!(logger.drain(), 3);
+        let mut buf = [0; 1024];
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        assert_eq!(&buf[..len], b"message 0\n");
+        assert!(logger.peek_queue(10).is_empty());
+
+        let unbuffered = UdpLogger::new("127.0.0.1:1999").unwrap();
+        unbuffered.send_raw("message").unwrap();
+        assert!(unbuffered.peek_queue(10).is_empty());
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        for builder in [
+            UdpLoggerBuilder::new(receiver.local_addr().unwrap()),
+            UdpLoggerBuilder::new(receiver.local_addr().unwrap()).manual(),
+            UdpLoggerBuilder::new(receiver.local_addr().unwrap()).buffered(),
+        ] {
+            let logger = builder.build().expect("Can bind to localhost");
+            // Larger than any UDP datagram, so the send fails
+            let _ = logger.send_raw(&"x".repeat(70_000));
+            logger.drain();
+            logger.flush_timeout(Duration::from_secs(2));
+            assert_eq!(logger.stats().errors, 1);
+
+            let before = logger.local_addr().unwrap();
+            logger.reconnect().expect("Can rebind");
+            let after = logger.local_addr().unwrap();
+            assert_ne!(before.port(), after.port());
+            assert_eq!(logger.stats().rebinds, 1);
+
+            logger.send_raw("recovered").unwrap();
+            logger.drain();
+            let mut buf = [0; 1024];
+            let (len, from) = receiver.recv_from(&mut buf).expect("Message arrives");
+            assert_eq!(&buf[..len], b"recovered\n");
+            assert_eq!(from.port(), after.port());
+        }
+    }
+
+    #[test]
+    fn test_manual_drain() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .manual()
+            .build()
+            .expect("Can bind to localhost");
+        for i in 0..3 {
+            logger.send_raw(&format!("message {}", i)).unwrap();
+        }
+
+        let mut buf = [0; 1024];
+        assert!(receiver.recv(&mut buf).is_err());
+        assert_eq!(logger.flush_timeout(Duration::from_millis(10)), 3);
+
+        assert_eq!(logger.drain(), 3);
+        for i in 0..3 {
+            let len = receiver.recv(&mut buf).expect("Message arrives");
+            assert_eq!(&buf[..len], format!("message {}\n", i).as_bytes());
+        }
+        assert_eq!(logger.drain(), 0);
+    }
+
+    #[test]
+    fn test_message_ttl() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .manual()
+            .with_message_ttl(Duration::from_millis(20))
+            .build()
+            .expect("Can bind to localhost");
+        logger.send_raw("stale").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        logger.send_raw("fresh").unwrap();
+
+        assert_eq!(logger.drain(), 1);
+        let mut buf = [0; 1024];
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        assert_eq!(&buf[..len], b"fresh\n");
+        assert_eq!(logger.stats().expired, 1);
+    }
+
+    #[test]
+    fn test_level_ttl() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .manual()
+            .level(Level::Debug)
+            .with_timestamp_format(TimestampFormat::None)
+            .with_level_ttl(Level::Debug, Duration::from_millis(10))
+            .build()
+            .expect("Can bind to localhost");
+        for (level, message) in [
+            (Level::Error, "first"),
+            (Level::Debug, "stale"),
+            (Level::Debug, "staler"),
+            (Level::Error, "second"),
+        ]
+        .iter()
+        {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(*level)
+                    .build(),
+            );
+        }
+        thread::sleep(Duration::from_millis(30));
+
+        assert_eq!(logger.drain(), 2);
+        let mut buf = [0; 1024];
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        assert_eq!(&buf[..len], b"ERROR first\n");
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        assert_eq!(&buf[..len], b"ERROR second\n");
+        assert_eq!(logger.stats().expired, 2);
+
+        let options = BufferedOptions {
+            ttl: Some(Duration::from_secs(1)),
+            level_ttls: vec![(Level::Debug, Duration::from_millis(10))],
+            ..BufferedOptions::default()
+        };
+        assert_eq!(
+            options.ttl_for(Some(Level::Debug)),
+            Some(Duration::from_millis(10))
+        );
+        assert_eq!(
+            options.ttl_for(Some(Level::Info)),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(options.ttl_for(Some(Level::Error)), None);
+        assert_eq!(options.ttl_for(None), Some(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_requeue() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let options = BufferedOptions {
+            max_requeues: Some(1),
+            ..BufferedOptions::default()
+        };
+        let stats = Arc::new(Stats::default());
+        let writer = ManualWriter::new(
+            receiver.local_addr().unwrap(),
+            &options,
+            Box::new(BoundedQueue::new(None, DropPolicy::default())),
+            &SocketOptions::default(),
+            stats.clone(),
+        )
+        .unwrap();
+        // An IPv6 socket can't send to an IPv4 destination, so every send fails until
+        // it's replaced
+        writer
+            .out
+            .store(Arc::new(UdpSocket::bind("[::1]:0").unwrap()));
+
+        writer.push("retried\n".to_string().into()).unwrap();
+        assert_eq!(writer.drain(), 0);
+        let queued = writer.peek_queue(10);
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].requeues(), 1);
+
+        writer.reconnect().unwrap();
+        assert_eq!(writer.drain(), 1);
+        let mut buf = [0; 1024];
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        assert_eq!(&buf[..len], b"retried\n");
+        let stats = stats.snapshot();
+        assert_eq!((stats.sent, stats.errors, stats.requeued), (1, 1, 1));
+
+        // Out of requeues, a message is dropped after its second failure
+        writer
+            .out
+            .store(Arc::new(UdpSocket::bind("[::1]:0").unwrap()));
+        writer.push("lost\n".to_string().into()).unwrap();
+        assert_eq!(writer.drain(), 0);
+        assert_eq!(writer.drain(), 0);
+        assert!(writer.peek_queue(10).is_empty());
+    }
+
+    #[test]
+    fn test_panic_hook() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .buffered()
+            .with_timestamp_format(TimestampFormat::None)
+            .build()
+            .expect("Can bind to localhost");
+        // The global logger is taken by another test, and the hook needs a 'static one
+        let logger: &'static UdpLogger = Box::leak(Box::new(logger));
+        let original = std::panic::take_hook();
+        std::panic::set_hook(Box::new(|_| {}));
+        panic_hook::install(logger);
+        let line = line!() + 1;
+        let result = std::panic::catch_unwind(|| panic!("boom {}", 42));
+        // Restore the original hook for other tests
+        drop(std::panic::take_hook());
+        std::panic::set_hook(original);
+        assert!(result.is_err());
+
+        // Tests panicking concurrently are logged too, so look for this one
+        let location = format!(" panicked at src/lib.rs:{}:", line);
+        let mut buf = [0; 1024];
+        loop {
+            let len = receiver.recv(&mut buf).expect("Panic arrives");
+            let message = String::from_utf8_lossy(&buf[..len]);
+            if message.ends_with(": boom 42\n") {
+                assert!(message.starts_with("ERROR thread '"), "{}", message);
+                assert!(message.contains(&location), "{}", message);
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn test_weighted_destinations() {
+        let receivers: Vec<UdpSocket> = (0..3)
+            .map(|_| {
+                let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+                receiver
+                    .set_read_timeout(Some(Duration::from_millis(200)))
+                    .unwrap();
+                receiver
+            })
+            .collect();
+        let addr = |i: usize| receivers[i].local_addr().unwrap();
+        let logger = UdpLoggerBuilder::new(addr(0))
+            .add_weighted_destination(addr(1), 2)
+            .add_weighted_destination(addr(2), 3)
+            .build()
+            .expect("Can bind to localhost");
+        for i in 0..60 {
+            logger.send_raw(&format!("message {}", i)).unwrap();
+        }
+
+        let mut buf = [0; 1024];
+        let counts: Vec<usize> = receivers
+            .iter()
+            .map(|receiver| std::iter::from_fn(|| receiver.recv(&mut buf).ok()).count())
+            .collect();
+        assert_eq!(counts, [10, 20, 30]);
+        assert_eq!(logger.stats().sent, 60);
+
+        let zero = UdpLoggerBuilder::new(addr(0))
+            .add_weighted_destination(addr(1), 0)
+            .build();
+        assert_eq!(
+            zero.err().expect("Weight must be positive").kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn test_send_latency() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for builder in [
+            UdpLoggerBuilder::new(receiver.local_addr().unwrap()),
+            UdpLoggerBuilder::new(receiver.local_addr().unwrap()).manual(),
+        ] {
+            let logger = builder.build().expect("Can bind to localhost");
+            for i in 0..5 {
+                logger.send_raw(&format!("message {}", i)).unwrap();
+            }
+            logger.drain();
+            let latency = logger.stats().send_latency;
+            assert_eq!(latency.count(), 5);
+            assert!(latency.percentile(99.0).unwrap() <= latency.max().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_sink() {
+        use std::io::Write;
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let logger = UdpLogger::new(receiver.local_addr().unwrap()).expect("Can bind");
+        let mut sink = logger.sink();
+        sink.write_all(b"first line\nsecond ").unwrap();
+        sink.write_all(b"line\n\nunfinished").unwrap();
+        let mut buf = [0; 1024];
+        for expected in [&b"first line\n"[..], b"second line\n"].iter() {
+            let len = receiver.recv(&mut buf).expect("Message arrives");
+            assert_eq!(&buf[..len], *expected);
+        }
+        assert_eq!(logger.stats().empty, 1);
+        // Flushing keeps the unfinished line for its newline
+        sink.flush().unwrap();
+        assert_eq!(logger.stats().sent, 2);
+
+        drop(sink);
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        assert_eq!(&buf[..len], b"unfinished\n");
+        assert_eq!(logger.stats().sent, 3);
+    }
+
+    #[test]
+    fn test_max_sends_per_flush() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .manual()
+            .with_max_sends_per_flush(2)
+            .build()
+            .expect("Can bind to localhost");
+        for i in 0..5 {
+            logger.send_raw(&format!("message {}", i)).unwrap();
+        }
+        assert_eq!(logger.drain(), 2);
+        assert_eq!(logger.peek_queue(10).len(), 3);
+        assert_eq!(logger.drain(), 2);
+        assert_eq!(logger.drain(), 1);
+        assert_eq!(logger.drain(), 0);
+
+        let mut buf = [0; 1024];
+        for i in 0..5 {
+            let len = receiver.recv(&mut buf).expect("Message arrives");
+            assert_eq!(&buf[..len], format!("message {}\n", i).as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_max_sends_per_flush_buffered() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let options = BufferedOptions {
+            interval: Duration::from_millis(200),
+            max_sends_per_flush: Some(1),
+            ..Default::default()
+        };
+        let writer = UdpBufferedWriter::new(
+            receiver.local_addr().unwrap(),
+            &options,
+            Box::new(VecDeque::new()),
+            &SocketOptions::default(),
+            Arc::default(),
+        )
+        .unwrap();
+        writer
+            .push_batch(vec![
+                "first\n".to_string().into(),
+                "second\n".to_string().into(),
+            ])
+            .unwrap();
+
+        let mut buf = [0; 128];
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        assert_eq!(&buf[..len], b"first\n");
+        let first = Instant::now();
+        // The capped drain left the second message for the next interval
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        assert_eq!(&buf[..len], b"second\n");
+        assert!(first.elapsed() >= Duration::from_millis(150));
+    }
+
+    #[test]
+    fn test_self_metrics_manual() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .manual()
+            .with_self_metrics(Duration::from_millis(20))
+            .build()
+            .expect("Can bind to localhost");
+        logger.send_raw("message").unwrap();
+        assert_eq!(logger.drain(), 1);
+        thread::sleep(Duration::from_millis(30));
+        // Reported after the drain once the interval has passed
+        assert_eq!(logger.drain(), 0);
+
+        let mut buf = [0; 1024];
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        assert_eq!(&buf[..len], b"message\n");
+        let len = receiver.recv(&mut buf).expect("Metrics arrive");
+        let metrics = String::from_utf8_lossy(&buf[..len]);
+        assert!(metrics.starts_with("UDP_LOGGER_METRICS ["), "{}", metrics);
+        assert!(
+            metrics.ends_with("] sent=1 dropped=0 errors=0 queue_depth=0\n"),
+            "{}",
+            metrics
+        );
+    }
+
+    #[test]
+    fn test_drop_notice() {
+        use chrono::TimeZone;
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let time = Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .manual()
+            .with_clock(FixedClock(time))
+            .with_queue_limit(1)
+            .with_drop_notice(Level::Error, "lost {count} in {interval}")
+            .with_drop_notice_interval(Duration::from_secs(60))
+            .build()
+            .expect("Can bind to localhost");
+        for i in 0..3 {
+            logger.send_raw(&format!("message {}", i)).unwrap();
+        }
+        assert_eq!(logger.drain(), 1);
+
+        let mut buf = [0; 1024];
+        let len = receiver.recv(&mut buf).expect("Notice arrives");
+        let notice = String::from_utf8_lossy(&buf[..len]);
+        assert_eq!(
+            notice,
+            format!("ERROR [{}] lost 2 in 60s\n", time.to_rfc3339())
+        );
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        assert_eq!(&buf[..len], b"message 2\n");
+    }
+
+    #[test]
+    fn test_json_keys() {
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_json_keys(JsonKeys {
+                timestamp: "ts".into(),
+                ..Default::default()
+            })
+            .build()
+            .expect("Can bind to localhost");
+        let line = logger.format(
+            &Record::builder()
+                .args(format_args!("testing"))
+                .level(Level::Info)
+                .build(),
+        );
+        let json: serde_json::Value = serde_json::from_slice(&line).expect("Valid JSON");
+        assert!(json["ts"].is_string());
+        assert_eq!(json["message"], "testing");
+    }
 
     #[test]
-    fn test_new() {
-        UdpLogger::new("127.0.0.1:1999")
-            .expect("Can bind to localhost")
-            .set_level(Level::Debug);
+    fn test_schema_version() {
+        let record = Record::builder()
+            .args(format_args!("testing"))
+            .level(Level::Info)
+            .build();
+        for (format, key) in [
+            (Format::Json, "schema_version"),
+            (Format::Gelf, "_schema_version"),
+        ] {
+            let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+                .with_format(format)
+                .with_schema_version(7)
+                .build()
+                .expect("Can bind to localhost");
+            let json: serde_json::Value =
+                serde_json::from_slice(&logger.format(&record)).expect("Valid JSON");
+            assert_eq!(json[key], 7);
+
+            let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+                .with_format(format)
+                .build()
+                .expect("Can bind to localhost");
+            let json: serde_json::Value =
+                serde_json::from_slice(&logger.format(&record)).expect("Valid JSON");
+            assert_eq!(json[key], SCHEMA_VERSION);
+        }
+
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_timestamp_format(TimestampFormat::None)
+            .build()
+            .expect("Can bind to localhost");
+        assert_eq!(logger.format(&record), b"INFO testing\n");
     }
 
     #[test]
-    fn test_message_queue() {
-        let _ = UdpLogger::new("127.0.0.1:1999")
-            .expect("Can bind to localhost")
-            .set_level(Level::Debug);
+    fn test_flush_on_drop() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .buffered()
+            .with_pacing(Duration::from_millis(5))
+            .build()
+            .expect("Can bind to localhost");
+        for i in 0..5 {
+            logger.send_raw(&format!("message {}", i)).unwrap();
+        }
+        drop(logger);
 
-        info!("testing");
+        // Everything was sent before drop returned
+        receiver.set_nonblocking(true).unwrap();
+        let mut buf = [0; 1024];
+        for i in 0..5 {
+            let len = receiver.recv(&mut buf).expect("Message arrived");
+            assert_eq!(&buf[..len], format!("message {}\n", i).as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_flush_on_level() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .buffered()
+            .with_aligned_flush(true)
+            .with_flush_on_level(Level::Error)
+            .with_timestamp_format(TimestampFormat::None)
+            .build()
+            .expect("Can bind to localhost");
+        // Start early in a second, so the aligned flush is at least 400ms away
+        while Utc::now().timestamp_subsec_millis() >= 500 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        thread::sleep(Duration::from_millis(20));
+        let log = |level, message| {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(level)
+                    .build(),
+            )
+        };
+        let mut buf = [0; 128];
+        log(Level::Info, "waits");
+        assert!(receiver.recv(&mut buf).is_err(), "Info waits for the flush");
+        log(Level::Error, "urgent");
+        let len = receiver.recv(&mut buf).expect("Error wakes the drain");
+        assert_eq!(&buf[..len], b"INFO waits\n");
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"ERROR urgent\n");
+    }
+
+    #[test]
+    fn test_idle_flush() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_millis(300)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .buffered()
+            .with_aligned_flush(true)
+            .with_idle_flush(Duration::from_millis(50))
+            .with_timestamp_format(TimestampFormat::None)
+            .build()
+            .expect("Can bind to localhost");
+        // Start early in a second, so the aligned flush is at least 400ms away
+        while Utc::now().timestamp_subsec_millis() >= 500 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        thread::sleep(Duration::from_millis(20));
+        let sent = Instant::now();
+        logger.log(
+            &Record::builder()
+                .args(format_args!("lone"))
+                .level(Level::Info)
+                .build(),
+        );
+
+        let mut buf = [0; 128];
+        let len = receiver
+            .recv(&mut buf)
+            .expect("Idle queue is flushed early");
+        assert_eq!(&buf[..len], b"INFO lone\n");
+        assert!(sent.elapsed() < Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_idle_flush_capped() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let options = BufferedOptions {
+            interval: Duration::from_millis(500),
+            idle_flush: Some(Duration::from_millis(20)),
+            max_sends_per_flush: Some(1),
+            ..Default::default()
+        };
+        let writer = UdpBufferedWriter::new(
+            receiver.local_addr().unwrap(),
+            &options,
+            Box::new(VecDeque::new()),
+            &SocketOptions::default(),
+            Arc::default(),
+        )
+        .unwrap();
+        let start = Instant::now();
+        writer
+            .push_batch(vec![
+                "first\n".to_string().into(),
+                "second\n".to_string().into(),
+            ])
+            .unwrap();
+
+        let mut buf = [0; 128];
+        let len = receiver
+            .recv(&mut buf)
+            .expect("Idle queue is flushed early");
+        assert_eq!(&buf[..len], b"first\n");
+        assert!(start.elapsed() < Duration::from_millis(400));
+        // What the capped pass left queued waits for the next interval
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        assert_eq!(&buf[..len], b"second\n");
+        assert!(start.elapsed() >= Duration::from_millis(450));
+    }
+
+    #[test]
+    fn test_drop_flush_timeout() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_millis(300)))
+            .unwrap();
+        // Sending everything would take 4s
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .buffered()
+            .with_pacing(Duration::from_millis(200))
+            .with_drop_flush_timeout(Duration::from_millis(100))
+            .build()
+            .expect("Can bind to localhost");
+        for i in 0..20 {
+            logger.send_raw(&format!("message {}", i)).unwrap();
+        }
+        let start = Instant::now();
+        drop(logger);
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        let mut buf = [0; 1024];
+        let mut received = 0;
+        while receiver.recv(&mut buf).is_ok() {
+            received += 1;
+        }
+        assert!(received < 20, "{} messages were sent", received);
+    }
+
+    #[test]
+    fn test_no_flush_on_drop() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_millis(300)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .buffered()
+            .with_pacing(Duration::from_millis(100))
+            .with_flush_on_drop(false)
+            .build()
+            .expect("Can bind to localhost");
+        for i in 0..5 {
+            logger.send_raw(&format!("message {}", i)).unwrap();
+        }
+        let start = Instant::now();
+        drop(logger);
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        let mut buf = [0; 1024];
+        let mut received = 0;
+        while receiver.recv(&mut buf).is_ok() {
+            received += 1;
+        }
+        assert!(received < 5);
+    }
+
+    #[test]
+    fn test_target_floor() {
+        let enabled = |logger: &UdpLogger, level, target| {
+            logger.enabled(&Metadata::builder().level(level).target(target).build())
+        };
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .level(Level::Trace)
+            .with_target_floor("my_app::noisy", Level::Warn)
+            .build()
+            .expect("Can bind to localhost");
+        assert!(enabled(&logger, Level::Warn, "my_app::noisy"));
+        assert!(!enabled(&logger, Level::Info, "my_app::noisy"));
+        assert!(!enabled(&logger, Level::Info, "my_app::noisy::inner"));
+        assert!(enabled(&logger, Level::Trace, "my_app::noisy_neighbor"));
+        assert!(enabled(&logger, Level::Trace, "my_app"));
+
+        // Unlike an override, a looser floor can't enable what the global level rejects
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .level(Level::Warn)
+            .with_target_floor("my_app::chatty", Level::Trace)
+            .build()
+            .expect("Can bind to localhost");
+        assert!(!enabled(&logger, Level::Debug, "my_app::chatty"));
+        assert!(enabled(&logger, Level::Error, "my_app::chatty"));
+    }
+
+    #[test]
+    fn test_runtime_target_filters() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .with_timestamp_format(TimestampFormat::None)
+            .build()
+            .expect("Can bind to localhost");
+        let log = |level, target: &str| {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", target))
+                    .level(level)
+                    .target(target)
+                    .build(),
+            )
+        };
+        let handle = logger.level_handle();
+        log(Level::Info, "app::db");
+        handle.add_suppressed_target("app::db");
+        log(Level::Error, "app::db");
+        log(Level::Info, "app::http");
+        handle.set_target_level("app::http", LevelFilter::Debug);
+        log(Level::Debug, "app::http");
+        log(Level::Debug, "app::db");
+
+        let mut buf = [0; 1024];
+        let mut received = Vec::new();
+        while let Ok(len) = receiver.recv(&mut buf) {
+            received.push(String::from_utf8_lossy(&buf[..len]).to_string());
+        }
+        assert_eq!(
+            received,
+            ["INFO app::db\n", "INFO app::http\n", "DEBUG app::http\n"]
+        );
+        assert_eq!(logger.level_for("app::db"), LevelFilter::Off);
+    }
+
+    #[test]
+    fn test_hard_min_level() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .with_timestamp_format(TimestampFormat::None)
+            .level(Level::Trace)
+            .with_hard_min_level(Level::Info)
+            .build()
+            .expect("Can bind to localhost");
+        logger
+            .level_handle()
+            .set_target_level("app::db", LevelFilter::Debug);
+        for level in [Level::Debug, Level::Info].iter() {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("opened"))
+                    .level(*level)
+                    .target("app::db")
+                    .build(),
+            );
+        }
+
+        let mut buf = [0; 1024];
+        let mut received = Vec::new();
+        while let Ok(len) = receiver.recv(&mut buf) {
+            received.push(String::from_utf8_lossy(&buf[..len]).to_string());
+        }
+        assert_eq!(received, ["INFO opened\n"]);
+        assert_eq!(logger.level_for("app::db"), LevelFilter::Info);
+        assert_eq!(logger.level_for("app::http"), LevelFilter::Info);
+    }
+
+    #[test]
+    fn test_filter_stage_counters() {
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_target_floor("app::noisy", Level::Warn)
+            .with_filter(|record| !record.args().to_string().contains("secret"))
+            .build()
+            .expect("Can bind to localhost");
+        logger.level_handle().add_suppressed_target("app::muted");
+        let log = |level, target: &str, message: &str| {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(level)
+                    .target(target)
+                    .build(),
+            )
+        };
+        log(Level::Debug, "app", "verbose");
+        log(Level::Trace, "app::noisy", "verbose");
+        log(Level::Info, "app::noisy", "chatty");
+        log(Level::Error, "app::muted", "muted");
+        log(Level::Info, "app", "the secret");
+        log(Level::Info, "app", "kept");
+
+        let stats = logger.stats();
+        assert_eq!(stats.filtered_by_level, 2);
+        assert_eq!(stats.filtered_by_target, 2);
+        assert_eq!(stats.filtered_by_filter, 1);
+        assert_eq!(stats.per_level[Level::Info as usize - 1], 1);
+    }
+
+    #[test]
+    fn test_dual_stack() {
+        let v4 = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let v6 = UdpSocket::bind("[::1]:0").unwrap();
+        let mut buf = [0; 1024];
+        for receiver in [v4, v6] {
+            receiver
+                .set_read_timeout(Some(Duration::from_secs(2)))
+                .unwrap();
+            let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+                .with_dual_stack(true)
+                .build()
+                .expect("Can bind a dual-stack socket");
+            assert!(logger.local_addr().unwrap().is_ipv6());
+            logger.send_raw("both").unwrap();
+            let len = receiver.recv(&mut buf).expect("Message arrives");
+            assert_eq!(&buf[..len], b"both\n");
+        }
+    }
+
+    #[test]
+    fn test_shutdown_hook() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let delivered = Arc::new(AtomicBool::new(false));
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .buffered()
+            .with_shutdown_hook({
+                let calls = calls.clone();
+                let delivered = delivered.clone();
+                Box::new(move || {
+                    // The final flush has already happened
+                    let mut buf = [0; 1024];
+                    delivered.store(receiver.recv(&mut buf).is_ok(), Ordering::SeqCst);
+                    calls.fetch_add(1, Ordering::SeqCst);
+                })
+            })
+            .build()
+            .expect("Can bind to localhost");
+        logger.send_raw("last words").unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        drop(logger);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(delivered.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_strict() {
+        let err = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .buffered()
+            .with_queue_limit(0)
+            .with_strict(true)
+            .build()
+            .err()
+            .expect("Zero queue limit is rejected");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("queue limit"));
+
+        // Batching is ignored without a queue
+        let builder = || UdpLoggerBuilder::new("127.0.0.1:1999").with_batching(1400);
+        builder().build().expect("Lenient by default");
+        let err = builder()
+            .with_strict(true)
+            .build()
+            .err()
+            .expect("Unbuffered batching is rejected");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        builder()
+            .buffered()
+            .with_strict(true)
+            .build()
+            .expect("Batching applies to a buffered logger");
+
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_formatter(|_| String::new())
+            .with_strict(true)
+            .build()
+            .expect("Can bind to localhost");
+        logger.log(
+            &Record::builder()
+                .args(format_args!("testing"))
+                .level(Level::Info)
+                .build(),
+        );
+        assert_eq!(logger.stats().empty, 1);
+        let err = logger.last_error().expect("Empty message is reported");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_resolver() {
+        #[derive(Clone, Copy)]
+        struct Stub(SocketAddr);
+        impl Resolver for Stub {
+            fn resolve(&self, host: &str) -> io::Result<Vec<SocketAddr>> {
+                match host {
+                    "collector:1999" => Ok(vec![self.0]),
+                    _ => Ok(vec![]),
+                }
+            }
+        }
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let stub = Stub(receiver.local_addr().unwrap());
+
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_resolver("collector:1999", stub)
+            .build()
+            .expect("Stub resolves the collector");
+        logger.send_raw("resolved").unwrap();
+        let mut buf = [0; 1024];
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        assert_eq!(&buf[..len], b"resolved\n");
+
+        let err = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_resolver("elsewhere:1999", stub)
+            .build()
+            .err()
+            .expect("Stub has no addresses");
+        assert_eq!(err.kind(), io::ErrorKind::AddrNotAvailable);
+        assert!(err.to_string().contains("elsewhere:1999"));
+
+        assert_eq!(
+            SystemResolver.resolve("127.0.0.1:1999").unwrap(),
+            ["127.0.0.1:1999".parse::<SocketAddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_resolve_retry() {
+        /// Fails until it has been asked `failures` times
+        struct Flaky {
+            failures: usize,
+            calls: Arc<AtomicUsize>,
+            addr: SocketAddr,
+        }
+        impl Resolver for Flaky {
+            fn resolve(&self, _host: &str) -> io::Result<Vec<SocketAddr>> {
+                match self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+                    true => Err(io::Error::new(io::ErrorKind::NotFound, "no such host")),
+                    false => Ok(vec![self.addr]),
+                }
+            }
+        }
+        let flaky = |failures| Flaky {
+            failures,
+            calls: Arc::default(),
+            addr: "127.0.0.1:1999".parse().unwrap(),
+        };
+
+        let resolver = flaky(2);
+        let calls = resolver.calls.clone();
+        UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_resolver("collector:1999", resolver)
+            .with_resolve_retry(3, Duration::from_millis(1))
+            .build()
+            .expect("Resolves on the third attempt");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // The source field reuses the resolved destination instead of asking again
+        let resolver = flaky(0);
+        let calls = resolver.calls.clone();
+        UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_resolver("collector:1999", resolver)
+            .with_source_field(true)
+            .build()
+            .expect("Resolves on the first attempt");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let resolver = flaky(3);
+        let calls = resolver.calls.clone();
+        let err = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_resolver("collector:1999", resolver)
+            .with_resolve_retry(3, Duration::from_millis(1))
+            .build()
+            .err()
+            .expect("Every attempt fails");
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        let err = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_resolve_retry(3, Duration::from_millis(1))
+            .with_strict(true)
+            .build()
+            .err()
+            .expect("Nothing to retry");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_bind_retry() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .with_timestamp_format(TimestampFormat::None)
+            .with_bind_retry(3, Duration::from_millis(1))
+            .build()
+            .expect("Can bind to localhost");
+        logger.log(
+            &Record::builder()
+                .args(format_args!("bound"))
+                .level(Level::Info)
+                .build(),
+        );
+        let mut buf = [0; 64];
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        assert_eq!(&buf[..len], b"INFO bound\n");
+    }
+
+    #[test]
+    fn test_per_level_stats() {
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .level(Level::Debug)
+            .build()
+            .expect("Can bind to localhost");
+        for level in [
+            Level::Error,
+            Level::Info,
+            Level::Error,
+            Level::Debug,
+            Level::Trace,
+            Level::Error,
+        ] {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("testing"))
+                    .level(level)
+                    .build(),
+            );
+        }
+        let by_level = logger.stats().by_level();
+        assert_eq!(by_level[&Level::Error], 3);
+        assert_eq!(by_level[&Level::Warn], 0);
+        assert_eq!(by_level[&Level::Info], 1);
+        assert_eq!(by_level[&Level::Debug], 1);
+        // Filtered out by the level, so never logged
+        assert_eq!(by_level[&Level::Trace], 0);
+        assert_eq!(logger.take_stats().per_level, [3, 0, 1, 1, 0]);
+        assert_eq!(logger.stats().per_level, [0; 5]);
+    }
+
+    #[test]
+    fn test_version_from_env() {
+        let format = |builder: UdpLoggerBuilder| {
+            let logger = builder
+                .with_timestamp_format(TimestampFormat::None)
+                .build()
+                .expect("Can bind to localhost");
+            let record = Record::builder()
+                .args(format_args!("started"))
+                .level(Level::Info)
+                .build();
+            String::from_utf8(logger.format(&record)).unwrap()
+        };
+        let var = format!("UDP_LOGGER_TEST_VERSION_{}", std::process::id());
+        std::env::set_var(&var, "2.1.0");
+        assert_eq!(
+            format(UdpLoggerBuilder::new("127.0.0.1:1999").with_version_from_env(&var)),
+            "INFO version=2.1.0 started\n"
+        );
+        std::env::remove_var(&var);
+        assert_eq!(
+            format(UdpLoggerBuilder::new("127.0.0.1:1999").with_version_from_env(&var)),
+            "INFO started\n"
+        );
+        assert_eq!(
+            format(
+                UdpLoggerBuilder::new("127.0.0.1:1999")
+                    .with_version(env!("CARGO_PKG_VERSION"))
+                    .with_version_from_env(&var)
+            ),
+            format!("INFO version={} started\n", env!("CARGO_PKG_VERSION"))
+        );
+    }
+
+    #[test]
+    fn test_build_info_handshake() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .with_timestamp_format(TimestampFormat::None)
+            .with_app_name("myapp")
+            .with_build_info(env!("CARGO_PKG_VERSION"), Some("3f9c2ab"))
+            .build()
+            .expect("Can bind to localhost");
+        logger.log(
+            &Record::builder()
+                .args(format_args!("testing"))
+                .level(Level::Info)
+                .build(),
+        );
+
+        let mut buf = [0; 1024];
+        let len = receiver.recv(&mut buf).expect("Handshake arrives");
+        let handshake = String::from_utf8_lossy(&buf[..len]);
+        assert!(
+            handshake.starts_with("UDP_LOGGER_HANDSHAKE ["),
+            "{}",
+            handshake
+        );
+        let expected = format!(
+            "] version={} commit=3f9c2ab app=myapp\n",
+            env!("CARGO_PKG_VERSION")
+        );
+        assert!(handshake.ends_with(&expected), "{}", handshake);
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        assert!(buf[..len].ends_with(b"testing\n"));
+
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .with_build_info("1.0.0", None)
+            .build()
+            .expect("Can bind to localhost");
+        let len = receiver.recv(&mut buf).expect("Handshake arrives");
+        assert!(buf[..len].ends_with(b"] version=1.0.0\n"));
+        drop(logger);
+    }
+
+    #[test]
+    fn test_shutdown_summary() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        for builder in [
+            UdpLoggerBuilder::new(receiver.local_addr().unwrap()),
+            UdpLoggerBuilder::new(receiver.local_addr().unwrap()).buffered(),
+        ] {
+            let logger = builder
+                .with_shutdown_summary(true)
+                .build()
+                .expect("Can bind to localhost");
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("testing"))
+                    .level(Level::Warn)
+                    .build(),
+            );
+            logger.flush_timeout(Duration::from_secs(1));
+            drop(logger);
+
+            let mut buf = [0; 1024];
+            let len = receiver.recv(&mut buf).expect("Message arrives");
+            assert!(buf[..len].ends_with(b"testing\n"));
+            let len = receiver.recv(&mut buf).expect("Summary arrives");
+            let summary = String::from_utf8_lossy(&buf[..len]);
+            assert!(summary.starts_with("UDP_LOGGER_SUMMARY ["), "{}", summary);
+            assert!(
+                summary.contains(" sent=1 dropped=0 errors=0 pending=0 duration="),
+                "{}",
+                summary
+            );
+            assert!(
+                summary.ends_with(" error=0 warn=1 info=0 debug=0 trace=0\n"),
+                "{}",
+                summary
+            );
+        }
+    }
+
+    #[test]
+    fn test_aggregation() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .with_aggregation(|r| r.target().to_string(), Duration::from_secs(3600))
+            .build()
+            .expect("Can bind to localhost");
+        for (target, n) in [("db", 5), ("cache", 3)] {
+            for _ in 0..n {
+                logger.log(
+                    &Record::builder()
+                        .args(format_args!("repetitive"))
+                        .level(Level::Info)
+                        .target(target)
+                        .build(),
+                );
+            }
+        }
+        assert_eq!(logger.stats().sent, 0);
+        logger.flush();
+
+        let mut buf = [0; 1024];
+        let mut aggregates = Vec::new();
+        for _ in 0..2 {
+            let len = receiver.recv(&mut buf).expect("Aggregate arrives");
+            aggregates.push(String::from_utf8_lossy(&buf[..len]).into_owned());
+        }
+        assert!(aggregates[0].ends_with(" count=3 window=3600s cache\n"));
+        assert!(aggregates[1].ends_with(" count=5 window=3600s db\n"));
+        assert_eq!(logger.stats().sent, 2);
+    }
+
+    #[test]
+    fn test_aggregation_timer() {
+        let recv = || {
+            let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+            receiver
+                .set_read_timeout(Some(Duration::from_secs(2)))
+                .unwrap();
+            receiver
+        };
+        let (primary, secondary) = (recv(), recv());
+        let logger = UdpLoggerBuilder::new(primary.local_addr().unwrap())
+            .add_destination_with_formatter(secondary.local_addr().unwrap(), |record| {
+                format!("{}\n", record.args())
+            })
+            .with_aggregation(|r| r.target().to_string(), Duration::from_millis(100))
+            .build()
+            .expect("Can bind to localhost");
+        for _ in 0..3 {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("repetitive"))
+                    .level(Level::Warn)
+                    .target("db")
+                    .build(),
+            );
+        }
+        assert_eq!(logger.stats().per_level, [0, 3, 0, 0, 0]);
+
+        // No record follows, so the timer sends the window as it ends
+        let mut buf = [0; 1024];
+        for receiver in [&primary, &secondary] {
+            let len = receiver.recv(&mut buf).expect("Aggregate arrives");
+            let aggregate = String::from_utf8_lossy(&buf[..len]).into_owned();
+            assert!(
+                aggregate.ends_with(" count=3 window=100ms db\n"),
+                "{}",
+                aggregate
+            );
+        }
+        assert_eq!(logger.stats().sent, 2);
+    }
+
+    #[test]
+    fn test_shared_level() {
+        let level = Arc::new(AtomicU8::new(Level::Warn as u8));
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_shared_level(level.clone())
+            .build()
+            .expect("Can bind to localhost");
+        let enabled =
+            |level| logger.enabled(&Metadata::builder().level(level).target("my_app").build());
+        assert!(enabled(Level::Warn));
+        assert!(!enabled(Level::Info));
+
+        level.store(Level::Debug as u8, Ordering::Relaxed);
+        assert!(enabled(Level::Debug));
+        assert!(!enabled(Level::Trace));
+
+        level.store(0, Ordering::Relaxed);
+        assert!(!enabled(Level::Error));
+        assert_eq!(logger.level_for("my_app"), LevelFilter::Off);
+    }
+
+    #[test]
+    fn test_filter() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .with_filter(|r| !r.args().to_string().contains("password"))
+            .build()
+            .expect("Can bind to localhost");
+        for message in ["user logged in", "password=hunter2", "user logged out"] {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(Level::Info)
+                    .build(),
+            );
+        }
+
+        let mut buf = [0; 1024];
+        for expected in ["user logged in\n", "user logged out\n"] {
+            let len = receiver.recv(&mut buf).expect("Message arrives");
+            assert!(buf[..len].ends_with(expected.as_bytes()));
+        }
+        assert_eq!(logger.stats().sent, 2);
+        assert_eq!(logger.stats().by_level()[&Level::Info], 2);
+    }
+
+    #[test]
+    fn test_file() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let dir = std::env::temp_dir().join(format!("udp_logger_test_file_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.log");
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .with_formatter(|r| format!("{}\n", r.args()))
+            .with_file(&path, 20, 1)
+            .build()
+            .expect("Can open the file");
+        for message in ["first message", "second message"] {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(Level::Info)
+                    .build(),
+            );
+        }
+
+        let mut buf = [0; 1024];
+        for expected in ["first message\n", "second message\n"] {
+            let len = receiver.recv(&mut buf).expect("Message arrives");
+            assert_eq!(&buf[..len], expected.as_bytes());
+        }
+        // The second message would exceed 20 bytes, so the first was rotated out
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second message\n");
+        assert_eq!(
+            std::fs::read_to_string(dir.join("app.log.1")).unwrap(),
+            "first message\n"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_journald_native() {
+        use std::os::unix::net::UnixDatagram;
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let dir =
+            std::env::temp_dir().join(format!("udp_logger_test_journal_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("socket");
+        let journal = UnixDatagram::bind(&path).unwrap();
+        journal
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let mut builder = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .with_app_name("myapp")
+            .with_journald_native();
+        builder.journald = Some(path);
+        let logger = builder.build().expect("Can bind to localhost");
+        logger.log(
+            &Record::builder()
+                .args(format_args!("testing"))
+                .level(Level::Info)
+                .target("my_app")
+                .build(),
+        );
+        let mut buf = [0; 1024];
+        let len = journal.recv(&mut buf).expect("Message arrives");
+        assert_eq!(
+            &buf[..len],
+            b"MESSAGE=testing\nPRIORITY=6\nTARGET=my_app\nSYSLOG_IDENTIFIER=myapp\n"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_newline_policy() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        for (policy, expected) in [
+            (
+                NewlinePolicy::Keep,
+                vec!["ERROR failed\n  at main\r\n  at start\n"],
+            ),
+            (
+                NewlinePolicy::Escape,
+                vec!["ERROR failed\\n  at main\\r\\n  at start\n"],
+            ),
+            (
+                NewlinePolicy::Replace('|'),
+                vec!["ERROR failed|  at main|  at start\n"],
+            ),
+            (
+                NewlinePolicy::SplitRecords,
+                vec!["ERROR failed\n", "ERROR   at main\n", "ERROR   at start\n"],
+            ),
+        ] {
+            let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+                .with_newline_policy(policy)
+                .with_timestamp_format(TimestampFormat::None)
+                .build()
+                .expect("Can bind to localhost");
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("failed\n  at main\r\n  at start"))
+                    .level(Level::Error)
+                    .build(),
+            );
+            let mut buf = [0; 128];
+            let mut received = Vec::new();
+            while let Ok(len) = receiver.recv(&mut buf) {
+                received.push(String::from_utf8_lossy(&buf[..len]).to_string());
+            }
+            assert_eq!(received, expected, "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn test_newline_policy_lone_cr() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        for (policy, expected) in [
+            (
+                NewlinePolicy::SplitRecords,
+                vec!["INFO 50%\n", "INFO done\n"],
+            ),
+            (NewlinePolicy::Replace(' '), vec!["INFO 50% done\n"]),
+        ] {
+            let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+                .with_newline_policy(policy)
+                .with_timestamp_format(TimestampFormat::None)
+                .build()
+                .expect("Can bind to localhost");
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("50%\rdone"))
+                    .level(Level::Info)
+                    .build(),
+            );
+            let mut buf = [0; 128];
+            let mut received = Vec::new();
+            while let Ok(len) = receiver.recv(&mut buf) {
+                received.push(String::from_utf8_lossy(&buf[..len]).to_string());
+            }
+            assert_eq!(received, expected, "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn test_empty_message_policy() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let mut buf = [0; 1024];
+        for (policy, expected) in [
+            (EmptyMessagePolicy::Keep, Some("] \n")),
+            (EmptyMessagePolicy::Skip, None),
+            (
+                EmptyMessagePolicy::Placeholder("<empty>".into()),
+                Some("] <empty>\n"),
+            ),
+        ] {
+            let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+                .with_empty_message_policy(policy)
+                .build()
+                .expect("Can bind to localhost");
+            logger.log(
+                &Record::builder()
+                    .args(format_args!(""))
+                    .level(Level::Info)
+                    .build(),
+            );
+            logger.send_raw("marker").unwrap();
+
+            let len = receiver.recv(&mut buf).expect("Message arrives");
+            match expected {
+                Some(expected) => {
+                    assert!(buf[..len].ends_with(expected.as_bytes()));
+                    assert_eq!(logger.stats().empty, 0);
+                    let len = receiver.recv(&mut buf).expect("Marker arrives");
+                    assert_eq!(&buf[..len], b"marker\n");
+                }
+                None => {
+                    assert_eq!(&buf[..len], b"marker\n");
+                    assert_eq!(logger.stats().empty, 1);
+                }
+            }
+        }
+
+        // The placeholder keeps the record's fields
+        #[cfg(feature = "kv")]
+        {
+            let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+                .with_timestamp_format(TimestampFormat::None)
+                .with_empty_message_policy(EmptyMessagePolicy::Placeholder("<empty>".into()))
+                .build()
+                .expect("Can bind to localhost");
+            let key_values = [("request_id", "42")];
+            logger.log(
+                &Record::builder()
+                    .args(format_args!(""))
+                    .level(Level::Info)
+                    .key_values(&key_values)
+                    .build(),
+            );
+            let len = receiver.recv(&mut buf).expect("Message arrives");
+            assert_eq!(&buf[..len], b"INFO request_id=42 <empty>\n");
+        }
+    }
+
+    #[test]
+    fn test_level_for() {
+        let mut logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .level(Level::Debug)
+            .with_target_floor("my_app::noisy", Level::Warn)
+            .with_target_floor("my_app::noisy::db", Level::Error)
+            .with_target_floor("my_app::chatty", Level::Trace)
+            .build()
+            .expect("Can bind to localhost");
+        assert_eq!(logger.level_for("my_app"), LevelFilter::Debug);
+        assert_eq!(logger.level_for("my_app::noisy"), LevelFilter::Warn);
+        assert_eq!(logger.level_for("my_app::noisy::db"), LevelFilter::Error);
+        assert_eq!(
+            logger.level_for("my_app::noisy::db::pool"),
+            LevelFilter::Error
+        );
+        assert_eq!(
+            logger.level_for("my_app::noisy_neighbor"),
+            LevelFilter::Debug
+        );
+        // A looser floor is capped by the logger's level
+        assert_eq!(logger.level_for("my_app::chatty"), LevelFilter::Debug);
+        assert!(logger.would_log("my_app::noisy", Level::Warn));
+        assert!(!logger.would_log("my_app::noisy::db", Level::Warn));
+        assert!(!logger.would_log("my_app::chatty", Level::Trace));
+
+        logger.set_level(Level::Error);
+        assert_eq!(logger.level_for("my_app::noisy"), LevelFilter::Error);
+        assert!(!logger.would_log("my_app", Level::Warn));
+    }
+
+    #[test]
+    fn test_last_error() {
+        // Sending to the broadcast address fails without SO_BROADCAST
+        let logger = UdpLogger::new("255.255.255.255:1999").expect("Can bind");
+        assert!(logger.last_error().is_none());
+        logger.log(
+            &Record::builder()
+                .args(format_args!("testing"))
+                .level(Level::Info)
+                .build(),
+        );
+        let err = logger.last_error().expect("Send failed");
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(logger.stats().errors, 1);
+    }
+
+    #[test]
+    fn test_crate_field() {
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_json()
+            .with_crate_field(true)
+            .build()
+            .expect("Can bind to localhost");
+        let json = |module_path| {
+            let line = logger.format(
+                &Record::builder()
+                    .args(format_args!("testing"))
+                    .level(Level::Info)
+                    .module_path(module_path)
+                    .build(),
+            );
+            serde_json::from_slice::<serde_json::Value>(&line).expect("Valid JSON")
+        };
+        assert_eq!(json(Some("my_app::server::http"))["crate"], "my_app");
+        assert_eq!(json(Some("my_app"))["crate"], "my_app");
+        assert!(json(None).get("crate").is_none());
+    }
+
+    #[test]
+    fn test_log_batch() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        for builder in [
+            UdpLoggerBuilder::new(receiver.local_addr().unwrap()),
+            UdpLoggerBuilder::new(receiver.local_addr().unwrap()).buffered(),
+        ] {
+            let logger = builder.build().expect("Can bind to localhost");
+            logger
+                .log_batch(
+                    (0..3)
+                        .map(|i| format!("message {}", i))
+                        .chain([String::new()]),
+                )
+                .unwrap();
+            let mut buf = [0; 1024];
+            for i in 0..3 {
+                let len = receiver.recv(&mut buf).expect("Message arrives");
+                assert_eq!(&buf[..len], format!("message {}\n", i).as_bytes());
+            }
+            assert_eq!(logger.stats().empty, 1);
+        }
+    }
+
+    /// Sends the newest message first
+    #[derive(Default)]
+    struct Stack(Vec<Envelope>);
+
+    impl MessageQueue for Stack {
+        fn push(&mut self, message: Envelope) -> bool {
+            self.0.push(message);
+            false
+        }
+
+        fn pop(&mut self) -> Option<Envelope> {
+            self.0.pop()
+        }
+
+        fn peek(&self) -> Option<&Envelope> {
+            self.0.last()
+        }
+
+        fn len(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    #[test]
+    fn test_custom_queue() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .manual()
+            .with_queue(Box::new(Stack::default()))
+            .build()
+            .expect("Can bind to localhost");
+        for i in 0..3 {
+            logger.send_raw(&format!("message {}", i)).unwrap();
+        }
+        assert_eq!(logger.flush_timeout(Duration::ZERO), 3);
+        assert_eq!(logger.drain(), 3);
+
+        let mut buf = [0; 1024];
+        for i in (0..3).rev() {
+            let len = receiver.recv(&mut buf).expect("Message arrives");
+            assert_eq!(&buf[..len], format!("message {}\n", i).as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_max_queue_bytes() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .manual()
+            .with_queue_limit(100)
+            .with_max_queue_bytes(64)
+            .build()
+            .expect("Can bind to localhost");
+        for i in 0..4 {
+            logger.send_raw(&format!("small {}", i)).unwrap();
+        }
+        // Far below the count limit, but 4 * 8 + 40 bytes is over the byte limit
+        logger.send_raw(&"x".repeat(39)).unwrap();
+        assert_eq!(logger.stats().dropped, 1);
+        assert_eq!(logger.drain(), 4);
+
+        let mut buf = [0; 1024];
+        for expected in ["small 1\n", "small 2\n", "small 3\n"].iter() {
+            let len = receiver.recv(&mut buf).expect("Message arrives");
+            assert_eq!(&buf[..len], expected.as_bytes());
+        }
+        let len = receiver.recv(&mut buf).expect("Message arrives");
+        assert_eq!(len, 40);
+    }
+
+    #[test]
+    fn test_thread_recycle() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let options = BufferedOptions {
+            thread_recycle: Some(Duration::from_millis(20)),
+            ..BufferedOptions::default()
+        };
+        let writer = UdpBufferedWriter::new(
+            receiver.local_addr().unwrap(),
+            &options,
+            Box::new(BoundedQueue::new(None, DropPolicy::default())),
+            &SocketOptions::default(),
+            Arc::new(Stats::default()),
+        )
+        .unwrap();
+        let thread_id = || lock_thread(&writer.drain).as_ref().unwrap().thread().id();
+        let first = thread_id();
+        for i in 0..20 {
+            writer.push(format!("message {}\n", i).into()).unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_ne!(thread_id(), first);
+        drop(writer);
+
+        let mut buf = [0; 1024];
+        for i in 0..20 {
+            let len = receiver.recv(&mut buf).expect("Message arrives");
+            assert_eq!(&buf[..len], format!("message {}\n", i).as_bytes());
+        }
+    }
+
+    /// Panics the first time a message is popped, like a bug in a custom queue
+    #[derive(Default)]
+    struct PanicOnce {
+        messages: VecDeque<Envelope>,
+        panicked: bool,
+    }
+
+    impl MessageQueue for PanicOnce {
+        fn push(&mut self, message: Envelope) -> bool {
+            self.messages.push_back(message);
+            false
+        }
+
+        fn pop(&mut self) -> Option<Envelope> {
+            if !self.panicked && !self.messages.is_empty() {
+                self.panicked = true;
+                panic!("simulated drain thread death");
+            }
+            self.messages.pop_front()
+        }
+
+        fn peek(&self) -> Option<&Envelope> {
+            self.messages.front()
+        }
+
+        fn len(&self) -> usize {
+            self.messages.len()
+        }
+    }
+
+    #[test]
+    fn test_dead_drain_fallback() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .buffered()
+            .with_queue(Box::new(PanicOnce::default()))
+            .build()
+            .expect("Can bind to localhost");
+        logger.send_raw("first").unwrap();
+        // The drain thread panics on its next pass, then the queue is sent from here
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while logger.stats().drain_failures == 0 && Instant::now() < deadline {
+            logger.flush_timeout(Duration::from_millis(10));
+        }
+        assert_eq!(logger.stats().drain_failures, 1);
+        logger.send_raw("second").unwrap();
+        assert_eq!(logger.flush_timeout(Duration::ZERO), 0);
+
+        let mut buf = [0; 1024];
+        for expected in ["first\n", "second\n"].iter() {
+            let len = receiver.recv(&mut buf).expect("Message arrives");
+            assert_eq!(&buf[..len], expected.as_bytes());
+        }
+        assert_eq!(logger.stats().drain_failures, 1);
+    }
+
+    #[test]
+    fn test_spill() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let path =
+            std::env::temp_dir().join(format!("udp_logger_test_spill_{}", std::process::id()));
+        let logger = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .manual()
+            .with_queue_limit(2)
+            .with_spill(&path, 1024)
+            .build()
+            .expect("Can bind to localhost");
+        for i in 0..5 {
+            logger.send_raw(&format!("message {}", i)).unwrap();
+        }
+        assert_eq!(logger.stats().spilled, 3);
+        assert!(std::fs::metadata(&path).unwrap().len() > 0);
+
+        assert_eq!(logger.drain(), 5);
+        let mut buf = [0; 1024];
+        for i in 0..5 {
+            let len = receiver.recv(&mut buf).expect("Message arrives");
+            assert_eq!(&buf[..len], format!("message {}\n", i).as_bytes());
+        }
+        let stats = logger.stats();
+        assert_eq!((stats.replayed, stats.dropped), (3, 0));
+        std::fs::remove_file(&path).ok();
+
+        let err = UdpLoggerBuilder::new(receiver.local_addr().unwrap())
+            .manual()
+            .with_spill(&path, 1024)
+            .build()
+            .err()
+            .expect("Nothing to spill beyond");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_buffered_filtered() {
+        let log = |logger: &UdpLogger, level| {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("queued"))
+                    .level(level)
+                    .build(),
+            )
+        };
+        // Paused, a buffered logger holds what it logs in its queue
+        let logger = UdpLogger::buffered_filtered("127.0.0.1:1999", LevelFilter::Debug).unwrap();
+        logger.pause();
+        log(&logger, Level::Debug);
+        log(&logger, Level::Trace);
+        assert_eq!(logger.level_for("my_app"), LevelFilter::Debug);
+        assert_eq!(logger.peek_queue(10).len(), 1);
+
+        let logger = UdpLogger::buffered_filtered("127.0.0.1:1999", LevelFilter::Off).unwrap();
+        logger.pause();
+        log(&logger, Level::Error);
+        assert_eq!(logger.level_for("my_app"), LevelFilter::Off);
+        assert!(logger.peek_queue(10).is_empty());
+        assert_eq!(logger.stats().filtered_by_level, 1);
+        // Raised through the handle `try_buffered_init_filtered` returns
+        logger.level_handle().set_filter(LevelFilter::Error);
+        log(&logger, Level::Error);
+        assert_eq!(logger.peek_queue(10).len(), 1);
+    }
+
+    #[test]
+    fn test_level_handle_sets_max_level() {
+        // The only test that installs a global logger, since that can only happen once
+        static_logger!(LOGGER);
+        static_logger!(OTHER);
+        let handle = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .level(Level::Warn)
+            .init_static(&LOGGER)
+            .expect("Can install the logger");
+        assert_eq!(log::max_level(), log::LevelFilter::Warn);
+        assert!(!log::log_enabled!(Level::Info));
+
+        // Later installs fail without touching the installed logger, and a retry reports
+        // the same error rather than the filled slot
+        let install_other =
+            |builder: UdpLoggerBuilder| builder.level(Level::Trace).init_static(&OTHER);
+        let error = install_other(UdpLoggerBuilder::new("127.0.0.1:1999"))
+            .expect_err("A logger is already installed");
+        assert!(error.is::<SetLoggerError>(), "{}", error);
+        let retried = install_other(UdpLoggerBuilder::new("127.0.0.1:1999"))
+            .expect_err("A logger is still installed");
+        assert!(retried.is::<SetLoggerError>(), "{}", retried);
+        assert!(UdpLoggerBuilder::new("127.0.0.1:1999")
+            .init_with_handle()
+            .is_err());
+        assert_eq!(log::max_level(), log::LevelFilter::Warn);
+        assert_eq!(LOGGER.get().unwrap().level_handle().level(), Level::Warn);
+
+        handle.set_level(Level::Debug);
+        assert_eq!(handle.level(), Level::Debug);
+        assert_eq!(log::max_level(), log::LevelFilter::Debug);
+
+        handle.set_level(Level::Error);
+        assert_eq!(log::max_level(), log::LevelFilter::Error);
+        assert!(!log::log_enabled!(Level::Warn));
+        assert!(log::log_enabled!(Level::Error));
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encryption() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let key = [42; 32];
+        for builder in [
+            UdpLoggerBuilder::new(receiver.local_addr().unwrap()),
+            UdpLoggerBuilder::new(receiver.local_addr().unwrap()).buffered(),
+        ] {
+            let logger = builder.with_encryption(key).build().unwrap();
+            logger.send_raw("secret").unwrap();
+
+            let mut buf = [0; 1024];
+            let len = receiver.recv(&mut buf).expect("Message arrives");
+            assert!(!buf[..len].windows(6).any(|w| w == b"secret"));
+            assert_eq!(decrypt(&key, &buf[..len]).unwrap(), b"secret\n");
+        }
+    }
+
+    #[test]
+    fn test_local_addr() {
+        let logger = UdpLogger::new("127.0.0.1:1999").expect("Can bind to localhost");
+        assert_ne!(logger.local_addr().unwrap().port(), 0);
+        let logger = UdpLogger::new_buffered("127.0.0.1:1999").expect("Can bind to localhost");
+        assert_ne!(logger.local_addr().unwrap().port(), 0);
+    }
+
+    #[test]
+    fn test_syslog_facility_per_target() {
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_syslog_facility(|record| {
+                if record.target().starts_with("auth::") {
+                    Facility::Auth
+                } else {
+                    Facility::User
+                }
+            })
+            .build()
+            .expect("Can bind to localhost");
+
+        let auth = logger.format(
+            &Record::builder()
+                .args(format_args!("login"))
+                .level(Level::Info)
+                .target("auth::login")
+                .build(),
+        );
+        let other = logger.format(
+            &Record::builder()
+                .args(format_args!("request"))
+                .level(Level::Info)
+                .target("my_app::server")
+                .build(),
+        );
+        // PRI == facility * 8 + severity, Info == 6
+        assert!(auth.starts_with(b"<38>INFO ["));
+        assert!(other.starts_with(b"<14>INFO ["));
     }
 }
@@ -0,0 +1,301 @@
+use std::io;
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::{UdpLogger, UdpLoggerBuilder};
+
+/// Builds one logger that sends each record to several named outputs, e.g. syslog, a
+/// JSON collector, and a local file
+///
+/// Each output is a `UdpLoggerBuilder` with its own destination, format, level, and
+/// `with_filter`, so every record goes only to the outputs that accept it, rendered the
+/// way each one is configured. Nothing is shared between outputs: each has its own
+/// writer mode, batching and framing, `with_transform` (e.g. compression), encryption,
+/// rate limit, and stats, unlike `add_destination_with_formatter`, whose destinations
+/// share everything but the formatter.
+/// ```
+/// use log::Level;
+/// use udp_logger::{Facility, MultiOutputBuilder, UdpLoggerBuilder};
+///
+/// let logger = MultiOutputBuilder::new()
+///     .output(
+///         "syslog",
+///         UdpLoggerBuilder::new("127.0.0.1:514").with_syslog_facility(|_| Facility::Daemon),
+///     )
+///     .output(
+///         "collector",
+///         UdpLoggerBuilder::new("127.0.0.1:1999")
+///             .with_json()
+///             .level(Level::Debug)
+///             .with_filter(|record| record.target().starts_with("app")),
+///     )
+///     .build()
+///     .unwrap();
+/// assert!(logger.output("collector").is_some());
+/// ```
+#[derive(Default)]
+pub struct MultiOutputBuilder {
+    outputs: Vec<(String, UdpLoggerBuilder)>,
+}
+
+impl MultiOutputBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an output called `name`, configured like a standalone UdpLogger
+    pub fn output(mut self, name: impl Into<String>, output: UdpLoggerBuilder) -> Self {
+        self.outputs.push((name.into(), output));
+        self
+    }
+
+    /// Build every output
+    ///
+    /// Fails with the first output's error, or `InvalidInput` if there are no outputs
+    /// or two share a name.
+    pub fn build(self) -> io::Result<MultiOutputLogger> {
+        if self.outputs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a multi-output logger needs at least one output",
+            ));
+        }
+        let mut outputs: Vec<(String, UdpLogger)> = Vec::with_capacity(self.outputs.len());
+        for (name, output) in self.outputs {
+            if outputs.iter().any(|(existing, _)| *existing == name) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("duplicate output name {:?}", name),
+                ));
+            }
+            outputs.push((name, output.build()?));
+        }
+        Ok(MultiOutputLogger { outputs })
+    }
+
+    /// Build every output and install them as the destination for `Log` macros
+    ///
+    /// `log::max_level` is set to `Trace`, since each output's level can be changed on
+    /// its own through `MultiOutputLogger::output`, so disabled records are skipped by
+    /// the outputs rather than the `log` macros.
+    pub fn init(self) -> Result<(), Box<dyn std::error::Error>> {
+        let logger = self.build()?;
+        log::set_boxed_logger(Box::new(logger))?;
+        log::set_max_level(LevelFilter::Trace);
+        Ok(())
+    }
+}
+
+/// The logger built by `MultiOutputBuilder`, dispatching each record to its outputs
+pub struct MultiOutputLogger {
+    outputs: Vec<(String, UdpLogger)>,
+}
+
+impl MultiOutputLogger {
+    /// The output called `name`, e.g. to read its stats or change its level
+    pub fn output(&self, name: &str) -> Option<&UdpLogger> {
+        self.outputs
+            .iter()
+            .find(|(output, _)| output == name)
+            .map(|(_, logger)| logger)
+    }
+
+    /// The names of the outputs, in the order they were added
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.outputs.iter().map(|(name, _)| name.as_str())
+    }
+}
+
+impl Log for MultiOutputLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.outputs
+            .iter()
+            .any(|(_, logger)| logger.enabled(metadata))
+    }
+
+    /// Each output checks its own level and filter
+    fn log(&self, record: &Record) {
+        for (_, logger) in &self.outputs {
+            logger.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        for (_, logger) in &self.outputs {
+            logger.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    use log::Level;
+
+    use super::*;
+    use crate::TimestampFormat;
+
+    fn receiver() -> UdpSocket {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        receiver
+    }
+
+    fn received(receiver: &UdpSocket) -> Vec<String> {
+        let mut buf = [0; 1024];
+        let mut received = Vec::new();
+        while let Ok(len) = receiver.recv(&mut buf) {
+            received.push(String::from_utf8_lossy(&buf[..len]).to_string());
+        }
+        received
+    }
+
+    #[test]
+    fn test_multi_output() {
+        let (text, json) = (receiver(), receiver());
+        let logger = MultiOutputBuilder::new()
+            .output(
+                "text",
+                UdpLoggerBuilder::new(text.local_addr().unwrap())
+                    .with_timestamp_format(TimestampFormat::None),
+            )
+            .output(
+                "json",
+                UdpLoggerBuilder::new(json.local_addr().unwrap())
+                    .with_json()
+                    .with_timestamp_format(TimestampFormat::None)
+                    .level(Level::Debug)
+                    .with_filter(|record| record.target() == "db"),
+            )
+            .build()
+            .expect("Can bind to localhost");
+        assert_eq!(logger.names().collect::<Vec<_>>(), ["text", "json"]);
+
+        for (target, level) in [
+            ("db", Level::Debug),
+            ("db", Level::Info),
+            ("http", Level::Info),
+        ]
+        .iter()
+        {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{} query", target))
+                    .level(*level)
+                    .target(target)
+                    .build(),
+            );
+        }
+
+        assert_eq!(received(&text), ["INFO db query\n", "INFO http query\n"]);
+        let json = received(&json);
+        assert_eq!(json.len(), 2);
+        assert!(json[0].starts_with("{\"level\":\"DEBUG\""), "{}", json[0]);
+        assert!(json[1].contains("\"message\":\"db query\""), "{}", json[1]);
+        assert_eq!(logger.output("json").unwrap().stats().sent, 2);
+        assert!(logger.output("file").is_none());
+    }
+
+    /// Run-length encode `bytes` as `(count, byte)` pairs, a stand-in for compression
+    fn compress(bytes: Vec<u8>) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+        for byte in bytes {
+            match out.len() {
+                len if len >= 2 && out[len - 1] == byte && out[len - 2] < u8::MAX => {
+                    out[len - 2] += 1
+                }
+                _ => out.extend_from_slice(&[1, byte]),
+            }
+        }
+        out
+    }
+
+    fn decompress(bytes: &[u8]) -> Vec<u8> {
+        bytes
+            .chunks(2)
+            .flat_map(|pair| std::iter::repeat_n(pair[1], pair[0].into()))
+            .collect()
+    }
+
+    #[test]
+    fn test_independent_outputs() {
+        let (plain, compressed) = (receiver(), receiver());
+        let logger = MultiOutputBuilder::new()
+            .output(
+                "plain",
+                UdpLoggerBuilder::new(plain.local_addr().unwrap())
+                    .with_timestamp_format(TimestampFormat::None),
+            )
+            .output(
+                "compressed",
+                UdpLoggerBuilder::new(compressed.local_addr().unwrap())
+                    .with_json()
+                    .with_timestamp_format(TimestampFormat::None)
+                    .with_transform(Box::new(compress)),
+            )
+            .build()
+            .expect("Can bind to localhost");
+        logger.log(
+            &Record::builder()
+                .args(format_args!("zzzzzzzz"))
+                .level(Level::Info)
+                .target("app")
+                .build(),
+        );
+
+        assert_eq!(received(&plain), ["INFO zzzzzzzz\n"]);
+        let mut buf = [0; 1024];
+        let len = compressed.recv(&mut buf).unwrap();
+        assert_eq!(
+            decompress(&buf[..len]),
+            b"{\"level\":\"INFO\",\"target\":\"app\",\"schema_version\":1,\"message\":\"zzzzzzzz\"}\n"
+        );
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encrypted_output() {
+        let (plain, encrypted) = (receiver(), receiver());
+        let key = [7; 32];
+        let logger = MultiOutputBuilder::new()
+            .output(
+                "plain",
+                UdpLoggerBuilder::new(plain.local_addr().unwrap())
+                    .with_timestamp_format(TimestampFormat::None),
+            )
+            .output(
+                "encrypted",
+                UdpLoggerBuilder::new(encrypted.local_addr().unwrap())
+                    .with_timestamp_format(TimestampFormat::None)
+                    .with_encryption(key),
+            )
+            .build()
+            .expect("Can bind to localhost");
+        logger.log(
+            &Record::builder()
+                .args(format_args!("secret"))
+                .level(Level::Info)
+                .build(),
+        );
+
+        assert_eq!(received(&plain), ["INFO secret\n"]);
+        let mut buf = [0; 1024];
+        let len = encrypted.recv(&mut buf).unwrap();
+        assert_eq!(crate::decrypt(&key, &buf[..len]).unwrap(), b"INFO secret\n");
+    }
+
+    #[test]
+    fn test_duplicate_output() {
+        let error = MultiOutputBuilder::new()
+            .output("udp", UdpLoggerBuilder::new("127.0.0.1:1999"))
+            .output("udp", UdpLoggerBuilder::new("127.0.0.1:1998"))
+            .build()
+            .err()
+            .expect("Names must be unique");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
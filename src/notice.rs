@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use log::Level;
+
+/// The notice text used unless a custom template is given
+const DEFAULT_TEMPLATE: &str = "udp_logger dropped {count} messages in the last {interval}";
+
+/// A synthetic message sent by the drain when messages were dropped
+#[derive(Debug, Clone)]
+pub(crate) struct DropNotice {
+    pub level: Level,
+    /// Notice text, `{count}` and `{interval}` are substituted
+    pub template: String,
+    /// At most one notice is sent per interval
+    pub interval: Duration,
+}
+
+impl Default for DropNotice {
+    fn default() -> Self {
+        Self {
+            level: Level::Warn,
+            template: DEFAULT_TEMPLATE.into(),
+            interval: Duration::from_secs(10),
+        }
+    }
+}
+
+impl DropNotice {
+    /// Render the notice line for `count` drops
+    pub(crate) fn render(&self, count: u64, timestamp: &str) -> String {
+        let text = self
+            .template
+            .replace("{count}", &count.to_string())
+            .replace("{interval}", &format!("{:?}", self.interval));
+        format!("{} [{}] {}\n", self.level, timestamp, text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let notice = DropNotice::default();
+        assert_eq!(
+            notice.render(3, "2020-06-15T03:15:39+00:00"),
+            "WARN [2020-06-15T03:15:39+00:00] udp_logger dropped 3 messages in the last 10s\n"
+        );
+
+        let notice = DropNotice {
+            level: Level::Error,
+            template: "DATA LOSS count={count} window={interval}".into(),
+            interval: Duration::from_millis(500),
+        };
+        assert_eq!(
+            notice.render(42, "ts"),
+            "ERROR [ts] DATA LOSS count=42 window=500ms\n"
+        );
+    }
+}
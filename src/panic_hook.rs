@@ -0,0 +1,80 @@
+use std::panic::{self, PanicHookInfo};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+use log::{Level, Log, Record};
+
+use crate::format;
+
+/// Waits up to the given time for queued messages to be sent, see `UdpLogger::flush_timeout`
+pub(crate) type Flush = Box<dyn Fn(Duration) -> usize + Send + Sync>;
+
+/// How long the hook waits for queued messages
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Flushes the UdpLogger installed as the global logger, if it was installed by this crate
+static INSTALLED: OnceLock<Flush> = OnceLock::new();
+
+/// Record how to flush the newly installed global logger
+pub(crate) fn register(flush: Flush) {
+    // Only one global logger can be installed
+    let _ = INSTALLED.set(flush);
+}
+
+/// Log every panic at Error level to `logger`, then run the panic hook set before this
+///
+/// A custom formatter's panic isn't logged, since the logger catches it and logging it
+/// would run the formatter (and likely panic) again.
+pub(crate) fn install(logger: &'static dyn Log) {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if !format::is_formatting() {
+            log_panic(logger, info);
+        }
+        previous(info);
+    }));
+}
+
+/// Log a panic like the default hook prints it, e.g.
+/// `thread 'main' panicked at src/main.rs:2:5: boom`
+fn log_panic(logger: &dyn Log, info: &PanicHookInfo) {
+    let thread = thread::current();
+    let name = thread.name().unwrap_or("<unnamed>");
+    let payload = info.payload();
+    let message = match (
+        payload.downcast_ref::<&str>(),
+        payload.downcast_ref::<String>(),
+    ) {
+        (Some(message), _) => message,
+        (None, Some(message)) => message.as_str(),
+        (None, None) => "Box<dyn Any>",
+    };
+    let location = info.location();
+    match location {
+        Some(location) => logger.log(
+            &Record::builder()
+                .args(format_args!(
+                    "thread '{}' panicked at {}: {}",
+                    name, location, message
+                ))
+                .level(Level::Error)
+                .target("panic")
+                .file(Some(location.file()))
+                .line(Some(location.line()))
+                .build(),
+        ),
+        None => logger.log(
+            &Record::builder()
+                .args(format_args!("thread '{}' panicked: {}", name, message))
+                .level(Level::Error)
+                .target("panic")
+                .build(),
+        ),
+    }
+    // The process may be about to exit, so queued messages are sent now
+    logger.flush();
+    if let Some(flush) = INSTALLED.get() {
+        flush(FLUSH_TIMEOUT);
+    }
+}
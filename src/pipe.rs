@@ -0,0 +1,190 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::queue::Envelope;
+use crate::socket::SocketOptions;
+use crate::stats::Stats;
+use crate::tcp::frame;
+use crate::Writer;
+
+/// Where Windows named pipes live
+const PIPE_PREFIX: &str = r"\\.\pipe\";
+
+/// The path of the named pipe `name`, which may already be a full `\\.\pipe\` path
+pub(crate) fn pipe_path(name: &str) -> PathBuf {
+    match name.starts_with(PIPE_PREFIX) {
+        true => PathBuf::from(name),
+        false => PathBuf::from(format!("{}{}", PIPE_PREFIX, name)),
+    }
+}
+
+/// Sends each message to a local collector over a Windows named pipe, length-prefixed
+/// like `TcpWriter` since pipes are byte streams too
+///
+/// The pipe is opened when the writer is created, and reopened (counted in
+/// `StatsSnapshot::rebinds`) when a write fails, retrying that message once.
+pub(crate) struct PipeWriter {
+    path: PathBuf,
+    socket: SocketOptions,
+    /// `None` after a failed reopen, until the next message tries again
+    pipe: Mutex<Option<File>>,
+    stats: Arc<Stats>,
+}
+
+impl PipeWriter {
+    pub(crate) fn new(path: &Path, socket: &SocketOptions, stats: Arc<Stats>) -> io::Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            socket: socket.clone(),
+            pipe: Mutex::new(Some(open(path)?)),
+            stats,
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<File>> {
+        self.pipe.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Replace the pipe with a new connection to it, leaving none on error
+    fn reopen(&self, pipe: &mut Option<File>) -> io::Result<()> {
+        *pipe = None;
+        *pipe = Some(open(&self.path)?);
+        self.stats.record_rebind();
+        Ok(())
+    }
+}
+
+/// Connect to the pipe's server for writing
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).open(path)
+}
+
+impl Writer for PipeWriter {
+    fn push(&self, message: Envelope) -> io::Result<()> {
+        if self.socket.is_paused() {
+            self.stats.record_drop();
+            return Ok(());
+        }
+        let message = self.socket.seal(&message.payload)?;
+        let framed = frame(&message)?;
+        let mut pipe = self.lock();
+        let mut result = match pipe.as_mut() {
+            Some(pipe) => pipe.write_all(&framed),
+            None => Err(io::ErrorKind::NotConnected.into()),
+        };
+        if result.is_err() {
+            // The server may have seen part of the frame, so it's resent whole
+            result = self
+                .reopen(&mut pipe)
+                .and_then(|()| pipe.as_mut().unwrap().write_all(&framed));
+        }
+        match result {
+            Ok(()) => {
+                self.stats.record_send();
+                self.socket.sent(&message);
+                Ok(())
+            }
+            Err(e) => {
+                self.stats.record_error();
+                Err(e)
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Err(io::Error::new(
+            io::ErrorKind::NotConnected,
+            "pipe writer has no UDP socket",
+        ))
+    }
+
+    fn reconnect(&self) -> io::Result<()> {
+        self.reopen(&mut self.lock())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::c_void;
+    use std::io::Read;
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::io::FromRawHandle;
+
+    const PIPE_ACCESS_INBOUND: u32 = 0x1;
+    const PIPE_TYPE_BYTE: u32 = 0x0;
+    const ERROR_PIPE_CONNECTED: i32 = 535;
+
+    extern "system" {
+        fn CreateNamedPipeW(
+            name: *const u16,
+            open_mode: u32,
+            pipe_mode: u32,
+            max_instances: u32,
+            out_buffer_size: u32,
+            in_buffer_size: u32,
+            default_timeout: u32,
+            security_attributes: *mut c_void,
+        ) -> *mut c_void;
+        fn ConnectNamedPipe(pipe: *mut c_void, overlapped: *mut c_void) -> i32;
+    }
+
+    /// Create the server end of a named pipe, reading what the writer sends
+    fn pipe_server(path: &Path) -> *mut c_void {
+        let name: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+        // SAFETY: `name` is a NUL-terminated UTF-16 string that outlives the call
+        let handle = unsafe {
+            CreateNamedPipeW(
+                name.as_ptr(),
+                PIPE_ACCESS_INBOUND,
+                PIPE_TYPE_BYTE,
+                1,
+                4096,
+                4096,
+                0,
+                std::ptr::null_mut(),
+            )
+        };
+        assert_ne!(handle as isize, -1, "{}", io::Error::last_os_error());
+        handle
+    }
+
+    #[test]
+    fn test_pipe_path() {
+        assert_eq!(pipe_path("collector"), PathBuf::from(r"\\.\pipe\collector"));
+        assert_eq!(
+            pipe_path(r"\\.\pipe\collector"),
+            PathBuf::from(r"\\.\pipe\collector")
+        );
+    }
+
+    #[test]
+    fn test_pipe_writer() {
+        let path = pipe_path(&format!("udp_logger_test_{}", std::process::id()));
+        let server = pipe_server(&path);
+        let writer =
+            PipeWriter::new(&path, &SocketOptions::default(), Arc::new(Stats::default())).unwrap();
+        // SAFETY: `server` is a valid pipe handle, and the call doesn't overlap
+        if unsafe { ConnectNamedPipe(server, std::ptr::null_mut()) } == 0 {
+            // The writer connected before the server started waiting
+            let error = io::Error::last_os_error();
+            assert_eq!(
+                error.raw_os_error(),
+                Some(ERROR_PIPE_CONNECTED),
+                "{}",
+                error
+            );
+        }
+        // SAFETY: `server` is a valid handle owned by nothing else
+        let mut server = unsafe { File::from_raw_handle(server) };
+
+        writer.push("first\n".to_string().into()).unwrap();
+        writer.push("second\n".to_string().into()).unwrap();
+        let mut received = vec![0; 21];
+        server.read_exact(&mut received).unwrap();
+        assert_eq!(received, b"\0\0\0\x06first\n\0\0\0\x07second\n");
+    }
+}
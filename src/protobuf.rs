@@ -0,0 +1,54 @@
+use log::Record;
+use prost::Message;
+
+use crate::format::Fields;
+
+include!(concat!(env!("OUT_DIR"), "/udp_logger.rs"));
+
+/// Encode a record as the `LogRecord` message in `proto/log_record.proto`
+///
+/// Extra fields aren't part of the schema, so they're left out.
+pub(crate) fn encode(record: &Record, fields: &Fields) -> Vec<u8> {
+    LogRecord {
+        level: record.level() as u32,
+        ts_unix_nanos: fields
+            .time
+            .and_then(|time| time.timestamp_nanos_opt())
+            .unwrap_or(0)
+            .max(0) as u64,
+        target: record.target().to_string(),
+        message: record.args().to_string(),
+    }
+    .encode_to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use log::Level;
+
+    #[test]
+    fn test_round_trip() {
+        let fields = Fields::new(Some(Utc.timestamp_opt(1_700_000_000, 5).unwrap()));
+        let encoded = encode(
+            &Record::builder()
+                .args(format_args!("testing"))
+                .level(Level::Warn)
+                .target("my_app")
+                .build(),
+            &fields,
+        );
+
+        let decoded = LogRecord::decode(&encoded[..]).expect("Valid protobuf");
+        assert_eq!(
+            decoded,
+            LogRecord {
+                level: 2,
+                ts_unix_nanos: 1_700_000_000_000_000_005,
+                target: "my_app".into(),
+                message: "testing".into(),
+            }
+        );
+    }
+}
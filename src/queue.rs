@@ -0,0 +1,525 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use log::Level;
+
+/// Which queued message is discarded when a bounded queue is full
+///
+/// Lower-priority messages are always evicted before higher-priority ones; the
+/// policy decides which message goes among those sharing the lowest priority.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum DropPolicy {
+    /// Evict the oldest queued message
+    #[default]
+    DropOldest,
+    /// Discard the newest message (including the one being pushed)
+    DropNewest,
+}
+
+/// The bytes of a queued message
+///
+/// With the `bytes` feature this is a `bytes::Bytes`, so the copies made to fan a
+/// message out to several destinations, or to keep it for a requeue, share one buffer
+/// instead of each allocating their own.
+#[cfg(feature = "bytes")]
+pub(crate) type Payload = bytes::Bytes;
+#[cfg(not(feature = "bytes"))]
+pub(crate) type Payload = Vec<u8>;
+
+/// Take `bytes` as a payload, without copying them
+pub(crate) fn payload(bytes: Vec<u8>) -> Payload {
+    #[cfg(feature = "bytes")]
+    let bytes = Payload::from(bytes);
+    bytes
+}
+
+/// Take a payload's bytes, only copying them if the buffer is shared
+pub(crate) fn into_vec(payload: Payload) -> Vec<u8> {
+    #[cfg(feature = "bytes")]
+    let payload = Vec::from(payload);
+    payload
+}
+
+/// A formatted message along with the metadata the writers use to handle it
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    pub(crate) payload: Payload,
+    pub(crate) priority: u8,
+    pub(crate) queued_at: Instant,
+    /// Times the message was put back in the queue after a failed send
+    pub(crate) requeues: u32,
+    /// The record's target, only kept for `with_grouped_batches`
+    pub(crate) target: Option<Arc<str>>,
+    /// When the record was logged, only kept for `with_batch_timestamps`
+    pub(crate) time: Option<DateTime<Utc>>,
+    /// The record's level, `None` for messages that aren't records, like `send_raw` ones
+    pub(crate) level: Option<Level>,
+}
+
+impl Envelope {
+    pub(crate) fn new(payload: impl Into<Payload>, priority: u8) -> Self {
+        Self {
+            payload: payload.into(),
+            priority,
+            queued_at: Instant::now(),
+            requeues: 0,
+            target: None,
+            time: None,
+            level: None,
+        }
+    }
+
+    /// The bytes that will be sent
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// The queue priority, higher values are more important
+    pub fn priority(&self) -> u8 {
+        self.priority
+    }
+
+    /// When the message was created
+    pub fn queued_at(&self) -> Instant {
+        self.queued_at
+    }
+
+    /// How many times the message was put back in the queue after a failed send
+    pub fn requeues(&self) -> u32 {
+        self.requeues
+    }
+}
+
+/// Storage for messages waiting to be sent by a buffered or manual writer
+///
+/// Messages must be popped in the order they should be sent. The writer serializes
+/// access behind a mutex, so implementations don't need their own synchronization.
+pub trait MessageQueue: Send {
+    /// Add a message, returning `true` if a message (possibly this one) was dropped
+    fn push(&mut self, message: Envelope) -> bool;
+
+    /// Add a message, returning how many messages (possibly including this one) were dropped
+    ///
+    /// Writers call this rather than `push` so every drop is counted. Queues that may
+    /// evict several messages to make room for one should override it.
+    fn push_counted(&mut self, message: Envelope) -> usize {
+        usize::from(self.push(message))
+    }
+
+    /// Remove the next message to send
+    fn pop(&mut self) -> Option<Envelope>;
+
+    /// The next message to send, without removing it
+    fn peek(&self) -> Option<&Envelope>;
+
+    /// Copies of up to `max` messages in the order they'd be sent, without removing them
+    ///
+    /// For debugging, e.g. `UdpLogger::peek_queue`. The default only copies the `peek`
+    /// message, queues that can walk their messages should override it.
+    fn peek_many(&self, max: usize) -> Vec<Envelope> {
+        self.peek().into_iter().take(max).cloned().collect()
+    }
+
+    /// How many messages are queued
+    fn len(&self) -> usize;
+
+    /// Keep only the messages `keep` returns `true` for, in order, returning how many
+    /// were removed
+    ///
+    /// Used for per-level expiry, see `UdpLoggerBuilder::with_level_ttl`. The default
+    /// pops every message and pushes back the kept ones, queues that can filter in place
+    /// should override it.
+    fn retain(&mut self, keep: &mut dyn FnMut(&Envelope) -> bool) -> usize {
+        let mut kept = Vec::with_capacity(self.len());
+        let mut removed = 0;
+        while let Some(message) = self.pop() {
+            match keep(&message) {
+                true => kept.push(message),
+                false => removed += 1,
+            }
+        }
+        for message in kept {
+            self.push(message);
+        }
+        removed
+    }
+
+    /// Whether no messages are queued
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// An unbounded FIFO queue
+impl MessageQueue for VecDeque<Envelope> {
+    fn push(&mut self, message: Envelope) -> bool {
+        self.push_back(message);
+        false
+    }
+
+    fn pop(&mut self) -> Option<Envelope> {
+        self.pop_front()
+    }
+
+    fn peek(&self) -> Option<&Envelope> {
+        self.front()
+    }
+
+    fn peek_many(&self, max: usize) -> Vec<Envelope> {
+        self.iter().take(max).cloned().collect()
+    }
+
+    fn len(&self) -> usize {
+        VecDeque::len(self)
+    }
+
+    fn retain(&mut self, keep: &mut dyn FnMut(&Envelope) -> bool) -> usize {
+        let len = self.len();
+        VecDeque::retain(self, |message| keep(message));
+        len - self.len()
+    }
+}
+
+/// The default queue, a FIFO optionally bounded by a limit and `DropPolicy`
+pub(crate) struct BoundedQueue {
+    messages: VecDeque<Envelope>,
+    limit: Option<usize>,
+    /// Most payload bytes queued at once
+    max_bytes: Option<usize>,
+    /// Payload bytes currently queued
+    bytes: usize,
+    policy: DropPolicy,
+}
+
+impl BoundedQueue {
+    pub(crate) fn new(limit: Option<usize>, policy: DropPolicy) -> Self {
+        Self {
+            messages: VecDeque::new(),
+            limit,
+            max_bytes: None,
+            bytes: 0,
+            policy,
+        }
+    }
+
+    /// Also bound the queue to `max_bytes` of payloads, whichever limit is hit first applies
+    pub(crate) fn with_max_bytes(mut self, max_bytes: Option<usize>) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Whether a `size` byte message can't be queued without evicting another
+    fn is_full(&self, size: usize) -> bool {
+        self.limit.is_some_and(|limit| self.messages.len() >= limit)
+            || self.max_bytes.is_some_and(|max| self.bytes + size > max)
+    }
+
+    /// Whether evicting every message `message` may displace would free enough bytes
+    fn has_room_for(&self, message: &Envelope) -> bool {
+        let max = match self.max_bytes {
+            Some(max) => max,
+            None => return true,
+        };
+        let evictable: usize = self
+            .messages
+            .iter()
+            .filter(|m| {
+                m.priority < message.priority
+                    || (m.priority == message.priority && self.policy == DropPolicy::DropOldest)
+            })
+            .map(|m| m.payload.len())
+            .sum();
+        self.bytes - evictable + message.payload.len() <= max
+    }
+
+    /// Pre-allocate room for `capacity` messages (never more than the limit)
+    pub(crate) fn with_capacity(mut self, capacity: usize) -> Self {
+        let capacity = self.limit.map_or(capacity, |limit| capacity.min(limit));
+        self.messages.reserve_exact(capacity);
+        self
+    }
+}
+
+impl MessageQueue for BoundedQueue {
+    fn push(&mut self, message: Envelope) -> bool {
+        self.push_counted(message) > 0
+    }
+
+    fn push_counted(&mut self, message: Envelope) -> usize {
+        // Don't evict anything for a message that would be dropped anyway
+        if !self.has_room_for(&message) {
+            return 1;
+        }
+        let mut dropped = 0;
+        while self.is_full(message.payload.len()) {
+            let index = match victim(&self.messages, &message, self.policy) {
+                Some(index) => index,
+                None => return dropped + 1,
+            };
+            let evicted = self.messages.remove(index).expect("Victim is queued");
+            self.bytes -= evicted.payload.len();
+            dropped += 1;
+        }
+        self.bytes += message.payload.len();
+        self.messages.push_back(message);
+        dropped
+    }
+
+    fn pop(&mut self) -> Option<Envelope> {
+        let message = self.messages.pop_front()?;
+        self.bytes -= message.payload.len();
+        Some(message)
+    }
+
+    fn peek(&self) -> Option<&Envelope> {
+        self.messages.front()
+    }
+
+    fn peek_many(&self, max: usize) -> Vec<Envelope> {
+        self.messages.iter().take(max).cloned().collect()
+    }
+
+    fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    fn retain(&mut self, keep: &mut dyn FnMut(&Envelope) -> bool) -> usize {
+        let len = self.messages.len();
+        let bytes = &mut self.bytes;
+        self.messages.retain(|message| {
+            let kept = keep(message);
+            if !kept {
+                *bytes -= message.payload.len();
+            }
+            kept
+        });
+        len - self.messages.len()
+    }
+}
+
+/// Wraps a queue to keep a copy of every message popped from it
+pub(crate) struct Recording<'a> {
+    queue: &'a mut dyn MessageQueue,
+    pub(crate) popped: Vec<Envelope>,
+}
+
+impl<'a> Recording<'a> {
+    pub(crate) fn new(queue: &'a mut dyn MessageQueue) -> Self {
+        Self {
+            queue,
+            popped: Vec::new(),
+        }
+    }
+}
+
+impl MessageQueue for Recording<'_> {
+    fn push(&mut self, message: Envelope) -> bool {
+        self.queue.push(message)
+    }
+
+    fn push_counted(&mut self, message: Envelope) -> usize {
+        self.queue.push_counted(message)
+    }
+
+    fn pop(&mut self) -> Option<Envelope> {
+        let message = self.queue.pop()?;
+        self.popped.push(message.clone());
+        Some(message)
+    }
+
+    fn peek(&self) -> Option<&Envelope> {
+        self.queue.peek()
+    }
+
+    fn peek_many(&self, max: usize) -> Vec<Envelope> {
+        self.queue.peek_many(max)
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    // Expired messages aren't sent, so they aren't recorded
+    fn retain(&mut self, keep: &mut dyn FnMut(&Envelope) -> bool) -> usize {
+        self.queue.retain(keep)
+    }
+}
+
+impl From<Vec<u8>> for Envelope {
+    fn from(payload: Vec<u8>) -> Self {
+        Envelope::new(payload, default_priority(Level::Info))
+    }
+}
+
+impl From<String> for Envelope {
+    fn from(payload: String) -> Self {
+        payload.into_bytes().into()
+    }
+}
+
+/// The default priority for a level, higher values are more important (Error == 5, Trace == 1)
+pub(crate) fn default_priority(level: Level) -> u8 {
+    6 - level as u8
+}
+
+/// The index of the message to evict from a full `queue` to make room for `message`
+///
+/// Returns `None` if `message` itself should be dropped
+fn victim(queue: &VecDeque<Envelope>, message: &Envelope, policy: DropPolicy) -> Option<usize> {
+    // A zero limit can't hold anything
+    let lowest = queue.iter().map(|m| m.priority).min()?;
+    match policy {
+        _ if message.priority < lowest => None,
+        DropPolicy::DropOldest => queue.iter().position(|m| m.priority == lowest),
+        DropPolicy::DropNewest if message.priority == lowest => None,
+        DropPolicy::DropNewest => queue.iter().rposition(|m| m.priority == lowest),
+    }
+}
+
+/// Remove messages queued longer before `now` than their `ttl`, which `None` never
+/// expires, returning how many were removed
+pub(crate) fn expire_each(
+    queue: &mut dyn MessageQueue,
+    ttl: impl Fn(&Envelope) -> Option<Duration>,
+    now: Instant,
+) -> usize {
+    queue.retain(&mut |m| match ttl(m) {
+        Some(ttl) => now.saturating_duration_since(m.queued_at) <= ttl,
+        None => true,
+    })
+}
+
+/// Remove messages queued more than `ttl` before `now`, returning how many were removed
+///
+/// Messages are queued in order and eviction never reorders them, so expired messages
+/// are always at the front
+pub(crate) fn expire(queue: &mut dyn MessageQueue, ttl: Duration, now: Instant) -> usize {
+    let mut expired = 0;
+    while queue
+        .peek()
+        .is_some_and(|m| now.saturating_duration_since(m.queued_at) > ttl)
+    {
+        queue.pop();
+        expired += 1;
+    }
+    expired
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payloads(queue: &VecDeque<Envelope>) -> Vec<&str> {
+        queue
+            .iter()
+            .map(|m| std::str::from_utf8(&m.payload).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_unbounded() {
+        let mut queue = BoundedQueue::new(None, DropPolicy::DropOldest);
+        for i in 0..10 {
+            assert!(!queue.push(Envelope::new(i.to_string().into_bytes(), 1)));
+        }
+        assert_eq!(queue.len(), 10);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let queue = BoundedQueue::new(None, DropPolicy::DropOldest).with_capacity(4096);
+        assert!(queue.messages.capacity() >= 4096);
+        // Room beyond the limit would never be used
+        let queue = BoundedQueue::new(Some(10), DropPolicy::DropOldest).with_capacity(4096);
+        assert!(queue.messages.capacity() >= 10 && queue.messages.capacity() < 4096);
+    }
+
+    #[test]
+    fn test_expire() {
+        let mut queue = VecDeque::new();
+        for m in ["a", "b", "c"] {
+            queue.push_back(Envelope::new(m.as_bytes().to_vec(), 3));
+        }
+        let now = queue[1].queued_at + Duration::from_secs(1);
+        queue[2].queued_at = now;
+        assert_eq!(expire(&mut queue, Duration::from_millis(500), now), 2);
+        assert_eq!(payloads(&queue), ["c"]);
+    }
+
+    #[test]
+    fn test_drop_policies_same_priority() {
+        let mut queue = BoundedQueue::new(Some(2), DropPolicy::DropOldest);
+        for m in ["a", "b", "c"] {
+            queue.push(Envelope::new(m.as_bytes().to_vec(), 3));
+        }
+        assert_eq!(payloads(&queue.messages), ["b", "c"]);
+
+        let mut queue = BoundedQueue::new(Some(2), DropPolicy::DropNewest);
+        for m in ["a", "b", "c"] {
+            queue.push(Envelope::new(m.as_bytes().to_vec(), 3));
+        }
+        assert_eq!(payloads(&queue.messages), ["a", "b"]);
+    }
+
+    #[test]
+    fn test_high_priority_survives() {
+        for policy in [DropPolicy::DropOldest, DropPolicy::DropNewest] {
+            let mut queue = BoundedQueue::new(Some(3), policy);
+            let messages = [
+                ("error 1", 5),
+                ("debug 1", 2),
+                ("error 2", 5),
+                ("debug 2", 2),
+                ("error 3", 5),
+                ("debug 3", 2),
+            ];
+            for (m, priority) in messages {
+                queue.push(Envelope::new(m.as_bytes().to_vec(), priority));
+            }
+            assert_eq!(payloads(&queue.messages), ["error 1", "error 2", "error 3"]);
+        }
+    }
+
+    #[test]
+    fn test_max_bytes() {
+        let message = |text: &str, priority| Envelope::new(text.as_bytes().to_vec(), priority);
+        // Room for plenty of messages, but only 16 bytes of them
+        let mut queue =
+            BoundedQueue::new(Some(100), DropPolicy::DropOldest).with_max_bytes(Some(16));
+        for m in ["a", "b", "c", "d"] {
+            assert_eq!(queue.push_counted(message(m, 3)), 0);
+        }
+        // Evicts the three oldest to fit
+        assert_eq!(queue.push_counted(message("0123456789abcde", 3)), 3);
+        assert_eq!(payloads(&queue.messages), ["d", "0123456789abcde"]);
+        assert_eq!(queue.bytes, 16);
+        // Larger than the limit on its own, so nothing is evicted for it
+        assert_eq!(queue.push_counted(message("0123456789abcdefg", 5)), 1);
+        assert_eq!(payloads(&queue.messages), ["d", "0123456789abcde"]);
+        queue.pop();
+        assert_eq!(queue.bytes, 15);
+
+        // Lower priority messages can't evict higher ones to make room
+        let mut queue = BoundedQueue::new(None, DropPolicy::DropNewest).with_max_bytes(Some(16));
+        assert_eq!(queue.push_counted(message("0123456789", 5)), 0);
+        assert_eq!(queue.push_counted(message("small", 2)), 0);
+        assert_eq!(queue.push_counted(message("too big", 2)), 1);
+        assert_eq!(queue.push_counted(message("urgent", 5)), 1);
+        assert_eq!(payloads(&queue.messages), ["0123456789", "urgent"]);
+
+        // The count limit still applies when it's hit first
+        let mut queue =
+            BoundedQueue::new(Some(2), DropPolicy::DropOldest).with_max_bytes(Some(1024));
+        for m in ["a", "b", "c"] {
+            queue.push(message(m, 3));
+        }
+        assert_eq!(payloads(&queue.messages), ["b", "c"]);
+    }
+}
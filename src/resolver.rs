@@ -0,0 +1,18 @@
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+
+/// Looks up the addresses of a `host:port` destination
+pub trait Resolver: Send + Sync {
+    /// The addresses for `host`, in order of preference
+    fn resolve(&self, host: &str) -> io::Result<Vec<SocketAddr>>;
+}
+
+/// The system resolver (via `ToSocketAddrs`), used by default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve(&self, host: &str) -> io::Result<Vec<SocketAddr>> {
+        host.to_socket_addrs().map(Iterator::collect)
+    }
+}
@@ -0,0 +1,57 @@
+use chrono::{DateTime, Local, NaiveTime, Utc};
+use log::Level;
+
+/// Levels that replace the logger's level during daily windows of local time
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LevelSchedule {
+    /// `(start, end, level)`, the first window containing the time wins
+    windows: Vec<(NaiveTime, NaiveTime, Level)>,
+}
+
+impl LevelSchedule {
+    pub(crate) fn new(windows: Vec<(NaiveTime, NaiveTime, Level)>) -> Self {
+        Self { windows }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.windows.is_empty()
+    }
+
+    /// The level scheduled at `now` (in local time), `None` outside every window
+    pub(crate) fn level_at(&self, now: DateTime<Utc>) -> Option<Level> {
+        let time = now.with_timezone(&Local).time();
+        self.windows
+            .iter()
+            .find(|(start, end, _)| contains(*start, *end, time))
+            .map(|(_, _, level)| *level)
+    }
+}
+
+/// Whether `time` is in `[start, end)`, which wraps past midnight if `end` is before `start`
+fn contains(start: NaiveTime, end: NaiveTime, time: NaiveTime) -> bool {
+    if start <= end {
+        start <= time && time < end
+    } else {
+        start <= time || time < end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(hour: u32, min: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, min, 0).unwrap()
+    }
+
+    #[test]
+    fn test_contains() {
+        assert!(contains(at(2, 0), at(4, 0), at(2, 0)));
+        assert!(contains(at(2, 0), at(4, 0), at(3, 59)));
+        assert!(!contains(at(2, 0), at(4, 0), at(4, 0)));
+        // Past midnight
+        assert!(contains(at(22, 0), at(2, 0), at(23, 0)));
+        assert!(contains(at(22, 0), at(2, 0), at(1, 0)));
+        assert!(!contains(at(22, 0), at(2, 0), at(12, 0)));
+    }
+}
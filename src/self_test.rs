@@ -0,0 +1,30 @@
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// The text of the probe record `UdpLogger::self_test` sends
+pub(crate) const SELF_TEST_MESSAGE: &str = "UDP_LOGGER_SELF_TEST";
+
+/// How long `UdpLogger::self_test` waits for a queued probe to be sent
+pub(crate) const SELF_TEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The outcome of `UdpLogger::self_test`
+#[derive(Debug)]
+pub struct SelfTestReport {
+    /// The resolved address of the primary destination, `None` if it doesn't send UDP
+    /// datagrams (e.g. a file, pipe, or `with_dry_run` logger)
+    pub destination: Option<SocketAddr>,
+    /// The local address the probe was sent from, if the writer has a UDP socket
+    pub local_addr: Option<SocketAddr>,
+    /// Whether the probe was sent with no error reported
+    pub sent: bool,
+    /// Why the probe wasn't sent, if it wasn't
+    pub error: Option<io::Error>,
+}
+
+impl SelfTestReport {
+    /// Whether the probe was sent with no error reported
+    pub fn is_ok(&self) -> bool {
+        self.sent
+    }
+}
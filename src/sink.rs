@@ -0,0 +1,91 @@
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+
+use crate::stats::Stats;
+use crate::Writer;
+
+/// The largest payload of a UDP datagram over IPv4, beyond which a line is sent unfinished
+const MAX_LINE_LEN: usize = 65_507;
+
+/// An `io::Write` that sends each line written to it as a message, see `UdpLogger::sink`
+///
+/// Bytes are held until a newline ends the line, so a line written in pieces is still
+/// sent as one datagram. `flush` keeps an unfinished line until its newline arrives,
+/// dropping the sink sends it with a newline added, and a line growing past the largest
+/// UDP payload is sent as it is.
+/// Lines are sent as raw messages, like `UdpLogger::send_raw`, and empty lines are
+/// counted in `StatsSnapshot::empty` rather than sent.
+/// ```
+/// # use std::net::UdpSocket;
+/// use std::io::Write;
+/// use udp_logger::UdpLogger;
+///
+/// # let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+/// let logger = UdpLogger::new(receiver.local_addr().unwrap()).unwrap();
+/// let mut sink = logger.sink();
+/// writeln!(sink, "exit code {}", 0).unwrap();
+/// ```
+pub struct Sink {
+    writer: Arc<ArcSwap<Box<dyn Writer>>>,
+    stats: Arc<Stats>,
+    line: Vec<u8>,
+}
+
+impl Sink {
+    pub(crate) fn new(writer: Arc<ArcSwap<Box<dyn Writer>>>, stats: Arc<Stats>) -> Self {
+        Self {
+            writer,
+            stats,
+            line: Vec::new(),
+        }
+    }
+
+    /// Send the buffered line, adding a newline if it has none
+    fn send_line(&mut self) -> io::Result<()> {
+        let mut line = std::mem::take(&mut self.line);
+        if line.is_empty() || line == b"\n" {
+            self.stats.record_empty();
+            return Ok(());
+        }
+        if !line.ends_with(b"\n") {
+            line.push(b'\n');
+        }
+        self.writer.load().push(line.into())
+    }
+}
+
+impl Write for Sink {
+    /// Buffer `buf`, sending every line it completes
+    ///
+    /// On error the rest of `buf` is still buffered (and its lines sent), so the error
+    /// is only that of the first line that failed to send.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut result = Ok(());
+        for &byte in buf {
+            self.line.push(byte);
+            if byte == b'\n' || self.line.len() >= MAX_LINE_LEN {
+                let sent = self.send_line();
+                if result.is_ok() {
+                    result = sent;
+                }
+            }
+        }
+        result.map(|()| buf.len())
+    }
+
+    /// Complete lines are sent as they're written, and an unfinished line isn't a
+    /// record yet, so there's nothing to flush
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Sink {
+    fn drop(&mut self) {
+        if !self.line.is_empty() {
+            self.send_line().ok();
+        }
+    }
+}
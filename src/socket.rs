@@ -0,0 +1,832 @@
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::Hasher;
+use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use arc_swap::ArcSwap;
+use chrono::Utc;
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+
+use crate::checksum::Checksum;
+#[cfg(feature = "compression")]
+use crate::compress;
+#[cfg(feature = "encryption")]
+use crate::encrypt::Sealer;
+
+/// Options applied to the UdpSocket a writer sends from
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SocketOptions {
+    /// Never block in `send_to`, reporting `WouldBlock` instead
+    pub nonblocking: bool,
+    /// Give up on a blocking send after this long (`SO_SNDTIMEO`)
+    pub send_timeout: Option<Duration>,
+    /// Mark datagrams with this DSCP (`IP_TOS`, or `IPV6_TCLASS` on an IPv6 socket)
+    pub dscp: Option<u8>,
+    /// Check that each unbuffered send put the whole datagram on the wire, retrying if not
+    pub confirm_send: bool,
+    /// Skip a datagram identical to the one just sent, see `LastSent`
+    pub suppress_identical: bool,
+    /// Bind `[::]:0` with `IPV6_V6ONLY` cleared, so one socket reaches IPv4 and IPv6
+    pub dual_stack: bool,
+    /// Connect each writer's socket to its destination, so ICMP errors are reported
+    pub connected: bool,
+    /// Attempts at binding each socket (0 is treated as 1) and the wait between them
+    pub bind_retry: (u32, Duration),
+    /// Send multicast datagrams from the IPv4 address of this interface
+    #[cfg(unix)]
+    pub multicast_interface: Option<String>,
+    /// Bytes prepended to every datagram, e.g. `V1;utf-8;`
+    pub header: Option<Vec<u8>>,
+    /// Bytes prepended to every datagram after encryption, identifying it on a shared port
+    pub magic: Option<Vec<u8>>,
+    /// Append this checksum of the sealed datagram as a trailer
+    pub checksum: Option<Checksum>,
+    /// Compress datagrams larger than this, flagging whether each one is compressed
+    #[cfg(feature = "compression")]
+    pub compression_threshold: Option<usize>,
+    /// Encrypt every datagram before it's sent
+    #[cfg(feature = "encryption")]
+    pub sealer: Option<Arc<Sealer>>,
+    /// Called with every datagram that was sent
+    pub on_sent: Option<SentFn>,
+    /// Set while sending is paused, shared by every writer built from these options
+    pub paused: Arc<AtomicBool>,
+}
+
+/// How long after sending a datagram an identical one is suppressed
+pub(crate) const SUPPRESS_WINDOW: Duration = Duration::from_secs(1);
+
+/// A hash of the last datagram a writer sent and when, to suppress identical repeats
+#[derive(Debug, Default)]
+pub(crate) struct LastSent(Mutex<Option<(u64, Instant)>>);
+
+impl LastSent {
+    /// Whether `datagram` is the same as the last one sent, less than `SUPPRESS_WINDOW` ago
+    pub(crate) fn is_repeat(&self, datagram: &[u8], now: Instant) -> bool {
+        match *self.lock() {
+            Some((hash, sent_at)) => hash == digest(datagram) && now - sent_at < SUPPRESS_WINDOW,
+            None => false,
+        }
+    }
+
+    /// Remember that `datagram` was sent at `now`
+    pub(crate) fn record(&self, datagram: &[u8], now: Instant) {
+        *self.lock() = Some((digest(datagram), now));
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<(u64, Instant)>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A 64-bit hash of `datagram`, which only needs to tell consecutive datagrams apart
+fn digest(datagram: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(datagram);
+    hasher.finish()
+}
+
+/// A callback given the bytes of each datagram put on the wire
+pub(crate) type SentCallback = dyn Fn(&[u8]) + Send + Sync;
+
+#[derive(Clone)]
+pub(crate) struct SentFn(pub Arc<SentCallback>);
+
+impl fmt::Debug for SentFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SentFn").finish_non_exhaustive()
+    }
+}
+
+/// The socket a writer sends from, which `SocketOptions::rebind` replaces
+pub(crate) type SharedSocket = Arc<ArcSwap<UdpSocket>>;
+
+impl SocketOptions {
+    /// Bind a new UdpSocket that can later be replaced by `rebind`
+    pub(crate) fn bind_shared(&self, destination: SocketAddr) -> io::Result<SharedSocket> {
+        Ok(Arc::new(ArcSwap::from_pointee(self.bind_to(destination)?)))
+    }
+
+    /// Replace `out` with a newly bound UdpSocket, leaving it in place on error
+    ///
+    /// Sends already using the old socket finish on it, and it's closed after them
+    pub(crate) fn rebind(&self, out: &SharedSocket, destination: SocketAddr) -> io::Result<()> {
+        out.store(Arc::new(self.bind_to(destination)?));
+        Ok(())
+    }
+
+    /// Bind a new UdpSocket for sending to `destination`, connecting it if `connected`
+    fn bind_to(&self, destination: SocketAddr) -> io::Result<UdpSocket> {
+        let socket = self.bind()?;
+        if self.connected {
+            socket.connect(destination)?;
+        }
+        Ok(socket)
+    }
+
+    /// Send `datagram` from `out` to `destination`, which a connected socket already is
+    pub(crate) fn send(
+        &self,
+        out: &UdpSocket,
+        datagram: &[u8],
+        destination: SocketAddr,
+    ) -> io::Result<usize> {
+        match self.connected {
+            true => out.send(datagram),
+            false => out.send_to(datagram, destination),
+        }
+    }
+
+    /// Bind a new UdpSocket (system determined IP & port) with these options applied
+    pub(crate) fn bind(&self) -> io::Result<UdpSocket> {
+        let (attempts, delay) = self.bind_retry;
+        let socket = retry(attempts, delay, || match self.dual_stack {
+            true => bind_dual_stack(),
+            false => UdpSocket::bind("0.0.0.0:0"),
+        })?;
+        if self.nonblocking {
+            socket.set_nonblocking(true)?;
+        }
+        if let Some(timeout) = self.send_timeout {
+            socket.set_write_timeout(Some(timeout))?;
+        }
+        #[cfg(unix)]
+        if let Some(name) = &self.multicast_interface {
+            set_multicast_if(&socket, interface_addr(name)?)?;
+        }
+        if let Some(dscp) = self.dscp {
+            set_dscp(&socket, dscp)?;
+        }
+        Ok(socket)
+    }
+
+    /// Send probe datagrams to `destination` until one is sent without an error being
+    /// reported back, returning whether that happened within `timeout`
+    ///
+    /// The probes go from a separate socket connected to `destination`, so an ICMP
+    /// error (e.g. port unreachable) is reported on it. Each sent probe waits up to
+    /// `PROBE_GRACE` for such an error before it counts as a success.
+    pub(crate) fn probe(&self, destination: SocketAddr, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let probe = || -> io::Result<bool> {
+            let socket = self.bind()?;
+            socket.connect(destination)?;
+            loop {
+                let message = format!("UDP_LOGGER_PROBE [{}]\n", Utc::now().to_rfc3339());
+                let sent = match socket.send(&self.seal(message.as_bytes())?) {
+                    Ok(_) => {
+                        let remaining = deadline.saturating_duration_since(Instant::now());
+                        thread::sleep(remaining.min(PROBE_GRACE));
+                        socket.take_error()?.is_none()
+                    }
+                    // e.g. the error from an earlier probe
+                    Err(_) => false,
+                };
+                if sent {
+                    return Ok(true);
+                }
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Ok(false);
+                }
+                thread::sleep(remaining.min(PROBE_RETRY));
+            }
+        };
+        probe().unwrap_or(false)
+    }
+
+    /// The address to send to for `destination`, IPv4-mapped if the socket is dual-stack
+    pub(crate) fn target(&self, destination: SocketAddr) -> SocketAddr {
+        match destination {
+            SocketAddr::V4(v4) if self.dual_stack => {
+                SocketAddr::V6(SocketAddrV6::new(v4.ip().to_ipv6_mapped(), v4.port(), 0, 0))
+            }
+            destination => destination,
+        }
+    }
+}
+
+/// Bind an IPv6 UdpSocket that can also send to IPv4-mapped addresses
+fn bind_dual_stack() -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+    // Platforms disagree on the default (e.g. Linux clears it, Windows and OpenBSD set it),
+    // so always clear it explicitly
+    socket.set_only_v6(false)?;
+    socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)).into())?;
+    Ok(socket.into())
+}
+
+/// The local IP the OS would send to `destination` from
+///
+/// Connecting a UDP socket only picks the route, nothing is sent
+pub(crate) fn route_ip(destination: SocketAddr) -> io::Result<IpAddr> {
+    let socket = match destination {
+        SocketAddr::V4(_) => UdpSocket::bind("0.0.0.0:0")?,
+        SocketAddr::V6(_) => UdpSocket::bind("[::]:0")?,
+    };
+    socket.connect(destination)?;
+    Ok(socket.local_addr()?.ip())
+}
+
+/// How long a sent probe waits for an ICMP error before it counts as delivered
+pub(crate) const PROBE_GRACE: Duration = Duration::from_millis(50);
+
+/// How long to wait before probing again after a failed probe
+const PROBE_RETRY: Duration = Duration::from_millis(100);
+
+/// The highest DSCP, which only has 6 bits
+pub(crate) const MAX_DSCP: u8 = 63;
+
+/// Mark every datagram sent from `socket` with the DSCP `dscp`
+///
+/// The DSCP is the upper 6 bits of the traffic class, and the 2 ECN bits below it are
+/// left clear (Not-ECT), since UDP senders don't react to congestion marks
+fn set_dscp(socket: &UdpSocket, dscp: u8) -> io::Result<()> {
+    if dscp > MAX_DSCP {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("DSCP {} doesn't fit in 6 bits (max {})", dscp, MAX_DSCP),
+        ));
+    }
+    let traffic_class = u32::from(dscp) << 2;
+    let socket = SockRef::from(socket);
+    if socket.local_addr()?.is_ipv6() {
+        set_tclass_v6(&socket, traffic_class)?;
+        // Datagrams to IPv4-mapped destinations on a dual-stack socket use IP_TOS, which
+        // not every platform allows on an IPv6 socket
+        let _ = socket.set_tos(traffic_class);
+        Ok(())
+    } else {
+        socket.set_tos(traffic_class)
+    }
+}
+
+#[cfg(any(
+    target_os = "android",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "fuchsia",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd",
+))]
+fn set_tclass_v6(socket: &SockRef<'_>, traffic_class: u32) -> io::Result<()> {
+    socket.set_tclass_v6(traffic_class)
+}
+
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "fuchsia",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd",
+)))]
+fn set_tclass_v6(_socket: &SockRef<'_>, _traffic_class: u32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "IPV6_TCLASS isn't supported on this platform",
+    ))
+}
+
+impl SocketOptions {
+    /// Whether `seal` transforms datagrams, so they can't be segmented after sealing
+    pub(crate) fn seals(&self) -> bool {
+        if self.header.is_some() || self.magic.is_some() || self.checksum.is_some() {
+            return true;
+        }
+        #[cfg(feature = "compression")]
+        if self.compression_threshold.is_some() {
+            return true;
+        }
+        #[cfg(feature = "encryption")]
+        if self.sealer.is_some() {
+            return true;
+        }
+        false
+    }
+
+    /// Whether `UdpLogger::pause` is holding back sends
+    pub(crate) fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Report a datagram that was sent to the `on_sent` callback, if any
+    pub(crate) fn sent(&self, datagram: &[u8]) {
+        if let Some(SentFn(on_sent)) = &self.on_sent {
+            on_sent(datagram);
+        }
+    }
+
+    /// Transform a datagram into the bytes put on the wire (e.g. encrypting it)
+    ///
+    /// The header is prepended first, so it's compressed and encrypted along with the
+    /// datagram, then the magic prefix, so it stays readable, and the checksum trailer
+    /// covers all of it
+    pub(crate) fn seal<'a>(&self, datagram: &'a [u8]) -> io::Result<Cow<'a, [u8]>> {
+        let datagram = match &self.header {
+            Some(header) => Cow::Owned([header.as_slice(), datagram].concat()),
+            None => Cow::Borrowed(datagram),
+        };
+        #[cfg(feature = "compression")]
+        let datagram = match self.compression_threshold {
+            Some(threshold) => Cow::Owned(compress::compress_over(threshold, &datagram)),
+            None => datagram,
+        };
+        #[cfg(feature = "encryption")]
+        let datagram = match &self.sealer {
+            Some(sealer) => Cow::Owned(sealer.seal(&datagram)?),
+            None => datagram,
+        };
+        let datagram = match &self.magic {
+            Some(magic) => Cow::Owned([magic.as_slice(), &datagram].concat()),
+            None => datagram,
+        };
+        Ok(match self.checksum {
+            Some(checksum) => Cow::Owned(checksum.append(&datagram)),
+            None => datagram,
+        })
+    }
+}
+
+/// Call `attempt` up to `attempts` times, waiting `delay` after each failure, until it
+/// succeeds or fails with an error retrying can't fix
+pub(crate) fn retry<T>(
+    attempts: u32,
+    delay: Duration,
+    mut attempt: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    let mut tries = 1;
+    loop {
+        match attempt() {
+            Err(e)
+                if tries < attempts
+                    && !matches!(
+                        e.kind(),
+                        io::ErrorKind::InvalidInput | io::ErrorKind::Unsupported
+                    ) =>
+            {
+                tries += 1;
+                thread::sleep(delay);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// How many times a short send is attempted before `send_confirmed` gives up
+pub(crate) const CONFIRM_SEND_ATTEMPTS: usize = 3;
+
+/// Send `datagram` with `send`, which returns how many bytes it put on the wire
+///
+/// With `confirm`, a short send (a truncated datagram) is retried with the whole
+/// datagram, and is an error once `CONFIRM_SEND_ATTEMPTS` sends have all been short
+pub(crate) fn send_confirmed(
+    datagram: &[u8],
+    confirm: bool,
+    mut send: impl FnMut(&[u8]) -> io::Result<usize>,
+) -> io::Result<()> {
+    let mut sent = send(datagram)?;
+    if !confirm {
+        return Ok(());
+    }
+    for _ in 1..CONFIRM_SEND_ATTEMPTS {
+        if sent == datagram.len() {
+            return Ok(());
+        }
+        sent = send(datagram)?;
+    }
+    match sent == datagram.len() {
+        true => Ok(()),
+        false => Err(io::Error::new(
+            io::ErrorKind::WriteZero,
+            format!(
+                "short send: {} of {} bytes after {} attempts",
+                sent,
+                datagram.len(),
+                CONFIRM_SEND_ATTEMPTS
+            ),
+        )),
+    }
+}
+
+/// Find the first IPv4 address assigned to the interface named `name`
+#[cfg(unix)]
+pub(crate) fn interface_addr(name: &str) -> io::Result<std::net::Ipv4Addr> {
+    use std::ffi::CStr;
+    use std::net::Ipv4Addr;
+
+    let mut addrs: *mut libc::ifaddrs = std::ptr::null_mut();
+    // SAFETY: `addrs` is only read if getifaddrs succeeds, and is freed below
+    if unsafe { libc::getifaddrs(&mut addrs) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut found = None;
+    let mut next = addrs;
+    while !next.is_null() {
+        // SAFETY: `next` is a non-null node of the list returned by getifaddrs
+        let ifa = unsafe { &*next };
+        next = ifa.ifa_next;
+        if ifa.ifa_addr.is_null() {
+            continue;
+        }
+        // SAFETY: `ifa_name` is a valid C string and `ifa_addr` is non-null
+        let (ifa_name, family) =
+            unsafe { (CStr::from_ptr(ifa.ifa_name), (*ifa.ifa_addr).sa_family) };
+        if ifa_name.to_bytes() == name.as_bytes() && i32::from(family) == libc::AF_INET {
+            // SAFETY: an AF_INET address is a sockaddr_in
+            let addr = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in) };
+            found = Some(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)));
+            break;
+        }
+    }
+    // SAFETY: `addrs` came from a successful getifaddrs and isn't used after this
+    unsafe { libc::freeifaddrs(addrs) };
+    found.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no IPv4 address for interface `{}`", name),
+        )
+    })
+}
+
+/// Set the interface (by address) that multicast datagrams are sent from
+#[cfg(unix)]
+fn set_multicast_if(socket: &UdpSocket, addr: std::net::Ipv4Addr) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let addr = libc::in_addr {
+        s_addr: u32::from(addr).to_be(),
+    };
+    // SAFETY: `addr` is a valid in_addr for the duration of the call
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IP,
+            libc::IP_MULTICAST_IF,
+            &addr as *const libc::in_addr as *const libc::c_void,
+            std::mem::size_of::<libc::in_addr>() as libc::socklen_t,
+        )
+    };
+    match result {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Send `buffer` as consecutive `segment`-byte datagrams in one syscall via UDP GSO
+///
+/// Every segment but the last must be exactly `segment` bytes
+#[cfg(target_os = "linux")]
+pub(crate) fn send_segments(
+    socket: &UdpSocket,
+    buffer: &[u8],
+    segment: u16,
+    destination: SocketAddr,
+) -> io::Result<()> {
+    use std::mem::size_of;
+    use std::os::unix::io::AsRawFd;
+
+    let addr = socket2::SockAddr::from(destination);
+    let mut iov = libc::iovec {
+        iov_base: buffer.as_ptr() as *mut libc::c_void,
+        iov_len: buffer.len(),
+    };
+    // SAFETY: CMSG_SPACE only computes a size
+    let space = unsafe { libc::CMSG_SPACE(size_of::<u16>() as u32) } as usize;
+    // u64s so the control buffer is aligned for a cmsghdr
+    let mut control = vec![0u64; space.div_ceil(size_of::<u64>())];
+    // SAFETY: msghdr is a plain C struct, for which all zeroes is valid
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_name = addr.as_ptr() as *mut libc::c_void;
+    msg.msg_namelen = addr.len();
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = space as _;
+    // SAFETY: `control` has room for one cmsghdr carrying a u16, so the first header
+    // is non-null and its data is in bounds
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_UDP;
+        (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(size_of::<u16>() as u32) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut u16, segment);
+    }
+    // SAFETY: every pointer in `msg` is valid for the duration of the call
+    match unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, 0) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Whether a GSO send failed because the kernel or device doesn't support it
+#[cfg(target_os = "linux")]
+pub(crate) fn is_gso_unsupported(error: &io::Error) -> bool {
+    matches!(
+        error.raw_os_error(),
+        Some(libc::EIO | libc::EINVAL | libc::ENOPROTOOPT | libc::EOPNOTSUPP)
+    )
+}
+
+/// Whether a send error is transient congestion rather than a real failure
+///
+/// A send timing out is reported as `WouldBlock` on Unix and `TimedOut` on Windows
+pub(crate) fn is_would_block(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// Whether a send failed because the OS send buffer is full (`ENOBUFS`), which passes
+/// once the queued datagrams have gone out
+pub(crate) fn is_send_buffer_full(error: &io::Error) -> bool {
+    #[cfg(unix)]
+    const ENOBUFS: i32 = libc::ENOBUFS;
+    #[cfg(windows)]
+    const ENOBUFS: i32 = 10055; // WSAENOBUFS
+    error.raw_os_error() == Some(ENOBUFS)
+}
+
+/// Whether a failed send is worth retrying after a pause: a full send buffer, or a
+/// non-blocking socket that would block
+pub(crate) fn is_congested(error: &io::Error) -> bool {
+    is_would_block(error) || is_send_buffer_full(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_would_block() {
+        assert!(is_would_block(&io::ErrorKind::WouldBlock.into()));
+        assert!(is_would_block(&io::ErrorKind::TimedOut.into()));
+        assert!(!is_would_block(&io::ErrorKind::ConnectionRefused.into()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_congested() {
+        let enobufs = io::Error::from_raw_os_error(libc::ENOBUFS);
+        assert!(is_send_buffer_full(&enobufs));
+        assert!(is_congested(&enobufs));
+        let eagain = io::Error::from_raw_os_error(libc::EAGAIN);
+        assert!(!is_send_buffer_full(&eagain));
+        assert!(is_congested(&eagain));
+        assert!(!is_congested(&io::Error::from_raw_os_error(
+            libc::EHOSTUNREACH
+        )));
+        assert!(!is_congested(&io::ErrorKind::ConnectionRefused.into()));
+    }
+
+    #[test]
+    fn test_send_confirmed() {
+        let datagram = b"INFO testing\n";
+        // Short sends are ignored unless confirmed
+        let mut attempts = 0;
+        send_confirmed(datagram, false, |_| {
+            attempts += 1;
+            Ok(4)
+        })
+        .unwrap();
+        assert_eq!(attempts, 1);
+
+        // A short send is retried whole
+        let mut sends = vec![];
+        send_confirmed(datagram, true, |bytes| {
+            sends.push(bytes.len());
+            Ok(match sends.len() {
+                1 => 4,
+                _ => bytes.len(),
+            })
+        })
+        .unwrap();
+        assert_eq!(sends, [datagram.len(), datagram.len()]);
+
+        // And is an error if it stays short
+        let mut attempts = 0;
+        let error = send_confirmed(datagram, true, |_| {
+            attempts += 1;
+            Ok(4)
+        })
+        .unwrap_err();
+        assert_eq!(attempts, CONFIRM_SEND_ATTEMPTS);
+        assert_eq!(error.kind(), io::ErrorKind::WriteZero);
+        assert!(error.to_string().contains("4 of 13 bytes"), "{}", error);
+
+        // Send errors aren't retried
+        let mut attempts = 0;
+        let error = send_confirmed(datagram, true, |_| {
+            attempts += 1;
+            Err(io::ErrorKind::ConnectionRefused.into())
+        })
+        .unwrap_err();
+        assert_eq!(
+            (attempts, error.kind()),
+            (1, io::ErrorKind::ConnectionRefused)
+        );
+    }
+
+    #[test]
+    fn test_send_timeout() {
+        let options = SocketOptions {
+            send_timeout: Some(Duration::from_millis(250)),
+            ..Default::default()
+        };
+        // The kernel rounds the timeout to its clock tick
+        let timeout = options.bind().unwrap().write_timeout().unwrap().unwrap();
+        assert!(timeout >= Duration::from_millis(250));
+        assert!(timeout < Duration::from_millis(300));
+        assert_eq!(
+            SocketOptions::default()
+                .bind()
+                .unwrap()
+                .write_timeout()
+                .unwrap(),
+            None
+        );
+
+        let options = SocketOptions {
+            send_timeout: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.bind().unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_dscp() {
+        let options = SocketOptions {
+            dscp: Some(8),
+            ..Default::default()
+        };
+        let tos = SockRef::from(&options.bind().unwrap()).tos().unwrap();
+        assert_eq!(tos, 8 << 2);
+
+        let options = SocketOptions {
+            dscp: Some(10),
+            dual_stack: true,
+            ..Default::default()
+        };
+        let socket = options.bind().unwrap();
+        assert_eq!(SockRef::from(&socket).tclass_v6().unwrap(), 10 << 2);
+
+        let options = SocketOptions {
+            dscp: Some(MAX_DSCP + 1),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.bind().unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn test_header() {
+        let options = SocketOptions {
+            header: Some(b"V1;utf-8;".to_vec()),
+            ..Default::default()
+        };
+        assert!(options.seals());
+        assert_eq!(
+            &options.seal(b"INFO [ts] testing\n").unwrap()[..],
+            b"V1;utf-8;INFO [ts] testing\n"
+        );
+        assert!(matches!(
+            SocketOptions::default().seal(b"testing").unwrap(),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn test_magic_prefix() {
+        let options = SocketOptions {
+            header: Some(b"V1;".to_vec()),
+            magic: Some(vec![0xca, 0xfe]),
+            ..Default::default()
+        };
+        assert!(options.seals());
+        assert_eq!(
+            &options.seal(b"testing\n").unwrap()[..],
+            b"\xca\xfeV1;testing\n"
+        );
+    }
+
+    #[test]
+    fn test_retry() {
+        // Fails like a bind before the network namespace is ready, twice
+        let mut calls = 0;
+        let mut flaky_bind = || {
+            calls += 1;
+            match calls {
+                1 | 2 => Err(io::Error::from(io::ErrorKind::AddrNotAvailable)),
+                _ => UdpSocket::bind("127.0.0.1:0"),
+            }
+        };
+        retry(3, Duration::from_millis(1), &mut flaky_bind).expect("Binds on the third attempt");
+        assert_eq!(calls, 3);
+
+        let mut calls = 0;
+        let error = retry(3, Duration::from_millis(1), || -> io::Result<()> {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::AddrNotAvailable))
+        })
+        .unwrap_err();
+        assert_eq!((error.kind(), calls), (io::ErrorKind::AddrNotAvailable, 3));
+
+        let mut calls = 0;
+        let error = retry(3, Duration::from_millis(1), || -> io::Result<()> {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::InvalidInput))
+        })
+        .unwrap_err();
+        assert_eq!((error.kind(), calls), (io::ErrorKind::InvalidInput, 1));
+    }
+
+    #[test]
+    fn test_checksum_trailer() {
+        let options = SocketOptions {
+            magic: Some(vec![0xca, 0xfe]),
+            checksum: Some(Checksum::Crc32),
+            ..Default::default()
+        };
+        assert!(options.seals());
+        let sealed = options.seal(b"testing\n").unwrap();
+        let (payload, trailer) = sealed.split_at(sealed.len() - 4);
+        assert_eq!(payload, b"\xca\xfetesting\n");
+        assert_eq!(trailer, crc32fast::hash(payload).to_be_bytes());
+    }
+
+    #[test]
+    fn test_dual_stack_target() {
+        let v4: SocketAddr = "127.0.0.1:1999".parse().unwrap();
+        let v6: SocketAddr = "[::1]:1999".parse().unwrap();
+        assert_eq!(SocketOptions::default().target(v4), v4);
+        let options = SocketOptions {
+            dual_stack: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            options.target(v4),
+            "[::ffff:127.0.0.1]:1999".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(options.target(v6), v6);
+        assert!(options.bind().unwrap().local_addr().unwrap().is_ipv6());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_send_segments() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let socket = SocketOptions::default().bind().unwrap();
+        match send_segments(&socket, b"aaaabbbbcc", 4, receiver.local_addr().unwrap()) {
+            Err(e) if is_gso_unsupported(&e) => return,
+            result => result.expect("GSO send succeeds"),
+        }
+        let mut buf = [0; 16];
+        for expected in [&b"aaaa"[..], b"bbbb", b"cc"] {
+            let len = receiver.recv(&mut buf).expect("Segment arrives");
+            assert_eq!(&buf[..len], expected);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unknown_multicast_interface() {
+        let options = SocketOptions {
+            multicast_interface: Some("no-such-if0".into()),
+            ..Default::default()
+        };
+        let err = options.bind().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().contains("no-such-if0"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_loopback_multicast_interface() {
+        assert_eq!(interface_addr("lo").unwrap(), std::net::Ipv4Addr::LOCALHOST);
+        let options = SocketOptions {
+            multicast_interface: Some("lo".into()),
+            ..Default::default()
+        };
+        options.bind().expect("Can send multicast from loopback");
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::Level;
+
+use crate::queue::{Envelope, MessageQueue};
+use crate::stats::Stats;
+
+/// Bytes before each spilled payload: a priority byte, a level byte (0 for none), when
+/// it was queued as little-endian u64 nanoseconds after the queue was created, and a
+/// little-endian u32 length
+const HEADER_LEN: usize = 14;
+
+/// A bounded in-memory queue that spills overflow to an append-only file
+///
+/// Once anything is spilled, new messages are spilled too so they stay in order behind
+/// it. Spilled messages are reloaded as the in-memory queue empties, and the file is
+/// truncated once it has been replayed entirely.
+pub(crate) struct SpillQueue {
+    memory: VecDeque<Envelope>,
+    limit: usize,
+    file: File,
+    max_bytes: u64,
+    /// Bytes appended to the file
+    written: u64,
+    /// Bytes already reloaded from the file
+    read: u64,
+    /// Messages in the file that haven't been reloaded
+    spilled: usize,
+    stats: Arc<Stats>,
+    /// What spilled messages' queue times are stored relative to
+    created: Instant,
+}
+
+impl SpillQueue {
+    /// Create a SpillQueue holding `limit` messages in memory, replacing any existing file
+    pub(crate) fn new(
+        limit: usize,
+        path: &Path,
+        max_bytes: u64,
+        stats: Arc<Stats>,
+    ) -> io::Result<Self> {
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(Self {
+            memory: VecDeque::new(),
+            limit,
+            file,
+            max_bytes,
+            written: 0,
+            read: 0,
+            spilled: 0,
+            stats,
+            created: Instant::now(),
+        })
+    }
+
+    /// Append a message to the file, returning `false` if it doesn't fit or can't be written
+    fn spill(&mut self, message: &Envelope) -> bool {
+        let mut record = Vec::with_capacity(HEADER_LEN + message.payload.len());
+        record.push(message.priority);
+        record.push(message.level.map_or(0, |level| level as u8));
+        let queued_at = message.queued_at.saturating_duration_since(self.created);
+        record.extend((queued_at.as_nanos() as u64).to_le_bytes());
+        record.extend((message.payload.len() as u32).to_le_bytes());
+        record.extend(&message.payload);
+        if self.written + record.len() as u64 > self.max_bytes {
+            return false;
+        }
+        let appended = self
+            .file
+            .seek(SeekFrom::Start(self.written))
+            .and_then(|_| self.file.write_all(&record));
+        if let Err(e) = appended {
+            eprintln!("Error spilling message: {}", e);
+            return false;
+        }
+        self.written += record.len() as u64;
+        self.spilled += 1;
+        self.stats.record_spilled();
+        true
+    }
+
+    /// Move spilled messages back into memory while there's room
+    fn reload(&mut self) {
+        while self.spilled > 0 && self.memory.len() < self.limit {
+            match self.read_next() {
+                Ok(message) => {
+                    self.memory.push_back(message);
+                    self.spilled -= 1;
+                    self.stats.record_replayed();
+                }
+                Err(e) => {
+                    eprintln!("Error replaying spilled messages: {}", e);
+                    self.spilled = 0;
+                }
+            }
+        }
+        if self.spilled == 0 && self.written > 0 {
+            self.file.set_len(0).ok();
+            self.written = 0;
+            self.read = 0;
+        }
+    }
+
+    fn read_next(&mut self) -> io::Result<Envelope> {
+        self.file.seek(SeekFrom::Start(self.read))?;
+        let mut header = [0; HEADER_LEN];
+        self.file.read_exact(&mut header)?;
+        let (queued_at, len) = header[2..].split_at(8);
+        let queued_at = u64::from_le_bytes(queued_at.try_into().unwrap());
+        let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+        let mut payload = vec![0; len];
+        self.file.read_exact(&mut payload)?;
+        self.read += (HEADER_LEN + len) as u64;
+        let mut message = Envelope::new(payload, header[0]);
+        message.level = Level::iter().find(|level| *level as u8 == header[1]);
+        message.queued_at = self.created + Duration::from_nanos(queued_at);
+        Ok(message)
+    }
+}
+
+impl MessageQueue for SpillQueue {
+    fn push(&mut self, message: Envelope) -> bool {
+        if self.spilled > 0 || self.memory.len() >= self.limit {
+            return !self.spill(&message);
+        }
+        self.memory.push_back(message);
+        false
+    }
+
+    fn pop(&mut self) -> Option<Envelope> {
+        if self.memory.is_empty() {
+            self.reload();
+        }
+        self.memory.pop_front()
+    }
+
+    fn peek(&self) -> Option<&Envelope> {
+        self.memory.front()
+    }
+
+    /// Only the messages in memory, spilled ones stay on disk
+    fn peek_many(&self, max: usize) -> Vec<Envelope> {
+        self.memory.iter().take(max).cloned().collect()
+    }
+
+    fn len(&self) -> usize {
+        self.memory.len() + self.spilled
+    }
+
+    /// Only filters the messages in memory, so spilled ones are checked once reloaded
+    /// rather than by rewriting the file
+    fn retain(&mut self, keep: &mut dyn FnMut(&Envelope) -> bool) -> usize {
+        let len = self.memory.len();
+        self.memory.retain(|message| keep(message));
+        len - self.memory.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spill_and_replay() {
+        let path = std::env::temp_dir().join(format!("udp_logger_spill_{}", std::process::id()));
+        let stats = Arc::new(Stats::default());
+        // Room for exactly two spilled 9 byte messages
+        let mut queue = SpillQueue::new(2, &path, 2 * (HEADER_LEN as u64 + 9), stats.clone())
+            .expect("Can create spill file");
+        for i in 0..5 {
+            let message = Envelope::new(format!("message {}", i).into_bytes(), 3);
+            assert_eq!(
+                queue.push(message),
+                i == 4,
+                "only the last message is dropped"
+            );
+        }
+        assert_eq!(queue.len(), 4);
+        assert!(std::fs::metadata(&path).unwrap().len() > 0);
+
+        let mut popped = Vec::new();
+        while let Some(m) = queue.pop() {
+            popped.push(String::from_utf8(m.payload.to_vec()).unwrap());
+        }
+        assert_eq!(popped, ["message 0", "message 1", "message 2", "message 3"]);
+        let stats = stats.snapshot();
+        assert_eq!((stats.spilled, stats.replayed), (2, 2));
+        // The fully replayed file is truncated
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_retain() {
+        let path =
+            std::env::temp_dir().join(format!("udp_logger_spill_retain_{}", std::process::id()));
+        let stats = Arc::new(Stats::default());
+        let mut queue =
+            SpillQueue::new(2, &path, 1024, stats.clone()).expect("Can create spill file");
+        let mut queued_at = Vec::new();
+        for i in 0..4 {
+            let mut message = Envelope::new(format!("message {}", i).into_bytes(), 3);
+            message.level = Some(Level::Debug);
+            queued_at.push(message.queued_at);
+            queue.push(message);
+        }
+        let written = std::fs::metadata(&path).unwrap().len();
+
+        let removed = queue.retain(&mut |m| &m.payload[..] != b"message 0");
+        assert_eq!(removed, 1);
+        assert_eq!(queue.len(), 3);
+        // Nothing was reloaded or spilled again
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), written);
+        let snapshot = stats.snapshot();
+        assert_eq!((snapshot.spilled, snapshot.replayed), (2, 0));
+        // Reloaded messages keep their level and queue time, for `with_level_ttl`
+        let popped: Vec<_> = std::iter::from_fn(|| queue.pop())
+            .map(|m| (m.level, m.queued_at))
+            .collect();
+        let expected: Vec<_> = queued_at[1..]
+            .iter()
+            .map(|&queued_at| (Some(Level::Debug), queued_at))
+            .collect();
+        assert_eq!(popped, expected);
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,573 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::Level;
+
+/// Counters shared between a UdpLogger and its writer
+#[derive(Default)]
+pub(crate) struct Stats {
+    sent: AtomicU64,
+    dropped: AtomicU64,
+    errors: AtomicU64,
+    empty: AtomicU64,
+    would_send: AtomicU64,
+    throttled: AtomicU64,
+    invalid_utf8: AtomicU64,
+    expired: AtomicU64,
+    spilled: AtomicU64,
+    replayed: AtomicU64,
+    drain_failures: AtomicU64,
+    rate_limited: AtomicU64,
+    capped: AtomicU64,
+    rebinds: AtomicU64,
+    requeued: AtomicU64,
+    suppressed: AtomicU64,
+    congestion_pauses: AtomicU64,
+    formatter_panics: AtomicU64,
+    filtered_by_level: AtomicU64,
+    filtered_by_target: AtomicU64,
+    filtered_by_filter: AtomicU64,
+    /// Records logged at each level, indexed by `level as usize - 1`
+    per_level: [AtomicU64; 5],
+    /// Drops not yet reported by a drop notice, unaffected by `take`/`reset`
+    unreported_drops: AtomicU64,
+    /// Epoch millis of the last successful send, 0 == never
+    last_send_ms: AtomicU64,
+    /// Socket sends by duration, see `SendLatency::buckets`
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS],
+    latency_total_us: AtomicU64,
+    latency_max_us: AtomicU64,
+    /// The logger's counters, when these are one destination's
+    parent: Option<Arc<Stats>>,
+}
+
+/// Buckets in a `SendLatency` histogram
+const LATENCY_BUCKETS: usize = 16;
+
+/// A point-in-time copy of a UdpLogger's counters
+///
+/// Counters are cumulative since the logger was created or last reset
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatsSnapshot {
+    /// Datagrams successfully sent
+    pub sent: u64,
+    /// Messages discarded because the queue was full
+    pub dropped: u64,
+    /// Datagrams that failed to send
+    pub errors: u64,
+    /// Records skipped because they formatted to an empty payload
+    pub empty: u64,
+    /// Datagrams that would have been sent by a dry-run logger
+    pub would_send: u64,
+    /// Records suppressed by `with_throttle`
+    pub throttled: u64,
+    /// Messages rejected for invalid UTF-8 under `Utf8Policy::Reject`
+    pub invalid_utf8: u64,
+    /// Queued messages discarded for outliving `with_message_ttl`
+    pub expired: u64,
+    /// Messages written to the spill file because the queue was full
+    pub spilled: u64,
+    /// Spilled messages reloaded into the queue
+    pub replayed: u64,
+    /// Times a buffered logger's drain thread died, after which it sends from the
+    /// logging thread
+    pub drain_failures: u64,
+    /// Datagrams not sent because they'd exceed `with_global_rate_limit`
+    pub rate_limited: u64,
+    /// Datagrams not sent because `with_send_cap` was reached
+    pub capped: u64,
+    /// Sockets replaced by `UdpLogger::reconnect`
+    pub rebinds: u64,
+    /// Messages put back in the queue after a failed send, see `with_requeue`
+    pub requeued: u64,
+    /// Datagrams skipped for being identical to the one just sent, see
+    /// `with_suppress_identical_consecutive`
+    pub suppressed: u64,
+    /// Pauses by the drain thread to retry a send after the OS send buffer was full
+    /// (`ENOBUFS`) or the socket would block
+    pub congestion_pauses: u64,
+    /// Records whose custom formatter panicked, see `with_formatter_panic_policy`
+    pub formatter_panics: u64,
+    /// Records rejected by the level, including a `with_scheduled_level` level and
+    /// `with_hard_min_level`
+    ///
+    /// Like the other filter counters, this only counts records that reach the logger:
+    /// ones above `log::max_level` are skipped by the `log` macros beforehand.
+    pub filtered_by_level: u64,
+    /// Records rejected by a target level, suppression, or target floor for their target
+    pub filtered_by_target: u64,
+    /// Records rejected by the `with_filter` closure
+    pub filtered_by_filter: u64,
+    /// Records logged at each level (Error first, Trace last), see `by_level`
+    pub per_level: [u64; 5],
+    /// How long each UDP socket send took, successful or not
+    pub send_latency: SendLatency,
+}
+
+/// A coarse histogram of how long socket sends took
+///
+/// Every `send_to` (or GSO `sendmsg`) by the UDP writers is timed, including ones that
+/// fail or are retried, so slow sends show up even when nothing is dropped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SendLatency {
+    /// Sends by duration in power-of-two microsecond buckets: under 1µs in `buckets[0]`,
+    /// then `[2^(i-1), 2^i)` µs in `buckets[i]`, and 16.384ms or longer in the last
+    pub buckets: [u64; LATENCY_BUCKETS],
+    /// The total duration of every send, in microseconds
+    pub total_us: u64,
+    /// The longest send, in microseconds
+    pub max_us: u64,
+}
+
+impl SendLatency {
+    /// How many sends were timed
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// The average send duration, `None` before any sends
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count() {
+            0 => None,
+            count => Some(Duration::from_micros(self.total_us / count)),
+        }
+    }
+
+    /// The longest send, `None` before any sends
+    pub fn max(&self) -> Option<Duration> {
+        match self.count() {
+            0 => None,
+            _ => Some(Duration::from_micros(self.max_us)),
+        }
+    }
+
+    /// An upper bound on the duration of `percentile` percent of sends, e.g. `99.0`
+    ///
+    /// This is the upper bound of the bucket holding that send, so it's at most twice
+    /// the real duration, and never more than the longest send.
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((percentile / 100.0 * count as f64).ceil() as u64).clamp(1, count);
+        let mut seen = 0;
+        let bucket = self
+            .buckets
+            .iter()
+            .position(|sends| {
+                seen += sends;
+                seen >= rank
+            })
+            .unwrap_or(LATENCY_BUCKETS - 1);
+        let upper_us = match bucket {
+            bucket if bucket == LATENCY_BUCKETS - 1 => self.max_us,
+            bucket => (1u64 << bucket).min(self.max_us),
+        };
+        Some(Duration::from_micros(upper_us))
+    }
+}
+
+/// The `SendLatency` bucket for a send taking `micros`
+fn latency_bucket(micros: u64) -> usize {
+    let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+    bucket.min(LATENCY_BUCKETS - 1)
+}
+
+impl StatsSnapshot {
+    /// Records logged at each level, e.g. to spot a spike in errors
+    pub fn by_level(&self) -> BTreeMap<Level, u64> {
+        Level::iter().zip(self.per_level).collect()
+    }
+}
+
+/// One destination's counters, from `UdpLogger::destinations`
+///
+/// Like `StatsSnapshot`, these are cumulative since the logger was created, but
+/// `UdpLogger::take_stats` and `reset_stats` don't zero them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DestinationStats {
+    /// Where the destination's datagrams are sent
+    pub address: SocketAddr,
+    /// Datagrams successfully sent
+    pub sent: u64,
+    /// Messages discarded, e.g. because the destination's queue was full
+    pub dropped: u64,
+    /// Datagrams that failed to send
+    pub errors: u64,
+}
+
+impl Stats {
+    /// Counters for one destination, also counted in `parent`
+    ///
+    /// Only sends, drops, and errors are kept for the destination itself, everything
+    /// else is counted in the parent alone.
+    pub(crate) fn scoped(parent: &Arc<Stats>) -> Arc<Stats> {
+        Arc::new(Stats {
+            parent: Some(parent.clone()),
+            ..Stats::default()
+        })
+    }
+
+    /// The logger-wide counters
+    fn root(&self) -> &Stats {
+        self.parent.as_deref().unwrap_or(self)
+    }
+
+    /// These counters, and the parent's if they're a destination's
+    fn scopes(&self) -> impl Iterator<Item = &Stats> {
+        std::iter::once(self).chain(self.parent.as_deref())
+    }
+
+    /// Record that a datagram was successfully sent
+    pub(crate) fn record_send(&self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        for stats in self.scopes() {
+            stats.sent.fetch_add(1, Ordering::Relaxed);
+            stats.last_send_ms.store(now.max(1), Ordering::Relaxed);
+        }
+    }
+
+    /// Record that a message was dropped from (or never made it into) the queue
+    pub(crate) fn record_drop(&self) {
+        for stats in self.scopes() {
+            stats.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        self.root().unreported_drops.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Read and zero the drops a drop notice hasn't reported yet
+    pub(crate) fn take_unreported_drops(&self) -> u64 {
+        self.root().unreported_drops.swap(0, Ordering::Relaxed)
+    }
+
+    /// Record that a datagram failed to send
+    pub(crate) fn record_error(&self) {
+        for stats in self.scopes() {
+            stats.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Record that a record was skipped because its payload was empty
+    pub(crate) fn record_empty(&self) {
+        self.root().empty.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a dry-run logger skipped sending a datagram
+    pub(crate) fn record_would_send(&self) {
+        self.root().would_send.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a record was suppressed by the throttle
+    pub(crate) fn record_throttled(&self) {
+        self.root().throttled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a message was rejected for invalid UTF-8
+    pub(crate) fn record_invalid_utf8(&self) {
+        self.root().invalid_utf8.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that queued messages expired before they could be sent
+    pub(crate) fn record_expired(&self, count: usize) {
+        self.root()
+            .expired
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    /// Record that a message was spilled to disk
+    pub(crate) fn record_spilled(&self) {
+        self.root().spilled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a spilled message was reloaded from disk
+    pub(crate) fn record_replayed(&self) {
+        self.root().replayed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a drain thread died
+    pub(crate) fn record_drain_failure(&self) {
+        self.root().drain_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a datagram was held back by the global rate limit
+    pub(crate) fn record_rate_limited(&self) {
+        self.root().rate_limited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a datagram was held back by the send cap
+    pub(crate) fn record_capped(&self) {
+        self.root().capped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a writer's socket was replaced by a newly bound one
+    pub(crate) fn record_rebind(&self) {
+        self.root().rebinds.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a message was put back in the queue after a failed send
+    pub(crate) fn record_requeue(&self) {
+        self.root().requeued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Run a socket send, recording how long it took in the send latency histogram
+    pub(crate) fn timed<T>(&self, send: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = send();
+        let micros = start.elapsed().as_micros().min(u64::MAX.into()) as u64;
+        self.root().latency_buckets[latency_bucket(micros)].fetch_add(1, Ordering::Relaxed);
+        self.root()
+            .latency_total_us
+            .fetch_add(micros, Ordering::Relaxed);
+        self.root()
+            .latency_max_us
+            .fetch_max(micros, Ordering::Relaxed);
+        result
+    }
+
+    /// Record that a datagram identical to the last one sent was skipped
+    pub(crate) fn record_suppressed(&self) {
+        self.root().suppressed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that the drain thread paused to retry a congested send
+    pub(crate) fn record_congestion_pause(&self) {
+        self.root()
+            .congestion_pauses
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a custom formatter panicked
+    pub(crate) fn record_formatter_panic(&self) {
+        self.root().formatter_panics.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that the level rejected a record
+    pub(crate) fn record_filtered_by_level(&self) {
+        self.root()
+            .filtered_by_level
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a target's level, suppression, or floor rejected a record
+    pub(crate) fn record_filtered_by_target(&self) {
+        self.root()
+            .filtered_by_target
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that the `with_filter` closure rejected a record
+    pub(crate) fn record_filtered_by_filter(&self) {
+        self.root()
+            .filtered_by_filter
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a record at `level` was logged
+    pub(crate) fn record_level(&self, level: Level) {
+        self.root().per_level[level as usize - 1].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The time of the last successful send, if any
+    pub(crate) fn last_send_at(&self) -> Option<SystemTime> {
+        match self.last_send_ms.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Some(UNIX_EPOCH + Duration::from_millis(ms)),
+        }
+    }
+
+    /// The logger-wide counters, even for a destination's
+    pub(crate) fn snapshot(&self) -> StatsSnapshot {
+        let stats = self.root();
+        StatsSnapshot {
+            sent: stats.sent.load(Ordering::Relaxed),
+            dropped: stats.dropped.load(Ordering::Relaxed),
+            errors: stats.errors.load(Ordering::Relaxed),
+            empty: stats.empty.load(Ordering::Relaxed),
+            would_send: stats.would_send.load(Ordering::Relaxed),
+            throttled: stats.throttled.load(Ordering::Relaxed),
+            invalid_utf8: stats.invalid_utf8.load(Ordering::Relaxed),
+            expired: stats.expired.load(Ordering::Relaxed),
+            spilled: stats.spilled.load(Ordering::Relaxed),
+            replayed: stats.replayed.load(Ordering::Relaxed),
+            drain_failures: stats.drain_failures.load(Ordering::Relaxed),
+            rate_limited: stats.rate_limited.load(Ordering::Relaxed),
+            capped: stats.capped.load(Ordering::Relaxed),
+            rebinds: stats.rebinds.load(Ordering::Relaxed),
+            requeued: stats.requeued.load(Ordering::Relaxed),
+            suppressed: stats.suppressed.load(Ordering::Relaxed),
+            congestion_pauses: stats.congestion_pauses.load(Ordering::Relaxed),
+            formatter_panics: stats.formatter_panics.load(Ordering::Relaxed),
+            filtered_by_level: stats.filtered_by_level.load(Ordering::Relaxed),
+            filtered_by_target: stats.filtered_by_target.load(Ordering::Relaxed),
+            filtered_by_filter: stats.filtered_by_filter.load(Ordering::Relaxed),
+            per_level: stats
+                .per_level
+                .each_ref()
+                .map(|count| count.load(Ordering::Relaxed)),
+            send_latency: SendLatency {
+                buckets: stats
+                    .latency_buckets
+                    .each_ref()
+                    .map(|count| count.load(Ordering::Relaxed)),
+                total_us: stats.latency_total_us.load(Ordering::Relaxed),
+                max_us: stats.latency_max_us.load(Ordering::Relaxed),
+            },
+        }
+    }
+
+    /// Read and zero every logger-wide counter, even for a destination's
+    ///
+    /// Each counter is swapped individually, so no increment is ever lost: it lands in
+    /// either this snapshot or the next one. Counters aren't swapped together though,
+    /// so an event racing with the take may be split across the two snapshots (e.g. a
+    /// send counted in this snapshot whose error is counted in the next).
+    pub(crate) fn take(&self) -> StatsSnapshot {
+        let stats = self.root();
+        StatsSnapshot {
+            sent: stats.sent.swap(0, Ordering::Relaxed),
+            dropped: stats.dropped.swap(0, Ordering::Relaxed),
+            errors: stats.errors.swap(0, Ordering::Relaxed),
+            empty: stats.empty.swap(0, Ordering::Relaxed),
+            would_send: stats.would_send.swap(0, Ordering::Relaxed),
+            throttled: stats.throttled.swap(0, Ordering::Relaxed),
+            invalid_utf8: stats.invalid_utf8.swap(0, Ordering::Relaxed),
+            expired: stats.expired.swap(0, Ordering::Relaxed),
+            spilled: stats.spilled.swap(0, Ordering::Relaxed),
+            replayed: stats.replayed.swap(0, Ordering::Relaxed),
+            drain_failures: stats.drain_failures.swap(0, Ordering::Relaxed),
+            rate_limited: stats.rate_limited.swap(0, Ordering::Relaxed),
+            capped: stats.capped.swap(0, Ordering::Relaxed),
+            rebinds: stats.rebinds.swap(0, Ordering::Relaxed),
+            requeued: stats.requeued.swap(0, Ordering::Relaxed),
+            suppressed: stats.suppressed.swap(0, Ordering::Relaxed),
+            congestion_pauses: stats.congestion_pauses.swap(0, Ordering::Relaxed),
+            formatter_panics: stats.formatter_panics.swap(0, Ordering::Relaxed),
+            filtered_by_level: stats.filtered_by_level.swap(0, Ordering::Relaxed),
+            filtered_by_target: stats.filtered_by_target.swap(0, Ordering::Relaxed),
+            filtered_by_filter: stats.filtered_by_filter.swap(0, Ordering::Relaxed),
+            per_level: stats
+                .per_level
+                .each_ref()
+                .map(|count| count.swap(0, Ordering::Relaxed)),
+            send_latency: SendLatency {
+                buckets: stats
+                    .latency_buckets
+                    .each_ref()
+                    .map(|count| count.swap(0, Ordering::Relaxed)),
+                total_us: stats.latency_total_us.swap(0, Ordering::Relaxed),
+                max_us: stats.latency_max_us.swap(0, Ordering::Relaxed),
+            },
+        }
+    }
+
+    /// This destination's own counters, see `Stats::scoped`
+    pub(crate) fn destination(&self, address: SocketAddr) -> DestinationStats {
+        DestinationStats {
+            address,
+            sent: self.sent.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Zero every counter, the time of the last send is kept
+    pub(crate) fn reset(&self) {
+        self.take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_and_reset() {
+        let stats = Stats::default();
+        stats.record_send();
+        stats.record_send();
+        stats.record_drop();
+        assert_eq!(stats.take().sent, 2);
+        assert_eq!(stats.snapshot(), StatsSnapshot::default());
+        assert!(stats.last_send_at().is_some());
+
+        stats.record_error();
+        stats.record_level(Level::Warn);
+        stats.reset();
+        assert_eq!(stats.snapshot(), StatsSnapshot::default());
+    }
+
+    #[test]
+    fn test_send_latency() {
+        assert_eq!(
+            [0, 1, 2, 3, 4, 1000, u64::MAX].map(latency_bucket),
+            [0, 1, 2, 2, 3, 10, 15]
+        );
+        let latency = SendLatency {
+            buckets: [0, 90, 0, 9, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            total_us: 150,
+            max_us: 20,
+        };
+        assert_eq!(latency.count(), 100);
+        assert_eq!(latency.mean(), Some(Duration::from_micros(1)));
+        assert_eq!(latency.percentile(50.0), Some(Duration::from_micros(2)));
+        assert_eq!(latency.percentile(99.0), Some(Duration::from_micros(8)));
+        assert_eq!(latency.percentile(100.0), Some(Duration::from_micros(20)));
+        assert_eq!(SendLatency::default().percentile(99.0), None);
+
+        let stats = Stats::default();
+        assert_eq!(stats.timed(|| 42), 42);
+        assert_eq!(stats.snapshot().send_latency.count(), 1);
+    }
+
+    #[test]
+    fn test_by_level() {
+        let stats = Stats::default();
+        for level in [Level::Error, Level::Info, Level::Info, Level::Trace] {
+            stats.record_level(level);
+        }
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.per_level, [1, 0, 2, 0, 1]);
+        let by_level = snapshot.by_level();
+        assert_eq!(by_level[&Level::Info], 2);
+        assert_eq!(by_level[&Level::Warn], 0);
+        assert_eq!(by_level.len(), 5);
+    }
+
+    #[test]
+    fn test_scoped() {
+        let stats = Arc::new(Stats::default());
+        let destination = Stats::scoped(&stats);
+        destination.record_send();
+        destination.record_error();
+        destination.record_rebind();
+        destination.record_level(Level::Warn);
+        stats.record_send();
+
+        let address = "127.0.0.1:1999".parse().unwrap();
+        assert_eq!(
+            destination.destination(address),
+            DestinationStats {
+                address,
+                sent: 1,
+                dropped: 0,
+                errors: 1
+            }
+        );
+        let snapshot = stats.snapshot();
+        assert_eq!(
+            (snapshot.sent, snapshot.errors, snapshot.rebinds),
+            (2, 1, 1)
+        );
+        assert_eq!(snapshot.per_level, [0, 1, 0, 0, 0]);
+        assert_eq!(destination.snapshot(), snapshot);
+        assert_eq!(destination.take(), snapshot);
+        assert_eq!(stats.snapshot(), StatsSnapshot::default());
+    }
+}
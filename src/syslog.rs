@@ -0,0 +1,90 @@
+use log::{Level, Record};
+
+/// Syslog facility codes as defined by RFC 5424
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Facility {
+    Kern = 0,
+    User = 1,
+    Mail = 2,
+    Daemon = 3,
+    Auth = 4,
+    Syslog = 5,
+    Lpr = 6,
+    News = 7,
+    Uucp = 8,
+    Cron = 9,
+    AuthPriv = 10,
+    Ftp = 11,
+    Local0 = 16,
+    Local1 = 17,
+    Local2 = 18,
+    Local3 = 19,
+    Local4 = 20,
+    Local5 = 21,
+    Local6 = 22,
+    Local7 = 23,
+}
+
+impl Facility {
+    /// The facility named `name`, as in syslog configs (e.g. `local0` or `authpriv`)
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "kern" => Facility::Kern,
+            "user" => Facility::User,
+            "mail" => Facility::Mail,
+            "daemon" => Facility::Daemon,
+            "auth" => Facility::Auth,
+            "syslog" => Facility::Syslog,
+            "lpr" => Facility::Lpr,
+            "news" => Facility::News,
+            "uucp" => Facility::Uucp,
+            "cron" => Facility::Cron,
+            "authpriv" => Facility::AuthPriv,
+            "ftp" => Facility::Ftp,
+            "local0" => Facility::Local0,
+            "local1" => Facility::Local1,
+            "local2" => Facility::Local2,
+            "local3" => Facility::Local3,
+            "local4" => Facility::Local4,
+            "local5" => Facility::Local5,
+            "local6" => Facility::Local6,
+            "local7" => Facility::Local7,
+            _ => return None,
+        })
+    }
+}
+
+/// Chooses the syslog facility for each record
+pub(crate) type FacilityFn = Box<dyn Fn(&Record) -> Facility + Send + Sync>;
+
+/// Map a log `Level` to its syslog severity (0 == Emergency, 7 == Debug)
+pub(crate) fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Compute the syslog PRI value for the given facility and level
+pub(crate) fn priority(facility: Facility, level: Level) -> u8 {
+    (facility as u8) * 8 + severity(level)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_priority() {
+        assert_eq!(priority(Facility::User, Level::Info), 14);
+        assert_eq!(priority(Facility::Local0, Level::Error), 131);
+        assert_eq!(priority(Facility::Kern, Level::Trace), 7);
+    }
+}
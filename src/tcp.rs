@@ -0,0 +1,177 @@
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use crate::queue::Envelope;
+use crate::socket::SocketOptions;
+use crate::stats::Stats;
+use crate::Writer;
+
+/// Sends each message over a TCP connection, prefixed with its length as a big-endian u32
+///
+/// The connection is opened when the writer is created, and reopened (counted in
+/// `StatsSnapshot::rebinds`) when a write fails, retrying that message once on the
+/// new connection.
+pub(crate) struct TcpWriter {
+    destination: SocketAddr,
+    socket: SocketOptions,
+    /// `None` after a failed reconnect, until the next message tries again
+    stream: Mutex<Option<TcpStream>>,
+    stats: Arc<Stats>,
+}
+
+impl TcpWriter {
+    pub(crate) fn new(
+        destination: SocketAddr,
+        socket: &SocketOptions,
+        stats: Arc<Stats>,
+    ) -> io::Result<Self> {
+        let socket = socket.clone();
+        let stream = connect(destination, &socket)?;
+        Ok(Self {
+            destination,
+            socket,
+            stream: Mutex::new(Some(stream)),
+            stats,
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<TcpStream>> {
+        self.stream.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Replace the connection with a new one, leaving none on error
+    fn reopen(&self, stream: &mut Option<TcpStream>) -> io::Result<()> {
+        *stream = None;
+        *stream = Some(connect(self.destination, &self.socket)?);
+        self.stats.record_rebind();
+        Ok(())
+    }
+}
+
+/// Connect to `destination`, with the send timeout of `socket` if it has one
+fn connect(destination: SocketAddr, socket: &SocketOptions) -> io::Result<TcpStream> {
+    let stream = match socket.send_timeout {
+        Some(timeout) => TcpStream::connect_timeout(&destination, timeout)?,
+        None => TcpStream::connect(destination)?,
+    };
+    stream.set_write_timeout(socket.send_timeout)?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+/// `message` prefixed with its length as a big-endian u32
+pub(crate) fn frame(message: &[u8]) -> io::Result<Vec<u8>> {
+    let len = u32::try_from(message.len()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("a {} byte message is too long to frame", message.len()),
+        )
+    })?;
+    let mut framed = Vec::with_capacity(4 + message.len());
+    framed.extend_from_slice(&len.to_be_bytes());
+    framed.extend_from_slice(message);
+    Ok(framed)
+}
+
+impl Writer for TcpWriter {
+    fn push(&self, message: Envelope) -> io::Result<()> {
+        if self.socket.is_paused() {
+            self.stats.record_drop();
+            return Ok(());
+        }
+        let message = self.socket.seal(&message.payload)?;
+        let framed = frame(&message)?;
+        let mut stream = self.lock();
+        let mut result = match stream.as_mut() {
+            Some(stream) => stream.write_all(&framed),
+            None => Err(io::ErrorKind::NotConnected.into()),
+        };
+        if result.is_err() {
+            // A write that failed part way may have sent part of the frame, so the
+            // message is resent whole on a new connection
+            result = self
+                .reopen(&mut stream)
+                .and_then(|()| stream.as_mut().unwrap().write_all(&framed));
+        }
+        match result {
+            Ok(()) => {
+                self.stats.record_send();
+                self.socket.sent(&message);
+                Ok(())
+            }
+            Err(e) => {
+                self.stats.record_error();
+                Err(e)
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self.lock().as_ref() {
+            Some(stream) => stream.local_addr(),
+            None => Err(io::ErrorKind::NotConnected.into()),
+        }
+    }
+
+    fn reconnect(&self) -> io::Result<()> {
+        self.reopen(&mut self.lock())
+    }
+
+    fn probe(&self, _timeout: Duration) -> bool {
+        let mut stream = self.lock();
+        stream.is_some() || self.reopen(&mut stream).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    /// Read one length-prefixed frame
+    fn read_frame(stream: &mut TcpStream) -> Vec<u8> {
+        let mut len = [0; 4];
+        stream.read_exact(&mut len).unwrap();
+        let mut message = vec![0; u32::from_be_bytes(len) as usize];
+        stream.read_exact(&mut message).unwrap();
+        message
+    }
+
+    #[test]
+    fn test_frame() {
+        assert_eq!(frame(b"hi\n").unwrap(), b"\0\0\0\x03hi\n");
+        assert_eq!(frame(b"").unwrap(), [0; 4]);
+    }
+
+    #[test]
+    fn test_reconnect_on_failure() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stats = Arc::new(Stats::default());
+        let writer = TcpWriter::new(
+            listener.local_addr().unwrap(),
+            &SocketOptions::default(),
+            stats.clone(),
+        )
+        .unwrap();
+        let (mut first, _) = listener.accept().unwrap();
+        writer.push("first\n".to_string().into()).unwrap();
+        assert_eq!(read_frame(&mut first), b"first\n");
+
+        // The collector hangs up, and the next failed write reconnects
+        drop(first);
+        let mut pushed = 0;
+        while stats.snapshot().rebinds == 0 {
+            writer.push("retried\n".to_string().into()).unwrap();
+            pushed += 1;
+            assert!(pushed < 100, "never noticed the closed connection");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let (mut second, _) = listener.accept().unwrap();
+        assert_eq!(read_frame(&mut second), b"retried\n");
+        assert_eq!(stats.snapshot().errors, 0);
+    }
+}
@@ -0,0 +1,171 @@
+use std::fmt::Write;
+use std::io;
+use std::thread;
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Utc};
+use log::Record;
+
+use crate::format::Fields;
+
+/// One piece of a parsed `with_template` template
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Literal(String),
+    Level,
+    /// The record's time, RFC 3339 or with this strftime spec
+    Ts(Option<String>),
+    Target,
+    Module,
+    File,
+    Line,
+    Msg,
+    Thread,
+}
+
+/// A `with_template` template, parsed once when it's set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Template {
+    tokens: Vec<Token>,
+}
+
+impl Template {
+    /// Parse `template`, failing with `InvalidInput` on an unknown token, an invalid
+    /// strftime spec, or an unmatched brace
+    pub(crate) fn parse(template: &str) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+        let mut tokens = Vec::new();
+        let mut literal = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find(['{', '}']) {
+            literal.push_str(&rest[..start]);
+            rest = &rest[start..];
+            // Doubled braces are literal braces
+            if rest.starts_with("{{") || rest.starts_with("}}") {
+                literal.push_str(&rest[..1]);
+                rest = &rest[2..];
+                continue;
+            }
+            if rest.starts_with('}') {
+                return Err(invalid(format!(
+                    "unmatched '}}' in template {:?}",
+                    template
+                )));
+            }
+            let end = rest
+                .find('}')
+                .ok_or_else(|| invalid(format!("unclosed '{{' in template {:?}", template)))?;
+            let token = match &rest[1..end] {
+                "level" => Token::Level,
+                "ts" => Token::Ts(None),
+                "target" => Token::Target,
+                "module" => Token::Module,
+                "file" => Token::File,
+                "line" => Token::Line,
+                "msg" => Token::Msg,
+                "thread" => Token::Thread,
+                name => match name.strip_prefix("ts:") {
+                    Some(spec) if StrftimeItems::new(spec).all(|item| item != Item::Error) => {
+                        Token::Ts(Some(spec.to_string()))
+                    }
+                    Some(spec) => return Err(invalid(format!("invalid strftime spec {:?}", spec))),
+                    None => return Err(invalid(format!("unknown template token {{{}}}", name))),
+                },
+            };
+            if !literal.is_empty() {
+                tokens.push(Token::Literal(std::mem::take(&mut literal)));
+            }
+            tokens.push(token);
+            rest = &rest[end + 1..];
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            tokens.push(Token::Literal(literal));
+        }
+        Ok(Self { tokens })
+    }
+
+    /// Render `record`, logged at `time`, as one line
+    ///
+    /// A record without a module, file, or line renders `?` in its place.
+    pub(crate) fn render(&self, record: &Record, fields: &Fields, time: DateTime<Utc>) -> String {
+        let mut out = String::new();
+        for token in &self.tokens {
+            let _ = match token {
+                Token::Literal(text) => write!(out, "{}", text),
+                Token::Level => write!(out, "{}", fields.level(record.level())),
+                Token::Ts(None) => write!(out, "{}", time.to_rfc3339()),
+                Token::Ts(Some(spec)) => write!(out, "{}", time.format(spec)),
+                Token::Target => write!(out, "{}", record.target()),
+                Token::Module => write!(out, "{}", record.module_path().unwrap_or("?")),
+                Token::File => write!(out, "{}", record.file().unwrap_or("?")),
+                Token::Line => match record.line() {
+                    Some(line) => write!(out, "{}", line),
+                    None => write!(out, "?"),
+                },
+                Token::Msg => write!(out, "{}", record.args()),
+                Token::Thread => write!(out, "{}", thread::current().name().unwrap_or("<unnamed>")),
+            };
+        }
+        out.push('\n');
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use log::Level;
+
+    #[test]
+    fn test_parse() {
+        let template = Template::parse("{{{level}}} {ts:%H:%M} {msg}").unwrap();
+        assert_eq!(
+            template.tokens,
+            [
+                Token::Literal("{".into()),
+                Token::Level,
+                Token::Literal("} ".into()),
+                Token::Ts(Some("%H:%M".into())),
+                Token::Literal(" ".into()),
+                Token::Msg,
+            ]
+        );
+        for invalid in ["{lvl} {msg}", "{msg", "msg}", "{ts:%Q}"].iter() {
+            let error = Template::parse(invalid).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let template =
+            Template::parse("{level} {ts:%H:%M:%S} {target} {module} {file}:{line} {msg}").unwrap();
+        let record = Record::builder()
+            .args(format_args!("disk full"))
+            .level(Level::Warn)
+            .target("app::disk")
+            .module_path(Some("app::disk"))
+            .file(Some("src/disk.rs"))
+            .line(Some(42))
+            .build();
+        let time = Utc.with_ymd_and_hms(2020, 6, 15, 3, 15, 39).unwrap();
+        assert_eq!(
+            template.render(&record, &Fields::new(None), time),
+            "WARN 03:15:39 app::disk app::disk src/disk.rs:42 disk full\n"
+        );
+
+        let template = Template::parse("{ts} {file}:{line} [{thread}] {msg}").unwrap();
+        let rendered = thread::Builder::new()
+            .name("worker".into())
+            .spawn(move || {
+                let record = Record::builder().args(format_args!("hi")).build();
+                template.render(&record, &Fields::new(None), time)
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(rendered, "2020-06-15T03:15:39+00:00 ?:? [worker] hi\n");
+    }
+}
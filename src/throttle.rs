@@ -0,0 +1,302 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use log::Record;
+
+/// Derives the throttle key for a record
+pub(crate) type KeyFn = Box<dyn Fn(&Record) -> String + Send + Sync>;
+
+/// How many distinct keys are remembered, the least recently seen key is forgotten first
+const CAPACITY: usize = 256;
+
+/// Allows each key through at most once per interval
+pub(crate) struct Throttle {
+    key: KeyFn,
+    interval: Duration,
+    /// When each key was last allowed through, most recently seen at the back
+    seen: Mutex<VecDeque<(String, Instant)>>,
+}
+
+impl Throttle {
+    pub(crate) fn new(key: KeyFn, interval: Duration) -> Self {
+        Self {
+            key,
+            interval,
+            seen: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Whether `record` should be sent at `now`, remembering it if so
+    pub(crate) fn allow(&self, record: &Record, now: Instant) -> bool {
+        let key = (self.key)(record);
+        let mut seen = self.seen.lock().unwrap();
+        if let Some(index) = seen.iter().position(|(k, _)| *k == key) {
+            let (key, last) = seen.remove(index).unwrap();
+            if now.duration_since(last) < self.interval {
+                seen.push_back((key, last));
+                return false;
+            }
+        } else if seen.len() >= CAPACITY {
+            seen.pop_front();
+        }
+        seen.push_back((key, now));
+        true
+    }
+}
+
+/// A steady rate along with how far a burst may exceed it
+///
+/// A `u32` converts to a limit whose burst is one second's worth of the rate
+/// ```
+/// use udp_logger::RateLimit;
+///
+/// let limit = RateLimit { per_sec: 100, burst: 500 };
+/// assert_eq!(RateLimit::from(100), RateLimit { per_sec: 100, burst: 100 });
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// How many datagrams per second are sent once any burst is used up
+    pub per_sec: u32,
+    /// How many datagrams may be sent at once after a quiet period
+    pub burst: u32,
+}
+
+impl From<u32> for RateLimit {
+    fn from(per_sec: u32) -> Self {
+        Self {
+            per_sec,
+            burst: per_sec,
+        }
+    }
+}
+
+/// Caps datagrams per second with a token bucket holding up to `burst` tokens
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    /// Tokens available and when they were last refilled
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    /// A limiter whose bucket starts full
+    pub(crate) fn new(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            limit,
+            bucket: Mutex::new((f64::from(limit.burst), now)),
+        }
+    }
+
+    /// Whether one more datagram may be sent at `now`, taking a token if so
+    pub(crate) fn allow(&self, now: Instant) -> bool {
+        let mut bucket = self.bucket.lock().unwrap();
+        let (tokens, last) = &mut *bucket;
+        let elapsed = now.saturating_duration_since(*last).as_secs_f64();
+        let refill = elapsed * f64::from(self.limit.per_sec);
+        *tokens = (*tokens + refill).min(f64::from(self.limit.burst));
+        *last = (*last).max(now);
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+}
+
+/// Stops sending after a total number of datagrams, for a capped capture
+pub(crate) struct SendCap {
+    max: usize,
+    sent: AtomicUsize,
+    /// Taken and run when the last datagram under the cap is allowed
+    on_reached: Mutex<Option<Box<dyn FnOnce() + Send>>>,
+}
+
+impl SendCap {
+    pub(crate) fn new(max: usize, on_reached: Option<Box<dyn FnOnce() + Send>>) -> Self {
+        Self {
+            max,
+            sent: AtomicUsize::new(0),
+            on_reached: Mutex::new(on_reached),
+        }
+    }
+
+    /// Whether one more datagram may be sent, running the callback if it's the last
+    pub(crate) fn allow(&self) -> bool {
+        let allowed = self
+            .sent
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |sent| {
+                (sent < self.max).then_some(sent + 1)
+            });
+        match allowed {
+            Ok(sent) => {
+                if sent + 1 == self.max {
+                    self.reached();
+                }
+                true
+            }
+            // A zero cap is reached before anything is sent
+            Err(_) => {
+                self.reached();
+                false
+            }
+        }
+    }
+
+    fn reached(&self) {
+        let callback = self
+            .on_reached
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(callback) = callback {
+            callback();
+        }
+    }
+}
+
+/// Tracks whether records are arriving faster than a threshold per second
+///
+/// Records are counted in one-second windows, and the rate is over the threshold while
+/// either the current or the previous window has exceeded it, so a spike is followed
+/// by at least one quiet second before switching back.
+pub(crate) struct RateMeter {
+    threshold: u32,
+    /// The current window's start and count, and the previous window's count
+    windows: Mutex<(Instant, u64, u64)>,
+}
+
+impl RateMeter {
+    pub(crate) fn new(threshold: u32, now: Instant) -> Self {
+        Self {
+            threshold,
+            windows: Mutex::new((now, 0, 0)),
+        }
+    }
+
+    /// Count a record at `now`, returning whether the rate is over the threshold
+    pub(crate) fn record(&self, now: Instant) -> bool {
+        let mut windows = self.windows.lock().unwrap();
+        let (start, current, previous) = &mut *windows;
+        let elapsed = now.saturating_duration_since(*start);
+        if elapsed >= Duration::from_secs(1) {
+            // A gap of more than a window means the previous one was empty
+            *previous = match elapsed < Duration::from_secs(2) {
+                true => *current,
+                false => 0,
+            };
+            *current = 0;
+            *start = now;
+        }
+        *current += 1;
+        let threshold = u64::from(self.threshold);
+        *current > threshold || *previous > threshold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    #[test]
+    fn test_throttle() {
+        let throttle = Throttle::new(
+            Box::new(|record| record.args().to_string()),
+            Duration::from_secs(1),
+        );
+        let allow = |message: &str, now| {
+            throttle.allow(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(Level::Warn)
+                    .build(),
+                now,
+            )
+        };
+        let start = Instant::now();
+        assert!(allow("disk full", start));
+        assert!(!allow("disk full", start + Duration::from_millis(500)));
+        assert!(allow("other", start + Duration::from_millis(500)));
+        assert!(!allow("disk full", start + Duration::from_millis(999)));
+        assert!(allow("disk full", start + Duration::from_secs(1)));
+        assert!(!allow("disk full", start + Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let start = Instant::now();
+        let limiter = RateLimiter::new(10.into(), start);
+        assert_eq!((0..20).filter(|_| limiter.allow(start)).count(), 10);
+        // Refills at the rate, one token every 100ms
+        assert!(!limiter.allow(start + Duration::from_millis(50)));
+        assert!(limiter.allow(start + Duration::from_millis(150)));
+        assert!(!limiter.allow(start + Duration::from_millis(150)));
+        // Never holds more than a second's worth
+        let later = start + Duration::from_secs(60);
+        assert_eq!((0..20).filter(|_| limiter.allow(later)).count(), 10);
+    }
+
+    #[test]
+    fn test_rate_meter() {
+        let start = Instant::now();
+        let meter = RateMeter::new(5, start);
+        let over: Vec<bool> = (0..8).map(|_| meter.record(start)).collect();
+        assert_eq!(over, [false, false, false, false, false, true, true, true]);
+        // Still over for the window after a spike
+        assert!(meter.record(start + Duration::from_millis(1500)));
+        // Then back under once a window stays quiet
+        assert!(!meter.record(start + Duration::from_millis(2600)));
+        // After a long gap it's under immediately
+        (0..10).for_each(|_| {
+            meter.record(start + Duration::from_secs(3));
+        });
+        assert!(!meter.record(start + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn test_rate_limit_burst() {
+        let start = Instant::now();
+        let limit = RateLimit {
+            per_sec: 10,
+            burst: 50,
+        };
+        let limiter = RateLimiter::new(limit, start);
+        // The whole burst passes immediately
+        assert_eq!((0..100).filter(|_| limiter.allow(start)).count(), 50);
+        // Then sustained traffic is paced at the steady rate, one every 100ms
+        let mut allowed = 0;
+        for ms in (0..2000).step_by(10) {
+            allowed += usize::from(limiter.allow(start + Duration::from_millis(ms)));
+        }
+        assert!((19..=20).contains(&allowed), "{} allowed", allowed);
+        // A quiet period refills the burst, but never beyond it
+        let later = start + Duration::from_secs(60);
+        assert_eq!((0..100).filter(|_| limiter.allow(later)).count(), 50);
+
+        // A burst smaller than the rate caps how many go at once
+        let limiter = RateLimiter::new(
+            RateLimit {
+                per_sec: 1000,
+                burst: 5,
+            },
+            start,
+        );
+        assert_eq!((0..100).filter(|_| limiter.allow(start)).count(), 5);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let throttle = Throttle::new(
+            Box::new(|record| record.args().to_string()),
+            Duration::from_secs(60),
+        );
+        let now = Instant::now();
+        for i in 0..=CAPACITY {
+            assert!(throttle.allow(&Record::builder().args(format_args!("{}", i)).build(), now));
+        }
+        // The least recently seen key was forgotten
+        assert!(throttle.allow(&Record::builder().args(format_args!("0")).build(), now));
+        assert!(!throttle.allow(&Record::builder().args(format_args!("2")).build(), now));
+    }
+}
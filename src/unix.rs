@@ -0,0 +1,126 @@
+use std::io;
+use std::net::SocketAddr;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{self, UnixDatagram};
+use std::sync::Arc;
+
+use crate::queue::Envelope;
+use crate::socket::SocketOptions;
+use crate::stats::Stats;
+use crate::Writer;
+
+/// The address of the Unix socket `name`: in the abstract namespace when it starts with
+/// `\0` or `@` (the rest is the name), otherwise a filesystem path
+pub(crate) fn unix_addr(name: &str) -> io::Result<net::SocketAddr> {
+    match name.strip_prefix('\0').or_else(|| name.strip_prefix('@')) {
+        Some(abstract_name) => net::SocketAddr::from_abstract_name(abstract_name),
+        None => net::SocketAddr::from_pathname(name),
+    }
+}
+
+/// Sends each message as one datagram to a local collector's AF_UNIX datagram socket
+///
+/// Datagrams keep message boundaries, so unlike `PipeWriter` nothing is framed.
+pub(crate) struct UnixDatagramWriter {
+    out: UnixDatagram,
+    destination: net::SocketAddr,
+    socket: SocketOptions,
+    stats: Arc<Stats>,
+}
+
+impl UnixDatagramWriter {
+    pub(crate) fn new(name: &str, socket: &SocketOptions, stats: Arc<Stats>) -> io::Result<Self> {
+        Ok(Self {
+            out: UnixDatagram::unbound()?,
+            destination: unix_addr(name)?,
+            socket: socket.clone(),
+            stats,
+        })
+    }
+}
+
+impl Writer for UnixDatagramWriter {
+    fn push(&self, message: Envelope) -> io::Result<()> {
+        if self.socket.is_paused() {
+            self.stats.record_drop();
+            return Ok(());
+        }
+        let message = self.socket.seal(&message.payload)?;
+        match self.out.send_to_addr(&message, &self.destination) {
+            Ok(_) => {
+                self.stats.record_send();
+                self.socket.sent(&message);
+                Ok(())
+            }
+            Err(e) => {
+                self.stats.record_error();
+                Err(e)
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Err(io::Error::new(
+            io::ErrorKind::NotConnected,
+            "unix datagram writer has no UDP socket",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use log::{Level, Log, Record};
+
+    use crate::{TimestampFormat, UdpLoggerBuilder};
+
+    #[test]
+    fn test_unix_addr() {
+        let addr = unix_addr("@udp_logger").unwrap();
+        assert_eq!(addr.as_abstract_name(), Some(&b"udp_logger"[..]));
+        let addr = unix_addr("\0udp_logger").unwrap();
+        assert_eq!(addr.as_abstract_name(), Some(&b"udp_logger"[..]));
+        let addr = unix_addr("/run/collector.sock").unwrap();
+        assert_eq!(
+            addr.as_pathname(),
+            Some(std::path::Path::new("/run/collector.sock"))
+        );
+    }
+
+    #[test]
+    fn test_abstract_socket() {
+        let name = format!("@udp_logger_test_{}", std::process::id());
+        let receiver = UnixDatagram::bind_addr(&unix_addr(&name).unwrap()).unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let writer =
+            UnixDatagramWriter::new(&name, &SocketOptions::default(), Arc::new(Stats::default()))
+                .unwrap();
+
+        writer.push("first\n".to_string().into()).unwrap();
+        writer.push("second\n".to_string().into()).unwrap();
+        let mut buf = [0; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"first\n");
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"second\n");
+        assert_eq!(writer.stats.snapshot().sent, 2);
+
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .unix_datagram(&name)
+            .with_timestamp_format(TimestampFormat::None)
+            .build()
+            .unwrap();
+        logger.log(
+            &Record::builder()
+                .args(format_args!("logged"))
+                .level(Level::Info)
+                .build(),
+        );
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"INFO logged\n");
+    }
+}
@@ -0,0 +1,204 @@
+use std::io;
+use std::str::FromStr;
+
+use log::Level;
+
+use crate::{Facility, Format, UdpLogger, UdpLoggerBuilder};
+
+/// The port used when a URL doesn't give one
+fn default_port(scheme: &str) -> Option<u16> {
+    match scheme {
+        "syslog+udp" => Some(514),
+        "gelf+udp" => Some(12201),
+        _ => None,
+    }
+}
+
+fn invalid(url: &str, reason: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("invalid logger URL {:?}: {}", url, reason),
+    )
+}
+
+impl UdpLoggerBuilder {
+    /// Create a builder from a collector URL, e.g. `syslog+udp://collector:514/?facility=local0`
+    ///
+    /// The scheme picks the format:
+    /// - `udp://host:port`: `Format::Text`
+    /// - `syslog+udp://host[:514]`: `Format::Text` with a syslog `<PRI>` prefix
+    /// - `gelf+udp://host[:12201]`: `Format::Gelf`
+    ///
+    /// Query parameters are `facility` (syslog only, default == `user`), `app` (see
+    /// `with_app_name`), and `level` (e.g. `debug`). An unknown scheme or parameter
+    /// is an `InvalidInput` error.
+    /// ```
+    /// use udp_logger::UdpLoggerBuilder;
+    ///
+    /// let logger = UdpLoggerBuilder::from_url("syslog+udp://127.0.0.1/?facility=local0&app=myapp")
+    ///     .unwrap()
+    ///     .buffered()
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn from_url(url: &str) -> io::Result<Self> {
+        let (scheme, rest) = url
+            .split_once("://")
+            .ok_or_else(|| invalid(url, "expected scheme://host:port"))?;
+        if !matches!(scheme, "udp" | "syslog+udp" | "gelf+udp") {
+            return Err(invalid(
+                url,
+                format!(
+                    "unknown scheme {:?}, expected udp, syslog+udp, or gelf+udp",
+                    scheme
+                ),
+            ));
+        }
+        let (address, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let host = address.strip_suffix('/').unwrap_or(address);
+        if host.is_empty() || host.contains('/') {
+            return Err(invalid(url, "expected a host:port with no path"));
+        }
+        // An IPv6 literal's colons are inside its brackets
+        let has_port = match host.rfind(']') {
+            Some(bracket) => host[bracket..].contains(':'),
+            None => host.contains(':'),
+        };
+        let destination = match (has_port, default_port(scheme)) {
+            (true, _) => host.to_string(),
+            (false, Some(port)) => format!("{}:{}", host, port),
+            (false, None) => return Err(invalid(url, "udp:// needs a port")),
+        };
+
+        let mut builder = UdpLoggerBuilder::new(destination.as_str());
+        let mut facility = Facility::User;
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            match key {
+                "facility" if scheme == "syslog+udp" => {
+                    facility = Facility::from_name(value)
+                        .ok_or_else(|| invalid(url, format!("unknown facility {:?}", value)))?;
+                }
+                "app" => builder = builder.with_app_name(value),
+                "level" => {
+                    let level = Level::from_str(value)
+                        .map_err(|_| invalid(url, format!("unknown level {:?}", value)))?;
+                    builder = builder.level(level);
+                }
+                _ => return Err(invalid(url, format!("unknown parameter {:?}", key))),
+            }
+        }
+        Ok(match scheme {
+            "syslog+udp" => builder.with_syslog(facility),
+            "gelf+udp" => builder.with_format(Format::Gelf),
+            _ => builder,
+        })
+    }
+}
+
+impl UdpLogger {
+    /// Create a new, unbuffered UdpLogger from a collector URL, see `UdpLoggerBuilder::from_url`
+    pub fn from_url(url: &str) -> io::Result<Self> {
+        UdpLoggerBuilder::from_url(url)?.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    use chrono::{DateTime, Utc};
+    use log::Record;
+
+    use crate::FixedClock;
+
+    /// The payload a built logger sends for a WARN `testing` record
+    fn format(builder: UdpLoggerBuilder) -> String {
+        let time = DateTime::parse_from_rfc3339("2020-06-15T03:15:39+00:00").unwrap();
+        let logger = builder
+            .with_clock(FixedClock(time.with_timezone(&Utc)))
+            .build()
+            .expect("Can bind to localhost");
+        let line = logger.format(
+            &Record::builder()
+                .args(format_args!("testing"))
+                .level(Level::Warn)
+                .target("my_app")
+                .build(),
+        );
+        String::from_utf8(line).unwrap()
+    }
+
+    fn destination(builder: &UdpLoggerBuilder) -> SocketAddr {
+        *builder.destination.as_ref().expect("Resolves")
+    }
+
+    #[test]
+    fn test_udp() {
+        let builder = UdpLoggerBuilder::from_url("udp://127.0.0.1:1999?level=debug").unwrap();
+        assert_eq!(destination(&builder), "127.0.0.1:1999".parse().unwrap());
+        assert_eq!(builder.level, Level::Debug);
+        assert_eq!(
+            format(builder),
+            "WARN [2020-06-15T03:15:39+00:00] testing\n"
+        );
+
+        let builder = UdpLoggerBuilder::from_url("udp://[::1]:1999/").unwrap();
+        assert_eq!(destination(&builder), "[::1]:1999".parse().unwrap());
+    }
+
+    #[test]
+    fn test_syslog() {
+        let builder =
+            UdpLoggerBuilder::from_url("syslog+udp://127.0.0.1/?facility=local0&app=myapp")
+                .unwrap();
+        assert_eq!(destination(&builder), "127.0.0.1:514".parse().unwrap());
+        assert_eq!(
+            format(builder),
+            "<132>WARN [2020-06-15T03:15:39+00:00] app=myapp testing\n"
+        );
+
+        let builder = UdpLoggerBuilder::from_url("syslog+udp://127.0.0.1:1514").unwrap();
+        assert_eq!(destination(&builder), "127.0.0.1:1514".parse().unwrap());
+        assert!(format(builder).starts_with("<12>WARN"));
+    }
+
+    #[test]
+    fn test_gelf() {
+        let builder = UdpLoggerBuilder::from_url("gelf+udp://127.0.0.1?app=myapp").unwrap();
+        assert_eq!(destination(&builder), "127.0.0.1:12201".parse().unwrap());
+        assert_eq!(builder.format, Format::Gelf);
+        let line = format(builder);
+        assert!(line.contains("\"short_message\":\"testing\""), "{}", line);
+        assert!(line.contains("\"_app\":\"myapp\""), "{}", line);
+
+        // One GELF object per datagram, so batching is rejected
+        let error = UdpLoggerBuilder::from_url("gelf+udp://127.0.0.1")
+            .unwrap()
+            .buffered()
+            .with_batching(1024)
+            .build()
+            .err()
+            .expect("Can't batch GELF");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let error = |url| {
+            UdpLoggerBuilder::from_url(url)
+                .err()
+                .expect("Invalid URL")
+                .to_string()
+        };
+        assert!(error("tcp://127.0.0.1:514").contains("unknown scheme \"tcp\""));
+        assert!(error("127.0.0.1:514").contains("expected scheme://"));
+        assert!(error("udp://127.0.0.1").contains("needs a port"));
+        assert!(error("udp://127.0.0.1:1999/logs").contains("no path"));
+        assert!(error("udp://127.0.0.1:1999?colour=blue").contains("unknown parameter"));
+        assert!(error("udp://127.0.0.1:1999?facility=local0").contains("unknown parameter"));
+        assert!(error("syslog+udp://127.0.0.1?facility=local9").contains("unknown facility"));
+        assert!(error("syslog+udp://127.0.0.1?level=loud").contains("unknown level"));
+    }
+}
@@ -0,0 +1,144 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::queue::Envelope;
+use crate::Writer;
+
+/// Spreads messages across writers in proportion to their weights
+///
+/// Uses smooth weighted round-robin, so with weights 3 and 1 every four messages go
+/// `a a b a` rather than in runs, and each writer's share is exact over every cycle of
+/// the total weight.
+pub(crate) struct WeightedWriter {
+    writers: Vec<(Box<dyn Writer>, i64)>,
+    total: i64,
+    /// Each writer's running score, the highest is sent to next
+    current: Mutex<Vec<i64>>,
+}
+
+impl WeightedWriter {
+    pub(crate) fn new(writers: Vec<(Box<dyn Writer>, u32)>) -> io::Result<Self> {
+        if writers.iter().any(|(_, weight)| *weight == 0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "destination weights must be positive",
+            ));
+        }
+        let writers: Vec<(Box<dyn Writer>, i64)> = writers
+            .into_iter()
+            .map(|(writer, weight)| (writer, weight.into()))
+            .collect();
+        Ok(Self {
+            total: writers.iter().map(|(_, weight)| weight).sum(),
+            current: Mutex::new(vec![0; writers.len()]),
+            writers,
+        })
+    }
+
+    /// The index of the writer to send the next message to
+    fn next(&self) -> usize {
+        let mut current = self.current.lock().unwrap_or_else(PoisonError::into_inner);
+        for (score, (_, weight)) in current.iter_mut().zip(&self.writers) {
+            *score += weight;
+        }
+        let (index, _) = current
+            .iter()
+            .enumerate()
+            .max_by_key(|(index, score)| (**score, std::cmp::Reverse(*index)))
+            .expect("At least one writer");
+        current[index] -= self.total;
+        index
+    }
+
+    fn writers(&self) -> impl Iterator<Item = &dyn Writer> {
+        self.writers.iter().map(|(writer, _)| &**writer)
+    }
+}
+
+impl Writer for WeightedWriter {
+    fn push(&self, message: Envelope) -> io::Result<()> {
+        self.writers[self.next()].0.push(message)
+    }
+
+    fn push_batch(&self, messages: Vec<Envelope>) -> io::Result<()> {
+        let mut batches: Vec<Vec<Envelope>> = self.writers.iter().map(|_| Vec::new()).collect();
+        for message in messages {
+            batches[self.next()].push(message);
+        }
+        // Every writer gets its batch, even if an earlier one failed
+        let mut result = Ok(());
+        for (batch, writer) in batches.into_iter().zip(self.writers()) {
+            if batch.is_empty() {
+                continue;
+            }
+            let pushed = writer.push_batch(batch);
+            if result.is_ok() {
+                result = pushed;
+            }
+        }
+        result
+    }
+
+    /// The local address of the first writer, the builder's own destination
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.writers[0].0.local_addr()
+    }
+
+    fn flush_timeout(&self, timeout: Duration) -> usize {
+        let deadline = Instant::now() + timeout;
+        self.writers()
+            .map(|writer| writer.flush_timeout(deadline.saturating_duration_since(Instant::now())))
+            .sum()
+    }
+
+    fn drain(&self) -> usize {
+        self.writers().map(|writer| writer.drain()).sum()
+    }
+
+    fn wake(&self) {
+        self.writers().for_each(|writer| writer.wake());
+    }
+
+    fn peek_queue(&self, max: usize) -> Vec<Envelope> {
+        let mut queued = Vec::new();
+        for writer in self.writers() {
+            queued.extend(writer.peek_queue(max - queued.len()));
+        }
+        queued
+    }
+
+    fn reconnect(&self) -> io::Result<()> {
+        self.writers().try_for_each(|writer| writer.reconnect())
+    }
+
+    fn probe(&self, timeout: Duration) -> bool {
+        self.writers().all(|writer| writer.probe(timeout))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Discard;
+
+    impl Writer for Discard {
+        fn push(&self, _message: Envelope) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            Err(io::ErrorKind::NotConnected.into())
+        }
+    }
+
+    #[test]
+    fn test_smooth_order() {
+        let writer = WeightedWriter::new(vec![(Box::new(Discard), 3), (Box::new(Discard), 1)]);
+        let writer = writer.unwrap();
+        let order: Vec<usize> = (0..8).map(|_| writer.next()).collect();
+        assert_eq!(order, [0, 0, 1, 0, 0, 0, 1, 0]);
+    }
+}
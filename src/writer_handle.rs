@@ -0,0 +1,150 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+
+use crate::stats::Stats;
+use crate::{UdpLoggerBuilder, Writer};
+
+/// Replaces a UdpLogger's primary writer at runtime, even after it has been installed globally
+///
+/// Records logged while the writer is being replaced go to either the old writer or the
+/// new one, never neither. The old writer is dropped once the last record using it has
+/// been pushed, so a buffered writer still sends its queue (see `with_flush_on_drop`).
+/// ```
+/// # use std::net::UdpSocket;
+/// use udp_logger::{UdpLogger, UdpLoggerBuilder};
+///
+/// # let first = UdpSocket::bind("127.0.0.1:0").unwrap();
+/// # let second = UdpSocket::bind("127.0.0.1:0").unwrap();
+/// let logger = UdpLogger::new_buffered(first.local_addr().unwrap()).unwrap();
+/// let handle = logger.writer_handle();
+/// handle
+///     .set_writer(UdpLoggerBuilder::new(second.local_addr().unwrap()).buffered())
+///     .unwrap();
+/// ```
+#[derive(Clone)]
+pub struct WriterHandle {
+    writer: Arc<ArcSwap<Box<dyn Writer>>>,
+    stats: Arc<Stats>,
+    paused: Arc<AtomicBool>,
+}
+
+impl WriterHandle {
+    pub(crate) fn new(
+        writer: Arc<ArcSwap<Box<dyn Writer>>>,
+        stats: Arc<Stats>,
+        paused: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            writer,
+            stats,
+            paused,
+        }
+    }
+
+    /// Send to the destination and with the writer kind and queue options of `builder`
+    ///
+    /// Only the writer is taken from `builder`: the logger keeps its own level, format,
+    /// extra destinations, counters, and `pause` state. On error the current writer is
+    /// left in place.
+    pub fn set_writer(&self, mut builder: UdpLoggerBuilder) -> io::Result<()> {
+        builder.socket.paused = self.paused.clone();
+        let writer = builder.primary_writer(&self.stats)?;
+        self.replace(writer);
+        Ok(())
+    }
+
+    /// Stop sending until `resume`, see `UdpLogger::pause`
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Start sending again after `pause`, see `UdpLogger::resume`
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+
+    fn replace(&self, writer: Box<dyn Writer>) {
+        // Dropped here unless a concurrent `log` call is still pushing to it
+        drop(self.writer.swap(Arc::new(writer)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use std::sync::Mutex;
+
+    use log::{Level, Log, Record};
+
+    use crate::{Envelope, UdpLogger};
+
+    /// Keeps every pushed payload for inspection
+    struct CaptureWriter {
+        captured: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Writer for CaptureWriter {
+        fn push(&self, message: Envelope) -> io::Result<()> {
+            let payload = String::from_utf8(message.payload.to_vec()).unwrap();
+            self.captured.lock().unwrap().push(payload);
+            Ok(())
+        }
+
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            Err(io::ErrorKind::NotConnected.into())
+        }
+    }
+
+    fn log(logger: &UdpLogger, message: &str) {
+        logger.log(
+            &Record::builder()
+                .args(format_args!("{}", message))
+                .level(Level::Info)
+                .build(),
+        );
+    }
+
+    #[test]
+    fn test_swap_writer() {
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .with_formatter(|record| format!("{}\n", record.args()))
+            .build()
+            .unwrap();
+        let handle = logger.writer_handle();
+        let (first, second) = (Arc::default(), Arc::default());
+        handle.replace(Box::new(CaptureWriter {
+            captured: Arc::clone(&first),
+        }));
+        log(&logger, "before");
+        handle.replace(Box::new(CaptureWriter {
+            captured: Arc::clone(&second),
+        }));
+        log(&logger, "after");
+
+        assert_eq!(*first.lock().unwrap(), ["before\n"]);
+        assert_eq!(*second.lock().unwrap(), ["after\n"]);
+        assert!(logger.local_addr().is_err());
+    }
+
+    #[test]
+    fn test_set_writer_keeps_pause() {
+        let logger = UdpLoggerBuilder::new("127.0.0.1:1999")
+            .manual()
+            .build()
+            .unwrap();
+        let handle = logger.writer_handle();
+        handle.pause();
+        handle
+            .set_writer(UdpLoggerBuilder::new("127.0.0.1:1999").manual())
+            .unwrap();
+        log(&logger, "held");
+        assert!(logger.is_paused());
+        assert_eq!(logger.drain(), 0);
+        handle.resume();
+        assert_eq!(logger.drain(), 1);
+    }
+}